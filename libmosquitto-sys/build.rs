@@ -53,6 +53,7 @@ fn main() {
         "mosquitto/lib/will_mosq.c",
     ]);
     cfg.define("WITH_THREADING", None);
+    cfg.define("WITH_SOCKS", None);
     if !target.contains("windows") {
         cfg.flag("-fvisibility=hidden");
         cfg.define("WITH_UNIX_SOCKETS", None);
@@ -0,0 +1,218 @@
+//! Derive macros for [mosquitto-rs](https://docs.rs/mosquitto-rs).
+//! These are re-exported by mosquitto-rs when its `derive` feature
+//! is enabled, and should be used from there.
+//!
+//! The input is parsed directly from the token stream, rather than via
+//! `syn`, as only the attributes and the name of the struct are needed.
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Literal, TokenStream, TokenTree};
+
+/// Implements `mosquitto_rs::MqttMessage` for a struct, along with
+/// `publish_to` and `subscribe` methods; see the documentation of the
+/// `MqttMessage` trait for the attributes that it accepts.
+#[proc_macro_derive(MqttMessage, attributes(mqtt))]
+pub fn derive_mqtt_message(input: TokenStream) -> TokenStream {
+    let result = parse(input).and_then(|message| message.expand());
+    match result {
+        Ok(tokens) => tokens,
+        Err(err) => format!("::std::compile_error!({:?});", err)
+            .parse()
+            .unwrap(),
+    }
+}
+
+struct MessageAttributes {
+    name: String,
+    topic: Option<String>,
+    qos: u8,
+    retain: bool,
+    codec: String,
+}
+
+fn parse(input: TokenStream) -> Result<MessageAttributes, String> {
+    let mut message = MessageAttributes {
+        name: String::new(),
+        topic: None,
+        qos: 0,
+        retain: false,
+        codec: "::mosquitto_rs::Json".to_string(),
+    };
+    let mut tokens = input.into_iter();
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Group(group) if group.delimiter() == Delimiter::Bracket => {
+                let mut attr = group.stream().into_iter();
+                match (attr.next(), attr.next()) {
+                    (Some(TokenTree::Ident(ident)), Some(TokenTree::Group(args)))
+                        if ident.to_string() == "mqtt" =>
+                    {
+                        parse_args(args.stream(), &mut message)?
+                    }
+                    _ => {}
+                }
+            }
+            TokenTree::Ident(ident) if ident.to_string() == "struct" => {
+                match tokens.next() {
+                    Some(TokenTree::Ident(name)) => message.name = name.to_string(),
+                    _ => return Err("expected the name of the struct".to_string()),
+                }
+                if let Some(TokenTree::Punct(p)) = tokens.next() {
+                    if p.as_char() == '<' {
+                        return Err("MqttMessage cannot be derived for generic structs".to_string());
+                    }
+                }
+                break;
+            }
+            TokenTree::Ident(ident) if ["enum", "union"].contains(&ident.to_string().as_str()) => {
+                return Err("MqttMessage can only be derived for structs".to_string());
+            }
+            _ => {}
+        }
+    }
+    Ok(message)
+}
+
+/// Parses the `key = value` and `key` arguments of `#[mqtt(...)]`
+fn parse_args(args: TokenStream, message: &mut MessageAttributes) -> Result<(), String> {
+    let mut args = args.into_iter().peekable();
+    while let Some(token) = args.next() {
+        let key = match token {
+            TokenTree::Ident(ident) => ident.to_string(),
+            TokenTree::Punct(p) if p.as_char() == ',' => continue,
+            other => return Err(format!("unexpected `{}` in #[mqtt(...)]", other)),
+        };
+        let value = match args.peek() {
+            Some(TokenTree::Punct(p)) if p.as_char() == '=' => {
+                args.next();
+                args.next()
+            }
+            _ => None,
+        };
+        match (key.as_str(), value) {
+            ("topic", Some(TokenTree::Literal(lit))) => message.topic = Some(string(&lit)?),
+            ("qos", Some(TokenTree::Literal(lit))) => {
+                message.qos = match lit.to_string().as_str() {
+                    "0" => 0,
+                    "1" => 1,
+                    "2" => 2,
+                    qos => return Err(format!("qos must be 0, 1 or 2, not {}", qos)),
+                }
+            }
+            ("retain", None) => message.retain = true,
+            ("retain", Some(TokenTree::Ident(value))) => {
+                message.retain = match value.to_string().as_str() {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err("retain must be true or false".to_string()),
+                }
+            }
+            ("codec", Some(TokenTree::Literal(lit))) => message.codec = string(&lit)?,
+            ("topic", _) | ("qos", _) | ("codec", _) | ("retain", _) => {
+                return Err(format!("invalid value for `{}` in #[mqtt(...)]", key))
+            }
+            _ => return Err(format!("unknown attribute `{}` in #[mqtt(...)]", key)),
+        }
+    }
+    Ok(())
+}
+
+/// Returns the contents of a string literal that has no escapes
+fn string(lit: &Literal) -> Result<String, String> {
+    let s = lit.to_string();
+    let raw = s.strip_prefix('r').map(|s| s.trim_matches('#'));
+    let quoted = raw.unwrap_or(&s);
+    match quoted.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(s) if raw.is_some() || !s.contains('\\') => Ok(s.to_string()),
+        _ => Err(format!("expected a string without escapes, not {}", s)),
+    }
+}
+
+impl MessageAttributes {
+    fn expand(&self) -> Result<TokenStream, String> {
+        let topic = self
+            .topic
+            .as_ref()
+            .ok_or("#[mqtt(topic = \"...\")] is required")?;
+
+        // Each `{field}` in the template must be a whole topic level,
+        // so that it can be replaced by `+` when subscribing
+        let mut format = vec![];
+        let mut fields = vec![];
+        for level in topic.split('/') {
+            match level.strip_prefix('{').and_then(|l| l.strip_suffix('}')) {
+                Some(field) if is_ident(field) => {
+                    format.push("{}");
+                    fields.push(format!("self.{}", field));
+                }
+                _ if level.contains(|c| "{}+#".contains(c)) => {
+                    return Err(format!("invalid topic level `{}` in {}", level, topic));
+                }
+                _ => format.push(level),
+            }
+        }
+
+        let qos = match self.qos {
+            0 => "AtMostOnce",
+            1 => "AtLeastOnce",
+            _ => "ExactlyOnce",
+        };
+        let code = format!(
+            r#"
+            impl ::mosquitto_rs::MqttMessage for {name} {{
+                const TOPIC: &'static str = {topic:?};
+                const QOS: ::mosquitto_rs::QoS = ::mosquitto_rs::QoS::{qos};
+                const RETAIN: bool = {retain};
+
+                fn topic(&self) -> ::std::string::String {{
+                    ::std::format!({format:?}, {fields})
+                }}
+
+                fn encode(&self) -> ::std::result::Result<::std::vec::Vec<u8>, ::mosquitto_rs::Error> {{
+                    <{codec} as ::mosquitto_rs::Codec<Self>>::encode(self)
+                }}
+
+                fn decode(payload: &[u8]) -> ::std::result::Result<Self, ::mosquitto_rs::Error> {{
+                    <{codec} as ::mosquitto_rs::Codec<Self>>::decode(payload)
+                }}
+            }}
+
+            impl {name} {{
+                /// Publish this message via `client`; see
+                /// `mosquitto_rs::Client::publish_message`
+                pub async fn publish_to(
+                    &self,
+                    client: &mut ::mosquitto_rs::Client,
+                ) -> ::std::result::Result<::mosquitto_rs::MessageId, ::mosquitto_rs::Error> {{
+                    client.publish_message(self).await
+                }}
+
+                /// Subscribe to the messages of this type via `client`; see
+                /// `mosquitto_rs::Client::subscribe_messages`
+                pub async fn subscribe(
+                    client: &mut ::mosquitto_rs::Client,
+                ) -> ::std::result::Result<::mosquitto_rs::TypedSubscriber<Self>, ::mosquitto_rs::Error> {{
+                    client.subscribe_messages::<Self>().await
+                }}
+            }}
+            "#,
+            name = self.name,
+            topic = topic,
+            qos = qos,
+            retain = self.retain,
+            format = format.join("/"),
+            fields = fields.join(", "),
+            codec = self.codec,
+        );
+        code.parse()
+            .map_err(|err| format!("failed to generate MqttMessage: {:?}", err))
+    }
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
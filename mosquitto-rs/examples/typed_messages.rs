@@ -0,0 +1,34 @@
+//! This example shows how to use `#[derive(MqttMessage)]` to publish
+//! and receive structs, rather than topics and payloads.
+use mosquitto_rs::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, MqttMessage, Serialize, Deserialize)]
+#[mqtt(topic = "sensors/{device}/temp", qos = 1)]
+struct Temperature {
+    device: String,
+    celsius: f32,
+}
+
+fn main() -> Result<(), Error> {
+    smol::block_on(async {
+        let mut client = Client::with_auto_id()?;
+        client
+            .connect("localhost", 1883, std::time::Duration::from_secs(5), None)
+            .await?;
+
+        let temperatures = Temperature::subscribe(&mut client).await?;
+
+        Temperature {
+            device: "kitchen".to_string(),
+            celsius: 21.5,
+        }
+        .publish_to(&mut client)
+        .await?;
+
+        let temperature = temperatures.recv().await?;
+        println!("{:?}", temperature);
+
+        Ok(())
+    })
+}
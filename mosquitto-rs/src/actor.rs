@@ -0,0 +1,233 @@
+use crate::{Client, ConnAck, Error, MessageId, QoS, Stats, SubscribeOptions, Subscriber};
+use async_channel::{bounded, unbounded, Receiver, Sender};
+use std::os::raw::c_int;
+use std::time::Duration;
+
+type Reply<T> = Sender<Result<T, Error>>;
+
+enum Command {
+    Connect {
+        host: String,
+        port: c_int,
+        keep_alive_interval: Duration,
+        bind_address: Option<String>,
+        reply: Reply<ConnAck>,
+    },
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        qos: QoS,
+        retain: bool,
+        reply: Reply<MessageId>,
+    },
+    Subscribe {
+        pattern: String,
+        qos: QoS,
+        options: SubscribeOptions,
+        reply: Reply<()>,
+    },
+    Subscriber {
+        reply: Reply<Option<Subscriber>>,
+    },
+    Stats {
+        reply: Reply<Stats>,
+    },
+}
+
+/// A cloneable handle that sends commands to a [Client](struct.Client.html)
+/// owned by a [ClientActor](struct.ClientActor.html).
+///
+/// This allows the client to be used from frameworks that are built
+/// around message passing, such as actix or xtra, and from multiple
+/// tasks or threads, without sharing the client itself.
+///
+/// ```no_run
+/// use mosquitto_rs::*;
+///
+/// # fn main() -> Result<(), Error> {
+/// let client = Client::with_auto_id()?;
+/// let (handle, actor) = ClientActor::new(client);
+/// std::thread::spawn(move || smol::block_on(actor.run()));
+///
+/// smol::block_on(async {
+///     handle
+///         .connect("localhost", 1883, std::time::Duration::from_secs(5), None)
+///         .await?;
+///     handle
+///         .publish("test", b"hello".to_vec(), QoS::AtMostOnce, false)
+///         .await?;
+///     Ok(())
+/// })
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ClientHandle {
+    tx: Sender<Command>,
+}
+
+/// Owns a [Client](struct.Client.html) and performs the commands sent
+/// via its [ClientHandle](struct.ClientHandle.html)s.
+///
+/// The actor does nothing until [run](#method.run) is awaited, which is
+/// typically done by spawning it onto an executor or a dedicated thread.
+pub struct ClientActor {
+    client: Client,
+    rx: Receiver<Command>,
+}
+
+impl ClientActor {
+    /// Create an actor that takes ownership of `client`, which should
+    /// already have been configured, along with a handle for sending
+    /// commands to it.
+    pub fn new(client: Client) -> (ClientHandle, Self) {
+        let (tx, rx) = unbounded();
+        (ClientHandle { tx }, Self { client, rx })
+    }
+
+    /// Perform commands until all of the handles have been dropped,
+    /// then return the client.
+    ///
+    /// Commands are performed one at a time, in the order that they
+    /// were sent, so a command that waits for a response from the
+    /// broker delays those that follow it.
+    pub async fn run(mut self) -> Client {
+        while let Ok(command) = self.rx.recv().await {
+            self.perform(command).await;
+        }
+        self.client
+    }
+
+    async fn perform(&mut self, command: Command) {
+        // A failure to reply means that the caller has gone away
+        // and is no longer interested in the result
+        match command {
+            Command::Connect {
+                host,
+                port,
+                keep_alive_interval,
+                bind_address,
+                reply,
+            } => {
+                let result = self
+                    .client
+                    .connect(&host, port, keep_alive_interval, bind_address.as_deref())
+                    .await;
+                let _ = reply.send(result).await;
+            }
+            Command::Publish {
+                topic,
+                payload,
+                qos,
+                retain,
+                reply,
+            } => {
+                let result = self.client.publish(&topic, &payload, qos, retain).await;
+                let _ = reply.send(result).await;
+            }
+            Command::Subscribe {
+                pattern,
+                qos,
+                options,
+                reply,
+            } => {
+                let result = self
+                    .client
+                    .subscribe_with_options(&pattern, qos, options)
+                    .await;
+                let _ = reply.send(result).await;
+            }
+            Command::Subscriber { reply } => {
+                let _ = reply.send(Ok(self.client.subscriber())).await;
+            }
+            Command::Stats { reply } => {
+                let _ = reply.send(Ok(self.client.stats())).await;
+            }
+        }
+    }
+}
+
+impl ClientHandle {
+    async fn request<T>(&self, command: impl FnOnce(Reply<T>) -> Command) -> Result<T, Error> {
+        let (reply, rx) = bounded(1);
+        self.tx
+            .send(command(reply))
+            .await
+            .map_err(|_| Error::ActorStopped)?;
+        rx.recv().await.map_err(|_| Error::ActorStopped)?
+    }
+
+    /// Connect to the broker.
+    /// See [Client::connect](struct.Client.html#method.connect).
+    pub async fn connect(
+        &self,
+        host: &str,
+        port: c_int,
+        keep_alive_interval: Duration,
+        bind_address: Option<&str>,
+    ) -> Result<ConnAck, Error> {
+        self.request(|reply| Command::Connect {
+            host: host.to_string(),
+            port,
+            keep_alive_interval,
+            bind_address: bind_address.map(String::from),
+            reply,
+        })
+        .await
+    }
+
+    /// Publish a message.
+    /// See [Client::publish](struct.Client.html#method.publish).
+    pub async fn publish(
+        &self,
+        topic: &str,
+        payload: Vec<u8>,
+        qos: QoS,
+        retain: bool,
+    ) -> Result<MessageId, Error> {
+        self.request(|reply| Command::Publish {
+            topic: topic.to_string(),
+            payload,
+            qos,
+            retain,
+            reply,
+        })
+        .await
+    }
+
+    /// Establish a subscription.
+    /// See [Client::subscribe](struct.Client.html#method.subscribe).
+    pub async fn subscribe(&self, pattern: &str, qos: QoS) -> Result<(), Error> {
+        self.subscribe_with_options(pattern, qos, SubscribeOptions::default())
+            .await
+    }
+
+    /// Establish a subscription using MQTT 5 subscription options.
+    /// See [Client::subscribe_with_options](struct.Client.html#method.subscribe_with_options).
+    pub async fn subscribe_with_options(
+        &self,
+        pattern: &str,
+        qos: QoS,
+        options: SubscribeOptions,
+    ) -> Result<(), Error> {
+        self.request(|reply| Command::Subscribe {
+            pattern: pattern.to_string(),
+            qos,
+            options,
+            reply,
+        })
+        .await
+    }
+
+    /// Obtain the channel of received messages.
+    /// As with [Client::subscriber](struct.Client.html#method.subscriber),
+    /// this yields the channel only once.
+    pub async fn subscriber(&self) -> Result<Option<Subscriber>, Error> {
+        self.request(|reply| Command::Subscriber { reply }).await
+    }
+
+    /// Returns the traffic counters of the client.
+    /// See [Client::stats](struct.Client.html#method.stats).
+    pub async fn stats(&self) -> Result<Stats, Error> {
+        self.request(|reply| Command::Stats { reply }).await
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+/// A topic alias to attach to an outgoing publish
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Alias {
+    /// The alias has just been assigned to the topic, so the topic
+    /// must be sent along with it to establish the mapping
+    New(u16),
+    /// The broker already knows the topic by this alias
+    Existing(u16),
+}
+
+/// Assigns MQTT 5 topic aliases to the most recently used publish
+/// topics, within the Topic Alias Maximum advertised by the broker.
+/// When all aliases are in use, the least recently used one is
+/// reassigned.
+/// Aliases only last for the lifetime of a connection, so this must
+/// be reset each time the client connects.
+#[derive(Debug, Default)]
+pub(crate) struct TopicAliases {
+    maximum: u16,
+    /// Maps each topic to its alias and the time that it was last used
+    topics: HashMap<String, (u16, u64)>,
+    clock: u64,
+}
+
+impl TopicAliases {
+    /// Forget all assigned aliases, and assign up to `maximum` from now on
+    pub fn reset(&mut self, maximum: u16) {
+        self.maximum = maximum;
+        self.topics.clear();
+    }
+
+    /// Returns the alias to use when publishing to `topic`, or `None`
+    /// if the broker doesn't accept aliases
+    pub fn assign(&mut self, topic: &str) -> Option<Alias> {
+        self.clock += 1;
+        if let Some((alias, last_used)) = self.topics.get_mut(topic) {
+            *last_used = self.clock;
+            return Some(Alias::Existing(*alias));
+        }
+
+        let alias = if self.topics.len() < self.maximum as usize {
+            // Aliases start at 1
+            self.topics.len() as u16 + 1
+        } else {
+            let lru = self
+                .topics
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(topic, _)| topic.clone())?;
+            self.topics.remove(&lru)?.0
+        };
+        self.topics.insert(topic.to_string(), (alias, self.clock));
+        Some(Alias::New(alias))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn assign() {
+        let mut aliases = TopicAliases::default();
+        assert_eq!(aliases.assign("a"), None);
+
+        aliases.reset(2);
+        assert_eq!(aliases.assign("a"), Some(Alias::New(1)));
+        assert_eq!(aliases.assign("b"), Some(Alias::New(2)));
+        assert_eq!(aliases.assign("a"), Some(Alias::Existing(1)));
+        // "b" is the least recently used
+        assert_eq!(aliases.assign("c"), Some(Alias::New(2)));
+        assert_eq!(aliases.assign("a"), Some(Alias::Existing(1)));
+        assert_eq!(aliases.assign("b"), Some(Alias::New(2)));
+
+        aliases.reset(2);
+        assert_eq!(aliases.assign("b"), Some(Alias::New(1)));
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+/// Describes an exponentially increasing delay between successive
+/// attempts at an operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// The delay after the first failure
+    pub initial: Duration,
+    /// The upper limit on the delay
+    pub max: Duration,
+    /// The factor by which the delay grows after each
+    /// consecutive failure
+    pub multiplier: u32,
+}
+
+impl Default for Backoff {
+    /// Starts at 1 second, doubling up to a maximum of 1 minute
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            multiplier: 2,
+        }
+    }
+}
+
+impl Backoff {
+    /// Returns the delay to use after `failures` consecutive failures.
+    /// `failures` of 0 or 1 both yield the initial delay.
+    pub fn delay(&self, failures: u32) -> Duration {
+        let mut delay = self.initial;
+        for _ in 1..failures {
+            if delay >= self.max {
+                break;
+            }
+            delay = delay.saturating_mul(self.multiplier);
+        }
+        delay.min(self.max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn delay() {
+        let backoff = Backoff::default();
+        assert_eq!(backoff.delay(0), Duration::from_secs(1));
+        assert_eq!(backoff.delay(1), Duration::from_secs(1));
+        assert_eq!(backoff.delay(2), Duration::from_secs(2));
+        assert_eq!(backoff.delay(4), Duration::from_secs(8));
+        assert_eq!(backoff.delay(7), Duration::from_secs(60));
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(60));
+    }
+}
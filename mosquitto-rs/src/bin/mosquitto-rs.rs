@@ -0,0 +1,381 @@
+//! A command line MQTT client built on the high level
+//! [Client](../mosquitto_rs/struct.Client.html), in the spirit of
+//! `mosquitto_pub` and `mosquitto_sub`.
+//!
+//! ```text
+//! mosquitto-rs pub -t sensors/1/temp -m 21.5 -q 1
+//! mosquitto-rs sub -t 'sensors/#' -V 5 --json
+//! ```
+//!
+//! Run `mosquitto-rs help` for the full list of options.
+use mosquitto_rs::*;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "\
+usage: mosquitto-rs pub [options] -t TOPIC (-m MESSAGE | -f FILE | -s | -l)
+       mosquitto-rs sub [options] -t PATTERN [-t PATTERN ...]
+
+connection options:
+  -h, --host HOST            broker host (default: localhost)
+  -p, --port PORT            broker port (default: 1883, or 8883 with TLS)
+  -i, --id ID                client id (default: random)
+  -k, --keepalive SECONDS    keepalive interval (default: 60)
+  -u, --username USERNAME
+  -P, --pw PASSWORD
+  -V, --protocol-version V   31, 311 or 5 (default: 311)
+      --cafile FILE          enable TLS, trusting the CAs in FILE
+      --capath DIR           enable TLS, trusting the CAs in DIR
+      --cert FILE            client certificate, for TLS
+      --key FILE             client private key, for TLS
+  -q, --qos QOS              0, 1 or 2 (default: 0)
+  -D, --property NAME VALUE  MQTT 5 property to send; NAME is one of
+                             content-type, response-topic, correlation-data,
+                             message-expiry-interval, payload-format-indicator,
+                             or user-property, which takes a KEY and a VALUE
+
+pub options:
+  -t, --topic TOPIC          topic to publish to
+  -m, --message MESSAGE      publish MESSAGE
+  -f, --file FILE            publish the contents of FILE
+  -s, --stdin-file           publish all of stdin as one message
+  -l, --stdin-line           publish each line of stdin as a message
+  -n, --null-message         publish an empty message
+  -r, --retain               retain the message
+      --repeat COUNT         publish the message COUNT times, and report
+                             the rate at which they were acknowledged
+
+sub options:
+  -t, --topic PATTERN        pattern to subscribe to; may be repeated
+  -C, --count COUNT          exit after receiving COUNT messages
+  -W, --timeout SECONDS      exit after SECONDS without a message
+  -v, --verbose              print the topic before each payload
+  -R, --no-retained          skip retained messages
+      --json                 print each message as a line of JSON
+";
+
+enum Payload {
+    Message(Vec<u8>),
+    File(PathBuf),
+    Stdin,
+    StdinLines,
+}
+
+#[derive(Default)]
+struct Options {
+    host: Option<String>,
+    port: Option<i32>,
+    id: Option<String>,
+    keepalive: u64,
+    username: Option<String>,
+    password: Option<String>,
+    protocol_version: Option<ProtocolVersion>,
+    ca_file: Option<PathBuf>,
+    ca_path: Option<PathBuf>,
+    cert_file: Option<PathBuf>,
+    key_file: Option<PathBuf>,
+    qos: QoS,
+    properties: Properties,
+    topics: Vec<String>,
+    payload: Option<Payload>,
+    retain: bool,
+    repeat: u64,
+    count: Option<u64>,
+    timeout: Option<Duration>,
+    verbose: bool,
+    no_retained: bool,
+    json: bool,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self {
+            keepalive: 60,
+            repeat: 1,
+            ..Self::default()
+        };
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("{} requires a value", name))
+            };
+            match arg.as_str() {
+                "-h" | "--host" => options.host = Some(value(&arg)?),
+                "-p" | "--port" => options.port = Some(number(&arg, &value(&arg)?)?),
+                "-i" | "--id" => options.id = Some(value(&arg)?),
+                "-k" | "--keepalive" => options.keepalive = number(&arg, &value(&arg)?)?,
+                "-u" | "--username" => options.username = Some(value(&arg)?),
+                "-P" | "--pw" => options.password = Some(value(&arg)?),
+                "-V" | "--protocol-version" => {
+                    options.protocol_version = Some(match value(&arg)?.as_str() {
+                        "31" | "mqttv31" => ProtocolVersion::V31,
+                        "311" | "mqttv311" => ProtocolVersion::V311,
+                        "5" | "mqttv5" => ProtocolVersion::V5,
+                        v => return Err(format!("unknown protocol version {}", v)),
+                    })
+                }
+                "--cafile" => options.ca_file = Some(value(&arg)?.into()),
+                "--capath" => options.ca_path = Some(value(&arg)?.into()),
+                "--cert" => options.cert_file = Some(value(&arg)?.into()),
+                "--key" => options.key_file = Some(value(&arg)?.into()),
+                "-q" | "--qos" => {
+                    options.qos = match value(&arg)?.as_str() {
+                        "0" => QoS::AtMostOnce,
+                        "1" => QoS::AtLeastOnce,
+                        "2" => QoS::ExactlyOnce,
+                        q => return Err(format!("invalid QoS {}", q)),
+                    }
+                }
+                "-D" | "--property" => {
+                    let name = value(&arg)?;
+                    let property = match name.as_str() {
+                        "content-type" => Property::ContentType(value(&arg)?),
+                        "response-topic" => Property::ResponseTopic(value(&arg)?),
+                        "correlation-data" => Property::CorrelationData(value(&arg)?.into_bytes()),
+                        "message-expiry-interval" => {
+                            Property::MessageExpiryInterval(number(&name, &value(&arg)?)?)
+                        }
+                        "payload-format-indicator" => {
+                            Property::PayloadFormatIndicator(number(&name, &value(&arg)?)?)
+                        }
+                        "user-property" => Property::UserProperty(value(&arg)?, value(&arg)?),
+                        _ => return Err(format!("unsupported property {}", name)),
+                    };
+                    options.properties.push(property);
+                }
+                "-t" | "--topic" => options.topics.push(value(&arg)?),
+                "-m" | "--message" => {
+                    options.payload = Some(Payload::Message(value(&arg)?.into_bytes()))
+                }
+                "-f" | "--file" => options.payload = Some(Payload::File(value(&arg)?.into())),
+                "-s" | "--stdin-file" => options.payload = Some(Payload::Stdin),
+                "-l" | "--stdin-line" => options.payload = Some(Payload::StdinLines),
+                "-n" | "--null-message" => options.payload = Some(Payload::Message(vec![])),
+                "-r" | "--retain" => options.retain = true,
+                "--repeat" => options.repeat = number(&arg, &value(&arg)?)?,
+                "-C" | "--count" => options.count = Some(number(&arg, &value(&arg)?)?),
+                "-W" | "--timeout" => {
+                    options.timeout = Some(Duration::from_secs(number(&arg, &value(&arg)?)?))
+                }
+                "-v" | "--verbose" => options.verbose = true,
+                "-R" | "--no-retained" => options.no_retained = true,
+                "--json" => options.json = true,
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+        if options.topics.is_empty() {
+            return Err("a topic must be specified with -t".to_string());
+        }
+        Ok(options)
+    }
+
+    fn uses_tls(&self) -> bool {
+        self.ca_file.is_some() || self.ca_path.is_some()
+    }
+
+    async fn connect(&self) -> Result<Client, Error> {
+        let mut client = match &self.id {
+            Some(id) => Client::with_id(id, true)?,
+            None => Client::with_auto_id()?,
+        };
+        if let Some(version) = self.protocol_version {
+            client.set_option(&ClientOption::ProtocolVersion(version))?;
+        }
+        if self.username.is_some() || self.password.is_some() {
+            client.set_username_and_password(self.username.as_deref(), self.password.as_deref())?;
+        }
+        if self.uses_tls() {
+            client.configure_tls(
+                self.ca_file.as_ref(),
+                self.ca_path.as_ref(),
+                self.cert_file.as_ref(),
+                self.key_file.as_ref(),
+                None,
+            )?;
+        }
+        let host = self.host.as_deref().unwrap_or("localhost");
+        let port = match self.port {
+            Some(port) => port,
+            None if self.uses_tls() => 8883,
+            None => 1883,
+        };
+        let connack = client
+            .connect(host, port, Duration::from_secs(self.keepalive), None)
+            .await?;
+        if !connack.is_successful() {
+            return Err(Error::RejectedConnection(connack.reason));
+        }
+        Ok(client)
+    }
+}
+
+fn number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {} for {}", value, name))
+}
+
+async fn publish(options: Options) -> Result<(), Error> {
+    let mut client = options.connect().await?;
+    if !options.properties.is_empty() {
+        let properties = options.properties.clone();
+        client.add_publish_middleware(move |publish: &mut OutgoingPublish| {
+            for property in properties.iter() {
+                publish.properties.push(property.clone());
+            }
+            Ok(())
+        });
+    }
+
+    let payloads: Box<dyn Iterator<Item = std::io::Result<Vec<u8>>>> = match &options.payload {
+        Some(Payload::Message(message)) => Box::new(std::iter::once(Ok(message.clone()))),
+        Some(Payload::File(path)) => Box::new(std::iter::once(std::fs::read(path))),
+        Some(Payload::Stdin) => {
+            let mut payload = vec![];
+            let result = std::io::Read::read_to_end(&mut std::io::stdin(), &mut payload);
+            Box::new(std::iter::once(result.map(|_| payload)))
+        }
+        Some(Payload::StdinLines) => Box::new(
+            std::io::stdin()
+                .lock()
+                .lines()
+                .map(|line| line.map(String::into_bytes)),
+        ),
+        None => {
+            return Err(Error::Denied(
+                "a message must be specified with -m, -f, -s, -l or -n".to_string(),
+            ))
+        }
+    };
+
+    let started = Instant::now();
+    let mut published = 0u64;
+    for payload in payloads {
+        let payload = payload?;
+        for topic in &options.topics {
+            for _ in 0..options.repeat {
+                client
+                    .publish(topic, &payload, options.qos, options.retain)
+                    .await?;
+                published += 1;
+            }
+        }
+    }
+    if options.repeat > 1 {
+        let elapsed = started.elapsed();
+        eprintln!(
+            "published {} messages in {:.3}s ({:.0} messages/s)",
+            published,
+            elapsed.as_secs_f64(),
+            published as f64 / elapsed.as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+async fn subscribe(options: Options) -> Result<(), Error> {
+    let mut client = options.connect().await?;
+    let subscriber = client.subscriber().ok_or(Error::InvalidArgument)?;
+    for pattern in &options.topics {
+        client.subscribe(pattern, options.qos).await?;
+    }
+
+    let mut received = 0;
+    while options.count != Some(received) {
+        let recv = async { subscriber.recv().await.map_err(|_| Error::ConnectionLost) };
+        let message = match options.timeout {
+            Some(timeout) => {
+                let timeout = async {
+                    async_io::Timer::after(timeout).await;
+                    Err(Error::Timeout)
+                };
+                futures_lite::future::or(recv, timeout).await
+            }
+            None => recv.await,
+        };
+        let message = match message {
+            Ok(message) => message,
+            Err(Error::Timeout) => break,
+            Err(err) => return Err(err),
+        };
+        if options.no_retained && message.retain {
+            continue;
+        }
+        received += 1;
+        if options.json {
+            println!("{}", to_json(&message));
+        } else if options.verbose {
+            println!(
+                "{} {}",
+                message.topic,
+                String::from_utf8_lossy(&message.payload)
+            );
+        } else {
+            println!("{}", String::from_utf8_lossy(&message.payload));
+        }
+    }
+    Ok(())
+}
+
+fn to_json(message: &Message) -> serde_json::Value {
+    let properties = message.properties();
+    let (payload, payload_hex) = match std::str::from_utf8(&message.payload) {
+        Ok(text) => (Some(text), None),
+        Err(_) => {
+            let hex: String = message
+                .payload
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            (None, Some(hex))
+        }
+    };
+    let user_properties: Vec<(&str, &str)> = properties.user_properties().collect();
+    serde_json::json!({
+        "topic": message.topic,
+        "payload": payload,
+        "payload_hex": payload_hex,
+        "qos": message.qos as u8,
+        "retain": message.retain,
+        "mid": message.mid,
+        "content_type": properties.content_type(),
+        "response_topic": properties.response_topic(),
+        "correlation_data": properties
+            .correlation_data()
+            .map(String::from_utf8_lossy),
+        "user_properties": user_properties,
+    })
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let command = args.next();
+    let options = match command.as_deref() {
+        Some("pub") | Some("sub") => Options::parse(args),
+        Some("help") | Some("--help") | None => {
+            print!("{}", USAGE);
+            return;
+        }
+        Some(command) => Err(format!("unknown command {}", command)),
+    };
+    let options = match options {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let result = futures_lite::future::block_on(async {
+        if command.as_deref() == Some("pub") {
+            publish(options).await
+        } else {
+            subscribe(options).await
+        }
+    });
+    if let Err(err) = result {
+        eprintln!("mosquitto-rs: {}", err);
+        std::process::exit(1);
+    }
+}
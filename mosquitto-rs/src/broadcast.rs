@@ -0,0 +1,176 @@
+use crate::lowlevel::topic_matches_sub;
+use crate::{Error, Message};
+use async_channel::{bounded, Receiver, Sender, TryRecvError, TrySendError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+/// The channel of one [BroadcastReceiver](struct.BroadcastReceiver.html)
+struct Subscription {
+    tx: Sender<Message>,
+    /// A receiver for the channel, used to discard the oldest message
+    /// when the channel is full
+    drain: Receiver<Message>,
+    lagged: Arc<AtomicU64>,
+}
+
+impl Subscription {
+    /// `drain` keeps the channel open, so a receiver that has gone
+    /// away can't be detected by sending
+    fn is_closed(&self) -> bool {
+        self.tx.receiver_count() <= 1
+    }
+
+    fn send(&self, message: Message) {
+        if let Err(TrySendError::Full(message)) = self.tx.try_send(message) {
+            if self.drain.try_recv().is_ok() {
+                self.lagged.fetch_add(1, Ordering::Relaxed);
+            }
+            let _ = self.tx.try_send(message);
+        }
+    }
+}
+
+/// Fans out the messages that match `pattern` to all of its receivers
+pub(crate) struct Broadcast {
+    pattern: String,
+    subscriptions: Mutex<Vec<Subscription>>,
+}
+
+impl Broadcast {
+    pub fn new(pattern: &str) -> Arc<Self> {
+        Arc::new(Self {
+            pattern: pattern.to_string(),
+            subscriptions: Mutex::new(vec![]),
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn subscribe(self: &Arc<Self>, capacity: usize) -> BroadcastReceiver {
+        let (tx, rx) = bounded(capacity.max(1));
+        let lagged = Arc::new(AtomicU64::new(0));
+        self.subscriptions.lock().unwrap().push(Subscription {
+            tx,
+            drain: rx.clone(),
+            lagged: Arc::clone(&lagged),
+        });
+        BroadcastReceiver {
+            rx,
+            lagged,
+            capacity,
+            broadcast: Arc::downgrade(self),
+        }
+    }
+
+    /// Returns true if any receivers remain
+    pub fn is_active(&self) -> bool {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        subscriptions.retain(|s| !s.is_closed());
+        !subscriptions.is_empty()
+    }
+
+    /// Deliver `message` to every receiver if it matches the pattern,
+    /// returning true if it was delivered
+    pub fn dispatch(&self, message: &Message) -> bool {
+        if !topic_matches_sub(&self.pattern, &message.topic).unwrap_or(false) {
+            return false;
+        }
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        subscriptions.retain(|s| !s.is_closed());
+        for subscription in subscriptions.iter() {
+            subscription.send(message.clone());
+        }
+        !subscriptions.is_empty()
+    }
+}
+
+/// Receives every message that matches the pattern of a broadcast,
+/// independently of the other receivers of that broadcast; see
+/// [Client::broadcast](struct.Client.html#method.broadcast).
+///
+/// Each receiver has a bounded queue of its own.
+/// When a receiver falls behind and its queue fills up, the oldest
+/// message is discarded to make room for the newest, and the next
+/// call to [recv](#method.recv) reports how many were missed via
+/// `Error::Lagged`, so that a slow receiver never holds up the others.
+pub struct BroadcastReceiver {
+    rx: Receiver<Message>,
+    lagged: Arc<AtomicU64>,
+    capacity: usize,
+    broadcast: Weak<Broadcast>,
+}
+
+impl BroadcastReceiver {
+    /// Wait for the next message.
+    ///
+    /// Yields `Error::Lagged` with the number of messages that were
+    /// discarded, if any were discarded since the previous call, and
+    /// `Error::Closed` once the client has been dropped and all of the
+    /// queued messages have been received.
+    pub async fn recv(&self) -> Result<Message, Error> {
+        self.check_lagged()?;
+        self.rx.recv().await.map_err(|_| Error::Closed)
+    }
+
+    /// Returns the next message if one is already queued, or `None`
+    /// if there is none.
+    /// Errors are reported as for [recv](#method.recv).
+    pub fn try_recv(&self) -> Result<Option<Message>, Error> {
+        self.check_lagged()?;
+        match self.rx.try_recv() {
+            Ok(message) => Ok(Some(message)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Closed) => Err(Error::Closed),
+        }
+    }
+
+    /// Create another receiver for the same broadcast, with the same
+    /// capacity.
+    /// The new receiver yields only the messages that arrive from now
+    /// on.
+    /// Yields `Error::Closed` if the client has been dropped.
+    pub fn resubscribe(&self) -> Result<Self, Error> {
+        let broadcast = self.broadcast.upgrade().ok_or(Error::Closed)?;
+        Ok(broadcast.subscribe(self.capacity))
+    }
+
+    fn check_lagged(&self) -> Result<(), Error> {
+        match self.lagged.swap(0, Ordering::Relaxed) {
+            0 => Ok(()),
+            n => Err(Error::Lagged(n)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lagging_receiver() {
+        let broadcast = Broadcast::new("a/#");
+        let fast = broadcast.subscribe(4);
+        let slow = broadcast.subscribe(2);
+
+        for n in 0..3u8 {
+            let message = Message {
+                topic: "a/b".to_string(),
+                payload: vec![n],
+                ..Message::default()
+            };
+            assert!(broadcast.dispatch(&message));
+            assert_eq!(fast.try_recv().unwrap().unwrap().payload, vec![n]);
+        }
+
+        assert!(matches!(slow.try_recv(), Err(Error::Lagged(1))));
+        assert_eq!(slow.try_recv().unwrap().unwrap().payload, vec![1]);
+        assert_eq!(slow.try_recv().unwrap().unwrap().payload, vec![2]);
+        assert!(matches!(slow.try_recv(), Ok(None)));
+
+        drop(fast);
+        drop(slow);
+        assert!(!broadcast.is_active());
+    }
+}
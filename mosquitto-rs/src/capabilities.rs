@@ -0,0 +1,186 @@
+use crate::{Error, Properties, Property, QoS};
+use std::time::Duration;
+
+/// The features and limits that an MQTT 5 broker advertised in
+/// the properties of its CONNACK.
+/// Brokers using an earlier version of the protocol don't advertise
+/// anything, so all features are assumed to be available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrokerCapabilities {
+    /// The highest QoS level that the broker supports for publishing
+    /// and subscribing
+    pub maximum_qos: QoS,
+    /// Whether the broker supports retained messages
+    pub retain_available: bool,
+    /// Whether the broker supports subscriptions using wildcards
+    pub wildcard_subscriptions_available: bool,
+    /// Whether the broker supports subscription identifiers
+    pub subscription_identifiers_available: bool,
+    /// Whether the broker supports shared subscriptions
+    pub shared_subscriptions_available: bool,
+    /// The largest packet that the broker will accept, if limited
+    pub maximum_packet_size: Option<u32>,
+    /// The number of QoS 1 and QoS 2 publishes that the broker
+    /// will process concurrently
+    pub receive_maximum: u16,
+    /// The highest topic alias that the broker will accept, where
+    /// 0 means that topic aliases are not supported
+    pub topic_alias_maximum: u16,
+    /// The keepalive interval chosen by the broker, which replaces
+    /// the one requested by the client
+    pub server_keep_alive: Option<Duration>,
+}
+
+impl Default for BrokerCapabilities {
+    /// Describes a broker that supports all features, which is what
+    /// MQTT 5 specifies when the corresponding properties are absent
+    fn default() -> Self {
+        Self {
+            maximum_qos: QoS::ExactlyOnce,
+            retain_available: true,
+            wildcard_subscriptions_available: true,
+            subscription_identifiers_available: true,
+            shared_subscriptions_available: true,
+            maximum_packet_size: None,
+            receive_maximum: u16::MAX,
+            topic_alias_maximum: 0,
+            server_keep_alive: None,
+        }
+    }
+}
+
+impl BrokerCapabilities {
+    /// Parse the capabilities from the properties of a CONNACK.
+    /// Properties that are absent take their default values.
+    pub fn from_properties(props: &Properties) -> Self {
+        let mut caps = Self::default();
+        for prop in props {
+            match prop {
+                Property::MaximumQos(0) => caps.maximum_qos = QoS::AtMostOnce,
+                Property::MaximumQos(1) => caps.maximum_qos = QoS::AtLeastOnce,
+                Property::RetainAvailable(v) => caps.retain_available = *v != 0,
+                Property::WildcardSubAvailable(v) => {
+                    caps.wildcard_subscriptions_available = *v != 0
+                }
+                Property::SubscriptionIdAvailable(v) => {
+                    caps.subscription_identifiers_available = *v != 0
+                }
+                Property::SharedSubAvailable(v) => caps.shared_subscriptions_available = *v != 0,
+                Property::MaximumPacketSize(v) => caps.maximum_packet_size = Some(*v),
+                Property::ReceiveMaximum(v) => caps.receive_maximum = *v,
+                Property::TopicAliasMaximum(v) => caps.topic_alias_maximum = *v,
+                Property::ServerKeepAlive(v) => {
+                    caps.server_keep_alive = Some(Duration::from_secs((*v).into()))
+                }
+                _ => {}
+            }
+        }
+        caps
+    }
+
+    /// Check that the broker supports the features used by a
+    /// subscription to `pattern`, which may be a shared subscription
+    /// of the form `$share/{group}/{filter}`.
+    /// This produces a descriptive error, rather than the broker
+    /// disconnecting the client when it receives the subscription.
+    pub fn check_subscription(&self, pattern: &str) -> Result<(), Error> {
+        let filter = match pattern.strip_prefix("$share/") {
+            Some(rest) => {
+                if !self.shared_subscriptions_available {
+                    return Err(Error::Unsupported("shared subscriptions".to_string()));
+                }
+                rest.split_once('/').map(|(_, filter)| filter).unwrap_or("")
+            }
+            None => pattern,
+        };
+        let has_wildcard = filter.split('/').any(|level| level == "+" || level == "#");
+        if has_wildcard && !self.wildcard_subscriptions_available {
+            return Err(Error::Unsupported("wildcard subscriptions".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Check that the broker supports subscription identifiers
+    pub fn check_subscription_identifiers(&self) -> Result<(), Error> {
+        if self.subscription_identifiers_available {
+            Ok(())
+        } else {
+            Err(Error::Unsupported("subscription identifiers".to_string()))
+        }
+    }
+
+    /// Returns `qos`, limited to the maximum supported by the broker
+    pub fn limit_qos(&self, qos: QoS) -> QoS {
+        if (qos as u8) > (self.maximum_qos as u8) {
+            self.maximum_qos
+        } else {
+            qos
+        }
+    }
+}
+
+/// Controls how the high-level client publishes retained messages
+/// when the broker has advertised that it doesn't support them.
+/// Sending such a message would cause the broker to disconnect
+/// the client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RetainPolicy {
+    /// Fail the publish with `Error::RetainNotSupported`
+    #[default]
+    Fail,
+    /// Publish the message without the retain flag, and report
+    /// `Event::RetainStripped`
+    Strip,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_properties() {
+        let props: Properties = vec![
+            Property::MaximumQos(1),
+            Property::RetainAvailable(0),
+            Property::TopicAliasMaximum(10),
+        ]
+        .into_iter()
+        .collect();
+        let caps = BrokerCapabilities::from_properties(&props);
+        assert_eq!(caps.maximum_qos, QoS::AtLeastOnce);
+        assert!(!caps.retain_available);
+        assert!(caps.shared_subscriptions_available);
+        assert_eq!(caps.topic_alias_maximum, 10);
+        assert_eq!(caps.limit_qos(QoS::ExactlyOnce), QoS::AtLeastOnce);
+        assert_eq!(caps.limit_qos(QoS::AtMostOnce), QoS::AtMostOnce);
+    }
+
+    #[test]
+    fn check_subscription() {
+        let caps = BrokerCapabilities {
+            wildcard_subscriptions_available: false,
+            ..BrokerCapabilities::default()
+        };
+        assert!(caps.check_subscription("a/b").is_ok());
+        assert!(caps.check_subscription("a/b+").is_ok());
+        assert!(caps.check_subscription("$share/group/a/b").is_ok());
+        assert!(matches!(
+            caps.check_subscription("a/+/b"),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            caps.check_subscription("$share/group/#"),
+            Err(Error::Unsupported(_))
+        ));
+
+        let caps = BrokerCapabilities {
+            shared_subscriptions_available: false,
+            ..BrokerCapabilities::default()
+        };
+        assert!(caps.check_subscription("a/#").is_ok());
+        assert!(matches!(
+            caps.check_subscription("$share/group/a"),
+            Err(Error::Unsupported(_))
+        ));
+    }
+}
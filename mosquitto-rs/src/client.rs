@@ -1,18 +1,574 @@
-use crate::lowlevel::sys::{mosq_err_t, mosq_opt_t};
-use crate::lowlevel::{Callbacks, MessageId, Mosq, QoS};
-use crate::{ConnectionStatus, Error, PasswdCallback};
-use async_channel::{bounded, unbounded, Receiver, Sender};
-use std::collections::HashMap;
+use crate::alias::{Alias, TopicAliases};
+use crate::broadcast::Broadcast;
+use crate::credentials::CredentialsState;
+#[cfg(unix)]
+use crate::event_loop::{EventLoop, Registration};
+use crate::exactly_once::message_id;
+use crate::latest::Watch;
+use crate::lowlevel::sys::mosq_opt_t;
+use crate::lowlevel::{
+    sub_topic_check, topic_matches_sub, Callbacks, MessageId, MessageRef, Mosq, QoS,
+};
+use crate::memory::{message_size, MemoryBudget};
+use crate::middleware::{apply_message_middleware, apply_publish_middleware};
+use crate::offline::{OfflineQueue, QueuedPublish};
+use crate::presence::Presence;
+use crate::probe::LatencyProbe;
+use crate::resolve::{interleave_families, race_connect};
+use crate::scope::{normalize_prefix, ScopedPatterns};
+use crate::stats::{QueueCounters, TopicCounters, TrafficCounters};
+use crate::watchdog::{close_socket, Activity, TimerThread};
+use crate::{
+    Backoff, BroadcastReceiver, BrokerCapabilities, Cipher, ConnAck, ConnectStrategy,
+    ConnectionStatus, CredentialsProvider, DeadLetter, DeadLetterReason, Encryption,
+    EndpointSelection, Error, Event, HttpProxy, HttpTunnel, LastWill, LatestValues, MessageFilter,
+    MessageMiddleware, MessageSink, MessageStream, MqttMessage, OutgoingPublish, OverflowPolicy,
+    PasswdCallback, PausePolicy, PresenceTracker, Properties, Property, PublishMiddleware,
+    PublishReport, Publisher, ReasonCode, Redirect, RedirectPolicy, RefusalPolicy, Resolver,
+    ResponseMatcher, RetainKind, RetainPolicy, ScopedClient, SelfTestReport, ServerReference,
+    ShutdownHook, SocketOptions, Stats, Store, SubscribeOptions, Subscriber, SystemResolver,
+    TlsConfig, TopicStats, TopicTree, Tuning, TypedSubscriber, Watchdog,
+};
+use async_channel::{bounded, unbounded, Receiver, Sender, TrySendError};
+use futures_lite::FutureExt;
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryInto;
+use std::future::Future;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::net::SocketAddr;
 use std::os::raw::c_int;
 use std::path::Path;
-use std::sync::Mutex;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The `level` at which libmosquitto logs errors
+const MOSQ_LOG_ERR: c_int = 0x08;
+
+/// How long the MQTT 5 session that
+/// [rotate_identity](struct.Client.html#method.rotate_identity) stages
+/// for the new identity outlives the drain timeout, to allow for the
+/// time taken to reconnect
+const STAGING_SESSION_GRACE: Duration = Duration::from_secs(60);
+
+/// How often the redirect follower thread checks for a redirect to
+/// follow
+const REDIRECT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Describe the `reason` that `on_disconnect_v5` was called with:
+/// either an MQTT 5 reason code sent by the broker, which are all
+/// at least 0x80, or a libmosquitto error code
+fn describe_disconnect(reason: c_int) -> String {
+    if reason >= 0x80 {
+        ReasonCode(reason as u8).to_string()
+    } else {
+        Error::from_err(reason).to_string()
+    }
+}
 
 struct Handler {
-    connect: Mutex<Option<Sender<ConnectionStatus>>>,
-    mids: Mutex<HashMap<MessageId, Sender<MessageId>>>,
+    connect: Mutex<Option<Sender<ConnAck>>>,
+    mids: Mutex<HashMap<MessageId, Sender<ReasonCode>>>,
+    /// Requests made via `Client::subscribe_multiple`, which are
+    /// granted a QoS per pattern
+    subscribe_batches: Mutex<HashMap<MessageId, Sender<Vec<QoS>>>>,
+    /// Publishes whose acknowledgement nobody is waiting for, because
+    /// they timed out, or were made in the background by the client
+    abandoned: Arc<Mutex<HashSet<MessageId>>>,
+    /// Acknowledgements that arrived before their publish was recorded
+    early_acks: Arc<Mutex<EarlyAcks>>,
     subscriber_tx: Mutex<Sender<Message>>,
     subscriber_rx: Mutex<Option<Receiver<Message>>>,
+    /// A receiver for the subscriber queue, used to discard the
+    /// oldest message when the queue is full
+    subscriber_drain: Mutex<Receiver<Message>>,
+    subscriber_overflow: Mutex<OverflowPolicy>,
+    /// Messages that arrived while the subscriber queue was full, under
+    /// `OverflowPolicy::Block`, in the order that they were received
+    subscriber_held: Mutex<VecDeque<Message>>,
+    /// Set when the client is driven by an `EventLoop`, which stops
+    /// reading from the broker while messages are held
+    can_pause_reading: Mutex<bool>,
+    subscriber_queue: QueueCounters,
+    /// Shared by the subscriber queues and the offline queue
+    memory: Arc<MemoryBudget>,
+    /// `None` unless per-topic statistics are enabled
+    topic_stats: Mutex<Option<TopicCounters>>,
+    broadcasts: Mutex<Vec<Arc<Broadcast>>>,
+    watches: Mutex<Vec<Watch>>,
+    filtered: Mutex<Vec<FilteredQueue>>,
+    /// Replies to requests made via `Client::request`
+    responses: ResponseMatcher,
+    socket_options: Mutex<Option<SocketOptions>>,
+    traffic: TrafficCounters,
+    events: Arc<Mutex<Option<Sender<Event>>>>,
+    activity: Arc<Activity>,
+    credentials: Arc<CredentialsState>,
+    connect_params: Mutex<Option<ConnectParams>>,
+    /// MQTT 5 properties sent with each CONNECT, if any
+    connect_properties: Mutex<Option<Properties>>,
+    endpoints: Arc<Mutex<Endpoints>>,
+    redirect_policy: Mutex<RedirectPolicy>,
+    redirects: Mutex<u32>,
+    /// The server that the pending connect call is to be redirected
+    /// to, once it has received the refusal
+    connect_redirect: Mutex<Option<ConnectParams>>,
+    /// The server that the redirect follower thread is to connect to,
+    /// for redirects that arrive when no connect call is pending
+    background_redirect: Arc<Mutex<Option<ConnectParams>>>,
+    refusal_policy: Mutex<RefusalPolicy>,
+    capabilities: Mutex<Option<BrokerCapabilities>>,
+    downgrade_qos: Mutex<bool>,
+    retain_policy: Mutex<RetainPolicy>,
+    /// `None` unless topic aliasing is enabled
+    topic_aliases: Mutex<Option<TopicAliases>>,
+    offline_queue: Mutex<Option<OfflineQueue>>,
+    offline_ttl: Mutex<Option<Duration>>,
+    offline_capacity: Mutex<Option<usize>>,
+    offline_overflow: Mutex<OverflowPolicy>,
+    quota: Mutex<QuotaThrottle>,
+    ordering: Mutex<PublishOrdering>,
+    in_order: Mutex<HashMap<String, InOrderPublish>>,
+    /// `None` unless the number of unacknowledged publishes is limited
+    inflight_limit: Mutex<Option<InflightLimit>>,
+    /// `None` unless duplicate detection is enabled
+    recent_messages: Mutex<Option<RecentMessages>>,
+    subscriptions: Mutex<HashMap<String, SubscribeOptions>>,
+    /// The QoS that the broker granted to each subscription
+    granted_qos: Mutex<HashMap<String, QoS>>,
+    /// The subscriptions removed by `Client::pause_delivery`, to be
+    /// made again on resumption
+    paused_subscriptions: Mutex<Vec<(String, QoS, SubscribeOptions)>>,
+    /// `None` unless NoLocal is set automatically
+    echo_filter: Mutex<Option<EchoFilter>>,
+    protocol_version: Mutex<ProtocolVersion>,
+    strict_qos: Mutex<bool>,
+    message_middleware: Mutex<Vec<Box<dyn MessageMiddleware>>>,
+    dead_letters: Mutex<Option<Sender<DeadLetter>>>,
+    dead_letter_topic: Mutex<Option<String>>,
+    /// The largest payload that is accepted, and whether larger
+    /// messages are dead-lettered
+    max_payload: Mutex<Option<(usize, bool)>>,
+    /// `None` unless the latency probe is enabled
+    latency_probe: Mutex<Option<Arc<LatencyProbe>>>,
+    #[cfg(feature = "json-schema")]
+    schemas: Mutex<Option<crate::SchemaRegistry>>,
+}
+
+/// Remembers the most recently received QoS 1 messages, so that
+/// redeliveries can be detected; see `Client::set_duplicate_detection`.
+/// libmosquitto doesn't expose the DUP flag from the PUBLISH header,
+/// so a message is considered to be a possible duplicate when it has
+/// the same message id, topic and payload as a recent message.
+/// This is a heuristic: the broker reuses message ids once they have
+/// been acknowledged, so a legitimate repeat of a message can be
+/// flagged too.
+/// QoS 2 messages are not considered, as libmosquitto only delivers
+/// them once the exchange that prevents duplicates has completed.
+#[derive(Default)]
+struct RecentMessages {
+    messages: VecDeque<(MessageId, u64)>,
+}
+
+impl RecentMessages {
+    const CAPACITY: usize = 64;
+
+    /// Records `message`, returning true if it is a possible duplicate
+    fn check(&mut self, message: &MessageRef) -> bool {
+        if message.qos != QoS::AtLeastOnce {
+            return false;
+        }
+        let mut hasher = DefaultHasher::new();
+        message.topic.hash(&mut hasher);
+        message.payload.hash(&mut hasher);
+        let key = (message.mid, hasher.finish());
+
+        if self.messages.contains(&key) {
+            return true;
+        }
+        if self.messages.len() == Self::CAPACITY {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(key);
+        false
+    }
+}
+
+/// Recognises the client's own publishes when they are echoed back by
+/// a broker that doesn't support the NoLocal subscription option; see
+/// `Client::set_auto_no_local`
+#[derive(Default)]
+struct EchoFilter {
+    /// The patterns that were subscribed to while the filter was enabled
+    patterns: HashSet<String>,
+    /// Fingerprints of the recent publishes to topics that match `patterns`
+    recent: VecDeque<u64>,
+}
+
+impl EchoFilter {
+    const CAPACITY: usize = 64;
+
+    fn fingerprint(topic: &str, payload: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        topic.hash(&mut hasher);
+        payload.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Records a publish, if the broker will echo it back
+    fn published(&mut self, topic: &str, payload: &[u8]) {
+        let echoed = self
+            .patterns
+            .iter()
+            .any(|pattern| topic_matches_sub(pattern, topic).unwrap_or(false));
+        if echoed {
+            if self.recent.len() == Self::CAPACITY {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(Self::fingerprint(topic, payload));
+        }
+    }
+
+    /// Returns true if a message is the echo of a recent publish,
+    /// which is then forgotten
+    fn is_echo(&mut self, topic: &str, payload: &[u8]) -> bool {
+        let fingerprint = Self::fingerprint(topic, payload);
+        match self.recent.iter().position(|f| *f == fingerprint) {
+            Some(index) => {
+                self.recent.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A QoS 1 or QoS 2 publish that is holding back subsequent publishes
+/// in order to preserve ordering
+struct InOrderPublish {
+    mid: MessageId,
+    /// Dropped when the publish completes, which wakes up any
+    /// publishes waiting on `wait`
+    _done: Sender<()>,
+    wait: Receiver<()>,
+}
+
+/// Acknowledgements that arrived while publishes were being handed to
+/// libmosquitto, before the publishing thread could record their mids.
+/// No lock is held while publishing, so the network thread can process
+/// the acknowledgement of a QoS 0 message first.
+#[derive(Default)]
+struct EarlyAcks {
+    /// The number of publishes being handed to libmosquitto
+    sending: usize,
+    acks: HashMap<MessageId, ReasonCode>,
+}
+
+impl EarlyAcks {
+    /// Note that a publish is about to be handed to libmosquitto
+    fn begin(&mut self) {
+        self.sending += 1;
+    }
+
+    /// Note that a publish was handed to libmosquitto as `mid`, or not
+    /// at all, returning its acknowledgement if it has already arrived
+    fn end(&mut self, mid: Option<MessageId>) -> Option<ReasonCode> {
+        self.sending = self.sending.saturating_sub(1);
+        let reason = mid.and_then(|mid| self.acks.remove(&mid));
+        if self.sending == 0 {
+            // Whatever is left doesn't belong to any publish
+            self.acks.clear();
+        }
+        reason
+    }
+
+    /// Hold on to the acknowledgement of `mid`, which isn't known,
+    /// if it may belong to a publish that is in progress
+    fn hold(&mut self, mid: MessageId, reason: ReasonCode) -> bool {
+        if self.sending == 0 {
+            return false;
+        }
+        self.acks.insert(mid, reason);
+        true
+    }
+}
+
+/// Publish a message via `publish` whose acknowledgement nobody waits
+/// for, so that it is ignored when it arrives
+fn publish_untracked<F>(
+    early_acks: &Mutex<EarlyAcks>,
+    abandoned: &Mutex<HashSet<MessageId>>,
+    publish: F,
+) -> Result<MessageId, Error>
+where
+    F: FnOnce() -> Result<MessageId, Error>,
+{
+    early_acks.lock().unwrap().begin();
+    let result = publish();
+    let mut early_acks = early_acks.lock().unwrap();
+    if let Ok(mid) = result {
+        if early_acks.end(Some(mid)).is_none() {
+            abandoned.lock().unwrap().insert(mid);
+        }
+    } else {
+        early_acks.end(None);
+    }
+    result
+}
+
+/// The queue of a subscriber created by `filtered_subscriber`
+struct FilteredQueue {
+    filter: Box<dyn MessageFilter>,
+    tx: Sender<Message>,
+}
+
+/// Limits the number of publishes that have been handed to libmosquitto
+/// but not yet acknowledged, including those whose caller gave up
+struct InflightLimit {
+    limit: usize,
+    /// The publishes that count towards the limit
+    held: HashSet<MessageId>,
+    /// Signalled when a publish is acknowledged, to wake a publish that
+    /// is waiting for a slot
+    released: (Sender<()>, Receiver<()>),
+}
+
+impl InflightLimit {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            held: HashSet::new(),
+            released: bounded(1),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.held.len() >= self.limit
+    }
+
+    fn release(&mut self, mid: MessageId) {
+        if self.held.remove(&mid) {
+            let _ = self.released.0.try_send(());
+        }
+    }
+}
+
+/// Tracks the pause in publishing that is applied after the broker
+/// signals that a quota or rate limit was exceeded
+#[derive(Default)]
+struct QuotaThrottle {
+    backoff: Option<Backoff>,
+    failures: u32,
+    paused_until: Option<Instant>,
+}
+
+impl QuotaThrottle {
+    fn record(&mut self, reason: ReasonCode) {
+        let backoff = match self.backoff {
+            Some(backoff) => backoff,
+            None => return,
+        };
+        match reason {
+            ReasonCode::QUOTA_EXCEEDED | ReasonCode::MESSAGE_RATE_TOO_HIGH => {
+                self.failures = self.failures.saturating_add(1);
+                self.paused_until = Some(Instant::now() + backoff.delay(self.failures));
+            }
+            reason if !reason.is_error() => {
+                self.failures = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The value of an option that was set via `Client::set_option`
+enum OptionValue {
+    Int(c_int),
+    Str(String),
+}
+
+/// The settings that libmosquitto discards when the client is
+/// reinitialised, recorded so that they can be applied again
+#[derive(Default)]
+struct Settings {
+    credentials: Option<(Option<String>, Option<String>)>,
+    options: Vec<(mosq_opt_t, OptionValue)>,
+    tls: Option<TlsConfig>,
+    tls_psk: Option<(String, String, Option<String>)>,
+    reconnect_delay: Option<(Duration, Duration, bool)>,
+    will: Option<LastWill>,
+}
+
+impl Settings {
+    fn apply(&self, mosq: &Mosq<Handler>) -> Result<(), Error> {
+        if let Some((username, password)) = &self.credentials {
+            mosq.set_username_and_password(username.as_deref(), password.as_deref())?;
+        }
+        for (option, value) in &self.options {
+            match value {
+                OptionValue::Int(v) => mosq.set_int_option(*option, *v)?,
+                OptionValue::Str(v) => mosq.set_string_option(*option, v)?,
+            }
+        }
+        if let Some(tls) = &self.tls {
+            mosq.set_tls(tls)?;
+        }
+        if let Some((identity, psk_hex, ciphers)) = &self.tls_psk {
+            mosq.set_tls_psk(identity, psk_hex, ciphers.as_deref())?;
+        }
+        if let Some((delay, max_delay, exponential)) = self.reconnect_delay {
+            mosq.set_reconnect_delay(delay, max_delay, exponential)?;
+        }
+        if let Some(will) = &self.will {
+            mosq.set_will(will)?;
+        }
+        Ok(())
+    }
+}
+
+/// The broker addresses found by periodically resolving the host name
+/// that the client connected to, and the policy for choosing between
+/// them; see `Client::set_endpoint_refresh` and
+/// `Client::set_endpoint_selection`
+#[derive(Default)]
+struct Endpoints {
+    /// The host name and port, if they were resolved by this crate
+    /// rather than by libmosquitto
+    host: Option<(String, u16)>,
+    /// The addresses from the most recent resolution
+    known: Vec<SocketAddr>,
+    current: Option<SocketAddr>,
+    /// The address to use for the next reconnection, if it should change
+    preferred: Option<SocketAddr>,
+    selection: EndpointSelection,
+    /// When the client moved from the primary address to another one
+    failed_over_at: Option<Instant>,
+    /// The number of connections made via `Client::connect_to_addrs`
+    connections: usize,
+}
+
+impl Endpoints {
+    /// Record the result of resolving the host name again, preferring
+    /// an address that wasn't previously known, or any address if the
+    /// current one is no longer listed
+    fn refreshed(&mut self, addrs: Vec<SocketAddr>) {
+        let discovered = addrs.iter().find(|addr| !self.known.contains(addr));
+        self.preferred = match (discovered, self.current) {
+            (Some(addr), _) => Some(*addr),
+            (None, Some(current)) if !addrs.contains(&current) => addrs.first().copied(),
+            _ => self.preferred.filter(|addr| addrs.contains(addr)),
+        };
+        self.known = addrs;
+    }
+
+    /// Returns the address to reconnect to after losing the connection,
+    /// if it should change: one newly discovered by a periodic
+    /// resolution, or else the one chosen by the selection policy
+    fn after_loss(&mut self) -> Option<SocketAddr> {
+        let current = self.current?;
+        if let Some(addr) = self.preferred.take().filter(|addr| *addr != current) {
+            return Some(addr);
+        }
+        let failed_over_for = self.failed_over_at.map(|at| at.elapsed());
+        self.selection
+            .after_loss(&self.known, current, failed_over_for, &mut random_u64)
+    }
+
+    /// Record that the client is connecting to `addr`
+    fn using(&mut self, addr: SocketAddr) {
+        self.current = Some(addr);
+        if self.known.first() == Some(&addr) {
+            self.failed_over_at = None;
+        } else if self.failed_over_at.is_none() {
+            self.failed_over_at = Some(Instant::now());
+        }
+    }
+}
+
+/// Returns a random number, used to choose between broker addresses
+fn random_u64() -> u64 {
+    RandomState::new().hash_one(Instant::now())
+}
+
+/// Abandons the acknowledgement awaited by a publish, subscribe or
+/// unsubscribe if its future is dropped before the acknowledgement
+/// arrives, so that cancelling the operation doesn't leave its entry
+/// behind, nor cause the late acknowledgement to be treated as a
+/// protocol error.
+struct AckGuard<'a> {
+    mosq: &'a Mosq<Handler>,
+    mid: MessageId,
+    armed: bool,
+}
+
+impl<'a> AckGuard<'a> {
+    fn new(mosq: &'a Mosq<Handler>, mid: MessageId) -> Self {
+        Self {
+            mosq,
+            mid,
+            armed: true,
+        }
+    }
+
+    /// Called once the acknowledgement has been received, or the
+    /// operation has otherwise been resolved
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl<'a> Drop for AckGuard<'a> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.mosq.get_callbacks().abandon(self.mid);
+        }
+    }
+}
+
+/// Aborts a connection attempt if the future that is waiting for its
+/// CONNACK is dropped, rather than leaving libmosquitto to complete a
+/// connection that nobody is waiting for.
+struct ConnectGuard<'a> {
+    mosq: &'a Mosq<Handler>,
+    armed: bool,
+}
+
+impl<'a> Drop for ConnectGuard<'a> {
+    fn drop(&mut self) {
+        if self.armed {
+            let pending = self.mosq.get_callbacks().connect.lock().unwrap().take();
+            if pending.is_some() {
+                let _ = self.mosq.disconnect();
+            }
+        }
+    }
+}
+
+/// Removes a subscription made for internal use if the future that
+/// made it is dropped before it would have removed it itself
+struct TemporarySubscription<'a> {
+    client: &'a Client,
+    pattern: &'a str,
+    armed: bool,
+}
+
+impl<'a> Drop for TemporarySubscription<'a> {
+    fn drop(&mut self) {
+        if self.armed {
+            let _ = self.client.forget_subscription(self.pattern);
+        }
+    }
+}
+
+/// The parameters of the most recent connection request, which are
+/// re-used when following a redirect or rotating the identity
+#[derive(Clone)]
+struct ConnectParams {
+    host: String,
+    port: c_int,
+    keep_alive_interval: Duration,
+    bind_address: Option<String>,
 }
 
 impl Handler {
@@ -21,10 +577,293 @@ impl Handler {
         Self {
             connect: Mutex::new(None),
             mids: Mutex::new(HashMap::new()),
+            subscribe_batches: Mutex::new(HashMap::new()),
+            abandoned: Arc::new(Mutex::new(HashSet::new())),
+            early_acks: Arc::new(Mutex::new(EarlyAcks::default())),
             subscriber_tx: Mutex::new(tx),
+            subscriber_drain: Mutex::new(rx.clone()),
             subscriber_rx: Mutex::new(Some(rx)),
+            subscriber_overflow: Mutex::new(OverflowPolicy::default()),
+            subscriber_held: Mutex::new(VecDeque::new()),
+            can_pause_reading: Mutex::new(false),
+            memory: Arc::new(MemoryBudget::default()),
+            broadcasts: Mutex::new(vec![]),
+            watches: Mutex::new(vec![]),
+            topic_stats: Mutex::new(None),
+            filtered: Mutex::new(vec![]),
+            responses: ResponseMatcher::new(),
+            subscriber_queue: QueueCounters::default(),
+            socket_options: Mutex::new(None),
+            traffic: TrafficCounters::default(),
+            events: Arc::new(Mutex::new(None)),
+            activity: Arc::new(Activity::default()),
+            credentials: Arc::new(CredentialsState::default()),
+            connect_params: Mutex::new(None),
+            connect_properties: Mutex::new(None),
+            endpoints: Arc::new(Mutex::new(Endpoints::default())),
+            redirect_policy: Mutex::new(RedirectPolicy::default()),
+            redirects: Mutex::new(0),
+            connect_redirect: Mutex::new(None),
+            background_redirect: Arc::new(Mutex::new(None)),
+            refusal_policy: Mutex::new(RefusalPolicy::default()),
+            capabilities: Mutex::new(None),
+            downgrade_qos: Mutex::new(false),
+            retain_policy: Mutex::new(RetainPolicy::default()),
+            topic_aliases: Mutex::new(None),
+            offline_queue: Mutex::new(None),
+            offline_ttl: Mutex::new(None),
+            offline_capacity: Mutex::new(None),
+            offline_overflow: Mutex::new(OverflowPolicy::default()),
+            quota: Mutex::new(QuotaThrottle::default()),
+            ordering: Mutex::new(PublishOrdering::default()),
+            in_order: Mutex::new(HashMap::new()),
+            inflight_limit: Mutex::new(None),
+            recent_messages: Mutex::new(None),
+            subscriptions: Mutex::new(HashMap::new()),
+            paused_subscriptions: Mutex::new(vec![]),
+            echo_filter: Mutex::new(None),
+            protocol_version: Mutex::new(ProtocolVersion::default()),
+            granted_qos: Mutex::new(HashMap::new()),
+            strict_qos: Mutex::new(false),
+            message_middleware: Mutex::new(vec![]),
+            dead_letters: Mutex::new(None),
+            dead_letter_topic: Mutex::new(None),
+            max_payload: Mutex::new(None),
+            latency_probe: Mutex::new(None),
+            #[cfg(feature = "json-schema")]
+            schemas: Mutex::new(None),
         }
     }
+
+    fn emit(&self, event: Event) {
+        if let Some(tx) = self.events.lock().unwrap().as_ref() {
+            let _ = tx.try_send(event);
+        }
+    }
+
+    /// Stop waiting for the acknowledgement of `mid`, so that it is
+    /// ignored when it arrives.
+    /// Returns false if it has already arrived.
+    fn abandon(&self, mid: MessageId) -> bool {
+        let mut mids = self.mids.lock().unwrap();
+        let waiting = mids.remove(&mid).is_some()
+            || self
+                .subscribe_batches
+                .lock()
+                .unwrap()
+                .remove(&mid)
+                .is_some();
+        if waiting {
+            self.abandoned.lock().unwrap().insert(mid);
+            self.in_order
+                .lock()
+                .unwrap()
+                .retain(|_, pending| pending.mid != mid);
+        }
+        waiting
+    }
+
+    /// Complete the publish `mid`, returning false if its
+    /// acknowledgement wasn't expected, or couldn't be delivered
+    fn publish_acknowledged(&self, mid: MessageId, reason: ReasonCode) -> bool {
+        self.in_order
+            .lock()
+            .unwrap()
+            .retain(|_, pending| pending.mid != mid);
+        if let Some(limit) = self.inflight_limit.lock().unwrap().as_mut() {
+            limit.release(mid);
+        }
+        let mut mids = self.mids.lock().unwrap();
+        let queued = self
+            .offline_queue
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|queue| queue.sent(mid));
+        if let Some(Err(err)) = &queued {
+            self.emit(Event::OfflineQueueFailed(err.to_string()));
+        }
+        match mids.remove(&mid) {
+            Some(tx) => tx.try_send(reason).is_ok(),
+            None => self.abandoned.lock().unwrap().remove(&mid) || queued.is_some(),
+        }
+    }
+
+    /// Returns the QoS to use for `topic`, which is `qos` limited to
+    /// the broker maximum if QoS downgrading is enabled
+    fn negotiate_qos(&self, topic: &str, qos: QoS) -> QoS {
+        if !*self.downgrade_qos.lock().unwrap() {
+            return qos;
+        }
+        let granted = match self.capabilities.lock().unwrap().as_ref() {
+            Some(caps) => caps.limit_qos(qos),
+            None => qos,
+        };
+        if granted != qos {
+            self.emit(Event::QosDowngraded {
+                topic: topic.to_string(),
+                requested: qos,
+                granted,
+            });
+        }
+        granted
+    }
+
+    /// Returns the retain flag to use for a publish to `topic`,
+    /// according to the `RetainPolicy` if the broker doesn't support
+    /// retained messages
+    fn negotiate_retain(&self, topic: &str, retain: bool) -> Result<bool, Error> {
+        let available = match self.capabilities.lock().unwrap().as_ref() {
+            Some(caps) => caps.retain_available,
+            None => true,
+        };
+        if !retain || available {
+            return Ok(retain);
+        }
+        match *self.retain_policy.lock().unwrap() {
+            RetainPolicy::Fail => Err(Error::RetainNotSupported),
+            RetainPolicy::Strip => {
+                self.emit(Event::RetainStripped {
+                    topic: topic.to_string(),
+                });
+                Ok(false)
+            }
+        }
+    }
+
+    /// Returns true if the subscriber has dropped the receiving end of
+    /// the subscriber queue, `tx`.
+    /// `subscriber_drain` keeps the channel open, so this can't be
+    /// detected by sending.
+    fn subscriber_closed(&self, tx: &Sender<Message>) -> bool {
+        self.subscriber_rx.lock().unwrap().is_none() && tx.receiver_count() <= 1
+    }
+
+    /// Returns true if adding a message of `size` bytes to the queue
+    /// fed by `tx` would exceed the memory budget, reporting the first
+    /// time that happens.
+    /// An empty queue is never over budget, so that each subscriber
+    /// can always make progress.
+    fn subscriber_over_budget(&self, tx: &Sender<Message>, size: usize) -> bool {
+        let over = !tx.is_empty() && self.memory.would_exceed(size);
+        if over {
+            self.memory_exceeded();
+        }
+        over
+    }
+
+    fn memory_exceeded(&self) {
+        if let Some(limit) = self.memory.record_exceeded() {
+            self.emit(Event::MemoryBudgetExceeded {
+                used: self.memory.used(),
+                limit,
+            });
+        }
+    }
+
+    /// Diverts a message that couldn't be delivered to the dead letter
+    /// channel and topic, if they have been configured
+    fn dead_letter(&self, client: &mut Mosq, message: Message, reason: DeadLetterReason) {
+        if let Some(topic) = self.dead_letter_topic.lock().unwrap().as_deref() {
+            // Avoid republishing dead letters to the topic that they came from
+            if topic != message.topic {
+                let props: Properties = vec![
+                    Property::UserProperty("dead-letter-topic".to_string(), message.topic.clone()),
+                    Property::UserProperty("dead-letter-reason".to_string(), reason.to_string()),
+                ]
+                .into_iter()
+                .collect();
+                // Nothing waits for the dead letter to be acknowledged
+                let _ = publish_untracked(&self.early_acks, &self.abandoned, || {
+                    client.publish_with_properties(
+                        topic,
+                        &message.payload,
+                        message.qos,
+                        false,
+                        &props,
+                    )
+                });
+            }
+        }
+        if let Some(tx) = self.dead_letters.lock().unwrap().as_ref() {
+            let _ = tx.try_send(DeadLetter { message, reason });
+        }
+    }
+
+    /// Reconnects to the preferred address from the most recent
+    /// periodic resolution, or to the address chosen by the endpoint
+    /// selection policy, if it differs from the current one
+    fn change_endpoint(&self, client: &mut Mosq) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let addr = match endpoints.after_loss() {
+            Some(addr) => addr,
+            None => return,
+        };
+        if let Some(params) = self.connect_params.lock().unwrap().as_ref() {
+            // libmosquitto will complete the connection from its
+            // network loop, and use the new address for any
+            // subsequent reconnections
+            let changed = client
+                .connect_non_blocking(
+                    &addr.ip().to_string(),
+                    addr.port().into(),
+                    params.keep_alive_interval,
+                    params.bind_address.as_deref(),
+                )
+                .is_ok();
+            if changed {
+                endpoints.using(addr);
+                self.emit(Event::EndpointChanged { addr });
+            }
+        }
+    }
+
+    /// Handles a request from the broker to use another server.
+    /// Returns true if the client is to reconnect to the referenced
+    /// server, which is done by the pending connect call if there is
+    /// one, or by the redirect follower thread otherwise, so that
+    /// libmosquitto is not asked to connect from within its own
+    /// packet handling.
+    fn redirect(&self, reason: c_int, props: &Properties) -> bool {
+        let reason = match reason.try_into().map(ReasonCode) {
+            Ok(code @ ReasonCode::USE_ANOTHER_SERVER) | Ok(code @ ReasonCode::SERVER_MOVED) => code,
+            _ => return false,
+        };
+        let servers = props
+            .server_reference()
+            .map(ServerReference::parse_list)
+            .unwrap_or_default();
+
+        let mut followed = false;
+        if let RedirectPolicy::Follow { max_redirects } = *self.redirect_policy.lock().unwrap() {
+            let mut redirects = self.redirects.lock().unwrap();
+            let params = self.connect_params.lock().unwrap();
+            if let (Some(server), Some(params), true) =
+                (servers.first(), params.as_ref(), *redirects < max_redirects)
+            {
+                let target = ConnectParams {
+                    host: server.host.clone(),
+                    port: server.port.map(c_int::from).unwrap_or(params.port),
+                    keep_alive_interval: params.keep_alive_interval,
+                    bind_address: params.bind_address.clone(),
+                };
+                if self.connect.lock().unwrap().is_some() {
+                    self.connect_redirect.lock().unwrap().replace(target);
+                } else {
+                    self.background_redirect.lock().unwrap().replace(target);
+                }
+                *redirects += 1;
+                followed = true;
+            }
+        }
+
+        self.emit(Event::Redirect(Redirect {
+            reason,
+            servers,
+            followed,
+        }));
+        followed
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -66,6 +905,11 @@ pub enum ClientOption<'a> {
     /// The default is false for no checking
     OcspRequired(bool),
 
+    /// Set whether the broker is verified against the CA certificates
+    /// trusted by the operating system, so that a CA file or path need
+    /// not be configured.  The default is false.
+    TlsUseOsCerts(bool),
+
     /// Configure the client for TLS Engine support; set this to a TLS Engine ID
     /// to be used when creating TLS connections.
     TlsEngine(&'a str),
@@ -85,6 +929,33 @@ pub enum ClientOption<'a> {
     /// as both MQTT and WebSockets, use this option to configure the ALPN option for the
     /// connection.
     TlsALPN(&'a str),
+
+    /// Set to true to disable Nagle's algorithm on client sockets.  This has the effect of
+    /// reducing latency of individual messages at the potential cost of increasing the number of
+    /// packets being sent.  Defaults to false.
+    TcpNoDelay(bool),
+}
+
+/// Controls whether the high-level client preserves the order of
+/// QoS 1 and QoS 2 publishes.
+///
+/// libmosquitto sends several messages concurrently, and messages can
+/// be reordered if some of them need to be retransmitted, for example
+/// after a reconnection.  The strict modes prevent this by holding back
+/// a publish until the preceding one has been acknowledged, at the cost
+/// of throughput.
+/// QoS 0 publishes are never held back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PublishOrdering {
+    /// Publishes may be reordered; this is the default
+    #[default]
+    Unordered,
+    /// Publishes to the same topic are delivered in order, by
+    /// allowing only one publish per topic to be in flight at a time
+    PerTopic,
+    /// All publishes are delivered in order, by allowing only one
+    /// publish to be in flight at a time
+    Global,
 }
 
 /// Represents a received message that matches one or
@@ -102,84 +973,609 @@ pub struct Message {
     /// message and send it to a subscriber at subscribe
     /// time.
     pub retain: bool,
+    /// Describes what the `retain` flag means, taking into account
+    /// the options of the subscriptions that the message matched
+    pub retain_kind: RetainKind,
     /// The message id
     pub mid: MessageId,
+    /// The MQTT 5 properties of the message.
+    /// These are always empty when connected using an earlier version
+    /// of the protocol.
+    pub properties: Properties,
+    /// Whether the message may be a redelivery of a message that was
+    /// already received, for example because the broker didn't receive
+    /// the acknowledgement before the connection was lost.
+    /// This is only ever true for QoS 1 messages, and only when
+    /// enabled via
+    /// [Client::set_duplicate_detection](struct.Client.html#method.set_duplicate_detection),
+    /// which describes its limitations.
+    pub possibly_duplicate: bool,
+}
+
+impl Message {
+    /// Returns the MQTT 5 properties of the message, which provide
+    /// typed access to the content type, response topic, correlation
+    /// data, user properties and subscription identifiers
+    pub fn properties(&self) -> &Properties {
+        &self.properties
+    }
+}
+
+impl From<&MessageRef<'_>> for Message {
+    fn from(message: &MessageRef) -> Self {
+        Self {
+            mid: message.mid,
+            topic: message.topic.to_string(),
+            payload: message.payload.to_vec(),
+            qos: message.qos,
+            retain: message.retain,
+            retain_kind: RetainKind::classify(message.retain, false),
+            properties: message.properties.clone(),
+            possibly_duplicate: false,
+        }
+    }
 }
 
 impl Callbacks for Handler {
-    fn on_connect(&self, client: &mut Mosq, reason: ConnectionStatus) {
+    fn on_connack(
+        &self,
+        client: &mut Mosq,
+        reason: ConnectionStatus,
+        session_present: bool,
+        props: &Properties,
+    ) {
+        if let Some(options) = self.socket_options.lock().unwrap().as_ref() {
+            // There's nowhere to report a failure here; any problem
+            // with the options will have been reported by the
+            // initial connect call
+            let _ = client.set_socket_options(options);
+        }
+        let mut broker_capabilities = None;
+        let mut redirected = false;
+        if reason.is_successful() {
+            let caps = BrokerCapabilities::from_properties(props);
+            broker_capabilities = Some(caps);
+            let previous = self.capabilities.lock().unwrap().replace(caps);
+            if previous != broker_capabilities {
+                self.emit(Event::CapabilitiesChanged {
+                    previous,
+                    current: caps,
+                });
+            }
+            if let Some(aliases) = self.topic_aliases.lock().unwrap().as_mut() {
+                aliases.reset(caps.topic_alias_maximum);
+            }
+            if let Some(queue) = self.offline_queue.lock().unwrap().as_mut() {
+                let ttl = *self.offline_ttl.lock().unwrap();
+                let result = queue.resend(client, ttl, |expired| {
+                    self.emit(Event::QueuedMessageExpired {
+                        topic: expired.topic.clone(),
+                    })
+                });
+                if let Err(err) = result {
+                    self.emit(Event::OfflineQueueFailed(err.to_string()));
+                }
+            }
+            self.traffic.connected();
+            self.activity.connected();
+            *self.redirects.lock().unwrap() = 0;
+        } else if self.redirect(reason.0, props) {
+            // The connect call follows the redirect itself when it
+            // receives the refusal, and so does the redirect follower
+            // for automatic reconnections
+            redirected = true;
+        }
+        if !reason.is_successful() && !redirected {
+            self.emit(Event::ConnectionRefused {
+                status: reason,
+                permanent: reason.is_permanent_refusal(),
+            });
+        }
         let mut connect = self.connect.lock().unwrap();
         if let Some(connect) = connect.take() {
-            if connect.try_send(reason).is_err() {
+            let connack = ConnAck {
+                reason,
+                session_present,
+                properties: props.clone(),
+                broker_capabilities,
+            };
+            if connect.try_send(connack).is_err() {
                 let _ = client.disconnect();
             }
+        } else if !redirected
+            && self
+                .refusal_policy
+                .lock()
+                .unwrap()
+                .stops_reconnecting(reason)
+        {
+            // This was an automatic reconnection attempt, which
+            // libmosquitto would otherwise repeat indefinitely
+            let _ = client.disconnect();
         }
     }
 
-    fn on_publish(&self, client: &mut Mosq, mid: MessageId) {
-        let mut mids = self.mids.lock().unwrap();
-        if let Some(tx) = mids.remove(&mid) {
-            if tx.try_send(mid).is_err() {
-                let _ = client.disconnect();
+    fn on_disconnect_v5(&self, client: &mut Mosq, reason: c_int, props: &Properties) {
+        self.traffic.disconnected();
+        self.activity.disconnected();
+        if !self.redirect(reason, props) {
+            // libmosquitto may reconnect automatically, so
+            // prepare the credentials that it will use
+            if let Err(err) = self.credentials.refresh(client) {
+                self.emit(Event::CredentialsUnavailable(err.to_string()));
+            }
+            // An explicit disconnect has a reason of zero
+            if reason != 0 {
+                self.emit(Event::BackgroundError {
+                    context: "connection lost",
+                    message: describe_disconnect(reason),
+                });
+                self.change_endpoint(client);
             }
-        } else {
-            let _ = client.disconnect();
         }
     }
 
-    fn on_subscribe(&self, client: &mut Mosq, mid: MessageId, _granted_qos: &[QoS]) {
-        let mut mids = self.mids.lock().unwrap();
-        if let Some(tx) = mids.remove(&mid) {
-            if tx.try_send(mid).is_err() {
-                let _ = client.disconnect();
+    fn on_log(&self, _client: &mut Mosq, level: c_int, message: &str) {
+        // The responses to keepalive pings are otherwise invisible to
+        // the callbacks; other packets are seen by the callbacks below
+        if message.ends_with(" received PINGRESP") {
+            self.activity.touch();
+        }
+        // Failures within the loop, such as TLS errors, are otherwise
+        // only reported as a lost connection
+        if level & MOSQ_LOG_ERR != 0 {
+            self.emit(Event::BackgroundError {
+                context: "message loop",
+                message: message.to_string(),
+            });
+        }
+    }
+
+    fn wants_read(&self, client: &mut Mosq) -> bool {
+        let mut held = self.subscriber_held.lock().unwrap();
+        if held.is_empty() {
+            return true;
+        }
+        let tx = self.subscriber_tx.lock().unwrap().clone();
+        let mut closed = false;
+        while let Some(m) = held.pop_front() {
+            let size = message_size(&m.topic, &m.payload);
+            if self.subscriber_closed(&tx) {
+                closed = true;
+                self.memory.release_subscriber(size);
+                self.subscriber_queue.record_dropped();
+                self.dead_letter(client, m, DeadLetterReason::SubscriberClosed);
+                continue;
             }
-        } else {
+            if !tx.is_empty() && self.memory.would_exceed(0) {
+                held.push_front(m);
+                return false;
+            }
+            match tx.try_send(m) {
+                Ok(()) => self.subscriber_queue.record_depth(tx.len()),
+                Err(TrySendError::Full(m)) => {
+                    held.push_front(m);
+                    return false;
+                }
+                Err(TrySendError::Closed(m)) => {
+                    closed = true;
+                    self.memory.release_subscriber(size);
+                    self.subscriber_queue.record_dropped();
+                    self.dead_letter(client, m, DeadLetterReason::SubscriberClosed);
+                }
+            }
+        }
+        if closed {
             let _ = client.disconnect();
         }
+        true
     }
 
-    fn on_message(
+    fn on_publish_v5(
         &self,
         client: &mut Mosq,
         mid: MessageId,
-        topic: String,
-        payload: &[u8],
-        qos: QoS,
-        retain: bool,
+        reason: ReasonCode,
+        _props: &Properties,
     ) {
-        let m = Message {
-            mid,
-            topic,
-            payload: payload.to_vec(),
-            qos,
-            retain,
-        };
-        if self.subscriber_tx.lock().unwrap().try_send(m).is_err() {
+        self.activity.touch();
+        self.quota.lock().unwrap().record(reason);
+        {
+            let mids = self.mids.lock().unwrap();
+            let known = mids.contains_key(&mid)
+                || self
+                    .offline_queue
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .is_some_and(|queue| queue.knows(mid));
+            if !known {
+                let mut early_acks = self.early_acks.lock().unwrap();
+                // The publishing thread will complete it once it has
+                // recorded the mid
+                if !self.abandoned.lock().unwrap().contains(&mid) && early_acks.hold(mid, reason) {
+                    return;
+                }
+            }
+        }
+        if !self.publish_acknowledged(mid, reason) {
             let _ = client.disconnect();
         }
     }
-}
 
-/// A high-level, asynchronous mosquitto MQTT client
-pub struct Client {
-    mosq: Mosq<Handler>,
-}
+    fn on_subscribe(&self, client: &mut Mosq, mid: MessageId, granted_qos: &[QoS]) {
+        self.activity.touch();
+        let batch = self.subscribe_batches.lock().unwrap().remove(&mid);
+        if let Some(tx) = batch {
+            if tx.try_send(granted_qos.to_vec()).is_err() {
+                let _ = client.disconnect();
+            }
+            return;
+        }
+        let mut mids = self.mids.lock().unwrap();
+        if let Some(tx) = mids.remove(&mid) {
+            // The reason codes for success are the granted QoS levels
+            let granted = granted_qos.first().map_or(0, |qos| *qos as u8);
+            if tx.try_send(ReasonCode(granted)).is_err() {
+                let _ = client.disconnect();
+            }
+        } else if !self.abandoned.lock().unwrap().remove(&mid) {
+            let _ = client.disconnect();
+        }
+    }
 
-impl Client {
-    /// Create a new client instance with the specified id.
-    /// If clean_session is true, instructs the broker to clean all messages
-    /// and subscriptions on disconnect.  Otherwise it will preserve them.
-    pub fn with_id(id: &str, clean_session: bool) -> Result<Self, Error> {
-        let mosq = Mosq::with_id(Handler::new(), id, clean_session)?;
-        mosq.start_loop_thread()?;
-        Ok(Self { mosq })
+    fn on_unsubscribe(&self, client: &mut Mosq, mid: MessageId) {
+        self.activity.touch();
+        // Subscriptions made for internal use are removed without
+        // waiting, so an unknown mid isn't an error here
+        let tx = self.mids.lock().unwrap().remove(&mid);
+        if let Some(tx) = tx {
+            if tx.try_send(ReasonCode::SUCCESS).is_err() {
+                let _ = client.disconnect();
+            }
+        } else {
+            self.abandoned.lock().unwrap().remove(&mid);
+        }
+    }
+
+    fn on_message_borrowed(&self, client: &mut Mosq, message: &MessageRef) {
+        self.activity.touch();
+        self.traffic
+            .record_received(&message.topic, message.payload.len(), message.qos);
+        if let Some(probe) = self.latency_probe.lock().unwrap().as_ref() {
+            // Probes are consumed here, rather than delivered
+            if probe.topic() == message.topic {
+                if let Some(rtt) = probe.receive(message.payload) {
+                    self.emit(Event::BrokerLatency { rtt });
+                }
+                return;
+            }
+        }
+        if *self.protocol_version.lock().unwrap() != ProtocolVersion::V5 {
+            if let Some(echoes) = self.echo_filter.lock().unwrap().as_mut() {
+                if echoes.is_echo(&message.topic, message.payload) {
+                    return;
+                }
+            }
+        }
+        if let Some((max, dead_letter)) = *self.max_payload.lock().unwrap() {
+            // Check before the payload is copied out of libmosquitto
+            if message.payload.len() > max {
+                self.traffic.record_oversized();
+                if dead_letter {
+                    let m = Message::from(message);
+                    self.dead_letter(client, m, DeadLetterReason::PayloadTooLarge);
+                }
+                return;
+            }
+        }
+        if let Some(topics) = self.topic_stats.lock().unwrap().as_mut() {
+            topics.record_received(&message.topic, message.payload.len(), message.qos);
+        }
+        let mut m = Message::from(message);
+        m.possibly_duplicate = self
+            .recent_messages
+            .lock()
+            .unwrap()
+            .as_mut()
+            .is_some_and(|recent| recent.check(message));
+        if m.retain {
+            let retain_as_published =
+                self.subscriptions
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .any(|(pattern, options)| {
+                        options.retain_as_published
+                            && topic_matches_sub(pattern, &m.topic).unwrap_or(false)
+                    });
+            m.retain_kind = RetainKind::classify(true, retain_as_published);
+        }
+        let m = match apply_message_middleware(&self.message_middleware.lock().unwrap(), m) {
+            Some(m) => m,
+            None => return,
+        };
+        #[cfg(feature = "json-schema")]
+        {
+            let valid = match self.schemas.lock().unwrap().as_ref() {
+                Some(schemas) => schemas.validate(&m.topic, &m.payload).is_ok(),
+                None => true,
+            };
+            if !valid {
+                self.dead_letter(client, m, DeadLetterReason::SchemaViolation);
+                return;
+            }
+        }
+        let m = match self.responses.dispatch(m) {
+            Some(m) => m,
+            None => return,
+        };
+        let mut filtered = false;
+        self.filtered.lock().unwrap().retain(|queue| {
+            if !queue.filter.matches(&m) {
+                return !queue.tx.is_closed();
+            }
+            filtered = true;
+            let size = message_size(&m.topic, &m.payload);
+            let result = if self.subscriber_over_budget(&queue.tx, size) {
+                Err(TrySendError::Full(m.clone()))
+            } else {
+                self.memory.acquire_subscriber(size);
+                queue.tx.try_send(m.clone())
+            };
+            if result.is_err() {
+                self.memory.release_subscriber(size);
+            }
+            match result {
+                Ok(()) => true,
+                Err(TrySendError::Full(m)) => {
+                    self.subscriber_queue.record_dropped();
+                    self.dead_letter(client, m, DeadLetterReason::SubscriberLagging);
+                    true
+                }
+                // The subscriber has been dropped
+                Err(TrySendError::Closed(_)) => false,
+            }
+        });
+        if filtered {
+            return;
+        }
+        let mut watched = false;
+        let mut watches = self.watches.lock().unwrap();
+        watches.retain(|w| w.is_active());
+        for w in watches.iter() {
+            watched |= w.dispatch(&m);
+        }
+        drop(watches);
+        if watched {
+            return;
+        }
+        let mut broadcast = false;
+        let mut broadcasts = self.broadcasts.lock().unwrap();
+        broadcasts.retain(|b| b.is_active());
+        for b in broadcasts.iter() {
+            broadcast |= b.dispatch(&m);
+        }
+        drop(broadcasts);
+        if broadcast {
+            return;
+        }
+        let tx = self.subscriber_tx.lock().unwrap().clone();
+        // The message is accounted for while it is in hand, and
+        // released below if it isn't queued
+        let size = message_size(&m.topic, &m.payload);
+        let closed = self.subscriber_closed(&tx);
+        let over_budget = !closed && self.subscriber_over_budget(&tx, size);
+        let policy = *self.subscriber_overflow.lock().unwrap();
+        // Messages that are already held must be delivered first
+        let held =
+            policy == OverflowPolicy::Block && !self.subscriber_held.lock().unwrap().is_empty();
+        self.memory.acquire_subscriber(size);
+        let result = if closed {
+            Err(TrySendError::Closed(m))
+        } else if over_budget || held {
+            Err(TrySendError::Full(m))
+        } else {
+            tx.try_send(m)
+        };
+        let result = match (result, policy) {
+            (Err(TrySendError::Full(m)), OverflowPolicy::DropOldest) => {
+                // Dropping one message makes room in the channel, but
+                // more may be needed to get back within the budget
+                loop {
+                    let oldest = self.subscriber_drain.lock().unwrap().try_recv();
+                    match oldest {
+                        Ok(oldest) => {
+                            self.memory
+                                .release_subscriber(message_size(&oldest.topic, &oldest.payload));
+                            self.subscriber_queue.record_dropped();
+                            self.dead_letter(client, oldest, DeadLetterReason::SubscriberLagging);
+                        }
+                        Err(_) => break,
+                    }
+                    if !tx.is_full() && (tx.is_empty() || !self.memory.would_exceed(0)) {
+                        break;
+                    }
+                }
+                tx.try_send(m)
+            }
+            (Err(TrySendError::Full(m)), OverflowPolicy::Block)
+                if *self.can_pause_reading.lock().unwrap() =>
+            {
+                // The event loop stops reading from the broker until
+                // the subscriber catches up; see wants_read
+                self.subscriber_held.lock().unwrap().push_back(m);
+                Ok(())
+            }
+            (Err(TrySendError::Full(mut m)), OverflowPolicy::Block) => {
+                // libmosquitto offers no way to pause its own thread, but
+                // it serves only this client, so stop reading by waiting
+                // here until the subscriber catches up, or goes away
+                loop {
+                    if self.subscriber_closed(&tx) {
+                        break Err(TrySendError::Closed(m));
+                    }
+                    if tx.is_empty() || !self.memory.would_exceed(0) {
+                        match tx.try_send(m) {
+                            Err(TrySendError::Full(full)) => m = full,
+                            result => break result,
+                        }
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+            }
+            (Err(TrySendError::Full(m)), OverflowPolicy::Error) => {
+                self.emit(Event::SubscriberOverflow {
+                    topic: m.topic.clone(),
+                });
+                Err(TrySendError::Full(m))
+            }
+            (result, _) => result,
+        };
+        self.subscriber_queue.record_depth(tx.len());
+        match result {
+            Ok(()) => {}
+            // The subscriber is lagging behind; discard the message
+            // rather than buffering without limit
+            Err(TrySendError::Full(m)) => {
+                self.memory.release_subscriber(size);
+                self.subscriber_queue.record_dropped();
+                self.dead_letter(client, m, DeadLetterReason::SubscriberLagging);
+            }
+            Err(TrySendError::Closed(m)) => {
+                self.memory.release_subscriber(size);
+                self.subscriber_queue.record_dropped();
+                self.dead_letter(client, m, DeadLetterReason::SubscriberClosed);
+                let _ = client.disconnect();
+            }
+        }
+    }
+}
+
+/// A high-level, asynchronous mosquitto MQTT client
+pub struct Client {
+    mosq: Mosq<Handler>,
+    /// `None` if the id was assigned randomly
+    client_id: Option<String>,
+    settings: Mutex<Settings>,
+    tunnel: Option<HttpTunnel>,
+    resolver: Option<Arc<dyn Resolver>>,
+    connect_strategy: ConnectStrategy,
+    connected_addr: Option<SocketAddr>,
+    /// The topic that replies to `request` are sent to, once subscribed
+    response_topic: Option<String>,
+    publish_middleware: Vec<Box<dyn PublishMiddleware>>,
+    shutdown_hooks: Vec<Box<dyn ShutdownHook>>,
+    watchdog: Option<TimerThread>,
+    token_refresh: Option<TimerThread>,
+    endpoint_refresh: Option<TimerThread>,
+    /// The thread that returns to the primary address, when the
+    /// `StickyPrimary` endpoint selection is in effect
+    failback: Option<TimerThread>,
+    /// The threads that republish the topics passed to `keep_retained`
+    retained_refresh: HashMap<String, TimerThread>,
+    latency_probe: Option<TimerThread>,
+    /// The thread that follows redirects that arrive when no connect
+    /// call is pending, while the `RedirectPolicy` is `Follow`
+    redirect_follower: Option<TimerThread>,
+    #[cfg(unix)]
+    event_loop: Option<Registration>,
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // These threads refer to the client, so must be
+        // stopped before it is destroyed
+        self.watchdog.take();
+        self.token_refresh.take();
+        self.retained_refresh.clear();
+        self.latency_probe.take();
+        self.failback.take();
+        self.redirect_follower.take();
+        #[cfg(unix)]
+        self.event_loop.take();
+    }
+}
+
+impl Client {
+    /// Create a new client instance with the specified id.
+    /// If clean_session is true, instructs the broker to clean all messages
+    /// and subscriptions on disconnect.  Otherwise it will preserve them.
+    pub fn with_id(id: &str, clean_session: bool) -> Result<Self, Error> {
+        let mosq = Mosq::with_id(Handler::new(), id, clean_session)?;
+        mosq.start_loop_thread()?;
+        Ok(Self::new(mosq, Some(id)))
     }
 
     /// Create a new client instance with a random client id
     pub fn with_auto_id() -> Result<Self, Error> {
         let mosq = Mosq::with_auto_id(Handler::new())?;
         mosq.start_loop_thread()?;
-        Ok(Self { mosq })
+        Ok(Self::new(mosq, None))
+    }
+
+    /// Create a new client instance with the specified id, whose network
+    /// traffic is driven by `event_loop` rather than by a thread of its own.
+    /// See [with_id](#method.with_id) for the meaning of the parameters.
+    #[cfg(unix)]
+    pub fn with_id_and_event_loop(
+        id: &str,
+        clean_session: bool,
+        event_loop: &EventLoop,
+    ) -> Result<Self, Error> {
+        let mosq = Mosq::with_id(Handler::new(), id, clean_session)?;
+        let mut client = Self::new(mosq, Some(id));
+        *client
+            .mosq
+            .get_callbacks()
+            .can_pause_reading
+            .lock()
+            .unwrap() = true;
+        // Safety: the registration is dropped before the client
+        client.event_loop = Some(unsafe { event_loop.register(&client.mosq) });
+        Ok(client)
+    }
+
+    /// Create a new client instance with a random client id, whose
+    /// network traffic is driven by `event_loop` rather than by a
+    /// thread of its own.
+    #[cfg(unix)]
+    pub fn with_auto_id_and_event_loop(event_loop: &EventLoop) -> Result<Self, Error> {
+        let mosq = Mosq::with_auto_id(Handler::new())?;
+        let mut client = Self::new(mosq, None);
+        *client
+            .mosq
+            .get_callbacks()
+            .can_pause_reading
+            .lock()
+            .unwrap() = true;
+        // Safety: the registration is dropped before the client
+        client.event_loop = Some(unsafe { event_loop.register(&client.mosq) });
+        Ok(client)
+    }
+
+    fn new(mosq: Mosq<Handler>, client_id: Option<&str>) -> Self {
+        Self {
+            mosq,
+            client_id: client_id.map(str::to_string),
+            settings: Mutex::new(Settings::default()),
+            tunnel: None,
+            resolver: None,
+            connect_strategy: ConnectStrategy::default(),
+            connected_addr: None,
+            response_topic: None,
+            publish_middleware: vec![],
+            shutdown_hooks: vec![],
+            watchdog: None,
+            token_refresh: None,
+            endpoint_refresh: None,
+            failback: None,
+            retained_refresh: HashMap::new(),
+            latency_probe: None,
+            redirect_follower: None,
+            #[cfg(unix)]
+            event_loop: None,
+        }
     }
 
     /// Configure the client with an optional username and password.
@@ -191,7 +1587,605 @@ impl Client {
         username: Option<&str>,
         password: Option<&str>,
     ) -> Result<(), Error> {
-        self.mosq.set_username_and_password(username, password)
+        self.mosq.set_username_and_password(username, password)?;
+        self.settings.lock().unwrap().credentials =
+            Some((username.map(str::to_string), password.map(str::to_string)));
+        Ok(())
+    }
+
+    /// Record each outgoing publish in `store` until libmosquitto
+    /// reports that it has been sent, so that publishes survive a
+    /// restart of the application.
+    /// A QoS 0 message is sent once it has been written to the network,
+    /// and a QoS 1 or QoS 2 message once the broker has acknowledged it.
+    ///
+    /// While the client is disconnected, publishes are held in `store`,
+    /// rather than by libmosquitto, and fail with `Error::Queued`.
+    /// Publishes that are pending in `store`, because they were made
+    /// while disconnected or by a previous run of the application, or
+    /// failed with a [retryable](enum.Error.html#method.is_retryable)
+    /// error, are sent each time the client connects, unless they have
+    /// expired; see [set_offline_queue_ttl](#method.set_offline_queue_ttl).
+    /// They are sent without being passed through the publish middleware
+    /// again, and without MQTT 5 properties, other than the id assigned
+    /// by [AssignMessageIds](struct.AssignMessageIds.html).
+    ///
+    /// As a message is only removed from `store` after it has been
+    /// sent, a message may be sent more than once if the application
+    /// stops in the meantime.
+    ///
+    /// This should be called prior to `connect`.
+    pub fn set_offline_queue<S: Store + 'static>(&self, store: S) -> Result<(), Error> {
+        let handlers = self.mosq.get_callbacks();
+        let queue = OfflineQueue::new(Box::new(store))?.with_budget(Arc::clone(&handlers.memory));
+        handlers.offline_queue.lock().unwrap().replace(queue);
+        Ok(())
+    }
+
+    /// Limit the number of messages in the offline queue, see
+    /// [set_offline_queue](#method.set_offline_queue), and choose what
+    /// happens when a message is published while the queue is full.
+    /// Messages that libmosquitto is sending remain in the queue, so
+    /// count towards the limit, but they are never dropped to make room.
+    /// Dropped messages are reported via `Event::QueuedMessageDropped`
+    /// and counted in `Stats::offline_queue`.
+    ///
+    /// The default is `None`, which means that the queue is unbounded.
+    pub fn set_offline_queue_capacity(&self, capacity: Option<usize>, policy: OverflowPolicy) {
+        let handlers = self.mosq.get_callbacks();
+        *handlers.offline_capacity.lock().unwrap() = capacity;
+        *handlers.offline_overflow.lock().unwrap() = policy;
+    }
+
+    /// Choose what happens when a message is received while the queue
+    /// that feeds the [subscriber](#method.subscriber) channel is full.
+    /// The capacity of the queue is set via
+    /// [apply_tuning](#method.apply_tuning).
+    /// Dropped messages are passed to the [dead_letters](#method.dead_letters)
+    /// channel and counted in `Stats::subscriber_queue`.
+    ///
+    /// The default is `OverflowPolicy::DropNewest`.
+    pub fn set_subscriber_overflow_policy(&self, policy: OverflowPolicy) {
+        *self
+            .mosq
+            .get_callbacks()
+            .subscriber_overflow
+            .lock()
+            .unwrap() = policy;
+    }
+
+    /// Limit the memory held by the messages waiting in the queues of
+    /// the client, which are the [subscriber](#method.subscriber)
+    /// channel, any [filtered subscribers](#method.filtered_subscriber),
+    /// and the [offline queue](#method.set_offline_queue), to `limit`
+    /// bytes of topic and payload in total.
+    /// This is useful when running many clients in one process on a
+    /// gateway with little memory, where the message count limits of
+    /// the individual queues don't bound the memory that they use.
+    ///
+    /// A message that would exceed the budget is treated as though its
+    /// queue were full, so the overflow policies set via
+    /// [set_subscriber_overflow_policy](#method.set_subscriber_overflow_policy)
+    /// and [set_offline_queue_capacity](#method.set_offline_queue_capacity)
+    /// apply; filtered subscribers always drop the new message.
+    /// An empty queue always accepts a message, so that a single large
+    /// message can't stall a queue forever.
+    /// `Event::MemoryBudgetExceeded` is reported when the budget is
+    /// reached, and again only once usage has fallen well below it.
+    /// The memory held is reported in `Stats::memory`.
+    ///
+    /// The default is `None`, which means that there is no limit.
+    pub fn set_memory_budget(&self, limit: Option<usize>) {
+        self.mosq.get_callbacks().memory.set_limit(limit);
+    }
+
+    /// Set how long a message may wait in the offline queue, see
+    /// [set_offline_queue](#method.set_offline_queue), before it is
+    /// considered stale.
+    /// When the client connects, expired messages are dropped rather
+    /// than sent; each is reported via `Event::QueuedMessageExpired`
+    /// and counted in `Stats::messages_expired`.
+    /// Messages that libmosquitto had already started sending when the
+    /// connection was lost are sent regardless.
+    ///
+    /// The default is `None`, which means that messages never expire.
+    pub fn set_offline_queue_ttl(&self, ttl: Option<Duration>) {
+        *self.mosq.get_callbacks().offline_ttl.lock().unwrap() = ttl;
+    }
+
+    /// Choose whether the next connection resumes the session that the
+    /// broker holds for this client, or discards it and starts afresh.
+    /// This is useful when the application detects that the state of
+    /// the session has been corrupted.
+    ///
+    /// libmosquitto fixes this choice when the client is created, so
+    /// the client is reinitialised: any connection is closed, the
+    /// messages that libmosquitto was still sending are discarded, and
+    /// publishes and subscriptions that are awaiting acknowledgement fail.
+    /// The username and password, options, TLS configuration and
+    /// reconnect delay that were set via this client are preserved,
+    /// as are the policies, middleware and channels of the high-level
+    /// client.
+    /// If `clean_session` is true, the subscriptions that the client
+    /// has made are forgotten.
+    ///
+    /// The change takes effect when [connect](#method.connect) is next
+    /// called, which must be done to re-establish the connection.
+    ///
+    /// Fails with `Error::InvalidArgument` if `clean_session` is false
+    /// and the client was created with a random id, as the broker cannot
+    /// associate a session with it.
+    pub fn set_clean_session(&mut self, clean_session: bool) -> Result<(), Error> {
+        if self.client_id.is_none() && !clean_session {
+            return Err(Error::InvalidArgument);
+        }
+        self.reinitialise(clean_session)
+    }
+
+    /// Recreate the libmosquitto client with the current client id,
+    /// closing any connection, and reset the state that depends on it
+    fn reinitialise(&mut self, clean_session: bool) -> Result<(), Error> {
+        self.stop_loop()?;
+        let result = self
+            .mosq
+            .reinitialise(self.client_id.as_deref(), clean_session)
+            .and_then(|_| self.settings.lock().unwrap().apply(&self.mosq));
+
+        let handlers = self.mosq.get_callbacks();
+        handlers.connect.lock().unwrap().take();
+        handlers.mids.lock().unwrap().clear();
+        handlers.subscribe_batches.lock().unwrap().clear();
+        handlers.abandoned.lock().unwrap().clear();
+        handlers.in_order.lock().unwrap().clear();
+        if let Some(limit) = handlers.inflight_limit.lock().unwrap().as_mut() {
+            limit.held.clear();
+        }
+        if let Some(recent) = handlers.recent_messages.lock().unwrap().as_mut() {
+            recent.messages.clear();
+        }
+        handlers.capabilities.lock().unwrap().take();
+        if let Some(queue) = handlers.offline_queue.lock().unwrap().as_mut() {
+            queue.reset();
+        }
+        if let Some(aliases) = handlers.topic_aliases.lock().unwrap().as_mut() {
+            aliases.reset(0);
+        }
+        if clean_session {
+            handlers.subscriptions.lock().unwrap().clear();
+            handlers.granted_qos.lock().unwrap().clear();
+        }
+
+        self.start_loop()?;
+        result
+    }
+
+    /// Switch the client to the client id `new_id` without losing the
+    /// messages that its subscriptions deliver, for deployments whose
+    /// security policy requires identities to be rotated periodically.
+    ///
+    /// The client must be connected.
+    /// A persistent session is first established for `new_id` on a
+    /// separate connection, with the same subscriptions, options and
+    /// credentials as this client, so that the broker starts queueing
+    /// messages for the new identity while the old one is still
+    /// receiving them.
+    /// Once the publishes that are awaiting acknowledgement have
+    /// completed, or `drain_timeout` has passed, the old connection is
+    /// closed and the client reconnects as `new_id`, resuming that
+    /// session; the records in the offline queue are sent over the new
+    /// connection.
+    /// Subsequent calls to [set_clean_session](#method.set_clean_session)
+    /// use `new_id`.
+    ///
+    /// Messages at QoS 1 and 2 may be received twice around the switch,
+    /// once by each identity, while those at QoS 0 may be missed.
+    /// `Event::IdentityRotated` is reported once the client has
+    /// reconnected.
+    ///
+    /// Fails with `Error::NoConnection` if the client has not
+    /// connected.
+    /// If the new session cannot be established, the client keeps
+    /// its current identity and connection.
+    /// If the client cannot then reconnect as `new_id`, it returns to
+    /// its previous identity and tries to reconnect with that, and the
+    /// error from connecting as `new_id` is returned.
+    pub async fn rotate_identity(
+        &mut self,
+        new_id: &str,
+        drain_timeout: Duration,
+    ) -> Result<ConnAck, Error> {
+        let params = self
+            .mosq
+            .get_callbacks()
+            .connect_params
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or(Error::NoConnection)?;
+        let subscriptions: Vec<(String, QoS, SubscribeOptions)> = {
+            let handlers = self.mosq.get_callbacks();
+            let granted = handlers.granted_qos.lock().unwrap();
+            let subscriptions = handlers
+                .subscriptions
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(pattern, options)| {
+                    let qos = granted.get(pattern).copied().unwrap_or(QoS::AtLeastOnce);
+                    (pattern.clone(), qos, *options)
+                })
+                .collect();
+            subscriptions
+        };
+
+        // Have the broker create the session of the new identity, so
+        // that it holds what is published from here on
+        {
+            let mut staging = Client::with_id(new_id, false)?;
+            self.settings.lock().unwrap().apply(&staging.mosq)?;
+            // An MQTT 5 session ends with its connection unless it has
+            // an expiry interval, so keep it until we have switched over
+            if *self.mosq.get_callbacks().protocol_version.lock().unwrap() == ProtocolVersion::V5 {
+                let mut properties = Properties::new();
+                properties.add_session_expiry_interval(drain_timeout + STAGING_SESSION_GRACE);
+                staging
+                    .mosq
+                    .get_callbacks()
+                    .connect_properties
+                    .lock()
+                    .unwrap()
+                    .replace(properties);
+            }
+            self.mosq
+                .get_callbacks()
+                .credentials
+                .refresh(&staging.mosq)?;
+            staging
+                .connect(
+                    &params.host,
+                    params.port,
+                    params.keep_alive_interval,
+                    params.bind_address.as_deref(),
+                )
+                .await?;
+            for (pattern, qos, options) in &subscriptions {
+                staging
+                    .subscribe_with_exact_options(pattern, *qos, *options, &Properties::new())
+                    .await?;
+            }
+            staging.mosq.disconnect()?;
+        }
+
+        let deadline = Instant::now() + drain_timeout;
+        while !self.mosq.get_callbacks().mids.lock().unwrap().is_empty()
+            && Instant::now() < deadline
+        {
+            async_io::Timer::after(Duration::from_millis(50)).await;
+        }
+
+        let _ = self.mosq.disconnect();
+        let previous = self.client_id.replace(new_id.to_string());
+        let connected = match self.reinitialise(false) {
+            Ok(()) => {
+                self.connect(
+                    &params.host,
+                    params.port,
+                    params.keep_alive_interval,
+                    params.bind_address.as_deref(),
+                )
+                .await
+            }
+            Err(err) => Err(err),
+        };
+        let connack = match connected {
+            Ok(connack) => connack,
+            Err(err) => {
+                // Go back to the identity that the broker last accepted
+                self.client_id = previous;
+                if self.reinitialise(false).is_ok() {
+                    let _ = self
+                        .connect(
+                            &params.host,
+                            params.port,
+                            params.keep_alive_interval,
+                            params.bind_address.as_deref(),
+                        )
+                        .await;
+                }
+                return Err(err);
+            }
+        };
+        if !connack.session_present {
+            for (pattern, qos, options) in &subscriptions {
+                self.subscribe_with_exact_options(pattern, *qos, *options, &Properties::new())
+                    .await?;
+            }
+        }
+        self.mosq.get_callbacks().emit(Event::IdentityRotated {
+            previous,
+            id: new_id.to_string(),
+        });
+        Ok(connack)
+    }
+
+    /// Stop processing the network traffic of the client
+    fn stop_loop(&self) -> Result<(), Error> {
+        #[cfg(unix)]
+        if let Some(registration) = &self.event_loop {
+            registration.detach();
+            return Ok(());
+        }
+        // The loop thread runs until the client is disconnected
+        let _ = self.mosq.disconnect();
+        self.mosq.stop_loop_thread(false)
+    }
+
+    /// Resume processing network traffic after [stop_loop](#method.stop_loop)
+    fn start_loop(&self) -> Result<(), Error> {
+        #[cfg(unix)]
+        if let Some(registration) = &self.event_loop {
+            // Safety: the registration is dropped before the client
+            unsafe { registration.attach(&self.mosq) };
+            return Ok(());
+        }
+        self.mosq.start_loop_thread()
+    }
+
+    /// Set the will message, which the broker publishes if the
+    /// connection is lost without the client having explicitly
+    /// disconnected, replacing any will that was previously set.
+    /// See [LastWill](struct.LastWill.html) for the options, such as
+    /// the MQTT 5 will delay interval.
+    ///
+    /// The will is sent to the broker as part of connecting, so this
+    /// should be called prior to `connect`; a change made while
+    /// connected takes effect from the next connection, including
+    /// automatic reconnections.
+    /// To apply a change immediately, use [update_will](#method.update_will).
+    pub fn set_last_will(&self, will: LastWill) -> Result<(), Error> {
+        self.mosq.set_will(&will)?;
+        self.settings.lock().unwrap().will.replace(will);
+        Ok(())
+    }
+
+    /// Shut down the connection without disconnecting, as though the
+    /// network had failed, so that the broker publishes the will
+    pub(crate) fn sever_connection(&self) {
+        close_socket(&self.mosq);
+    }
+
+    /// Remove the will message set via [set_last_will](#method.set_last_will)
+    /// or [update_will](#method.update_will), taking effect from the
+    /// next connection.
+    pub fn clear_last_will(&self) -> Result<(), Error> {
+        self.mosq.clear_last_will()?;
+        self.settings.lock().unwrap().will.take();
+        Ok(())
+    }
+
+    /// Set the will message, which the broker publishes to `topic` if
+    /// the connection is lost without the client having explicitly
+    /// disconnected, replacing any will that was previously set.
+    /// This allows state that is embedded in the will, such as a
+    /// firmware version, to be kept current.
+    ///
+    /// The will is sent to the broker as part of connecting, so a
+    /// change made while connected takes effect from the next
+    /// connection, including automatic reconnections.
+    /// If `reconnect` is true and the client is connected, it
+    /// disconnects and reconnects to apply the new will immediately,
+    /// and completes once the broker has accepted the new connection;
+    /// the broker doesn't publish the previous will, because the
+    /// disconnect is explicit.
+    pub async fn update_will(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+        reconnect: bool,
+    ) -> Result<(), Error> {
+        self.set_last_will(
+            LastWill::new(topic, payload)
+                .with_qos(qos)
+                .with_retain(retain),
+        )?;
+
+        if !reconnect || self.mosq.socket().is_none() {
+            return Ok(());
+        }
+        let (tx, rx) = bounded(1);
+        self.mosq
+            .get_callbacks()
+            .connect
+            .lock()
+            .unwrap()
+            .replace(tx);
+        self.mosq.disconnect()?;
+        self.stop_loop()?;
+        let result = self.mosq.reconnect();
+        self.start_loop()?;
+        result?;
+        self.complete_connect(rx).await.map(|_| ())
+    }
+
+    /// Install a provider that supplies the username and password
+    /// each time the client connects, including when it reconnects
+    /// automatically, replacing any credentials that were set via
+    /// [set_username_and_password](#method.set_username_and_password).
+    ///
+    /// If the provider fails during [connect](#method.connect), the
+    /// error is returned.  If it fails before an automatic reconnect,
+    /// `Event::CredentialsUnavailable` is reported via
+    /// [events](#method.events) and the previous credentials are used.
+    pub fn set_credentials_provider<P: CredentialsProvider + 'static>(&mut self, provider: P) {
+        self.mosq
+            .get_callbacks()
+            .credentials
+            .set_provider(Some(Box::new(provider)));
+    }
+
+    /// Remove a provider previously installed via
+    /// [set_credentials_provider](#method.set_credentials_provider).
+    /// The most recently supplied credentials remain in effect.
+    pub fn clear_credentials_provider(&mut self) {
+        self.mosq.get_callbacks().credentials.set_provider(None);
+    }
+
+    /// Reconnect with fresh credentials from the
+    /// [CredentialsProvider](trait.CredentialsProvider.html) `margin`
+    /// before the credentials that it previously supplied expire, as
+    /// indicated by `Credentials::expires_at`, or stop doing so if
+    /// `margin` is `None`.
+    /// This avoids the broker disconnecting the client when a token
+    /// lapses.
+    ///
+    /// The reconnection is made from a thread of its own.
+    /// A client that connected with `clean_session` set to false
+    /// retains its subscriptions across the reconnection.
+    ///
+    /// libmosquitto doesn't support MQTT 5 re-authentication, so
+    /// reconnecting is the only way to present new credentials.
+    pub fn set_token_refresh(&mut self, margin: Option<Duration>) {
+        self.token_refresh.take();
+        if let Some(margin) = margin {
+            let handlers = self.mosq.get_callbacks();
+            self.token_refresh.replace(TimerThread::token_refresh(
+                margin,
+                Arc::clone(&handlers.credentials),
+                Arc::clone(&handlers.activity),
+                Arc::clone(&handlers.events),
+                // Safety: the thread is stopped before the client is dropped
+                unsafe { self.mosq.ptr() },
+            ));
+        }
+        self.update_logging();
+    }
+
+    /// Have libmosquitto report its log messages only while they are
+    /// needed, to see the responses to keepalive pings for the
+    /// watchdog, and to report failures within the loop via
+    /// [events](#method.events)
+    fn update_logging(&self) {
+        let events = self.mosq.get_callbacks().events.lock().unwrap().is_some();
+        self.mosq.set_logging(self.watchdog.is_some() || events);
+    }
+
+    /// Start measuring the time taken for a message to travel through
+    /// the broker and back, by publishing a probe every `interval` to a
+    /// topic that the client subscribes to, or stop if `interval` is
+    /// `None`.
+    /// Unlike the keepalive ping, this covers the routing of messages
+    /// by the broker, so it reflects the latency seen by the application.
+    ///
+    /// Each measurement is reported via [events](#method.events) as
+    /// `Event::BrokerLatency`, and the most recent one via
+    /// [stats](#method.stats) as `broker_latency`.
+    /// A probe that hasn't returned by the time the next is sent isn't
+    /// measured.
+    ///
+    /// The probe topic is `mosquitto-rs/latency-probe/` followed by the
+    /// client id; the probes are published with QoS 0, and are not
+    /// delivered to subscribers.
+    /// The probes are published from a thread of its own, and only
+    /// while the client is connected.
+    pub async fn set_latency_probe(&mut self, interval: Option<Duration>) -> Result<(), Error> {
+        self.latency_probe.take();
+        let interval = match interval {
+            Some(interval) => interval,
+            None => {
+                self.mosq
+                    .get_callbacks()
+                    .latency_probe
+                    .lock()
+                    .unwrap()
+                    .take();
+                return Ok(());
+            }
+        };
+        let topic = format!("mosquitto-rs/latency-probe/{}", self.unique_id());
+        let probe = Arc::new(LatencyProbe::new(topic.clone()));
+        self.mosq
+            .get_callbacks()
+            .latency_probe
+            .lock()
+            .unwrap()
+            .replace(Arc::clone(&probe));
+        let options = SubscribeOptions::default();
+        self.subscribe_with_exact_options(&topic, QoS::AtMostOnce, options, &Properties::new())
+            .await?;
+
+        let handlers = self.mosq.get_callbacks();
+        let activity = Arc::clone(&handlers.activity);
+        let events = Arc::clone(&handlers.events);
+        let abandoned = Arc::clone(&handlers.abandoned);
+        let early_acks = Arc::clone(&handlers.early_acks);
+        // Safety: the thread is stopped before the client is dropped
+        let mosq = unsafe { self.mosq.ptr() };
+        self.latency_probe.replace(TimerThread::spawn(move || {
+            if !activity.is_connected() {
+                return interval;
+            }
+            mosq.with_client(|client| {
+                // Nothing waits for the probe to be sent
+                let result = publish_untracked(&early_acks, &abandoned, || {
+                    client.publish(&topic, &probe.send(), QoS::AtMostOnce, false)
+                });
+                if let Err(err) = result {
+                    if let Some(tx) = events.lock().unwrap().as_ref() {
+                        let _ = tx.try_send(Event::BackgroundError {
+                            context: "sending latency probe",
+                            message: err.to_string(),
+                        });
+                    }
+                }
+            });
+            interval
+        }));
+        Ok(())
+    }
+
+    /// Periodically resolve the broker host name again, every `interval`,
+    /// so that a long-running client follows a broker that is migrated
+    /// to new addresses behind DNS, or `None` to stop doing so.
+    ///
+    /// This applies when the host name is resolved by this crate, that
+    /// is when a resolver has been installed via
+    /// [set_resolver](#method.set_resolver), or a connect strategy other
+    /// than `Sequential` is in effect; libmosquitto resolves the host
+    /// name again itself on each reconnection otherwise.
+    /// The resolver that was installed when this is called is used.
+    ///
+    /// The existing connection is left alone; when it is next lost, the
+    /// client reconnects to a newly discovered address in preference to
+    /// the previous one, or to another address if the previous one is no
+    /// longer listed, and reports that via [events](#method.events) as
+    /// `Event::EndpointChanged`.
+    /// A failed resolution leaves the known addresses unchanged.
+    ///
+    /// [HybridResolver](struct.HybridResolver.html) can be used to combine
+    /// discovery, such as DNS SRV lookups, with static fallback addresses.
+    pub fn set_endpoint_refresh(&mut self, interval: Option<Duration>) {
+        self.endpoint_refresh.take();
+        if let Some(interval) = interval {
+            let resolver = self
+                .resolver
+                .clone()
+                .unwrap_or_else(|| Arc::new(SystemResolver));
+            let endpoints = Arc::clone(&self.mosq.get_callbacks().endpoints);
+            self.endpoint_refresh = Some(TimerThread::spawn(move || {
+                let host = endpoints.lock().unwrap().host.clone();
+                if let Some((host, port)) = host {
+                    // Resolve without holding the lock, as it may be slow
+                    if let Ok(addrs) = resolver.resolve(&host, port) {
+                        if !addrs.is_empty() {
+                            endpoints.lock().unwrap().refreshed(addrs);
+                        }
+                    }
+                }
+                interval
+            }));
+        }
     }
 
     /// Connect to the broker on the specified host and port.
@@ -203,141 +2197,2384 @@ impl Client {
     /// of 5 seconds for this and will generate an error if you use a smaller
     /// value.
     ///
-    /// `bind_address` can be used to specify the outgoing interface
-    /// for the connection.
+    /// `bind_address` can be used to specify the outgoing interface
+    /// for the connection.
+    ///
+    /// connect completes when the broker acknowledges the CONNECT
+    /// command.
+    ///
+    /// Yields the broker's acknowledgement, which includes the
+    /// connection return code, whether a previous session was resumed
+    /// and the MQTT 5 properties; see [ConnAck](struct.ConnAck.html).
+    /// If the connection was rejected, then an Error::RejectedConnection()
+    /// variant will be returned so that you don't have to manually check
+    /// the success.
+    pub async fn connect(
+        &mut self,
+        host: &str,
+        port: c_int,
+        keep_alive_interval: Duration,
+        bind_address: Option<&str>,
+    ) -> Result<ConnAck, Error> {
+        let selection_in_order = self
+            .mosq
+            .get_callbacks()
+            .endpoints
+            .lock()
+            .unwrap()
+            .selection
+            == EndpointSelection::InOrder;
+        if self.resolver.is_some()
+            || self.connect_strategy != ConnectStrategy::Sequential
+            || !selection_in_order
+        {
+            let port = port.try_into().map_err(|_| Error::InvalidArgument)?;
+            let addrs = match &self.resolver {
+                Some(resolver) => resolver.resolve(host, port)?,
+                None => SystemResolver.resolve(host, port)?,
+            };
+            self.mosq.get_callbacks().endpoints.lock().unwrap().host =
+                Some((host.to_string(), port));
+            return self
+                .connect_to_addrs(&addrs, keep_alive_interval, bind_address)
+                .await;
+        }
+
+        self.connected_addr = None;
+        self.mosq.get_callbacks().endpoints.lock().unwrap().host = None;
+
+        let rx = self.begin_connect(host, port, keep_alive_interval, bind_address)?;
+        self.complete_connect_with_retry(rx, host, port, keep_alive_interval, bind_address)
+            .await
+    }
+
+    /// Connect to the broker using a list of pre-resolved addresses.
+    /// The addresses are tried according to the strategy configured via
+    /// [set_connect_strategy](#method.set_connect_strategy) until one of
+    /// them accepts the connection.  Once a connection is made, the outcome
+    /// of the CONNECT handshake is returned, even if it was rejected.
+    ///
+    /// If none of the addresses can be connected, the error from the
+    /// last attempt is returned.
+    ///
+    /// The other parameters and the return value have the same meaning
+    /// as for [connect](#method.connect).
+    pub async fn connect_to_addrs(
+        &mut self,
+        addrs: &[SocketAddr],
+        keep_alive_interval: Duration,
+        bind_address: Option<&str>,
+    ) -> Result<ConnAck, Error> {
+        self.connected_addr = None;
+
+        let resolved = addrs;
+        let ordered = {
+            let handlers = self.mosq.get_callbacks();
+            let mut endpoints = handlers.endpoints.lock().unwrap();
+            let ordered = endpoints
+                .selection
+                .order(addrs, endpoints.connections, &mut random_u64);
+            endpoints.connections += 1;
+            ordered
+        };
+        let raced;
+        let addrs = match self.connect_strategy {
+            ConnectStrategy::HappyEyeballs { stagger, timeout } if ordered.len() > 1 => {
+                raced = [race_connect(&interleave_families(&ordered), stagger, timeout).await?];
+                &raced[..]
+            }
+            _ => &ordered[..],
+        };
+
+        let mut last_error = Error::Resolution("no addresses to connect to".to_string());
+        for addr in addrs {
+            match self.begin_connect(
+                &addr.ip().to_string(),
+                addr.port().into(),
+                keep_alive_interval,
+                bind_address,
+            ) {
+                Ok(rx) => {
+                    self.connected_addr = Some(*addr);
+                    {
+                        let handlers = self.mosq.get_callbacks();
+                        let mut endpoints = handlers.endpoints.lock().unwrap();
+                        endpoints.known = resolved.to_vec();
+                        endpoints.using(*addr);
+                        endpoints.preferred = None;
+                    }
+                    return self
+                        .complete_connect_with_retry(
+                            rx,
+                            &addr.ip().to_string(),
+                            addr.port().into(),
+                            keep_alive_interval,
+                            bind_address,
+                        )
+                        .await;
+                }
+                Err(err) => last_error = err,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Configure how [connect](#method.connect) chooses between multiple
+    /// candidate addresses for the broker.
+    /// The default is `ConnectStrategy::Sequential`.
+    ///
+    /// Any strategy other than `Sequential` causes the broker host name
+    /// to be resolved by this crate rather than by libmosquitto, using
+    /// the resolver set via [set_resolver](#method.set_resolver) if any,
+    /// or the system resolver otherwise.
+    pub fn set_connect_strategy(&mut self, strategy: ConnectStrategy) {
+        self.connect_strategy = strategy;
+    }
+
+    /// Configure which of the candidate addresses for the broker the
+    /// client uses when it connects, and when it reconnects after losing
+    /// the connection; see [EndpointSelection](enum.EndpointSelection.html).
+    /// The default is `EndpointSelection::InOrder`.
+    ///
+    /// Any selection other than `InOrder` causes the broker host name
+    /// to be resolved by this crate rather than by libmosquitto, as for
+    /// [set_connect_strategy](#method.set_connect_strategy).
+    /// A change of address after losing the connection is reported via
+    /// [events](#method.events) as `Event::EndpointChanged`.
+    ///
+    /// `StickyPrimary` returns to the primary address from a thread of
+    /// its own, by closing the connection to the other address, which is
+    /// only supported on unix systems; elsewhere the client returns at
+    /// the next reconnection.
+    pub fn set_endpoint_selection(&mut self, selection: EndpointSelection) {
+        self.failback.take();
+        let handlers = self.mosq.get_callbacks();
+        if let EndpointSelection::StickyPrimary { failback } = selection {
+            let endpoints = Arc::clone(&handlers.endpoints);
+            let activity = Arc::clone(&handlers.activity);
+            // Safety: the thread is stopped before the client is dropped
+            let mosq = unsafe { self.mosq.ptr() };
+            self.failback = Some(TimerThread::spawn(move || {
+                let failed_over_at = endpoints.lock().unwrap().failed_over_at;
+                match failed_over_at.map(|at| failback.saturating_sub(at.elapsed())) {
+                    Some(remaining) if remaining > Duration::ZERO => remaining,
+                    Some(_) => {
+                        // The client reconnects to the primary when it
+                        // sees the connection fail
+                        if activity.is_connected() {
+                            mosq.with_client(|client| close_socket(client));
+                        }
+                        failback
+                    }
+                    None => failback,
+                }
+            }));
+        }
+        handlers.endpoints.lock().unwrap().selection = selection;
+    }
+
+    /// Returns the address that was used for the most recent connection
+    /// attempt, if the address was resolved by this crate rather than
+    /// by libmosquitto.
+    /// This can be used to determine which address family was used
+    /// when the `HappyEyeballs` connect strategy is in effect.
+    pub fn connected_addr(&self) -> Option<SocketAddr> {
+        self.connected_addr
+    }
+
+    /// Install a resolver that will be used by [connect](#method.connect)
+    /// to map the broker host name to a list of addresses, rather than
+    /// having libmosquitto resolve the name itself.
+    pub fn set_resolver<R: Resolver + 'static>(&mut self, resolver: R) {
+        self.resolver = Some(Arc::new(resolver));
+    }
+
+    /// Remove a resolver previously installed via
+    /// [set_resolver](#method.set_resolver), restoring the default
+    /// libmosquitto name resolution.
+    pub fn clear_resolver(&mut self) {
+        self.resolver = None;
+    }
+
+    fn begin_connect(
+        &self,
+        host: &str,
+        port: c_int,
+        keep_alive_interval: Duration,
+        bind_address: Option<&str>,
+    ) -> Result<Receiver<ConnAck>, Error> {
+        let (tx, rx) = bounded(1);
+        let handlers = self.mosq.get_callbacks();
+        handlers.credentials.refresh(&self.mosq)?;
+        handlers.connect_redirect.lock().unwrap().take();
+        handlers.connect.lock().unwrap().replace(tx);
+        handlers.traffic.connecting(keep_alive_interval);
+        handlers
+            .connect_params
+            .lock()
+            .unwrap()
+            .replace(ConnectParams {
+                host: host.to_string(),
+                port,
+                keep_alive_interval,
+                bind_address: bind_address.map(String::from),
+            });
+        match handlers.connect_properties.lock().unwrap().as_ref() {
+            Some(properties) => self.mosq.connect_with_properties(
+                host,
+                port,
+                keep_alive_interval,
+                bind_address,
+                properties,
+            )?,
+            None => self
+                .mosq
+                .connect(host, port, keep_alive_interval, bind_address)?,
+        }
+        if let Some(options) = self
+            .mosq
+            .get_callbacks()
+            .socket_options
+            .lock()
+            .unwrap()
+            .as_ref()
+        {
+            self.mosq.set_socket_options(options)?;
+        }
+        Ok(rx)
+    }
+
+    async fn complete_connect(&self, rx: Receiver<ConnAck>) -> Result<ConnAck, Error> {
+        let mut guard = ConnectGuard {
+            mosq: &self.mosq,
+            armed: true,
+        };
+        let connack = rx.recv().await;
+        guard.armed = false;
+        let connack = connack.map_err(|_| Error::InvalidArgument)?;
+        if !connack.is_successful() {
+            Err(Error::RejectedConnection(connack.reason))
+        } else {
+            Ok(connack)
+        }
+    }
+
+    /// Completes a connection started by `begin_connect`, repeating it
+    /// after transient refusals as permitted by the `RefusalPolicy`,
+    /// and following redirects as permitted by the `RedirectPolicy`
+    async fn complete_connect_with_retry(
+        &self,
+        mut rx: Receiver<ConnAck>,
+        host: &str,
+        port: c_int,
+        keep_alive_interval: Duration,
+        bind_address: Option<&str>,
+    ) -> Result<ConnAck, Error> {
+        let mut host = host.to_string();
+        let mut port = port;
+        let mut refusals = 0;
+        loop {
+            match self.complete_connect(rx).await {
+                Err(Error::RejectedConnection(status)) => {
+                    let redirect = self
+                        .mosq
+                        .get_callbacks()
+                        .connect_redirect
+                        .lock()
+                        .unwrap()
+                        .take();
+                    if let Some(target) = redirect {
+                        host = target.host;
+                        port = target.port;
+                        refusals = 0;
+                        rx = self.begin_connect(&host, port, keep_alive_interval, bind_address)?;
+                        continue;
+                    }
+                    refusals += 1;
+                    let policy = *self.mosq.get_callbacks().refusal_policy.lock().unwrap();
+                    match policy.retry_delay(status, refusals) {
+                        Some(delay) => {
+                            async_io::Timer::after(delay).await;
+                            rx =
+                                self.begin_connect(&host, port, keep_alive_interval, bind_address)?;
+                        }
+                        None => return Err(Error::RejectedConnection(status)),
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Connect to the broker on the specified host and port by
+    /// tunnelling through an HTTP proxy using the `CONNECT` method.
+    ///
+    /// The tunnel remains active for the lifetime of the client, or until
+    /// a subsequent call replaces it, and it is used for any automatic
+    /// reconnection attempts.
+    /// libmosquitto is configured to reach the tunnel as a SOCKS5 proxy,
+    /// which it cannot be told to stop using, so later
+    /// [connect](#method.connect) calls also go through the HTTP proxy.
+    ///
+    /// libmosquitto still connects to `host` by name, so TLS server name
+    /// indication and certificate verification use `host`.
+    ///
+    /// The other parameters and the return value have the same meaning
+    /// as for [connect](#method.connect).
+    pub async fn connect_via_http_proxy(
+        &mut self,
+        proxy: &HttpProxy,
+        host: &str,
+        port: c_int,
+        keep_alive_interval: Duration,
+        bind_address: Option<&str>,
+    ) -> Result<ConnAck, Error> {
+        let tunnel_port = port.try_into().map_err(|_| Error::InvalidArgument)?;
+        let tunnel = proxy.open_tunnel(host, tunnel_port)?;
+        let local_addr = tunnel.local_addr();
+        self.mosq.set_socks5_proxy(
+            &local_addr.ip().to_string(),
+            local_addr.port(),
+            Some(tunnel.credentials()),
+        )?;
+        self.tunnel.replace(tunnel);
+
+        // The proxy resolves the host name, so it is passed through as-is
+        self.connected_addr = None;
+        self.mosq.get_callbacks().endpoints.lock().unwrap().host = None;
+
+        let rx = self.begin_connect(host, port, keep_alive_interval, bind_address)?;
+        self.complete_connect_with_retry(rx, host, port, keep_alive_interval, bind_address)
+            .await
+    }
+
+    /// Configure options for the socket used to connect to the broker.
+    /// The options are applied each time that a connection is
+    /// established, including automatic reconnections, and are applied
+    /// immediately if the client is currently connected.
+    pub fn set_socket_options(&self, options: SocketOptions) -> Result<(), Error> {
+        if self.mosq.socket().is_some() {
+            self.mosq.set_socket_options(&options)?;
+        }
+        self.mosq
+            .get_callbacks()
+            .socket_options
+            .lock()
+            .unwrap()
+            .replace(options);
+        Ok(())
+    }
+
+    /// Returns a channel that yields notable events, such as redirects
+    /// requested by the broker.
+    /// Failures that occur in the background, such as a lost connection
+    /// or a TLS error in the message loop, are reported as
+    /// `Event::BackgroundError`, since there is no call that could
+    /// return them.
+    /// Each call returns a new channel, which replaces any channel
+    /// returned by a previous call; only events that happen after
+    /// the call are delivered.
+    pub fn events(&self) -> Receiver<Event> {
+        let (tx, rx) = unbounded();
+        self.mosq.get_callbacks().events.lock().unwrap().replace(tx);
+        self.update_logging();
+        rx
+    }
+
+    /// Returns a channel that yields received messages that could not
+    /// be delivered to the [subscriber](#method.subscriber) channel,
+    /// rather than discarding them.
+    /// Each call returns a new channel, which replaces any channel
+    /// returned by a previous call.
+    pub fn dead_letters(&self) -> Receiver<DeadLetter> {
+        let (tx, rx) = unbounded();
+        self.mosq
+            .get_callbacks()
+            .dead_letters
+            .lock()
+            .unwrap()
+            .replace(tx);
+        rx
+    }
+
+    /// Republish received messages that could not be delivered to the
+    /// [subscriber](#method.subscriber) channel to `topic`, or stop
+    /// doing so if `topic` is `None`.
+    /// The original topic and the reason are attached as the
+    /// `dead-letter-topic` and `dead-letter-reason` MQTT 5 user
+    /// properties.
+    /// Dead letters are republished without the retain flag, and
+    /// without waiting for the broker to acknowledge them.
+    pub fn set_dead_letter_topic(&self, topic: Option<&str>) {
+        *self.mosq.get_callbacks().dead_letter_topic.lock().unwrap() = topic.map(String::from);
+    }
+
+    /// Discard received messages whose payload is larger than `max`
+    /// bytes, or accept messages of any size if `max` is `None`.
+    /// Oversized messages are discarded before their payload is copied,
+    /// so they never reach middleware or any subscriber queue, which
+    /// protects consumers with limited memory from abusive publishers.
+    /// They are counted in `Stats::messages_oversized`.
+    ///
+    /// If `dead_letter` is true, they are also delivered as dead
+    /// letters with `DeadLetterReason::PayloadTooLarge`; see
+    /// [dead_letters](#method.dead_letters).
+    pub fn set_max_payload_size(&self, max: Option<usize>, dead_letter: bool) {
+        *self.mosq.get_callbacks().max_payload.lock().unwrap() = max.map(|max| (max, dead_letter));
+    }
+
+    /// Validate the JSON payloads of outgoing and received messages
+    /// using `registry`, or stop validating them if `registry` is `None`.
+    /// See [SchemaRegistry](struct.SchemaRegistry.html).
+    #[cfg(feature = "json-schema")]
+    pub fn set_schema_registry(&self, registry: Option<crate::SchemaRegistry>) {
+        *self.mosq.get_callbacks().schemas.lock().unwrap() = registry;
+    }
+
+    /// Start monitoring the connection for silence from the broker,
+    /// replacing any previously configured watchdog, or stop
+    /// monitoring if `watchdog` is `None`.
+    /// The watchdog runs on a thread of its own, and only monitors
+    /// the connection while the client is connected.
+    /// See [Watchdog](struct.Watchdog.html).
+    pub fn set_silence_watchdog(&mut self, watchdog: Option<Watchdog>) {
+        self.watchdog.take();
+        if let Some(watchdog) = watchdog {
+            let handlers = self.mosq.get_callbacks();
+            self.watchdog.replace(TimerThread::watchdog(
+                watchdog,
+                Arc::clone(&handlers.activity),
+                Arc::clone(&handlers.events),
+                // Safety: the thread is stopped before the client is dropped
+                unsafe { self.mosq.ptr() },
+            ));
+        }
+    }
+
+    /// Configure how the client reacts when an MQTT 5 broker directs it
+    /// to use another server, by responding to the CONNECT, or by
+    /// disconnecting, with the `UseAnotherServer` or `ServerMoved`
+    /// reason codes.
+    /// Redirects are always reported via [events](#method.events).
+    /// The default is `RedirectPolicy::Ignore`.
+    ///
+    /// When the redirect is followed in response to a CONNECT,
+    /// [connect](#method.connect) completes with the outcome of the
+    /// connection to the referenced server, or fails if that connection
+    /// cannot be started.
+    /// Other redirects are followed by a background thread, which runs
+    /// while the policy is `RedirectPolicy::Follow`, and reports a
+    /// failure to connect as `Event::BackgroundError`.
+    pub fn set_redirect_policy(&mut self, policy: RedirectPolicy) {
+        let handlers = self.mosq.get_callbacks();
+        *handlers.redirect_policy.lock().unwrap() = policy;
+        if policy == RedirectPolicy::Ignore {
+            self.redirect_follower.take();
+            handlers.background_redirect.lock().unwrap().take();
+            return;
+        }
+        if self.redirect_follower.is_some() {
+            return;
+        }
+        let pending = Arc::clone(&handlers.background_redirect);
+        let credentials = Arc::clone(&handlers.credentials);
+        let events = Arc::clone(&handlers.events);
+        // Safety: the thread is stopped before the client is dropped
+        let mosq = unsafe { self.mosq.ptr() };
+        self.redirect_follower = Some(TimerThread::spawn(move || {
+            let target = pending.lock().unwrap().take();
+            if let Some(target) = target {
+                let emit = |event| {
+                    if let Some(tx) = events.lock().unwrap().as_ref() {
+                        let _ = tx.try_send(event);
+                    }
+                };
+                mosq.with_client(|client| {
+                    if let Err(err) = credentials.refresh(client) {
+                        emit(Event::CredentialsUnavailable(err.to_string()));
+                    }
+                    // libmosquitto completes the connection from its
+                    // network loop, and uses the new server for any
+                    // subsequent reconnections
+                    let result = client.connect_non_blocking(
+                        &target.host,
+                        target.port,
+                        target.keep_alive_interval,
+                        target.bind_address.as_deref(),
+                    );
+                    if let Err(err) = result {
+                        emit(Event::BackgroundError {
+                            context: "following redirect",
+                            message: err.to_string(),
+                        });
+                    }
+                });
+            }
+            REDIRECT_POLL_INTERVAL
+        }));
+    }
+
+    /// Returns the capabilities that the broker advertised when the
+    /// client most recently connected, or `None` if it hasn't
+    /// connected yet.
+    pub fn broker_capabilities(&self) -> Option<BrokerCapabilities> {
+        *self.mosq.get_callbacks().capabilities.lock().unwrap()
+    }
+
+    /// Configure whether publishes and subscriptions that request a
+    /// higher QoS than the broker supports, as advertised by its
+    /// Maximum QoS property, are automatically downgraded to that
+    /// maximum.  Each downgrade is reported via [events](#method.events)
+    /// as `Event::QosDowngraded`.
+    /// The default is `false`, in which case such requests fail with
+    /// `Error::QosNotSupported`.
+    ///
+    /// Some brokers, such as AWS IoT Core, only support QoS 0 and 1.
+    pub fn set_qos_downgrade(&self, enabled: bool) {
+        *self.mosq.get_callbacks().downgrade_qos.lock().unwrap() = enabled;
+    }
+
+    /// Configure whether topic aliases are automatically assigned to
+    /// outgoing messages, which reduces the size of messages that are
+    /// repeatedly published to the same topics.
+    /// Aliases are assigned to the most recently used topics, up to the
+    /// Topic Alias Maximum advertised by an MQTT 5 broker, and are
+    /// re-established after a reconnection.
+    /// The default is `false`.
+    ///
+    /// Aliases are only assigned to QoS 0 messages, because libmosquitto
+    /// may resend QoS 1 and 2 messages as they were originally sent,
+    /// on a new connection to which the alias is unknown, or whose
+    /// Topic Alias Maximum is smaller.
+    /// Messages that already have a `Property::TopicAlias` are left as-is.
+    pub fn set_topic_aliasing(&self, enabled: bool) {
+        let handlers = self.mosq.get_callbacks();
+        let mut aliases = handlers.topic_aliases.lock().unwrap();
+        if !enabled {
+            aliases.take();
+        } else if aliases.is_none() {
+            let mut assigned = TopicAliases::default();
+            if let Some(caps) = handlers.capabilities.lock().unwrap().as_ref() {
+                assigned.reset(caps.topic_alias_maximum);
+            }
+            aliases.replace(assigned);
+        }
+    }
+
+    /// Configure how retained messages are published when the broker
+    /// has advertised that it doesn't support them, via its Retain
+    /// Available property.
+    /// The default is `RetainPolicy::Fail`.
+    pub fn set_retain_policy(&self, policy: RetainPolicy) {
+        *self.mosq.get_callbacks().retain_policy.lock().unwrap() = policy;
+    }
+
+    /// Configure how the client reacts when the broker refuses a
+    /// connection attempt in its CONNACK, as opposed to the network
+    /// connection failing.
+    /// Refusals are always reported via [events](#method.events).
+    /// The default is `RefusalPolicy::Report`.
+    pub fn set_refusal_policy(&self, policy: RefusalPolicy) {
+        *self.mosq.get_callbacks().refusal_policy.lock().unwrap() = policy;
+    }
+
+    /// Configure the client to pause publishing when an MQTT 5 broker
+    /// rejects a publish with the `QuotaExceeded` or `MessageRateTooHigh`
+    /// reason codes, rather than making the situation worse by
+    /// continuing to publish at the same rate.
+    ///
+    /// While paused, calls to [publish](#method.publish) wait until
+    /// the delay computed by `backoff` has elapsed.  The delay grows with
+    /// each consecutive rejection, and is reset by a successful publish.
+    ///
+    /// Passing `None`, which is the default, disables the pause.
+    pub fn set_quota_backoff(&self, backoff: Option<Backoff>) {
+        let handlers = self.mosq.get_callbacks();
+        let mut quota = handlers.quota.lock().unwrap();
+        *quota = QuotaThrottle {
+            backoff,
+            ..QuotaThrottle::default()
+        };
+    }
+
+    /// Returns a snapshot of the message and byte counts for the traffic
+    /// exchanged with the broker since the client was created, or since
+    /// the last call to [reset_stats](#method.reset_stats).
+    /// This is useful for keeping track of data usage on metered
+    /// connections.
+    /// The snapshot also reports the depth of the subscriber queue,
+    /// along with its high-water mark and the number of messages that
+    /// were dropped because it was full or closed.
+    pub fn stats(&self) -> Stats {
+        let handlers = self.mosq.get_callbacks();
+        let mut stats = handlers.traffic.snapshot();
+        let tx = handlers.subscriber_tx.lock().unwrap();
+        stats.subscriber_queue = handlers.subscriber_queue.snapshot(tx.len(), tx.capacity());
+        if let Some(queue) = handlers.offline_queue.lock().unwrap().as_ref() {
+            stats.messages_expired = queue.expired();
+            stats.offline_queue = queue.stats(*handlers.offline_capacity.lock().unwrap());
+        }
+        if let Some(probe) = handlers.latency_probe.lock().unwrap().as_ref() {
+            stats.broker_latency = probe.latest();
+        }
+        stats.memory = handlers.memory.usage();
+        stats
+    }
+
+    /// Reset the counters reported by [stats](#method.stats) to zero
+    pub fn reset_stats(&self) {
+        let handlers = self.mosq.get_callbacks();
+        handlers.traffic.reset();
+        handlers.subscriber_queue.reset();
+        if let Some(queue) = handlers.offline_queue.lock().unwrap().as_mut() {
+            queue.reset_stats();
+        };
+        if let Some(topics) = handlers.topic_stats.lock().unwrap().as_mut() {
+            topics.reset();
+        };
+    }
+
+    /// Enable or disable the counting of received messages per topic,
+    /// which are reported by [top_topics](#method.top_topics).
+    /// Counts are kept for up to `capacity` topics; when a message
+    /// arrives on another topic, the topic that has gone the longest
+    /// without a message is forgotten to make room.
+    ///
+    /// The default is `None`, which disables counting.
+    pub fn set_topic_stats_capacity(&self, capacity: Option<usize>) {
+        *self.mosq.get_callbacks().topic_stats.lock().unwrap() = capacity.map(TopicCounters::new);
+    }
+
+    /// Returns the `n` topics on which the most messages have been
+    /// received, busiest first, which is useful to find devices that
+    /// are publishing more than expected.
+    /// This is empty unless counting has been enabled via
+    /// [set_topic_stats_capacity](#method.set_topic_stats_capacity).
+    pub fn top_topics(&self, n: usize) -> Vec<TopicStats> {
+        match self
+            .mosq
+            .get_callbacks()
+            .topic_stats
+            .lock()
+            .unwrap()
+            .as_ref()
+        {
+            Some(topics) => topics.top(n),
+            None => vec![],
+        }
+    }
+
+    /// Add a layer of middleware that will see each outgoing message
+    /// before it is published.
+    /// Layers run in the order that they were added.
+    /// See [PublishMiddleware](trait.PublishMiddleware.html).
+    pub fn add_publish_middleware<M: PublishMiddleware + 'static>(&mut self, middleware: M) {
+        self.publish_middleware.push(Box::new(middleware));
+    }
+
+    /// Remove all of the middleware added via
+    /// [add_publish_middleware](#method.add_publish_middleware)
+    pub fn clear_publish_middleware(&mut self) {
+        self.publish_middleware.clear();
+    }
+
+    /// Add a layer of middleware that will see each received message
+    /// before it is delivered to the [subscriber](#method.subscriber)
+    /// channel.
+    /// Layers run in the order that they were added.
+    /// See [MessageMiddleware](trait.MessageMiddleware.html).
+    pub fn add_message_middleware<M: MessageMiddleware + 'static>(&self, middleware: M) {
+        self.mosq
+            .get_callbacks()
+            .message_middleware
+            .lock()
+            .unwrap()
+            .push(Box::new(middleware));
+    }
+
+    /// Encrypt the payloads of the messages that are published to, and
+    /// decrypt those received on, the topics that `cipher` covers; see
+    /// [Encryption](struct.Encryption.html).
+    /// This adds a layer to both the publish and the message middleware,
+    /// so layers that are added afterwards see the ciphertext of outgoing
+    /// messages and the plaintext of received ones.
+    ///
+    /// This requires MQTT 5, as earlier versions of the protocol don't
+    /// support the property that marks encrypted messages, so every
+    /// message on a covered topic would be dropped.
+    /// Fails with `Error::NotSupported` unless the protocol version has
+    /// already been set to `ProtocolVersion::V5`.
+    pub fn add_encryption<C: Cipher + 'static>(&mut self, cipher: C) -> Result<(), Error> {
+        if *self.mosq.get_callbacks().protocol_version.lock().unwrap() != ProtocolVersion::V5 {
+            return Err(Error::NotSupported);
+        }
+        let encryption = Encryption::new(cipher);
+        self.add_message_middleware(encryption.clone());
+        self.add_publish_middleware(encryption);
+        Ok(())
+    }
+
+    /// Capture the messages that the client receives and publishes
+    /// using `recorder`; see [Recorder](struct.Recorder.html).
+    /// This adds a layer to both the publish and the message middleware,
+    /// so the recording holds outgoing messages as changed by the layers
+    /// that were added before it, and received messages as they arrived
+    /// from the broker, if it is added first.
+    #[cfg(feature = "record")]
+    pub fn add_recorder(&mut self, recorder: crate::Recorder) {
+        self.add_message_middleware(recorder.clone());
+        self.add_publish_middleware(recorder);
+    }
+
+    /// Remove all of the middleware added via
+    /// [add_message_middleware](#method.add_message_middleware)
+    pub fn clear_message_middleware(&self) {
+        self.mosq
+            .get_callbacks()
+            .message_middleware
+            .lock()
+            .unwrap()
+            .clear();
+    }
+
+    /// Register a hook that runs when the client is shut down via
+    /// [shutdown](#method.shutdown).
+    /// Hooks run in the order that they were added.
+    /// See [ShutdownHook](trait.ShutdownHook.html).
+    pub fn add_shutdown_hook<H: ShutdownHook + 'static>(&mut self, hook: H) {
+        self.shutdown_hooks.push(Box::new(hook));
+    }
+
+    /// Remove all of the hooks added via
+    /// [add_shutdown_hook](#method.add_shutdown_hook)
+    pub fn clear_shutdown_hooks(&mut self) {
+        self.shutdown_hooks.clear();
+    }
+
+    /// Run the hooks registered via
+    /// [add_shutdown_hook](#method.add_shutdown_hook), in order, and
+    /// then disconnect from the broker.
+    ///
+    /// The hooks share `deadline`: any that are still running when it
+    /// passes are abandoned, along with those that haven't started, and
+    /// the client disconnects regardless.
+    /// A hook that fails doesn't prevent the later ones from running.
+    ///
+    /// Returns the first error from a hook, or `Error::Timeout` if the
+    /// deadline passed, once the client has disconnected.
+    pub async fn shutdown(&mut self, deadline: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + deadline;
+        let mut result = Ok(());
+        for hook in &self.shutdown_hooks {
+            match before(Some(deadline), hook.run(self)).await {
+                Some(Ok(())) => {}
+                Some(Err(err)) => {
+                    if result.is_ok() {
+                        result = Err(err);
+                    }
+                }
+                None => {
+                    if result.is_ok() {
+                        result = Err(Error::Timeout);
+                    }
+                    break;
+                }
+            }
+        }
+        match self.mosq.disconnect() {
+            Ok(()) | Err(Error::NoConnection) => result,
+            Err(err) => result.and(Err(err)),
+        }
+    }
+
+    /// Publish a message to the specified topic.
+    ///
+    /// The payload size can be 0-283, 435 or 455 bytes; other values
+    /// will generate an error result.
+    ///
+    /// `retain` will set the message to be retained by the broker,
+    /// and delivered to new subscribers.
+    ///
+    /// Returns the assigned MessageId value for the publish.
+    /// The publish may not complete immediately.
+    /// You can use [set_callbacks](#method.set_callbacks) to register
+    /// an `on_publish` event to determine when it completes.
+    ///
+    /// If an MQTT 5 broker acknowledges the publish with a failure
+    /// reason code, an `Error::Reason` is returned.
+    ///
+    /// If a backoff has been configured via
+    /// [set_quota_backoff](#method.set_quota_backoff), this waits for
+    /// any backoff period to expire before publishing.
+    ///
+    /// The message is passed through any middleware that was added via
+    /// [add_publish_middleware](#method.add_publish_middleware) first,
+    /// which may modify or deny it.
+    pub async fn publish(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<MessageId, Error> {
+        self.publish_before(topic, payload, qos, retain, None, Properties::new())
+            .await
+    }
+
+    /// Publish a retained message to `topic` with a message expiry
+    /// interval of `expiry`, and republish it every `interval` while the
+    /// client is connected, so that an MQTT 5 broker keeps it for as
+    /// long as the client is running, and discards it once `expiry` has
+    /// passed after the client stops.
+    /// This suits state topics that shouldn't outlive their publisher.
+    /// `interval` should be comfortably shorter than `expiry`.
+    ///
+    /// Calling this again for the same topic replaces its payload.
+    /// The republishing is done from a thread of its own; failures are
+    /// reported via [events](#method.events) as
+    /// `Event::BackgroundError`.
+    /// Brokers that use an earlier version of the protocol ignore the
+    /// expiry, and keep the message indefinitely.
+    pub async fn keep_retained(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        expiry: Duration,
+        interval: Duration,
+    ) -> Result<MessageId, Error> {
+        let expiry: u32 = expiry
+            .as_secs()
+            .try_into()
+            .map_err(|_| Error::InvalidArgument)?;
+        let mut properties = Properties::new();
+        properties.push(Property::MessageExpiryInterval(expiry));
+
+        self.retained_refresh.remove(topic);
+        let mid = self
+            .publish_before(topic, payload, qos, true, None, properties.clone())
+            .await?;
+
+        let handlers = self.mosq.get_callbacks();
+        let activity = Arc::clone(&handlers.activity);
+        let events = Arc::clone(&handlers.events);
+        let abandoned = Arc::clone(&handlers.abandoned);
+        let early_acks = Arc::clone(&handlers.early_acks);
+        // Safety: the thread is stopped before the client is dropped
+        let mosq = unsafe { self.mosq.ptr() };
+        let (topic, payload) = (topic.to_string(), payload.to_vec());
+        let mut published = true;
+        let refresh = TimerThread::spawn({
+            let topic = topic.clone();
+            move || {
+                // The first call is made immediately, when the message
+                // has only just been published
+                if std::mem::take(&mut published) || !activity.is_connected() {
+                    return interval;
+                }
+                mosq.with_client(|client| {
+                    // Nothing waits for the refresh to be acknowledged
+                    let result = publish_untracked(&early_acks, &abandoned, || {
+                        client.publish_with_properties(&topic, &payload, qos, true, &properties)
+                    });
+                    if let Err(err) = result {
+                        if let Some(tx) = events.lock().unwrap().as_ref() {
+                            let _ = tx.try_send(Event::BackgroundError {
+                                context: "refreshing retained message",
+                                message: err.to_string(),
+                            });
+                        }
+                    }
+                });
+                interval
+            }
+        });
+        self.retained_refresh.insert(topic, refresh);
+        Ok(mid)
+    }
+
+    /// Stop republishing `topic`, as previously requested via
+    /// [keep_retained](#method.keep_retained), so that the broker
+    /// discards its retained message once it expires.
+    /// Returns true if the topic was being republished.
+    pub fn stop_keeping_retained(&mut self, topic: &str) -> bool {
+        self.retained_refresh.remove(topic).is_some()
+    }
+
+    /// Publish a message with MQTT 5 properties attached, such as User
+    /// Properties, a Response Topic or a Content Type, as for
+    /// [publish](#method.publish).
+    /// The properties are ignored by brokers that use an earlier
+    /// version of the protocol.
+    ///
+    /// ```no_run
+    /// # use mosquitto_rs::*;
+    /// # async fn report(client: &mut Client) -> Result<(), Error> {
+    /// let mut props = Properties::new();
+    /// props
+    ///     .add_content_type("application/json")
+    ///     .add_user_property("site", "plant-3");
+    /// client
+    ///     .publish_with_properties("readings", b"{}", QoS::AtLeastOnce, false, props)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn publish_with_properties(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+        properties: Properties,
+    ) -> Result<MessageId, Error> {
+        self.publish_before(topic, payload, qos, retain, None, properties)
+            .await
+    }
+
+    /// Publish `message` to its topic, as for [publish](#method.publish);
+    /// see [MqttMessage](trait.MqttMessage.html).
+    pub async fn publish_message<M: MqttMessage>(
+        &mut self,
+        message: &M,
+    ) -> Result<MessageId, Error> {
+        let topic = message.topic();
+        let payload = message.encode().map_err(|e| e.publishing(&topic, None))?;
+        self.publish(&topic, &payload, M::QOS, M::RETAIN).await
+    }
+
+    /// Publish a message, as for [publish](#method.publish), but give up
+    /// if it hasn't been acknowledged within `timeout`, including any
+    /// time spent waiting for a quota backoff or for preceding messages.
+    /// The timeout applies across reconnects.
+    ///
+    /// On timeout an `Error::Timeout` is returned, and a late
+    /// acknowledgement is ignored.  libmosquitto doesn't allow a message
+    /// to be withdrawn once it has been queued, so it may still be
+    /// delivered after the timeout; to have an MQTT 5 broker discard it,
+    /// also set a `Property::MessageExpiryInterval` via middleware.
+    pub async fn publish_with_timeout(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+        timeout: Duration,
+    ) -> Result<MessageId, Error> {
+        let deadline = Instant::now() + timeout;
+        self.publish_before(
+            topic,
+            payload,
+            qos,
+            retain,
+            Some(deadline),
+            Properties::new(),
+        )
+        .await
+    }
+
+    /// Publish a group of related messages, such as state that is
+    /// spread across several topics, and wait until each has been
+    /// acknowledged, has failed, or has not been acknowledged within
+    /// `timeout`; see [PublishReport](struct.PublishReport.html).
+    ///
+    /// The publishes are sent concurrently, in the order that they are
+    /// given, and each passes through the publish middleware as for
+    /// [publish](#method.publish).
+    /// Unlike `publish`, a failure doesn't stop the others from being
+    /// sent; the outcome of every publish is reported.
+    pub async fn publish_all_or_report(
+        &self,
+        publishes: &[OutgoingPublish],
+        timeout: Duration,
+    ) -> PublishReport {
+        let deadline = Instant::now() + timeout;
+        let mut pending: Vec<_> = publishes
+            .iter()
+            .map(|publish| {
+                Some(Box::pin(self.publish_before(
+                    &publish.topic,
+                    &publish.payload,
+                    publish.qos,
+                    publish.retain,
+                    Some(deadline),
+                    publish.properties.clone(),
+                )))
+            })
+            .collect();
+        let mut results: Vec<Option<Result<MessageId, Error>>> =
+            publishes.iter().map(|_| None).collect();
+        futures_lite::future::poll_fn(|cx| {
+            let mut complete = true;
+            for (slot, result) in pending.iter_mut().zip(results.iter_mut()) {
+                if let Some(publish) = slot {
+                    match publish.as_mut().poll(cx) {
+                        Poll::Ready(outcome) => {
+                            *result = Some(outcome);
+                            *slot = None;
+                        }
+                        Poll::Pending => complete = false,
+                    }
+                }
+            }
+            if complete {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        PublishReport::new(
+            publishes
+                .iter()
+                .zip(results)
+                .map(|(publish, result)| {
+                    (
+                        publish.topic.clone(),
+                        result.expect("every publish to have completed"),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns a [Publisher](struct.Publisher.html), which publishes the
+    /// messages that are sent to it as a `futures::Sink`.
+    pub fn publisher(&self) -> Publisher<'_> {
+        let handlers = self.mosq.get_callbacks();
+        let limit = handlers
+            .inflight_limit
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|l| l.limit);
+        Publisher::new(self, limit)
+    }
+
+    /// Subscribe to `patterns`, and return a sink that publishes the
+    /// messages sent to it along with a stream of the messages received
+    /// via those subscriptions, so that the client can be plugged into
+    /// stream processing code built on `futures`.
+    ///
+    /// The stream has a queue of its own, as for
+    /// [filtered_subscriber](#method.filtered_subscriber), so the messages
+    /// of other subscriptions continue to be delivered as usual.
+    ///
+    /// ```ignore
+    /// # use mosquitto_rs::*;
+    /// # async fn uppercase(client: &Client) -> Result<(), Error> {
+    /// use futures::StreamExt;
+    ///
+    /// let (sink, stream) = client.split(&["requests/#"], QoS::AtLeastOnce).await?;
+    /// stream
+    ///     .map(|message| {
+    ///         Ok(Message {
+    ///             topic: message.topic.replacen("requests/", "responses/", 1),
+    ///             payload: message.payload.to_ascii_uppercase(),
+    ///             ..message
+    ///         })
+    ///     })
+    ///     .forward(sink)
+    ///     .await
+    /// # }
+    /// ```
+    pub async fn split(
+        &self,
+        patterns: &[&str],
+        qos: QoS,
+    ) -> Result<(MessageSink<'_>, MessageStream), Error> {
+        for pattern in patterns {
+            sub_topic_check(pattern)?;
+        }
+        let filters: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        let stream = self.filtered_subscriber(
+            move |message: &Message| {
+                filters
+                    .iter()
+                    .any(|filter| topic_matches_sub(filter, &message.topic).unwrap_or(false))
+            },
+            None,
+        );
+        for pattern in patterns {
+            self.subscribe(pattern, qos).await?;
+        }
+        Ok((self.publisher(), stream))
+    }
+
+    /// Publish a request to `topic` and wait for the correlated reply,
+    /// using the MQTT 5 request/response pattern.
+    ///
+    /// The request is published at QoS 1 with a Response Topic that is
+    /// private to this client, which is subscribed to the first time
+    /// that a request is made, and with unique Correlation Data.
+    /// The responder is expected to publish its reply to that topic,
+    /// carrying the same Correlation Data.
+    ///
+    /// Yields `Error::Timeout` if the reply doesn't arrive within
+    /// `timeout`, which includes the time taken to publish the request.
+    /// Replies are consumed by this method, and are not seen by the
+    /// [subscriber](#method.subscriber).
+    ///
+    /// This requires a connection that uses
+    /// `ProtocolVersion::V5`.
+    pub async fn request(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> Result<Message, Error> {
+        let deadline = Instant::now() + timeout;
+        let response_topic = self.response_topic().await?;
+        let responses = self.mosq.get_callbacks().responses.clone();
+        let pending = responses.register(responses.next_correlation_data(), timeout);
+        let properties: Properties = vec![
+            Property::ResponseTopic(response_topic),
+            Property::CorrelationData(pending.correlation_data().to_vec()),
+        ]
+        .into_iter()
+        .collect();
+        self.publish_before(
+            topic,
+            payload,
+            QoS::AtLeastOnce,
+            false,
+            Some(deadline),
+            properties,
+        )
+        .await?;
+        pending.wait().await
+    }
+
+    /// Publish a request and wait for the correlated reply, as for
+    /// [request](#method.request), blocking the current thread.
+    /// This is intended for command line tools and scripts that don't
+    /// otherwise use an async runtime, and must not be called from
+    /// async code.
+    ///
+    /// ```no_run
+    /// use mosquitto_rs::*;
+    /// use std::time::Duration;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let mut client = Client::with_auto_id()?;
+    /// client.set_option(&ClientOption::ProtocolVersion(ProtocolVersion::V5))?;
+    /// smol::block_on(client.connect("localhost", 1883, Duration::from_secs(5), None))?;
+    /// let reply = client.request_blocking("service/time", b"", Duration::from_secs(5))?;
+    /// println!("{}", String::from_utf8_lossy(&reply.payload));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn request_blocking(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> Result<Message, Error> {
+        futures_lite::future::block_on(self.request(topic, payload, timeout))
+    }
+
+    /// Returns the topic that replies to requests are sent to,
+    /// subscribing to it if necessary
+    async fn response_topic(&mut self) -> Result<String, Error> {
+        if let Some(topic) = &self.response_topic {
+            return Ok(topic.clone());
+        }
+        let topic = format!("mosquitto-rs/responses/{}", self.unique_id());
+        let options = SubscribeOptions::default();
+        self.subscribe_with_exact_options(&topic, QoS::AtLeastOnce, options, &Properties::new())
+            .await?;
+        self.response_topic = Some(topic.clone());
+        Ok(topic)
+    }
+
+    /// Returns the client id, or an id that is unique to this process
+    /// and time if the id was assigned randomly
+    fn unique_id(&self) -> String {
+        match &self.client_id {
+            Some(id) => id.clone(),
+            None => {
+                let now = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default();
+                format!("{}-{}", std::process::id(), now.as_nanos())
+            }
+        }
+    }
+
+    /// Check that the client can subscribe to a test topic, publish a
+    /// message to it and receive that message back, within `timeout`,
+    /// returning a report of the outcome and latency of each step; see
+    /// [SelfTestReport](struct.SelfTestReport.html).
+    /// The client must already be connected.
+    ///
+    /// The test topic is `mosquitto-rs/self-test/` followed by the
+    /// client id, so that access to it can be granted per client.
+    /// The subscription is removed again before returning.
+    ///
+    /// This is useful to validate the credentials and permissions of a
+    /// device when it is provisioned:
+    ///
+    /// ```no_run
+    /// # use mosquitto_rs::*;
+    /// # async fn provision(client: &mut Client) -> Result<(), Error> {
+    /// let report = client.self_test(std::time::Duration::from_secs(5)).await;
+    /// if !report.is_ok() {
+    ///     eprintln!("self-test failed: {:?}", report);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn self_test(&mut self, timeout: Duration) -> SelfTestReport {
+        let deadline = Some(Instant::now() + timeout);
+        let topic = format!("mosquitto-rs/self-test/{}", self.unique_id());
+        let nonce = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            .to_be_bytes()
+            .to_vec();
+        let (filter_topic, filter_nonce) = (topic.clone(), nonce.clone());
+        let subscriber = self.filtered_subscriber(
+            move |message: &Message| {
+                message.topic == filter_topic && message.payload == filter_nonce
+            },
+            Some(1),
+        );
+
+        let mut report = SelfTestReport {
+            topic: topic.clone(),
+            subscribe: Ok(()),
+            granted_qos: None,
+            publish: Ok(()),
+            publish_latency: None,
+            round_trip: None,
+        };
+        let no_properties = Properties::new();
+        let subscribe = self.subscribe_with_exact_options(
+            &topic,
+            QoS::AtLeastOnce,
+            SubscribeOptions::default(),
+            &no_properties,
+        );
+        report.subscribe = before(deadline, subscribe)
+            .await
+            .unwrap_or_else(|| Err(Error::Timeout.subscribing(&topic)));
+        report.granted_qos = self.granted_qos(&topic);
+
+        let start = Instant::now();
+        report.publish = self
+            .publish_before(
+                &topic,
+                &nonce,
+                QoS::AtLeastOnce,
+                false,
+                deadline,
+                Properties::new(),
+            )
+            .await
+            .map(|_| ());
+        if report.publish.is_ok() {
+            report.publish_latency = Some(start.elapsed());
+        }
+        if report.subscribe.is_ok() && report.publish.is_ok() {
+            if let Some(Ok(_)) = before(deadline, subscriber.recv()).await {
+                report.round_trip = Some(start.elapsed());
+            }
+        }
+
+        if report.subscribe.is_ok() {
+            let _ = self.forget_subscription(&topic);
+        }
+        report
+    }
+
+    pub(crate) async fn publish_before(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+        deadline: Option<Instant>,
+        properties: Properties,
+    ) -> Result<MessageId, Error> {
+        let mut outgoing = OutgoingPublish {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            qos,
+            retain,
+            properties,
+        };
+        apply_publish_middleware(&self.publish_middleware, &mut outgoing)
+            .map_err(|e| e.publishing(topic, None))?;
+        let OutgoingPublish {
+            topic,
+            payload,
+            qos,
+            retain,
+            properties,
+        } = &outgoing;
+        let (topic, payload) = (topic.as_str(), payload.as_slice());
+        let qos = self.mosq.get_callbacks().negotiate_qos(topic, *qos);
+        let retain = self
+            .mosq
+            .get_callbacks()
+            .negotiate_retain(topic, *retain)
+            .map_err(|e| e.publishing(topic, None))?;
+        #[cfg(feature = "json-schema")]
+        {
+            let schemas = self.mosq.get_callbacks().schemas.lock().unwrap().clone();
+            if let Some(schemas) = schemas {
+                schemas
+                    .validate(topic, payload)
+                    .map_err(|e| e.publishing(topic, None))?;
+            }
+        }
+
+        let paused_until = self.mosq.get_callbacks().quota.lock().unwrap().paused_until;
+        if let Some(paused_until) = paused_until {
+            if deadline.is_some_and(|deadline| paused_until > deadline) {
+                return Err(Error::Timeout.publishing(topic, None));
+            }
+            if paused_until > Instant::now() {
+                async_io::Timer::at(paused_until).await;
+            }
+        }
+
+        let order_key = match *self.mosq.get_callbacks().ordering.lock().unwrap() {
+            _ if qos == QoS::AtMostOnce => None,
+            PublishOrdering::Unordered => None,
+            PublishOrdering::PerTopic => Some(topic),
+            PublishOrdering::Global => Some(""),
+        };
+        if let Some(key) = order_key {
+            loop {
+                let pending = match self.mosq.get_callbacks().in_order.lock().unwrap().get(key) {
+                    Some(pending) => pending.wait.clone(),
+                    None => break,
+                };
+                // Resolves when the preceding publish completes
+                if before(deadline, pending.recv()).await.is_none() {
+                    return Err(Error::Timeout.publishing(topic, None));
+                }
+            }
+        }
+
+        loop {
+            let space = {
+                let handlers = self.mosq.get_callbacks();
+                let capacity = *handlers.offline_capacity.lock().unwrap();
+                let policy = *handlers.offline_overflow.lock().unwrap();
+                let offline = handlers.offline_queue.lock().unwrap();
+                match offline.as_ref() {
+                    Some(queue)
+                        if policy == OverflowPolicy::Block
+                            && (queue.is_full(capacity)
+                                || queue.is_over_budget(message_size(topic, payload))) =>
+                    {
+                        queue.space()
+                    }
+                    _ => break,
+                }
+            };
+            // Resolves when a message leaves the queue
+            if before(deadline, space.recv()).await.is_none() {
+                return Err(Error::Timeout.publishing(topic, None));
+            }
+        }
+
+        loop {
+            let released = match self
+                .mosq
+                .get_callbacks()
+                .inflight_limit
+                .lock()
+                .unwrap()
+                .as_ref()
+            {
+                Some(limit) if limit.is_full() => limit.released.1.clone(),
+                _ => break,
+            };
+            // Resolves when an earlier publish is acknowledged
+            if before(deadline, released.recv()).await.is_none() {
+                return Err(Error::Timeout.publishing(topic, None));
+            }
+        }
+
+        {
+            let handlers = self.mosq.get_callbacks();
+            if *handlers.protocol_version.lock().unwrap() != ProtocolVersion::V5 {
+                if let Some(echoes) = handlers.echo_filter.lock().unwrap().as_mut() {
+                    echoes.published(topic, payload);
+                }
+            }
+        }
+
+        let (tx, rx) = bounded(1);
+
+        let mid = {
+            let handlers = self.mosq.get_callbacks();
+            let queued = {
+                let mut offline = handlers.offline_queue.lock().unwrap();
+                match offline.as_mut() {
+                    Some(queue) => {
+                        let capacity = *handlers.offline_capacity.lock().unwrap();
+                        let size = message_size(topic, payload);
+                        let full = queue.is_full(capacity);
+                        if full || queue.is_over_budget(size) {
+                            if !full {
+                                handlers.memory_exceeded();
+                            }
+                            match *handlers.offline_overflow.lock().unwrap() {
+                                OverflowPolicy::DropOldest => loop {
+                                    let dropped = queue
+                                        .drop_oldest()
+                                        .map_err(|e| e.publishing(topic, None))?;
+                                    match dropped {
+                                        Some(dropped) => {
+                                            handlers.emit(Event::QueuedMessageDropped {
+                                                topic: dropped.topic,
+                                            })
+                                        }
+                                        None => break,
+                                    }
+                                    if !queue.is_full(capacity) && !queue.is_over_budget(size) {
+                                        break;
+                                    }
+                                },
+                                OverflowPolicy::DropNewest => {
+                                    queue.record_dropped();
+                                    handlers.emit(Event::QueuedMessageDropped {
+                                        topic: topic.to_string(),
+                                    });
+                                    return Err(Error::QueueFull.publishing(topic, None));
+                                }
+                                OverflowPolicy::Error => {
+                                    return Err(Error::QueueFull.publishing(topic, None));
+                                }
+                                // Another publish took the room that we waited for
+                                OverflowPolicy::Block => {}
+                            }
+                        }
+                        let id = queue
+                            .record(&QueuedPublish {
+                                topic: topic.to_string(),
+                                payload: payload.to_vec(),
+                                qos,
+                                retain,
+                                message_id: message_id(properties).map(str::to_string),
+                                queued_at: SystemTime::now(),
+                            })
+                            .map_err(|e| e.publishing(topic, None))?;
+                        if !handlers.activity.is_connected() {
+                            // Hold the message until the client next connects,
+                            // where it can expire, rather than in libmosquitto
+                            return Err(Error::Queued.publishing(topic, None));
+                        }
+                        // Keep a reconnection from resending it meanwhile
+                        queue.claim(id);
+                        Some(id)
+                    }
+                    None => None,
+                }
+            };
+
+            // No other lock is held while publishing, so the
+            // acknowledgement may be processed before the mid is
+            // recorded below, in which case it is held back until then
+            handlers.early_acks.lock().unwrap().begin();
+            // Hold the lock while publishing, so that the aliases cannot
+            // be reset by a reconnection in the meantime
+            let mut aliases = handlers.topic_aliases.lock().unwrap();
+            let alias = match aliases.as_mut() {
+                // libmosquitto resends QoS 1 and 2 messages as they were
+                // originally sent, possibly on a new connection that
+                // doesn't know the alias, so only QoS 0 messages use them
+                Some(aliases)
+                    if qos == QoS::AtMostOnce
+                        && !properties
+                            .iter()
+                            .any(|p| matches!(p, Property::TopicAlias(_))) =>
+                {
+                    aliases.assign(topic)
+                }
+                _ => None,
+            };
+            let result = match alias {
+                Some(alias) => {
+                    let (send_topic, alias) = match alias {
+                        Alias::Existing(alias) => ("", alias),
+                        Alias::New(alias) => (topic, alias),
+                    };
+                    let mut properties = properties.clone();
+                    properties.push(Property::TopicAlias(alias));
+                    self.mosq
+                        .publish_with_properties(send_topic, payload, qos, retain, &properties)
+                        // Report the real topic rather than the empty one
+                        .map_err(|e| match e {
+                            Error::Publish { mid, source, .. } => Error::Publish {
+                                topic: topic.to_string(),
+                                mid,
+                                source,
+                            },
+                            e => e,
+                        })
+                }
+                None if properties.is_empty() => self.mosq.publish(topic, payload, qos, retain),
+                None => self
+                    .mosq
+                    .publish_with_properties(topic, payload, qos, retain, properties),
+            };
+            drop(aliases);
+
+            let mut mids = handlers.mids.lock().unwrap();
+            let mut in_order = handlers.in_order.lock().unwrap();
+            let mut offline = handlers.offline_queue.lock().unwrap();
+            if let (Some(queue), Some(id)) = (offline.as_mut(), queued) {
+                match &result {
+                    Ok(mid) => queue.sending(id, *mid),
+                    // Leave the entry to be sent when the client next connects
+                    Err(err) if err.is_retryable() => queue.release(id),
+                    Err(_) => {
+                        let _ = queue.discard(id);
+                    }
+                }
+            }
+            drop(offline);
+            let early = handlers
+                .early_acks
+                .lock()
+                .unwrap()
+                .end(result.as_ref().ok().copied());
+            let mid = result?;
+            mids.insert(mid, tx);
+            if let Some(limit) = handlers.inflight_limit.lock().unwrap().as_mut() {
+                limit.held.insert(mid);
+            }
+            if let Some(key) = order_key {
+                let (done, wait) = bounded(1);
+                in_order.insert(
+                    key.to_string(),
+                    InOrderPublish {
+                        mid,
+                        _done: done,
+                        wait,
+                    },
+                );
+            }
+            drop(in_order);
+            drop(mids);
+            handlers.traffic.record_sent(topic, payload.len(), qos);
+            if let Some(reason) = early {
+                if !handlers.publish_acknowledged(mid, reason) {
+                    let _ = self.mosq.disconnect();
+                }
+            }
+            mid
+        };
+
+        let guard = AckGuard::new(&self.mosq, mid);
+        let reason = match before(deadline, rx.recv()).await {
+            Some(reason) => {
+                guard.disarm();
+                reason.map_err(|_| Error::InvalidArgument.publishing(topic, Some(mid)))?
+            }
+            None => {
+                guard.disarm();
+                // The acknowledgement may have arrived in the meantime
+                if self.mosq.get_callbacks().abandon(mid) {
+                    return Err(Error::Timeout.publishing(topic, Some(mid)));
+                }
+                rx.try_recv()
+                    .map_err(|_| Error::InvalidArgument.publishing(topic, Some(mid)))?
+            }
+        };
+        if reason.is_error() {
+            return Err(Error::Reason(reason).publishing(topic, Some(mid)));
+        }
+
+        Ok(mid)
+    }
+
+    /// Returns a [Subscriber](struct.Subscriber.html) that yields
+    /// messages from topics that this client has subscribed to.
+    /// This method can be called only once; the first time it returns
+    /// the subscriber and subsequently it no longer has the channel
+    /// receiver to retur, so will yield None.
+    pub fn subscriber(&mut self) -> Option<Subscriber> {
+        let handlers = self.mosq.get_callbacks();
+        let x = handlers.subscriber_rx.lock().unwrap().take();
+        x.map(|rx| Subscriber::new(rx).with_budget(Arc::clone(&handlers.memory)))
+    }
+
+    /// Pause `subscriber`, as for [Subscriber::pause](struct.Subscriber.html#method.pause),
+    /// and, if `policy` is `PausePolicy::Unsubscribe`, unsubscribe from
+    /// each of the subscriptions of the client, so that the broker stops
+    /// sending messages while the consumer performs maintenance.
+    /// The options of the subscriptions are kept, to be used by
+    /// [resume_delivery](#method.resume_delivery).
+    ///
+    /// The unsubscriptions are sent without waiting for the broker to
+    /// acknowledge them.
+    /// They include any made internally, such as the one that receives
+    /// replies to [request](#method.request).
+    pub fn pause_delivery(
+        &self,
+        subscriber: &Subscriber,
+        policy: PausePolicy,
+    ) -> Result<(), Error> {
+        subscriber.pause();
+        if policy == PausePolicy::Unsubscribe {
+            let handlers = self.mosq.get_callbacks();
+            let subscriptions: Vec<(String, SubscribeOptions)> = handlers
+                .subscriptions
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(pattern, options)| (pattern.clone(), *options))
+                .collect();
+            for (pattern, options) in subscriptions {
+                let qos = handlers
+                    .granted_qos
+                    .lock()
+                    .unwrap()
+                    .get(&pattern)
+                    .copied()
+                    .unwrap_or(QoS::AtMostOnce);
+                self.forget_subscription(&pattern)?;
+                handlers
+                    .paused_subscriptions
+                    .lock()
+                    .unwrap()
+                    .push((pattern, qos, options));
+            }
+        }
+        Ok(())
+    }
+
+    /// Subscribe again to the subscriptions removed by
+    /// [pause_delivery](#method.pause_delivery), waiting for the broker
+    /// to acknowledge each one, then resume `subscriber`.
+    ///
+    /// If a subscription fails, its error is returned and the
+    /// subscriber is left paused; the subscriptions that haven't been
+    /// made again are kept, so that this can be retried.
+    pub async fn resume_delivery(&self, subscriber: &Subscriber) -> Result<(), Error> {
+        loop {
+            let next = self
+                .mosq
+                .get_callbacks()
+                .paused_subscriptions
+                .lock()
+                .unwrap()
+                .first()
+                .cloned();
+            let (pattern, qos, options) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            self.subscribe_with_options(&pattern, qos, options).await?;
+            self.mosq
+                .get_callbacks()
+                .paused_subscriptions
+                .lock()
+                .unwrap()
+                .retain(|(p, _, _)| *p != pattern);
+        }
+        subscriber.resume();
+        Ok(())
+    }
+
+    /// Returns a receiver that yields every received message whose
+    /// topic matches `pattern`, so that several independent tasks can
+    /// observe the same messages without each subscribing separately.
+    /// Further receivers can be created by calling this method again
+    /// with the same pattern, or via
+    /// [BroadcastReceiver::resubscribe](struct.BroadcastReceiver.html#method.resubscribe).
     ///
-    /// connect completes when the broker acknowledges the CONNECT
-    /// command.
+    /// Each receiver queues up to `capacity` messages; see
+    /// [BroadcastReceiver](struct.BroadcastReceiver.html) for what happens
+    /// when a receiver falls behind.
     ///
-    /// Yields the connection return code; if the status was rejected,
-    /// then an Error::RejectedConnection() variant will be returned
-    /// so that you don't have to manually check the success.
-    pub async fn connect(
-        &mut self,
-        host: &str,
-        port: c_int,
-        keep_alive_interval: Duration,
-        bind_address: Option<&str>,
-    ) -> Result<ConnectionStatus, Error> {
+    /// This doesn't subscribe to `pattern`; that must be done via
+    /// [subscribe](#method.subscribe) as usual.
+    /// While any receivers remain, matching messages are delivered to
+    /// them instead of to the [subscriber](#method.subscriber) channel.
+    pub fn broadcast(&self, pattern: &str, capacity: usize) -> BroadcastReceiver {
         let handlers = self.mosq.get_callbacks();
-        let (tx, rx) = bounded(1);
-        handlers.connect.lock().unwrap().replace(tx);
-        self.mosq
-            .connect(host, port, keep_alive_interval, bind_address)?;
-        let rc = rx
-            .recv()
-            .await
-            .map_err(|_| Error::Mosq(mosq_err_t::MOSQ_ERR_INVAL))?;
-        if !rc.is_successful() {
-            Err(Error::RejectedConnection(rc))
-        } else {
-            Ok(rc)
+        let mut broadcasts = handlers.broadcasts.lock().unwrap();
+        match broadcasts.iter().find(|b| b.pattern() == pattern) {
+            Some(broadcast) => broadcast.subscribe(capacity),
+            None => {
+                let broadcast = Broadcast::new(pattern);
+                let receiver = broadcast.subscribe(capacity);
+                broadcasts.push(broadcast);
+                receiver
+            }
         }
     }
 
-    /// Publish a message to the specified topic.
+    /// Subscribe to `pattern`, keeping only the most recent message of
+    /// each matching topic; see [LatestValues](struct.LatestValues.html).
     ///
-    /// The payload size can be 0-283, 435 or 455 bytes; other values
-    /// will generate an error result.
+    /// Messages that match the pattern of a watch are recorded by each
+    /// matching watch, and are not delivered to [broadcast](#method.broadcast)
+    /// receivers or the [subscriber](#method.subscriber) channel.
+    /// A watch is removed once its `LatestValues` has been dropped.
+    pub async fn watch(&self, pattern: &str, qos: QoS) -> Result<LatestValues, Error> {
+        sub_topic_check(pattern)?;
+        let (watch, latest) = Watch::new(pattern);
+        self.mosq
+            .get_callbacks()
+            .watches
+            .lock()
+            .unwrap()
+            .push(watch);
+        self.subscribe(pattern, qos).await?;
+        Ok(latest)
+    }
+
+    /// Load the retained state of the topics that match `pattern`, such
+    /// as a tree of configuration values under `config/#`.
     ///
-    /// `retain` will set the message to be retained by the broker,
-    /// and delivered to new subscribers.
+    /// This subscribes to `pattern`, collects the retained messages that
+    /// the broker sends in response until none has arrived for `quiet`,
+    /// then unsubscribes again, and returns the most recent retained
+    /// message of each topic; see [TopicTree](struct.TopicTree.html).
     ///
-    /// Returns the assigned MessageId value for the publish.
-    /// The publish may not complete immediately.
-    /// You can use [set_callbacks](#method.set_callbacks) to register
-    /// an `on_publish` event to determine when it completes.
-    pub async fn publish(
-        &mut self,
-        topic: &str,
-        payload: &[u8],
+    /// While the snapshot is being collected, all of the messages that
+    /// match `pattern` are consumed by it, so it shouldn't overlap with
+    /// other subscriptions of the client.
+    pub async fn snapshot_retained(
+        &self,
+        pattern: &str,
+        quiet: Duration,
+    ) -> Result<TopicTree<Message>, Error> {
+        sub_topic_check(pattern)?;
+        let filter = pattern.to_string();
+        let subscriber = self.filtered_subscriber(
+            move |message: &Message| topic_matches_sub(&filter, &message.topic).unwrap_or(false),
+            None,
+        );
+        let mut guard = TemporarySubscription {
+            client: self,
+            pattern,
+            armed: true,
+        };
+        self.subscribe(pattern, QoS::AtLeastOnce).await?;
+
+        let mut retained = TopicTree::new();
+        loop {
+            let next = async { subscriber.recv().await.ok() };
+            let timeout = async {
+                async_io::Timer::after(quiet).await;
+                None
+            };
+            match next.or(timeout).await {
+                Some(message) if message.retain => {
+                    let topic = message.topic.clone();
+                    retained.insert(&topic, message);
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+
+        guard.armed = false;
+        self.forget_subscription(pattern)?;
+        Ok(retained)
+    }
+
+    /// Establish subscriptions to each of `patterns`, with the same QoS
+    /// and options, in a single request, completing once the broker has
+    /// acknowledged it.
+    /// This is much cheaper than subscribing to hundreds of filters one
+    /// at a time.
+    ///
+    /// Each pattern is handled as for
+    /// [subscribe_with_options](#method.subscribe_with_options); if the
+    /// broker downgrades any of them and
+    /// [set_strict_subscribe_qos](#method.set_strict_subscribe_qos) is enabled, the error
+    /// describes the first, but the others are still subscribed.
+    pub async fn subscribe_multiple(
+        &self,
+        patterns: &[&str],
         qos: QoS,
-        retain: bool,
-    ) -> Result<MessageId, Error> {
+        mut options: SubscribeOptions,
+    ) -> Result<(), Error> {
         let (tx, rx) = bounded(1);
+        let (qos, mid) = {
+            let handlers = self.mosq.get_callbacks();
+            let mut qos_for_all = qos;
+            for pattern in patterns {
+                if let Some(caps) = handlers.capabilities.lock().unwrap().as_ref() {
+                    caps.check_subscription(pattern)
+                        .map_err(|e| e.subscribing(pattern))?;
+                }
+                qos_for_all = handlers.negotiate_qos(pattern, qos);
+            }
+            if let Some(echoes) = handlers.echo_filter.lock().unwrap().as_mut() {
+                options.no_local = true;
+                echoes
+                    .patterns
+                    .extend(patterns.iter().map(|p| p.to_string()));
+            }
+            // Lock the map before we send, so that we can guarantee to
+            // win the race with populating the map vs. signalling completion
+            let mut batches = handlers.subscribe_batches.lock().unwrap();
+            let mid = self
+                .mosq
+                .subscribe_multiple(patterns, qos_for_all, options)?;
+            batches.insert(mid, tx);
+            let mut subscriptions = handlers.subscriptions.lock().unwrap();
+            for pattern in patterns {
+                subscriptions.insert(pattern.to_string(), options);
+            }
+            (qos_for_all, mid)
+        };
 
-        {
+        let guard = AckGuard::new(&self.mosq, mid);
+        let granted = rx.recv().await;
+        guard.disarm();
+        let granted =
+            granted.map_err(|_| Error::InvalidArgument.subscribing(&patterns.join(", ")))?;
+        let mut result = Ok(());
+        for (pattern, granted) in patterns.iter().zip(granted) {
+            let recorded = self.record_granted_qos(pattern, qos, granted);
+            if result.is_ok() {
+                result = recorded;
+            }
+        }
+        result
+    }
+
+    /// Remove the subscriptions for each of `patterns` in a single
+    /// request, completing once the broker has acknowledged it.
+    pub async fn unsubscribe_multiple(&self, patterns: &[&str]) -> Result<(), Error> {
+        let (tx, rx) = bounded(1);
+        let mid = {
             let handlers = self.mosq.get_callbacks();
             // Lock the map before we send, so that we can guarantee to
             // win the race with populating the map vs. signalling completion
             let mut mids = handlers.mids.lock().unwrap();
-            let mid = self.mosq.publish(topic, payload, qos, retain)?;
+            let mid = self.mosq.unsubscribe_multiple(patterns)?;
             mids.insert(mid, tx);
+            mid
+        };
+        for pattern in patterns {
+            self.forget_subscription_state(pattern);
         }
+        let guard = AckGuard::new(&self.mosq, mid);
+        let result = rx.recv().await;
+        guard.disarm();
+        result.map_err(|_| Error::InvalidArgument.subscribing(&patterns.join(", ")))?;
+        Ok(())
+    }
 
-        let mid = rx
-            .recv()
+    /// Remove the subscription for `pattern`, completing once the
+    /// broker has acknowledged the request.
+    /// Messages that match the subscription may still arrive until
+    /// then.
+    pub async fn unsubscribe(&self, pattern: &str) -> Result<(), Error> {
+        self.unsubscribe_with_properties(pattern, &Properties::new())
             .await
-            .map_err(|_| Error::Mosq(mosq_err_t::MOSQ_ERR_INVAL))?;
+    }
 
-        Ok(mid)
+    /// Remove the subscription for `pattern`, as for
+    /// [unsubscribe](#method.unsubscribe), attaching MQTT 5 properties,
+    /// such as User Properties, to the request.
+    /// The properties are ignored when connected using an earlier
+    /// version of the protocol.
+    pub async fn unsubscribe_with_properties(
+        &self,
+        pattern: &str,
+        properties: &Properties,
+    ) -> Result<(), Error> {
+        let (tx, rx) = bounded(1);
+        let mid = {
+            let handlers = self.mosq.get_callbacks();
+            // Lock the map before we send, so that we can guarantee to
+            // win the race with populating the map vs. signalling completion
+            let mut mids = handlers.mids.lock().unwrap();
+            let mid = if properties.is_empty() {
+                self.mosq.unsubscribe(pattern)?
+            } else {
+                self.mosq.unsubscribe_with_properties(pattern, properties)?
+            };
+            mids.insert(mid, tx);
+            mid
+        };
+        self.forget_subscription_state(pattern);
+        let guard = AckGuard::new(&self.mosq, mid);
+        let result = rx.recv().await;
+        guard.disarm();
+        result.map_err(|_| Error::InvalidArgument.subscribing(pattern))?;
+        Ok(())
     }
 
-    /// Returns a channel that yields messages from topics that this
-    /// client has subscribed to.
-    /// This method can be called only once; the first time it returns
-    /// the channel and subsequently it no longer has the channel
-    /// receiver to retur, so will yield None.
-    pub fn subscriber(&mut self) -> Option<Receiver<Message>> {
+    /// Remove the subscription for `pattern`, which was made for
+    /// internal use, without waiting for the broker to acknowledge it
+    fn forget_subscription(&self, pattern: &str) -> Result<(), Error> {
+        // Even if the request can't be sent, the subscription mustn't
+        // be re-established after a reconnection
+        self.forget_subscription_state(pattern);
+        self.mosq.unsubscribe(pattern)?;
+        Ok(())
+    }
+
+    /// Discard what the client knows about the subscription for `pattern`
+    fn forget_subscription_state(&self, pattern: &str) {
         let handlers = self.mosq.get_callbacks();
-        let x = handlers.subscriber_rx.lock().unwrap().take();
-        x
+        handlers.subscriptions.lock().unwrap().remove(pattern);
+        handlers.granted_qos.lock().unwrap().remove(pattern);
+        let mut echoes = handlers.echo_filter.lock().unwrap();
+        if let Some(echoes) = echoes.as_mut() {
+            echoes.patterns.remove(pattern);
+        }
+    }
+
+    /// Returns a [Subscriber](struct.Subscriber.html) with a queue of
+    /// its own, which receives the messages that match `filter`; see
+    /// [MessageFilter](trait.MessageFilter.html).
+    /// Unlike [Subscriber::filter_topic](struct.Subscriber.html#method.filter_topic),
+    /// the filter is evaluated before messages are queued, so messages
+    /// that don't match take up no space in the queue.
+    ///
+    /// The queue holds up to `capacity` messages, or is unbounded if
+    /// `capacity` is `None`; when it is full, newly received messages
+    /// are passed to the [dead_letters](#method.dead_letters) channel.
+    ///
+    /// Messages that match any filter are delivered to each matching
+    /// subscriber, and not to [broadcast](#method.broadcast) receivers or
+    /// the [subscriber](#method.subscriber) channel.
+    /// A filter is removed once its subscriber, and all of its clones,
+    /// have been dropped.
+    pub fn filtered_subscriber<F: MessageFilter + 'static>(
+        &self,
+        filter: F,
+        capacity: Option<usize>,
+    ) -> Subscriber {
+        let (tx, rx) = match capacity {
+            Some(capacity) => bounded(capacity.max(1)),
+            None => unbounded(),
+        };
+        let handlers = self.mosq.get_callbacks();
+        handlers.filtered.lock().unwrap().push(FilteredQueue {
+            filter: Box::new(filter),
+            tx,
+        });
+        Subscriber::new(rx).with_budget(Arc::clone(&handlers.memory))
+    }
+
+    /// Subscribe to the messages of type `M`, which are decoded as they
+    /// are received; see [MqttMessage](trait.MqttMessage.html).
+    /// The messages are delivered via their own queue, as for
+    /// [filtered_subscriber](#method.filtered_subscriber).
+    pub async fn subscribe_messages<M: MqttMessage>(
+        &mut self,
+    ) -> Result<TypedSubscriber<M>, Error> {
+        let pattern = M::subscription_pattern();
+        let filter = pattern.clone();
+        let subscriber = self.filtered_subscriber(
+            move |message: &Message| topic_matches_sub(&filter, &message.topic).unwrap_or(false),
+            None,
+        );
+        self.subscribe(&pattern, M::QOS).await?;
+        Ok(TypedSubscriber::new(subscriber))
+    }
+
+    /// Returns a view of the client that is confined to the topics
+    /// beneath `prefix`, such as `tenant/device123/`; see
+    /// [ScopedClient](struct.ScopedClient.html).
+    /// A `/` is appended to `prefix` if it doesn't end with one.
+    ///
+    /// Fails with `Error::InvalidArgument` if `prefix` is empty or
+    /// contains a wildcard.
+    pub fn scoped(&self, prefix: &str) -> Result<ScopedClient<'_>, Error> {
+        let prefix = normalize_prefix(prefix)?;
+        let patterns = Arc::new(ScopedPatterns::default());
+        let subscriber = self.filtered_subscriber(Arc::clone(&patterns), None);
+        Ok(ScopedClient::new(self, prefix, patterns, subscriber))
+    }
+
+    /// Subscribe to the status topics that match `pattern`, such as
+    /// `+/status`, and track which peers are online; see
+    /// [PresenceTracker](struct.PresenceTracker.html).
+    /// The status messages are delivered via their own queue, as for
+    /// [filtered_subscriber](#method.filtered_subscriber).
+    pub async fn track_presence(&self, pattern: &str) -> Result<PresenceTracker, Error> {
+        sub_topic_check(pattern)?;
+        let presence = Presence::new(pattern);
+        let subscriber = self.filtered_subscriber(Arc::clone(&presence), None);
+        self.subscribe(pattern, QoS::AtLeastOnce).await?;
+        Ok(PresenceTracker::new(presence, subscriber))
     }
 
     /// Establish a subscription to topics matching pattern.
     /// The messages will be delivered via the channel returned
     /// via the [subscriber](#method.subscriber) method.
     pub async fn subscribe(&self, pattern: &str, qos: QoS) -> Result<(), Error> {
-        let (tx, rx) = bounded(1);
+        self.subscribe_with_options(pattern, qos, SubscribeOptions::default())
+            .await
+    }
+
+    /// Establish a subscription to topics matching pattern, using
+    /// MQTT 5 subscription options.
+    /// The options are ignored when connected using an earlier version
+    /// of the protocol.
+    ///
+    /// Messages that match a subscription made with `retain_as_published`
+    /// have their `retain_kind` set to `RetainKind::AsPublished` when the
+    /// retain flag is set.
+    ///
+    /// If the broker has advertised that it doesn't support wildcard
+    /// or shared subscriptions, subscribing to a pattern that uses them
+    /// fails with `Error::Unsupported` without contacting the broker.
+    ///
+    /// `no_local` is set regardless of `options` if
+    /// [set_auto_no_local](#method.set_auto_no_local) is in effect.
+    pub async fn subscribe_with_options(
+        &self,
+        pattern: &str,
+        qos: QoS,
+        options: SubscribeOptions,
+    ) -> Result<(), Error> {
+        self.subscribe_with_properties(pattern, qos, options, &Properties::new())
+            .await
+    }
 
+    /// Establish a subscription to topics matching pattern, as for
+    /// [subscribe_with_options](#method.subscribe_with_options), with
+    /// MQTT 5 properties attached to the request, such as a
+    /// Subscription Identifier, which the broker then attaches to the
+    /// matching messages, or User Properties.
+    /// The properties are ignored when connected using an earlier
+    /// version of the protocol.
+    ///
+    /// If the broker has advertised that it doesn't support
+    /// subscription identifiers, subscribing with one fails with
+    /// `Error::Unsupported` without contacting the broker.
+    pub async fn subscribe_with_properties(
+        &self,
+        pattern: &str,
+        qos: QoS,
+        mut options: SubscribeOptions,
+        properties: &Properties,
+    ) -> Result<(), Error> {
+        if let Some(echoes) = self
+            .mosq
+            .get_callbacks()
+            .echo_filter
+            .lock()
+            .unwrap()
+            .as_mut()
         {
+            options.no_local = true;
+            echoes.patterns.insert(pattern.to_string());
+        }
+        self.subscribe_with_exact_options(pattern, qos, options, properties)
+            .await
+    }
+
+    /// Subscribe as for [subscribe_with_properties](#method.subscribe_with_properties),
+    /// without setting `no_local` automatically, for subscriptions made
+    /// for internal use that receive the client's own publishes
+    async fn subscribe_with_exact_options(
+        &self,
+        pattern: &str,
+        qos: QoS,
+        options: SubscribeOptions,
+        properties: &Properties,
+    ) -> Result<(), Error> {
+        let (tx, rx) = bounded(1);
+
+        let (qos, mid) = {
             let handlers = self.mosq.get_callbacks();
+            if let Some(caps) = handlers.capabilities.lock().unwrap().as_ref() {
+                caps.check_subscription(pattern)
+                    .map_err(|e| e.subscribing(pattern))?;
+                if properties.subscription_identifiers().next().is_some() {
+                    caps.check_subscription_identifiers()
+                        .map_err(|e| e.subscribing(pattern))?;
+                }
+            }
+            let qos = handlers.negotiate_qos(pattern, qos);
             // Lock the map before we send, so that we can guarantee to
             // win the race with populating the map vs. signalling completion
             let mut mids = handlers.mids.lock().unwrap();
-            let mid = self.mosq.subscribe(pattern, qos)?;
+            let mid = self
+                .mosq
+                .subscribe_with_properties(pattern, qos, options, properties)?;
             mids.insert(mid, tx);
-        }
+            handlers
+                .subscriptions
+                .lock()
+                .unwrap()
+                .insert(pattern.to_string(), options);
+            (qos, mid)
+        };
 
-        let _ = rx
-            .recv()
-            .await
-            .map_err(|_| Error::Mosq(mosq_err_t::MOSQ_ERR_INVAL))?;
+        let guard = AckGuard::new(&self.mosq, mid);
+        let reason = rx.recv().await;
+        guard.disarm();
+        let reason = reason.map_err(|_| Error::InvalidArgument.subscribing(pattern))?;
+        let granted = match reason {
+            ReasonCode::GRANTED_QOS2 => QoS::ExactlyOnce,
+            ReasonCode::GRANTED_QOS1 => QoS::AtLeastOnce,
+            _ => QoS::AtMostOnce,
+        };
+        self.record_granted_qos(pattern, qos, granted)
+    }
+
+    /// Record the QoS that the broker granted to the subscription for
+    /// `pattern`, failing if it was downgraded and the client is strict
+    fn record_granted_qos(&self, pattern: &str, qos: QoS, granted: QoS) -> Result<(), Error> {
+        let handlers = self.mosq.get_callbacks();
+        handlers
+            .granted_qos
+            .lock()
+            .unwrap()
+            .insert(pattern.to_string(), granted);
+        if (granted as u8) < (qos as u8) {
+            handlers.emit(Event::QosDowngraded {
+                topic: pattern.to_string(),
+                requested: qos,
+                granted,
+            });
+            if *handlers.strict_qos.lock().unwrap() {
+                return Err(Error::SubscriptionDowngraded {
+                    requested: qos,
+                    granted,
+                }
+                .subscribing(pattern));
+            }
+        }
 
         Ok(())
     }
 
+    /// Returns the QoS that the broker granted to the subscription for
+    /// `pattern`, which may be lower than the QoS that was requested,
+    /// or `None` if there is no such subscription.
+    pub fn granted_qos(&self, pattern: &str) -> Option<QoS> {
+        self.mosq
+            .get_callbacks()
+            .granted_qos
+            .lock()
+            .unwrap()
+            .get(pattern)
+            .copied()
+    }
+
+    /// Configure whether subscriptions made from now on prevent the
+    /// client from receiving its own publishes, so that a device that
+    /// both publishes and subscribes to the same state topics doesn't
+    /// react to its own messages in an echo loop.
+    /// The default is `false`.
+    ///
+    /// When enabled, [subscribe](#method.subscribe) and the methods built
+    /// on it set the MQTT 5 NoLocal option.
+    /// Earlier versions of the protocol have no such option, so instead
+    /// the client remembers its recent publishes to topics that match
+    /// those subscriptions, and discards a received message with the
+    /// same topic and payload as one of them; a message published by
+    /// another client that happens to be identical is discarded too.
+    /// The protocol version is the one set via
+    /// [set_option](#method.set_option).
+    ///
+    /// Subscriptions that the client makes for its own use, such as for
+    /// [request](#method.request) and [self_test](#method.self_test),
+    /// are unaffected.
+    pub fn set_auto_no_local(&self, enabled: bool) {
+        let handlers = self.mosq.get_callbacks();
+        let mut echoes = handlers.echo_filter.lock().unwrap();
+        match (enabled, echoes.is_some()) {
+            (true, false) => *echoes = Some(EchoFilter::default()),
+            (false, _) => *echoes = None,
+            (true, true) => {}
+        }
+    }
+
+    /// Configure whether received QoS 1 messages that may be
+    /// redeliveries are flagged via `Message::possibly_duplicate`.
+    /// The default is `false`.
+    ///
+    /// libmosquitto doesn't expose the DUP flag of received messages,
+    /// so this is a lossy heuristic: a message is flagged when it has
+    /// the same message id, topic and payload as one of the last 64
+    /// QoS 1 messages.
+    /// The broker reuses message ids once they have been acknowledged,
+    /// so a legitimate repeat of a message, such as a periodic status
+    /// that hasn't changed, may also be flagged, while a redelivery
+    /// that arrives after 64 other messages is not.
+    /// Messages are flagged rather than discarded, so that the
+    /// application can decide what to do with them.
+    pub fn set_duplicate_detection(&self, enabled: bool) {
+        let handlers = self.mosq.get_callbacks();
+        let mut recent = handlers.recent_messages.lock().unwrap();
+        match (enabled, recent.is_some()) {
+            (true, false) => *recent = Some(RecentMessages::default()),
+            (false, _) => *recent = None,
+            (true, true) => {}
+        }
+    }
+
+    /// Configure whether [subscribe](#method.subscribe) fails with
+    /// `Error::SubscriptionDowngraded` when the broker grants a lower
+    /// QoS than was requested.
+    /// The subscription remains in place at the granted QoS, so the
+    /// caller can decide whether to keep it.
+    /// The default is `false`, in which case the downgrade is only
+    /// reported via [events](#method.events) as `Event::QosDowngraded`.
+    pub fn set_strict_subscribe_qos(&self, strict: bool) {
+        *self.mosq.get_callbacks().strict_qos.lock().unwrap() = strict;
+    }
+
     /// Set an option for the client.
     /// Most options need to be set prior to calling `connect` in order
     /// to have any effect.
     pub fn set_option(&self, option: &ClientOption) -> Result<(), Error> {
-        match option {
-            ClientOption::ProtocolVersion(v) => self
-                .mosq
-                .set_int_option(mosq_opt_t::MOSQ_OPT_PROTOCOL_VERSION, *v as c_int),
-            ClientOption::ReceiveMaximum(v) => self
-                .mosq
-                .set_int_option(mosq_opt_t::MOSQ_OPT_RECEIVE_MAXIMUM, *v as c_int),
-            ClientOption::SendMaximum(v) => self
-                .mosq
-                .set_int_option(mosq_opt_t::MOSQ_OPT_SEND_MAXIMUM, *v as c_int),
-            ClientOption::OcspRequired(v) => self.mosq.set_int_option(
+        let option_requested = option;
+        let (option, value) = match option {
+            ClientOption::ProtocolVersion(v) => (
+                mosq_opt_t::MOSQ_OPT_PROTOCOL_VERSION,
+                OptionValue::Int(*v as c_int),
+            ),
+            ClientOption::ReceiveMaximum(v) => (
+                mosq_opt_t::MOSQ_OPT_RECEIVE_MAXIMUM,
+                OptionValue::Int(*v as c_int),
+            ),
+            ClientOption::SendMaximum(v) => (
+                mosq_opt_t::MOSQ_OPT_SEND_MAXIMUM,
+                OptionValue::Int(*v as c_int),
+            ),
+            ClientOption::OcspRequired(v) => (
                 mosq_opt_t::MOSQ_OPT_TLS_OCSP_REQUIRED,
-                if *v { 1 } else { 0 },
+                OptionValue::Int(if *v { 1 } else { 0 }),
             ),
-            ClientOption::TlsEngine(e) => self
-                .mosq
-                .set_string_option(mosq_opt_t::MOSQ_OPT_TLS_ENGINE, e),
-            ClientOption::TlsKeyForm(e) => self
-                .mosq
-                .set_string_option(mosq_opt_t::MOSQ_OPT_TLS_KEYFORM, e),
-            ClientOption::TlsKPassSha1(e) => self
-                .mosq
-                .set_string_option(mosq_opt_t::MOSQ_OPT_TLS_ENGINE_KPASS_SHA1, e),
-            ClientOption::TlsALPN(e) => self
-                .mosq
-                .set_string_option(mosq_opt_t::MOSQ_OPT_TLS_ALPN, e),
+            ClientOption::TlsUseOsCerts(v) => (
+                mosq_opt_t::MOSQ_OPT_TLS_USE_OS_CERTS,
+                OptionValue::Int(if *v { 1 } else { 0 }),
+            ),
+            ClientOption::TlsEngine(e) => (
+                mosq_opt_t::MOSQ_OPT_TLS_ENGINE,
+                OptionValue::Str(e.to_string()),
+            ),
+            ClientOption::TlsKeyForm(e) => (
+                mosq_opt_t::MOSQ_OPT_TLS_KEYFORM,
+                OptionValue::Str(e.to_string()),
+            ),
+            ClientOption::TlsKPassSha1(e) => (
+                mosq_opt_t::MOSQ_OPT_TLS_ENGINE_KPASS_SHA1,
+                OptionValue::Str(e.to_string()),
+            ),
+            ClientOption::TlsALPN(e) => (
+                mosq_opt_t::MOSQ_OPT_TLS_ALPN,
+                OptionValue::Str(e.to_string()),
+            ),
+            ClientOption::TcpNoDelay(v) => (
+                mosq_opt_t::MOSQ_OPT_TCP_NODELAY,
+                OptionValue::Int(if *v { 1 } else { 0 }),
+            ),
+        };
+        match &value {
+            OptionValue::Int(v) => self.mosq.set_int_option(option, *v)?,
+            OptionValue::Str(v) => self.mosq.set_string_option(option, v)?,
+        }
+        if let ClientOption::ProtocolVersion(v) = option_requested {
+            *self.mosq.get_callbacks().protocol_version.lock().unwrap() = *v;
+        }
+
+        // Record the option, replacing any earlier value
+        let mut settings = self.settings.lock().unwrap();
+        settings.options.retain(|(o, _)| *o != option);
+        settings.options.push((option, value));
+        Ok(())
+    }
+
+    /// Configure whether the client preserves the order of QoS 1 and
+    /// QoS 2 publishes; see [PublishOrdering](enum.PublishOrdering.html).
+    ///
+    /// `PublishOrdering::Global` also limits the number of messages that
+    /// libmosquitto keeps in flight to 1, so that messages are retransmitted
+    /// in order after a reconnection; switching back to another mode
+    /// restores the limit to the default of 20.
+    /// This overrides `ClientOption::SendMaximum` and
+    /// [apply_tuning](#method.apply_tuning), so it should be called
+    /// after those.
+    pub fn set_publish_ordering(&self, ordering: PublishOrdering) -> Result<(), Error> {
+        let max_inflight = match ordering {
+            PublishOrdering::Global => 1,
+            _ => Tuning::default().max_inflight,
+        };
+        self.set_option(&ClientOption::SendMaximum(max_inflight))?;
+        *self.mosq.get_callbacks().ordering.lock().unwrap() = ordering;
+        Ok(())
+    }
+
+    /// Limit the number of publishes that have been handed to libmosquitto
+    /// but not yet acknowledged by the broker, or sent, for QoS 0.
+    /// Once the limit is reached, [publish](#method.publish) waits for an
+    /// earlier publish to complete before handing over its message, so
+    /// that a task that publishes faster than the broker accepts messages
+    /// cannot grow the memory held by libmosquitto without bound.
+    ///
+    /// Publishes that timed out or whose future was dropped count
+    /// towards the limit until libmosquitto completes them.
+    /// Messages held in the offline queue while the client is
+    /// disconnected are limited separately, via
+    /// [set_offline_queue_capacity](#method.set_offline_queue_capacity).
+    ///
+    /// This differs from `ClientOption::SendMaximum`, which limits the
+    /// number of messages that libmosquitto sends at once, but not the
+    /// number that it holds waiting to be sent.
+    ///
+    /// The default is `None`, which means that there is no limit.
+    /// A limit of zero is rejected with `Error::InvalidArgument`.
+    pub fn set_inflight_limit(&self, limit: Option<usize>) -> Result<(), Error> {
+        if limit == Some(0) {
+            return Err(Error::InvalidArgument);
+        }
+        let handlers = self.mosq.get_callbacks();
+        let mut inflight_limit = handlers.inflight_limit.lock().unwrap();
+        match (limit, inflight_limit.as_mut()) {
+            (Some(limit), Some(existing)) => {
+                existing.limit = limit;
+                let _ = existing.released.0.try_send(());
+            }
+            (Some(limit), None) => {
+                inflight_limit.replace(InflightLimit::new(limit));
+            }
+            (None, _) => {
+                inflight_limit.take();
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply a set of performance related settings to the client.
+    /// See [Tuning](struct.Tuning.html) for the available presets.
+    ///
+    /// This should be called prior to `connect`.
+    /// The subscriber queue capacity can only be changed before
+    /// the channel has been obtained via [subscriber](#method.subscriber);
+    /// calling this method afterwards will leave the existing
+    /// queue unchanged.
+    pub fn apply_tuning(&self, tuning: &Tuning) -> Result<(), Error> {
+        self.set_option(&ClientOption::TcpNoDelay(tuning.tcp_nodelay))?;
+        self.set_option(&ClientOption::SendMaximum(tuning.max_inflight))?;
+
+        let handlers = self.mosq.get_callbacks();
+        let mut subscriber_rx = handlers.subscriber_rx.lock().unwrap();
+        if subscriber_rx.is_some() {
+            let (tx, rx) = match tuning.subscriber_queue_capacity {
+                Some(capacity) => bounded(capacity.max(1)),
+                None => unbounded(),
+            };
+            *handlers.subscriber_tx.lock().unwrap() = tx;
+            *handlers.subscriber_drain.lock().unwrap() = rx.clone();
+            subscriber_rx.replace(rx);
         }
+        Ok(())
     }
 
     /// Configures the TLS parameters for the client.
@@ -373,8 +4610,55 @@ impl Client {
         CERTFILE: AsRef<Path>,
         KEYFILE: AsRef<Path>,
     {
-        self.mosq
-            .configure_tls(ca_file, ca_path, cert_file, key_file, pw_callback)
+        let use_os_certs = self.settings.lock().unwrap().options.iter().any(|(o, v)| {
+            *o == mosq_opt_t::MOSQ_OPT_TLS_USE_OS_CERTS && matches!(v, OptionValue::Int(1))
+        });
+        let mut tls = TlsConfig::new()
+            .with_raw_password_callback(pw_callback)
+            .with_os_certs(use_os_certs);
+        if let Some(ca_file) = ca_file {
+            tls = tls.with_ca_file(ca_file);
+        }
+        if let Some(ca_path) = ca_path {
+            tls = tls.with_ca_path(ca_path);
+        }
+        match (cert_file, key_file) {
+            (Some(cert_file), Some(key_file)) => {
+                tls = tls.with_client_certificate(cert_file, key_file);
+            }
+            (None, None) => {}
+            _ => return Err(Error::InvalidArgument),
+        }
+        self.set_tls(tls)
+    }
+
+    /// Configures the certificates and keys used to connect to the
+    /// broker over TLS; see [TlsConfig](struct.TlsConfig.html).
+    /// The configuration is kept if the client is reinitialised by
+    /// [set_clean_session](#method.set_clean_session).
+    pub fn set_tls(&self, tls: TlsConfig) -> Result<(), Error> {
+        self.mosq.set_tls(&tls)?;
+        self.settings.lock().unwrap().tls = Some(tls);
+        Ok(())
+    }
+
+    /// Configures the client to connect using TLS with a pre-shared
+    /// key; see [Mosq::set_tls_psk](struct.Mosq.html#method.set_tls_psk).
+    /// The key is kept if the client is reinitialised by
+    /// [set_clean_session](#method.set_clean_session).
+    pub fn set_tls_psk(
+        &self,
+        identity: &str,
+        psk_hex: &str,
+        ciphers: Option<&str>,
+    ) -> Result<(), Error> {
+        self.mosq.set_tls_psk(identity, psk_hex, ciphers)?;
+        self.settings.lock().unwrap().tls_psk = Some((
+            identity.to_string(),
+            psk_hex.to_string(),
+            ciphers.map(String::from),
+        ));
+        Ok(())
     }
 
     /// Controls reconnection behavior when running in the message loop.
@@ -403,6 +4687,182 @@ impl Client {
             reconnect_delay,
             max_reconnect_delay,
             use_exponential_backoff,
-        )
+        )?;
+        self.settings.lock().unwrap().reconnect_delay = Some((
+            reconnect_delay,
+            max_reconnect_delay,
+            use_exponential_backoff,
+        ));
+        Ok(())
+    }
+}
+
+/// Await `future`, giving up and returning `None` at `deadline`, if any
+async fn before<F: std::future::Future>(deadline: Option<Instant>, future: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => {
+            async { Some(future.await) }
+                .or(async {
+                    async_io::Timer::at(deadline).await;
+                    None
+                })
+                .await
+        }
+        None => Some(future.await),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_lite::future::{block_on, poll_once};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    /// Reads an MQTT packet from `stream`, returning false at the end
+    /// of the stream
+    fn read_packet(stream: &mut TcpStream) -> bool {
+        let mut byte = [0u8];
+        if stream.read_exact(&mut byte).is_err() {
+            return false;
+        }
+        let mut remaining = 0usize;
+        for shift in (0..28).step_by(7) {
+            if stream.read_exact(&mut byte).is_err() {
+                return false;
+            }
+            remaining |= usize::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0u8; remaining];
+        stream.read_exact(&mut body).is_ok()
+    }
+
+    /// Starts a broker that accepts a single connection, and then
+    /// acknowledges its CONNECT if `accept` is true, but nothing else,
+    /// so that the requests of the client remain pending
+    fn silent_broker(accept: bool) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            if read_packet(&mut stream) && accept {
+                let _ = stream.write_all(&[0x20, 2, 0, 0]);
+            }
+            while read_packet(&mut stream) {}
+        });
+        addr
+    }
+
+    /// Returns a client that is connected to a `silent_broker`
+    fn connected_client() -> Client {
+        let addr = silent_broker(true);
+        let mut client = Client::with_auto_id().unwrap();
+        block_on(client.connect(
+            &addr.ip().to_string(),
+            addr.port().into(),
+            Duration::from_secs(60),
+            None,
+        ))
+        .unwrap();
+        client
+    }
+
+    #[test]
+    fn cancelled_publish() {
+        let mut client = connected_client();
+        {
+            let mut publish = Box::pin(client.publish("a/b", b"payload", QoS::AtLeastOnce, false));
+            assert!(block_on(poll_once(&mut publish)).is_none());
+        }
+
+        let handlers = client.mosq.get_callbacks();
+        assert!(handlers.mids.lock().unwrap().is_empty());
+        let mid = {
+            let abandoned = handlers.abandoned.lock().unwrap();
+            assert_eq!(abandoned.len(), 1);
+            *abandoned.iter().next().unwrap()
+        };
+        // The late acknowledgement is ignored, but only once
+        assert!(handlers.publish_acknowledged(mid, ReasonCode::SUCCESS));
+        assert!(handlers.abandoned.lock().unwrap().is_empty());
+        assert!(!handlers.publish_acknowledged(mid, ReasonCode::SUCCESS));
+    }
+
+    #[test]
+    fn cancelled_subscribe() {
+        let client = connected_client();
+        {
+            let mut subscribe = Box::pin(client.subscribe_with_options(
+                "a/#",
+                QoS::AtLeastOnce,
+                SubscribeOptions::default(),
+            ));
+            assert!(block_on(poll_once(&mut subscribe)).is_none());
+        }
+
+        let handlers = client.mosq.get_callbacks();
+        assert!(handlers.mids.lock().unwrap().is_empty());
+        let mid = {
+            let abandoned = handlers.abandoned.lock().unwrap();
+            assert_eq!(abandoned.len(), 1);
+            *abandoned.iter().next().unwrap()
+        };
+        // Safety: the client outlives the pointer
+        let mosq = unsafe { client.mosq.ptr() };
+        mosq.with_client(|m| handlers.on_subscribe(m, mid, &[QoS::AtLeastOnce]));
+        assert!(handlers.abandoned.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn cancelled_connect() {
+        let addr = silent_broker(false);
+        let client = Client::with_auto_id().unwrap();
+        let rx = client
+            .begin_connect(
+                &addr.ip().to_string(),
+                addr.port().into(),
+                Duration::from_secs(60),
+                None,
+            )
+            .unwrap();
+        {
+            let mut connect = Box::pin(client.complete_connect(rx));
+            assert!(block_on(poll_once(&mut connect)).is_none());
+        }
+
+        assert!(client
+            .mosq
+            .get_callbacks()
+            .connect
+            .lock()
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn cancelled_snapshot() {
+        let client = connected_client();
+        {
+            let mut snapshot = Box::pin(client.snapshot_retained("a/#", Duration::from_secs(60)));
+            assert!(block_on(poll_once(&mut snapshot)).is_none());
+            assert!(client
+                .mosq
+                .get_callbacks()
+                .subscriptions
+                .lock()
+                .unwrap()
+                .contains_key("a/#"));
+        }
+
+        assert!(!client
+            .mosq
+            .get_callbacks()
+            .subscriptions
+            .lock()
+            .unwrap()
+            .contains_key("a/#"));
     }
 }
@@ -0,0 +1,327 @@
+use crate::{Client, Error, LastWill, Message, QoS, Subscriber};
+use futures_lite::FutureExt;
+use std::os::raw::c_int;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The outcome of one of the checks made by
+/// [check_conformance](fn.check_conformance.html)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The behavior is supported
+    Passed,
+    /// The behavior is not supported; the string explains what
+    /// went wrong
+    Failed(String),
+    /// The check was not made; the string explains why
+    Skipped(String),
+}
+
+/// The result of one of the checks made by
+/// [check_conformance](fn.check_conformance.html)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    /// A short description of the behavior that was checked
+    pub name: &'static str,
+    pub outcome: Outcome,
+}
+
+/// Describes which behaviors a broker supports, as produced by
+/// [check_conformance](fn.check_conformance.html).
+/// The `Display` implementation renders a line per check.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConformanceReport {
+    pub results: Vec<CheckResult>,
+}
+
+impl ConformanceReport {
+    /// Returns true if none of the checks failed
+    pub fn all_passed(&self) -> bool {
+        !self
+            .results
+            .iter()
+            .any(|r| matches!(r.outcome, Outcome::Failed(_)))
+    }
+
+    fn record(&mut self, name: &'static str, outcome: Result<(), String>) {
+        self.results.push(CheckResult {
+            name,
+            outcome: match outcome {
+                Ok(()) => Outcome::Passed,
+                Err(reason) => Outcome::Failed(reason),
+            },
+        });
+    }
+}
+
+impl std::fmt::Display for ConformanceReport {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for result in &self.results {
+            match &result.outcome {
+                Outcome::Passed => writeln!(fmt, "PASS {}", result.name)?,
+                Outcome::Failed(reason) => writeln!(fmt, "FAIL {}: {}", result.name, reason)?,
+                Outcome::Skipped(reason) => writeln!(fmt, "SKIP {}: {}", result.name, reason)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Describes the broker to be checked by
+/// [check_conformance](fn.check_conformance.html)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceOptions {
+    pub host: String,
+    pub port: c_int,
+    pub keep_alive_interval: Duration,
+    /// How long to wait for each message to arrive
+    pub timeout: Duration,
+    /// The topics used by the checks are placed beneath this prefix,
+    /// along with a component that is unique to each run
+    pub topic_prefix: String,
+    /// The size of the payload used to check large messages
+    pub large_payload_size: usize,
+}
+
+impl ConformanceOptions {
+    /// Check the broker at `host` and `port`, with the remaining
+    /// options set to their defaults
+    pub fn new(host: &str, port: c_int) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            keep_alive_interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(5),
+            topic_prefix: "mosquitto-rs/conformance".to_string(),
+            large_payload_size: 256 * 1024,
+        }
+    }
+}
+
+/// Exercise a broker using a pair of clients, and report which
+/// behaviors the combination of the broker and this client supports.
+/// This is useful for validating a new broker deployment.
+///
+/// The checks cover publishing and receiving at each QoS level,
+/// retained messages, large payloads, UTF-8 topics and will messages.
+/// Any retained messages that are published are cleared afterwards.
+///
+/// An error is returned only if the clients cannot connect; the
+/// outcome of each check is recorded in the report.
+///
+/// ```no_run
+/// use mosquitto_rs::*;
+///
+/// smol::block_on(async {
+///     let report = check_conformance(&ConformanceOptions::new("localhost", 1883)).await?;
+///     print!("{}", report);
+///     Ok::<(), Error>(())
+/// })?;
+/// # Ok::<(), Error>(())
+/// ```
+pub async fn check_conformance(options: &ConformanceOptions) -> Result<ConformanceReport, Error> {
+    let prefix = format!("{}/{}", options.topic_prefix, unique_id());
+    let (mut client, messages) = connect(options).await?;
+    let mut report = ConformanceReport::default();
+
+    for (name, qos) in [
+        ("publish and receive at QoS 0", QoS::AtMostOnce),
+        ("publish and receive at QoS 1", QoS::AtLeastOnce),
+        ("publish and receive at QoS 2", QoS::ExactlyOnce),
+    ] {
+        let topic = format!("{}/qos{}", prefix, qos as c_int);
+        let outcome = round_trip(&mut client, &messages, &topic, b"hello", qos, options).await;
+        report.record(name, outcome);
+    }
+
+    let payload: Vec<u8> = (0..options.large_payload_size)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    let topic = format!("{}/large", prefix);
+    let outcome = round_trip(
+        &mut client,
+        &messages,
+        &topic,
+        &payload,
+        QoS::AtLeastOnce,
+        options,
+    )
+    .await;
+    report.record("large payload", outcome);
+
+    let topic = format!("{}/utf8/ünïcødé/日本語", prefix);
+    let outcome = round_trip(
+        &mut client,
+        &messages,
+        &topic,
+        b"hello",
+        QoS::AtLeastOnce,
+        options,
+    )
+    .await;
+    report.record("UTF-8 topic", outcome);
+
+    let topic = format!("{}/retained", prefix);
+    let outcome = retained(&mut client, &topic, options).await;
+    report.record("retained message", outcome);
+
+    if cfg!(unix) {
+        let topic = format!("{}/will", prefix);
+        let outcome = will(&client, &messages, &topic, options).await;
+        report.record("will message", outcome);
+    } else {
+        report.results.push(CheckResult {
+            name: "will message",
+            outcome: Outcome::Skipped(
+                "severing the connection is only supported on unix systems".to_string(),
+            ),
+        });
+    }
+
+    Ok(report)
+}
+
+/// Returns a value that is unlikely to be shared with another run
+fn unique_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!("{}-{}", std::process::id(), nanos)
+}
+
+async fn connect(options: &ConformanceOptions) -> Result<(Client, Subscriber), Error> {
+    let mut client = Client::with_auto_id()?;
+    let messages = client.subscriber().ok_or(Error::InvalidArgument)?;
+    client
+        .connect(
+            &options.host,
+            options.port,
+            options.keep_alive_interval,
+            None,
+        )
+        .await?;
+    Ok((client, messages))
+}
+
+/// Wait for a message on `topic`, ignoring any others
+async fn expect(messages: &Subscriber, topic: &str, timeout: Duration) -> Result<Message, String> {
+    let receive = async {
+        loop {
+            match messages.recv().await {
+                Ok(message) if message.topic == topic => return Ok(message),
+                Ok(_) => {}
+                Err(_) => return Err("the client was disconnected".to_string()),
+            }
+        }
+    };
+    let timeout = async {
+        async_io::Timer::after(timeout).await;
+        Err(format!("no message was received within {:?}", timeout))
+    };
+    receive.or(timeout).await
+}
+
+async fn round_trip(
+    client: &mut Client,
+    messages: &Subscriber,
+    topic: &str,
+    payload: &[u8],
+    qos: QoS,
+    options: &ConformanceOptions,
+) -> Result<(), String> {
+    client
+        .subscribe(topic, qos)
+        .await
+        .map_err(|e| e.to_string())?;
+    client
+        .publish(topic, payload, qos, false)
+        .await
+        .map_err(|e| e.to_string())?;
+    let message = expect(messages, topic, options.timeout).await?;
+    if message.payload != payload {
+        return Err("the payload was altered".to_string());
+    }
+    if message.qos != qos {
+        return Err(format!("the message was delivered at {:?}", message.qos));
+    }
+    Ok(())
+}
+
+async fn retained(
+    client: &mut Client,
+    topic: &str,
+    options: &ConformanceOptions,
+) -> Result<(), String> {
+    client
+        .publish(topic, b"retained", QoS::AtLeastOnce, true)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let result = async {
+        let (late, messages) = connect(options).await.map_err(|e| e.to_string())?;
+        late.subscribe(topic, QoS::AtLeastOnce)
+            .await
+            .map_err(|e| e.to_string())?;
+        let message = expect(&messages, topic, options.timeout).await?;
+        if !message.retain {
+            return Err("the retain flag was not set".to_string());
+        }
+        if message.payload != b"retained" {
+            return Err("the payload was altered".to_string());
+        }
+        Ok(())
+    }
+    .await;
+
+    // Clear the retained message
+    let _ = client.publish(topic, b"", QoS::AtLeastOnce, true).await;
+    result
+}
+
+/// Check that the broker publishes the will of a client whose
+/// connection fails
+async fn will(
+    client: &Client,
+    messages: &Subscriber,
+    topic: &str,
+    options: &ConformanceOptions,
+) -> Result<(), String> {
+    client
+        .subscribe(topic, QoS::AtLeastOnce)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut doomed = Client::with_auto_id().map_err(|e| e.to_string())?;
+    doomed
+        .set_last_will(LastWill::new(topic, b"gone").with_qos(QoS::AtLeastOnce))
+        .map_err(|e| e.to_string())?;
+    doomed
+        .connect(
+            &options.host,
+            options.port,
+            options.keep_alive_interval,
+            None,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+    doomed.sever_connection();
+    let message = expect(messages, topic, options.timeout).await?;
+    if message.payload != b"gone" {
+        return Err("the payload was altered".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn report() {
+        let mut report = ConformanceReport::default();
+        report.record("a", Ok(()));
+        assert!(report.all_passed());
+        report.record("b", Err("broken".to_string()));
+        assert!(!report.all_passed());
+        assert_eq!(report.to_string(), "PASS a\nFAIL b: broken\n");
+    }
+}
@@ -0,0 +1,36 @@
+use crate::{BrokerCapabilities, ConnectionStatus, Properties};
+
+/// The broker's acknowledgement of a connection, as returned by
+/// [Client::connect](struct.Client.html#method.connect).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnAck {
+    /// The connection return code, or MQTT 5 reason code
+    pub reason: ConnectionStatus,
+    /// True if the broker resumed a session that was left by a previous
+    /// connection with the same client id, rather than starting a new one.
+    /// This is always false when `clean_session` is true.
+    pub session_present: bool,
+    /// The MQTT 5 properties of the CONNACK, which are empty when an
+    /// earlier version of the protocol is used
+    pub properties: Properties,
+    /// The capabilities that the broker advertised via `properties`, or
+    /// `None` if the connection was refused
+    pub broker_capabilities: Option<BrokerCapabilities>,
+}
+
+impl ConnAck {
+    /// Returns true if the connection was accepted
+    pub fn is_successful(&self) -> bool {
+        self.reason.is_successful()
+    }
+}
+
+impl std::fmt::Display for ConnAck {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "{}", self.reason)?;
+        if self.session_present {
+            write!(fmt, " (session present)")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use crate::lowlevel::{Callbacks, Mosq, MosqPtr};
+use crate::watchdog::{Activity, TimerThread};
+use crate::{Error, Event};
+use async_channel::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// A username and password with which to authenticate to the broker
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// When the credentials stop being valid, for example the `exp`
+    /// claim of a JWT.
+    /// This is used by
+    /// [Client::set_token_refresh](struct.Client.html#method.set_token_refresh)
+    /// to reconnect with fresh credentials before they expire.
+    pub expires_at: Option<SystemTime>,
+}
+
+/// A `CredentialsProvider` supplies the credentials that are used
+/// each time the client connects or reconnects to the broker.
+///
+/// This is useful with brokers that accept short-lived tokens, such
+/// as JWTs or SAS tokens, in place of a password, as a fresh token
+/// can be produced for each connection.
+///
+/// libmosquitto doesn't implement the MQTT 5 AUTH packet, so enhanced
+/// authentication, and re-authentication of a live connection with
+/// `MQTT_RC_REAUTHENTICATE`, are not available.  Brokers that require
+/// credentials to be rotated periodically can instead be accommodated
+/// by reconnecting with fresh credentials; see
+/// [Client::set_token_refresh](struct.Client.html#method.set_token_refresh).
+///
+/// Providers are installed via
+/// [Client::set_credentials_provider](struct.Client.html#method.set_credentials_provider).
+/// Any `Fn() -> Result<Credentials, Error>` closure can be used as
+/// a provider:
+///
+/// ```no_run
+/// use mosquitto_rs::*;
+///
+/// # fn mint_token() -> String { String::new() }
+/// let mut client = Client::with_auto_id()?;
+/// client.set_credentials_provider(|| {
+///     Ok(Credentials {
+///         username: Some("device-1".to_string()),
+///         password: Some(mint_token()),
+///         expires_at: None,
+///     })
+/// });
+/// # Ok::<(), Error>(())
+/// ```
+pub trait CredentialsProvider: Send + Sync {
+    /// Returns the credentials to use for the next connection attempt.
+    /// This is called from the libmosquitto loop thread when the client
+    /// reconnects automatically, so it should avoid blocking for long.
+    fn credentials(&self) -> Result<Credentials, Error>;
+}
+
+impl<F> CredentialsProvider for F
+where
+    F: Fn() -> Result<Credentials, Error> + Send + Sync,
+{
+    fn credentials(&self) -> Result<Credentials, Error> {
+        (self)()
+    }
+}
+
+/// The credentials provider of a client, along with the expiry
+/// time of the credentials that it most recently supplied
+#[derive(Default)]
+pub(crate) struct CredentialsState {
+    provider: Mutex<Option<Box<dyn CredentialsProvider>>>,
+    expires_at: Mutex<Option<SystemTime>>,
+}
+
+impl CredentialsState {
+    pub fn set_provider(&self, provider: Option<Box<dyn CredentialsProvider>>) {
+        *self.provider.lock().unwrap() = provider;
+        self.expires_at.lock().unwrap().take();
+    }
+
+    /// Applies fresh credentials from the provider, if there is one
+    pub fn refresh<CB: Callbacks>(&self, client: &Mosq<CB>) -> Result<(), Error> {
+        if let Some(provider) = self.provider.lock().unwrap().as_ref() {
+            let credentials = provider.credentials()?;
+            client.set_username_and_password(
+                credentials.username.as_deref(),
+                credentials.password.as_deref(),
+            )?;
+            *self.expires_at.lock().unwrap() = credentials.expires_at;
+        }
+        Ok(())
+    }
+}
+
+impl TimerThread {
+    /// The longest that the token refresh thread sleeps before checking
+    /// whether the expiry time has changed
+    const RECHECK: Duration = Duration::from_secs(60);
+
+    /// Start a thread that reconnects with fresh credentials `margin`
+    /// before the current credentials expire.
+    /// The owner of `mosq` must drop the returned thread before
+    /// dropping the client.
+    pub fn token_refresh(
+        margin: Duration,
+        credentials: Arc<CredentialsState>,
+        activity: Arc<Activity>,
+        events: Arc<Mutex<Option<Sender<Event>>>>,
+        mosq: MosqPtr,
+    ) -> Self {
+        Self::spawn(move || {
+            let expires_at = match *credentials.expires_at.lock().unwrap() {
+                Some(expires_at) => expires_at,
+                None => return Self::RECHECK,
+            };
+            let due = expires_at.checked_sub(margin).unwrap_or(expires_at);
+            if let Ok(wait) = due.duration_since(SystemTime::now()) {
+                return wait.min(Self::RECHECK);
+            }
+            // Credentials are applied as part of connecting, so there
+            // is nothing to do until then
+            if !activity.is_connected() {
+                return Self::RECHECK;
+            }
+            mosq.with_client(|client| match credentials.refresh(client) {
+                Ok(()) => {
+                    let _ = client.reconnect();
+                }
+                Err(err) => {
+                    if let Some(tx) = events.lock().unwrap().as_ref() {
+                        let _ = tx.try_send(Event::CredentialsUnavailable(err.to_string()));
+                    }
+                }
+            });
+            // Avoid reconnecting repeatedly if the provider keeps
+            // returning credentials that are about to expire
+            margin.min(Self::RECHECK).max(Duration::from_secs(1))
+        })
+    }
+}
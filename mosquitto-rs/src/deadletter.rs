@@ -0,0 +1,47 @@
+use crate::Message;
+
+/// Explains why a received message could not be delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadLetterReason {
+    /// The [subscriber](struct.Client.html#method.subscriber) channel
+    /// was full because messages were not being consumed quickly enough
+    SubscriberLagging,
+    /// The [subscriber](struct.Client.html#method.subscriber) channel
+    /// has been closed
+    SubscriberClosed,
+    /// The payload was larger than the limit set via
+    /// [Client::set_max_payload_size](struct.Client.html#method.set_max_payload_size)
+    PayloadTooLarge,
+    /// The payload didn't conform to the JSON Schema for its topic.
+    /// See [SchemaRegistry](struct.SchemaRegistry.html), which requires
+    /// the `json-schema` feature.
+    SchemaViolation,
+}
+
+impl DeadLetterReason {
+    /// Returns a short name for the reason, which is used as the
+    /// value of the `dead-letter-reason` user property when dead
+    /// letters are republished
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::SubscriberLagging => "subscriber-lagging",
+            Self::SubscriberClosed => "subscriber-closed",
+            Self::PayloadTooLarge => "payload-too-large",
+            Self::SchemaViolation => "schema-violation",
+        }
+    }
+}
+
+impl std::fmt::Display for DeadLetterReason {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.write_str(self.as_str())
+    }
+}
+
+/// A received message that could not be delivered, as yielded by
+/// [Client::dead_letters](struct.Client.html#method.dead_letters).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetter {
+    pub message: Message,
+    pub reason: DeadLetterReason,
+}
@@ -0,0 +1,263 @@
+use crate::{Error, Message, MessageMiddleware, OutgoingPublish, Property, PublishMiddleware};
+use std::sync::Arc;
+
+/// The name of the MQTT 5 user property that marks a message whose
+/// payload has been encrypted; its value is the
+/// [name](trait.Cipher.html#tymethod.name) of the cipher
+pub const ENCRYPTION_PROPERTY: &str = "encryption";
+
+/// Encrypts and decrypts message payloads for an
+/// [Encryption](struct.Encryption.html) layer.
+///
+/// Implementations decide which topics are encrypted, and can use a
+/// different key for each of them; the topic should also be bound to
+/// the ciphertext, so that the broker can't move a message to another
+/// topic without it failing to decrypt.
+pub trait Cipher: Send + Sync {
+    /// A short name for the scheme, such as `aes-256-gcm`, which is
+    /// attached to encrypted messages
+    fn name(&self) -> &str;
+
+    /// Returns true if the messages on `topic` are encrypted
+    fn covers(&self, topic: &str) -> bool;
+
+    /// Encrypt the payload of a message to `topic`
+    fn encrypt(&self, topic: &str, plaintext: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// Decrypt the payload of a message received on `topic`
+    fn decrypt(&self, topic: &str, ciphertext: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// End-to-end encryption of message payloads, for deployments that
+/// don't trust the broker with their contents.
+///
+/// This is both a [PublishMiddleware](trait.PublishMiddleware.html)
+/// that encrypts the payloads of outgoing messages on the topics that
+/// its [Cipher](trait.Cipher.html) covers, marking them with the
+/// `encryption` user property, and a
+/// [MessageMiddleware](trait.MessageMiddleware.html) that decrypts them
+/// on receipt; see
+/// [Client::add_encryption](struct.Client.html#method.add_encryption),
+/// which installs both.
+///
+/// Received messages on covered topics that aren't marked as encrypted
+/// with the same cipher, or that fail to decrypt, are dropped, so that
+/// nobody who can publish to the broker can forge them.
+/// Messages on other topics pass through unchanged.
+/// The topics, and the other properties of the messages, are not
+/// encrypted.
+///
+/// This requires MQTT 5, as earlier versions of the protocol don't
+/// support properties; without them, every message on a covered topic
+/// is dropped as a forgery.
+pub struct Encryption<C> {
+    cipher: Arc<C>,
+}
+
+impl<C> Clone for Encryption<C> {
+    fn clone(&self) -> Self {
+        Self {
+            cipher: Arc::clone(&self.cipher),
+        }
+    }
+}
+
+impl<C: Cipher> Encryption<C> {
+    pub fn new(cipher: C) -> Self {
+        Self {
+            cipher: Arc::new(cipher),
+        }
+    }
+}
+
+impl<C: Cipher> PublishMiddleware for Encryption<C> {
+    fn on_publish(&self, publish: &mut OutgoingPublish) -> Result<(), Error> {
+        if self.cipher.covers(&publish.topic) {
+            publish.payload = self.cipher.encrypt(&publish.topic, &publish.payload)?;
+            publish.properties.push(Property::UserProperty(
+                ENCRYPTION_PROPERTY.to_string(),
+                self.cipher.name().to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<C: Cipher> MessageMiddleware for Encryption<C> {
+    fn on_message(&self, mut message: Message) -> Option<Message> {
+        if !self.cipher.covers(&message.topic) {
+            return Some(message);
+        }
+        let encrypted = message
+            .properties
+            .user_properties()
+            .any(|(name, value)| name == ENCRYPTION_PROPERTY && value == self.cipher.name());
+        if !encrypted {
+            return None;
+        }
+        message.payload = self.cipher.decrypt(&message.topic, &message.payload).ok()?;
+        Some(message)
+    }
+}
+
+/// A [Cipher](trait.Cipher.html) that uses AES-256 in GCM mode, with a
+/// key for each of a set of topic patterns.
+///
+/// A random nonce is generated for each message, and is sent ahead of
+/// the ciphertext; the topic is authenticated along with the payload.
+///
+/// ```no_run
+/// # use mosquitto_rs::*;
+/// # fn configure(client: &mut Client, site_key: &[u8; 32]) -> Result<(), Error> {
+/// let mut cipher = AesGcmCipher::new();
+/// cipher.add_key("site/+/secrets/#", site_key)?;
+/// client.set_option(&ClientOption::ProtocolVersion(ProtocolVersion::V5))?;
+/// client.add_encryption(cipher)?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "encryption")]
+#[derive(Default)]
+pub struct AesGcmCipher {
+    keys: Vec<(String, aes_gcm::Aes256Gcm)>,
+}
+
+#[cfg(feature = "encryption")]
+impl AesGcmCipher {
+    /// The size of the nonce that precedes the ciphertext
+    const NONCE_SIZE: usize = 12;
+
+    /// Create a cipher that has no keys, and so covers no topics
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encrypt the messages on topics that match `pattern` using `key`.
+    /// When several patterns match a topic, the one that was added
+    /// first is used.
+    pub fn add_key(&mut self, pattern: &str, key: &[u8; 32]) -> Result<(), Error> {
+        use aes_gcm::KeyInit;
+
+        crate::sub_topic_check(pattern)?;
+        let cipher = aes_gcm::Aes256Gcm::new_from_slice(key)
+            .map_err(|err| Error::Encryption(err.to_string()))?;
+        self.keys.push((pattern.to_string(), cipher));
+        Ok(())
+    }
+
+    fn key(&self, topic: &str) -> Option<&aes_gcm::Aes256Gcm> {
+        self.keys
+            .iter()
+            .find(|(pattern, _)| crate::topic_matches_sub(pattern, topic).unwrap_or(false))
+            .map(|(_, cipher)| cipher)
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl Cipher for AesGcmCipher {
+    fn name(&self) -> &str {
+        "aes-256-gcm"
+    }
+
+    fn covers(&self, topic: &str) -> bool {
+        self.key(topic).is_some()
+    }
+
+    fn encrypt(&self, topic: &str, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+        use aes_gcm::aead::{Aead, AeadCore, OsRng, Payload};
+
+        let cipher = self.key(topic).ok_or(Error::InvalidArgument)?;
+        let nonce = aes_gcm::Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: plaintext,
+            aad: topic.as_bytes(),
+        };
+        let ciphertext = cipher
+            .encrypt(&nonce, payload)
+            .map_err(|err| Error::Encryption(err.to_string()))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    fn decrypt(&self, topic: &str, ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        use aes_gcm::aead::{Aead, Payload};
+
+        let cipher = self.key(topic).ok_or(Error::InvalidArgument)?;
+        if ciphertext.len() < Self::NONCE_SIZE {
+            return Err(Error::Encryption("the payload is too short".to_string()));
+        }
+        let (nonce, ciphertext) = ciphertext.split_at(Self::NONCE_SIZE);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: topic.as_bytes(),
+        };
+        cipher
+            .decrypt(aes_gcm::Nonce::from_slice(nonce), payload)
+            .map_err(|err| Error::Encryption(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Properties, QoS};
+
+    /// Reverses the payloads of topics beneath `secret/`
+    struct Reverse;
+
+    impl Cipher for Reverse {
+        fn name(&self) -> &str {
+            "reverse"
+        }
+
+        fn covers(&self, topic: &str) -> bool {
+            topic.starts_with("secret/")
+        }
+
+        fn encrypt(&self, _topic: &str, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+            Ok(plaintext.iter().rev().copied().collect())
+        }
+
+        fn decrypt(&self, _topic: &str, ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+            Ok(ciphertext.iter().rev().copied().collect())
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let encryption = Encryption::new(Reverse);
+        let mut publish = OutgoingPublish {
+            topic: "secret/a".to_string(),
+            payload: b"abc".to_vec(),
+            qos: QoS::AtMostOnce,
+            retain: false,
+            properties: Properties::new(),
+        };
+        encryption.on_publish(&mut publish).unwrap();
+        assert_eq!(publish.payload, b"cba");
+
+        let message = Message {
+            topic: publish.topic.clone(),
+            payload: publish.payload.clone(),
+            properties: publish.properties.clone(),
+            ..Message::default()
+        };
+        assert_eq!(encryption.on_message(message).unwrap().payload, b"abc");
+
+        // A plaintext message on a covered topic is a forgery
+        let forged = Message {
+            topic: "secret/a".to_string(),
+            payload: b"abc".to_vec(),
+            ..Message::default()
+        };
+        assert!(encryption.on_message(forged).is_none());
+
+        let public = Message {
+            topic: "public/a".to_string(),
+            payload: b"abc".to_vec(),
+            ..Message::default()
+        };
+        assert_eq!(encryption.on_message(public).unwrap().payload, b"abc");
+    }
+}
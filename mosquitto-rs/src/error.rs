@@ -1,5 +1,5 @@
 use crate::lowlevel::sys::mosq_err_t;
-use std::collections::HashMap;
+use crate::MessageId;
 use std::os::raw::c_int;
 use thiserror::Error;
 
@@ -11,65 +11,365 @@ pub enum Error {
     CString(#[from] std::ffi::NulError),
     #[error("IO error: {0}")]
     IO(#[from] std::io::Error),
-    #[error("mosq error: {:?}", .0)]
-    Mosq(mosq_err_t),
     #[error("mosq error code {0}")]
     UnknownMosq(c_int),
     #[error("hostname resolution error: {0}")]
     Resolution(String),
     #[error("broker rejected connection")]
     RejectedConnection(crate::ConnectionStatus),
+    #[error("HTTP proxy error: {0}")]
+    HttpProxy(String),
+    /// A [PublishMiddleware](trait.PublishMiddleware.html) denied
+    /// an operation
+    #[error("denied: {0}")]
+    Denied(String),
+    /// A JSON Schema passed to
+    /// [SchemaRegistry::add](struct.SchemaRegistry.html#method.add)
+    /// is not valid
+    #[error("invalid JSON schema: {0}")]
+    InvalidSchema(String),
+    /// A message payload did not conform to the JSON Schema
+    /// registered for its topic
+    #[error("payload for {topic} does not match its schema: {message}")]
+    SchemaViolation { topic: String, message: String },
+    /// The [ClientActor](struct.ClientActor.html) that a
+    /// [ClientHandle](struct.ClientHandle.html) refers to has stopped
+    #[error("the client actor has stopped")]
+    ActorStopped,
+    /// The broker has advertised that it doesn't support a feature
+    /// that the operation requires; the string names the feature
+    #[error("the broker does not support {0}")]
+    Unsupported(String),
+    /// The linked mosquitto client library was built without a feature
+    /// that the operation requires; the string names the feature.
+    /// See [lib_features](fn.lib_features.html).
+    #[error("the linked libmosquitto was built without {0} support")]
+    LibraryUnsupported(&'static str),
+    /// The client is not connected, so the message was added to the
+    /// offline queue, to be sent once the client connects; see
+    /// [Client::set_offline_queue](struct.Client.html#method.set_offline_queue).
+    /// Repeating the publish would send the message twice.
+    #[error("the client is not connected; the message was queued")]
+    Queued,
+    /// The offline queue is full; see
+    /// [Client::set_offline_queue_capacity](struct.Client.html#method.set_offline_queue_capacity)
+    #[error("the offline queue is full")]
+    QueueFull,
+    /// A [BroadcastReceiver](struct.BroadcastReceiver.html) fell behind,
+    /// and the given number of messages were discarded
+    #[error("the receiver lagged behind and missed {0} messages")]
+    Lagged(u64),
+    /// A [Codec](trait.Codec.html) failed to encode or decode a
+    /// payload; the string describes the failure
+    #[error("payload codec error: {0}")]
+    Codec(String),
+    /// A [Cipher](trait.Cipher.html) failed to encrypt or decrypt a
+    /// payload; the string describes the failure
+    #[error("encryption error: {0}")]
+    Encryption(String),
+    /// The client has been dropped, so no more messages will arrive
+    #[error("the channel is closed")]
+    Closed,
+    /// A [Store](trait.Store.html) failed; the string describes the
+    /// failure reported by the underlying database
+    #[error("storage error: {0}")]
+    Store(String),
+    /// The broker granted a lower QoS to a subscription than was
+    /// requested; see
+    /// [Client::set_strict_subscribe_qos](struct.Client.html#method.set_strict_subscribe_qos)
+    #[error("the broker granted {granted:?} rather than {requested:?}")]
+    SubscriptionDowngraded {
+        requested: crate::QoS,
+        granted: crate::QoS,
+    },
+    /// An MQTT 5 broker responded to a request with a failure reason code
+    #[error("the broker responded with {0}")]
+    Reason(crate::ReasonCode),
+
+    /// Connecting to the broker at `host` and `port` failed
+    #[error("connecting to {host}:{port}: {source}")]
+    Connect {
+        host: String,
+        port: c_int,
+        source: Box<Error>,
+    },
+    /// Publishing a message to `topic` failed.
+    /// `mid` holds the message id if the publish had been
+    /// accepted by libmosquitto before the failure.
+    #[error("publishing to {topic}: {source}")]
+    Publish {
+        topic: String,
+        mid: Option<MessageId>,
+        source: Box<Error>,
+    },
+    /// Subscribing to `pattern` failed
+    #[error("subscribing to {pattern}: {source}")]
+    Subscribe { pattern: String, source: Box<Error> },
+
+    /// `MOSQ_ERR_ERRNO`: a system call failed; the payload holds the
+    /// corresponding `errno` value.
+    #[error("system call failed: {0}")]
+    Errno(std::io::Error),
+    /// `MOSQ_ERR_AUTH_CONTINUE`
+    #[error("authentication should continue")]
+    AuthContinue,
+    /// `MOSQ_ERR_NO_SUBSCRIBERS`
+    #[error("no subscribers")]
+    NoSubscribers,
+    /// `MOSQ_ERR_SUB_EXISTS`
+    #[error("subscription already exists")]
+    SubscriptionExists,
+    /// `MOSQ_ERR_CONN_PENDING`
+    #[error("connection pending")]
+    ConnectionPending,
+    /// `MOSQ_ERR_NOMEM`
+    #[error("out of memory")]
+    OutOfMemory,
+    /// `MOSQ_ERR_PROTOCOL`
+    #[error("protocol error")]
+    Protocol,
+    /// `MOSQ_ERR_INVAL`
+    #[error("invalid function arguments")]
+    InvalidArgument,
+    /// `MOSQ_ERR_NO_CONN`
+    #[error("the client is not connected")]
+    NoConnection,
+    /// `MOSQ_ERR_CONN_REFUSED`
+    #[error("connection refused")]
+    ConnectionRefused,
+    /// `MOSQ_ERR_NOT_FOUND`
+    #[error("not found")]
+    NotFound,
+    /// `MOSQ_ERR_CONN_LOST`
+    #[error("the connection was lost")]
+    ConnectionLost,
+    /// `MOSQ_ERR_TLS`
+    #[error("a TLS error occurred")]
+    Tls,
+    /// `MOSQ_ERR_PAYLOAD_SIZE`
+    #[error("payload too large")]
+    PayloadTooLarge,
+    /// `MOSQ_ERR_NOT_SUPPORTED`
+    #[error("not supported")]
+    NotSupported,
+    /// `MOSQ_ERR_AUTH`
+    #[error("authentication failed")]
+    AuthenticationFailed,
+    /// `MOSQ_ERR_ACL_DENIED`
+    #[error("not authorized")]
+    NotAuthorized,
+    /// `MOSQ_ERR_UNKNOWN`
+    #[error("unknown error")]
+    Unknown,
+    /// `MOSQ_ERR_PROXY`
+    #[error("proxy error")]
+    Proxy,
+    /// `MOSQ_ERR_PLUGIN_DEFER`
+    #[error("plugin deferred the decision")]
+    PluginDefer,
+    /// `MOSQ_ERR_MALFORMED_UTF8`
+    #[error("malformed UTF-8")]
+    MalformedUtf8,
+    /// `MOSQ_ERR_KEEPALIVE`
+    #[error("keepalive exceeded")]
+    KeepaliveExceeded,
+    /// `MOSQ_ERR_LOOKUP`
+    #[error("lookup error")]
+    Lookup,
+    /// `MOSQ_ERR_MALFORMED_PACKET`
+    #[error("malformed packet")]
+    MalformedPacket,
+    /// `MOSQ_ERR_DUPLICATE_PROPERTY`
+    #[error("duplicate property in property list")]
+    DuplicateProperty,
+    /// `MOSQ_ERR_TLS_HANDSHAKE`
+    #[error("TLS handshake failed")]
+    TlsHandshake,
+    /// `MOSQ_ERR_QOS_NOT_SUPPORTED`
+    #[error("requested QoS not supported by the broker")]
+    QosNotSupported,
+    /// `MOSQ_ERR_OVERSIZE_PACKET`
+    #[error("packet larger than supported by the broker")]
+    OversizePacket,
+    /// `MOSQ_ERR_OCSP`
+    #[error("OCSP error")]
+    Ocsp,
+    /// `MOSQ_ERR_TIMEOUT`
+    #[error("timeout")]
+    Timeout,
+    /// `MOSQ_ERR_RETAIN_NOT_SUPPORTED`
+    #[error("retained messages not supported by the broker")]
+    RetainNotSupported,
+    /// `MOSQ_ERR_TOPIC_ALIAS_INVALID`
+    #[error("invalid topic alias")]
+    TopicAliasInvalid,
+    /// `MOSQ_ERR_ADMINISTRATIVE_ACTION`
+    #[error("administrative action")]
+    AdministrativeAction,
+    /// `MOSQ_ERR_ALREADY_EXISTS`
+    #[error("already exists")]
+    AlreadyExists,
 }
 
-lazy_static::lazy_static! {
-    static ref ERRMAP: HashMap<c_int, mosq_err_t> = Error::build_map();
+/// Defines the mapping between the `mosq_err_t` codes and
+/// the corresponding `Error` variants
+macro_rules! mosq_errors {
+    ($($variant:ident => $code:ident),* $(,)?) => {
+        impl Error {
+            fn from_mosq_err(err: c_int) -> Option<Self> {
+                $(
+                    if err == mosq_err_t::$code as c_int {
+                        return Some(Self::$variant);
+                    }
+                )*
+                None
+            }
+
+            /// Returns the libmosquitto error code that corresponds
+            /// to this error, if any.
+            pub fn mosq_err(&self) -> Option<mosq_err_t> {
+                match self {
+                    $(
+                        Self::$variant => Some(mosq_err_t::$code),
+                    )*
+                    Self::Errno(_) => Some(mosq_err_t::MOSQ_ERR_ERRNO),
+                    _ => None,
+                }
+            }
+        }
+    };
 }
 
+mosq_errors!(
+    AuthContinue => MOSQ_ERR_AUTH_CONTINUE,
+    NoSubscribers => MOSQ_ERR_NO_SUBSCRIBERS,
+    SubscriptionExists => MOSQ_ERR_SUB_EXISTS,
+    ConnectionPending => MOSQ_ERR_CONN_PENDING,
+    OutOfMemory => MOSQ_ERR_NOMEM,
+    Protocol => MOSQ_ERR_PROTOCOL,
+    InvalidArgument => MOSQ_ERR_INVAL,
+    NoConnection => MOSQ_ERR_NO_CONN,
+    ConnectionRefused => MOSQ_ERR_CONN_REFUSED,
+    NotFound => MOSQ_ERR_NOT_FOUND,
+    ConnectionLost => MOSQ_ERR_CONN_LOST,
+    Tls => MOSQ_ERR_TLS,
+    PayloadTooLarge => MOSQ_ERR_PAYLOAD_SIZE,
+    NotSupported => MOSQ_ERR_NOT_SUPPORTED,
+    AuthenticationFailed => MOSQ_ERR_AUTH,
+    NotAuthorized => MOSQ_ERR_ACL_DENIED,
+    Unknown => MOSQ_ERR_UNKNOWN,
+    Proxy => MOSQ_ERR_PROXY,
+    PluginDefer => MOSQ_ERR_PLUGIN_DEFER,
+    MalformedUtf8 => MOSQ_ERR_MALFORMED_UTF8,
+    KeepaliveExceeded => MOSQ_ERR_KEEPALIVE,
+    Lookup => MOSQ_ERR_LOOKUP,
+    MalformedPacket => MOSQ_ERR_MALFORMED_PACKET,
+    DuplicateProperty => MOSQ_ERR_DUPLICATE_PROPERTY,
+    TlsHandshake => MOSQ_ERR_TLS_HANDSHAKE,
+    QosNotSupported => MOSQ_ERR_QOS_NOT_SUPPORTED,
+    OversizePacket => MOSQ_ERR_OVERSIZE_PACKET,
+    Ocsp => MOSQ_ERR_OCSP,
+    Timeout => MOSQ_ERR_TIMEOUT,
+    RetainNotSupported => MOSQ_ERR_RETAIN_NOT_SUPPORTED,
+    TopicAliasInvalid => MOSQ_ERR_TOPIC_ALIAS_INVALID,
+    AdministrativeAction => MOSQ_ERR_ADMINISTRATIVE_ACTION,
+    AlreadyExists => MOSQ_ERR_ALREADY_EXISTS,
+);
+
 impl Error {
-    fn build_map() -> HashMap<c_int, mosq_err_t> {
-        let mut map = HashMap::new();
-        macro_rules! m {
-            ($($a:ident),* $(,)?) => {
-                $(
-                    map.insert(mosq_err_t::$a as c_int, mosq_err_t::$a);
-                 )*
-            };
+    /// Returns the underlying error, looking through any of the
+    /// variants that describe the operation that was being attempted
+    /// when the error occurred.
+    ///
+    /// This is useful when matching on the cause of the error:
+    ///
+    /// ```
+    /// # use mosquitto_rs::Error;
+    /// fn should_reconnect(err: &Error) -> bool {
+    ///     matches!(err.root_cause(), Error::NoConnection | Error::ConnectionLost)
+    /// }
+    /// ```
+    pub fn root_cause(&self) -> &Error {
+        match self {
+            Self::Connect { source, .. }
+            | Self::Publish { source, .. }
+            | Self::Subscribe { source, .. } => source.root_cause(),
+            _ => self,
         }
-        m!(
-            MOSQ_ERR_AUTH_CONTINUE,
-            MOSQ_ERR_NO_SUBSCRIBERS,
-            MOSQ_ERR_SUB_EXISTS,
-            MOSQ_ERR_CONN_PENDING,
-            MOSQ_ERR_SUCCESS,
-            MOSQ_ERR_NOMEM,
-            MOSQ_ERR_PROTOCOL,
-            MOSQ_ERR_INVAL,
-            MOSQ_ERR_NO_CONN,
-            MOSQ_ERR_CONN_REFUSED,
-            MOSQ_ERR_NOT_FOUND,
-            MOSQ_ERR_CONN_LOST,
-            MOSQ_ERR_TLS,
-            MOSQ_ERR_PAYLOAD_SIZE,
-            MOSQ_ERR_NOT_SUPPORTED,
-            MOSQ_ERR_AUTH,
-            MOSQ_ERR_ACL_DENIED,
-            MOSQ_ERR_UNKNOWN,
-            MOSQ_ERR_ERRNO,
-            MOSQ_ERR_EAI,
-            MOSQ_ERR_PROXY,
-            MOSQ_ERR_PLUGIN_DEFER,
-            MOSQ_ERR_MALFORMED_UTF8,
-            MOSQ_ERR_KEEPALIVE,
-            MOSQ_ERR_LOOKUP,
-            MOSQ_ERR_MALFORMED_PACKET,
-            MOSQ_ERR_DUPLICATE_PROPERTY,
-            MOSQ_ERR_TLS_HANDSHAKE,
-            MOSQ_ERR_QOS_NOT_SUPPORTED,
-            MOSQ_ERR_OVERSIZE_PACKET,
-            MOSQ_ERR_OCSP,
-        );
+    }
 
-        map
+    /// Returns true if the operation that produced this error might
+    /// succeed if it is repeated later without modification, because
+    /// the error was caused by a transient condition such as a lost
+    /// connection or an overloaded broker.
+    ///
+    /// Errors caused by invalid input, missing authorization or
+    /// protocol violations are not retryable.
+    pub fn is_retryable(&self) -> bool {
+        self.retry_after().is_some()
+    }
+
+    /// Returns a suggested minimum delay before repeating the operation
+    /// that produced this error, or `None` if the error is not
+    /// [retryable](#method.is_retryable).
+    ///
+    /// A generic retry loop can be written like this:
+    ///
+    /// ```no_run
+    /// # use mosquitto_rs::*;
+    /// # async fn publish(client: &mut Client) -> Result<MessageId, Error> {
+    /// loop {
+    ///     match client.publish("topic", b"payload", QoS::AtLeastOnce, false).await {
+    ///         Err(err) => match err.retry_after() {
+    ///             Some(delay) => {
+    ///                 smol::Timer::after(delay).await;
+    ///             }
+    ///             None => return Err(err),
+    ///         },
+    ///         result => return result,
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn retry_after(&self) -> Option<std::time::Duration> {
+        use std::time::Duration;
+        match self.root_cause() {
+            Self::RejectedConnection(status) => status.retry_after(),
+            Self::Reason(code) => code.retry_after(),
+            Self::Errno(_)
+            | Self::IO(_)
+            | Self::Resolution(_)
+            | Self::Lookup
+            | Self::NoConnection
+            | Self::ConnectionLost
+            | Self::ConnectionPending
+            | Self::KeepaliveExceeded
+            | Self::Timeout => Some(Duration::from_secs(1)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn connecting(self, host: &str, port: c_int) -> Self {
+        Self::Connect {
+            host: host.to_string(),
+            port,
+            source: Box::new(self),
+        }
+    }
+
+    pub(crate) fn publishing(self, topic: &str, mid: Option<MessageId>) -> Self {
+        Self::Publish {
+            topic: topic.to_string(),
+            mid,
+            source: Box::new(self),
+        }
+    }
+
+    pub(crate) fn subscribing(self, pattern: &str) -> Self {
+        Self::Subscribe {
+            pattern: pattern.to_string(),
+            source: Box::new(self),
+        }
     }
 
     pub(crate) fn result<T>(err: c_int, res: T) -> Result<T, Self> {
@@ -82,7 +382,7 @@ impl Error {
 
     pub(crate) fn from_err(err: c_int) -> Self {
         if err == mosq_err_t::MOSQ_ERR_ERRNO as c_int {
-            Self::IO(std::io::Error::last_os_error())
+            Self::Errno(std::io::Error::last_os_error())
         } else if err == mosq_err_t::MOSQ_ERR_EAI as c_int {
             // Mosquitto stuffs the getaddrinfo() error code into errno,
             // so we can extract it and get the message manually here
@@ -90,11 +390,7 @@ impl Error {
             let reason = gai_error(&err);
             Self::Resolution(reason)
         } else {
-            if let Some(e) = ERRMAP.get(&err) {
-                Self::Mosq(*e)
-            } else {
-                Self::UnknownMosq(err)
-            }
+            Self::from_mosq_err(err).unwrap_or(Self::UnknownMosq(err))
         }
     }
 }
@@ -112,3 +408,60 @@ fn gai_error(err: &std::io::Error) -> String {
         reason.to_string_lossy().into()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mosq_err_round_trip() {
+        for code in [
+            mosq_err_t::MOSQ_ERR_INVAL,
+            mosq_err_t::MOSQ_ERR_CONN_REFUSED,
+            mosq_err_t::MOSQ_ERR_TLS,
+            mosq_err_t::MOSQ_ERR_ACL_DENIED,
+            mosq_err_t::MOSQ_ERR_ALREADY_EXISTS,
+        ] {
+            let err = Error::from_err(code as c_int);
+            assert_eq!(err.mosq_err(), Some(code));
+        }
+        assert!(matches!(
+            Error::from_err(mosq_err_t::MOSQ_ERR_PAYLOAD_SIZE as c_int),
+            Error::PayloadTooLarge
+        ));
+        assert!(matches!(Error::from_err(1000), Error::UnknownMosq(1000)));
+    }
+
+    #[test]
+    fn context() {
+        let err = Error::NotAuthorized.publishing("a/b", Some(3));
+        assert!(matches!(
+            &err,
+            Error::Publish { topic, mid: Some(3), .. } if topic == "a/b"
+        ));
+        assert!(matches!(err.root_cause(), Error::NotAuthorized));
+        assert_eq!(err.mosq_err(), None);
+        assert_eq!(
+            err.root_cause().mosq_err(),
+            Some(mosq_err_t::MOSQ_ERR_ACL_DENIED)
+        );
+    }
+
+    #[test]
+    fn retryable() {
+        use crate::{ConnectionStatus, ReasonCode};
+        assert!(Error::ConnectionLost.publishing("a", None).is_retryable());
+        assert!(!Error::NotAuthorized.is_retryable());
+        assert!(!Error::MalformedPacket.is_retryable());
+
+        let rejected = |code: ReasonCode| Error::RejectedConnection(ConnectionStatus(code.0 as _));
+        assert!(rejected(ReasonCode::SERVER_BUSY).is_retryable());
+        assert!(rejected(ReasonCode::QUOTA_EXCEEDED).is_retryable());
+        assert!(rejected(ReasonCode::CONNECTION_RATE_EXCEEDED).is_retryable());
+        assert!(!rejected(ReasonCode::NOT_AUTHORIZED).is_retryable());
+        assert!(!rejected(ReasonCode::MALFORMED_PACKET).is_retryable());
+        // MQTT 3.1.1 "server unavailable"
+        assert!(Error::RejectedConnection(ConnectionStatus(3)).is_retryable());
+        assert!(!Error::RejectedConnection(ConnectionStatus(5)).is_retryable());
+    }
+}
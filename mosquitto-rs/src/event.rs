@@ -0,0 +1,101 @@
+use crate::{BrokerCapabilities, ConnectionStatus, QoS, Redirect};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Notable occurrences in the lifetime of a [Client](struct.Client.html)
+/// connection, delivered via the channel returned by
+/// [Client::events](struct.Client.html#method.events).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The broker directed the client to use another server
+    Redirect(Redirect),
+    /// Nothing has been received from the broker for longer than the
+    /// timeout of the [Watchdog](struct.Watchdog.html)
+    BrokerSilent { silent_for: Duration },
+    /// The [CredentialsProvider](trait.CredentialsProvider.html) failed
+    /// to supply credentials for a reconnection attempt.
+    /// The string describes the error.
+    CredentialsUnavailable(String),
+    /// The broker refused a connection attempt.
+    /// `permanent` is true if retrying is not expected to help; see
+    /// [RefusalPolicy](enum.RefusalPolicy.html).
+    ConnectionRefused {
+        status: ConnectionStatus,
+        permanent: bool,
+    },
+    /// A publish to, or subscription for, `topic` requested a QoS that
+    /// the broker doesn't support, and was downgraded to the maximum
+    /// that it does; see
+    /// [Client::set_qos_downgrade](struct.Client.html#method.set_qos_downgrade).
+    /// This is also reported when the broker grants a subscription a
+    /// lower QoS than was requested; see
+    /// [Client::granted_qos](struct.Client.html#method.granted_qos).
+    QosDowngraded {
+        topic: String,
+        requested: QoS,
+        granted: QoS,
+    },
+    /// A retained message was published to `topic` without the retain
+    /// flag, because the broker doesn't support retained messages; see
+    /// [Client::set_retain_policy](struct.Client.html#method.set_retain_policy).
+    RetainStripped { topic: String },
+    /// The [Store](trait.Store.html) of the offline queue failed while
+    /// the client was sending queued publishes; see
+    /// [Client::set_offline_queue](struct.Client.html#method.set_offline_queue).
+    /// The string describes the error.
+    OfflineQueueFailed(String),
+    /// A message to `topic` was dropped from the offline queue, rather
+    /// than sent, because it had been queued for longer than the limit set via
+    /// [Client::set_offline_queue_ttl](struct.Client.html#method.set_offline_queue_ttl)
+    QueuedMessageExpired { topic: String },
+    /// A message to `topic` was dropped from the offline queue because
+    /// the queue was full; see
+    /// [Client::set_offline_queue_capacity](struct.Client.html#method.set_offline_queue_capacity)
+    QueuedMessageDropped { topic: String },
+    /// A message to `topic` was dropped because the queue that feeds the
+    /// [subscriber](struct.Client.html#method.subscriber) channel was
+    /// full, and its overflow policy is `OverflowPolicy::Error`
+    SubscriberOverflow { topic: String },
+    /// The messages waiting in the queues of the client reached the
+    /// memory budget of `limit` bytes, holding `used` bytes, so the
+    /// overflow policies of the queues are being applied; see
+    /// [Client::set_memory_budget](struct.Client.html#method.set_memory_budget).
+    MemoryBudgetExceeded { used: usize, limit: usize },
+    /// The connection was lost, and the client is reconnecting to `addr`,
+    /// which was found by the most recent periodic resolution of the
+    /// broker host name, or chosen by the endpoint selection policy; see
+    /// [Client::set_endpoint_refresh](struct.Client.html#method.set_endpoint_refresh)
+    /// and [Client::set_endpoint_selection](struct.Client.html#method.set_endpoint_selection).
+    EndpointChanged { addr: SocketAddr },
+    /// The broker advertised capabilities in its CONNACK that differ
+    /// from those of the previous connection, or this is the first
+    /// connection, in which case `previous` is `None`.
+    /// This includes the keepalive interval chosen by the broker, in
+    /// `server_keep_alive`, so that supervisory code can adjust its
+    /// own timers to match; see
+    /// [Client::broker_capabilities](struct.Client.html#method.broker_capabilities).
+    CapabilitiesChanged {
+        previous: Option<BrokerCapabilities>,
+        current: BrokerCapabilities,
+    },
+    /// The client switched to the client id `id`; see
+    /// [Client::rotate_identity](struct.Client.html#method.rotate_identity).
+    /// `previous` is `None` if the client had a random id.
+    IdentityRotated {
+        previous: Option<String>,
+        id: String,
+    },
+    /// A probe published by the client travelled through the broker and
+    /// back in `rtt`; see
+    /// [Client::set_latency_probe](struct.Client.html#method.set_latency_probe).
+    BrokerLatency { rtt: Duration },
+    /// A failure occurred in the background, such as in the thread that
+    /// runs the message loop, rather than in a call made by the
+    /// application.
+    /// `context` names what the client was doing, and `message`
+    /// describes the failure.
+    BackgroundError {
+        context: &'static str,
+        message: String,
+    },
+}
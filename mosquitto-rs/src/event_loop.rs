@@ -0,0 +1,259 @@
+use crate::lowlevel::{Callbacks, Mosq, MosqPtr, WantsReadFn};
+use crate::{Backoff, Error};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The longest that the loop waits for socket activity, which bounds
+/// both the latency of registering a client and the interval between
+/// calls to `loop_misc`
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+enum Command {
+    Add {
+        id: u64,
+        mosq: MosqPtr,
+        connection_lost: Arc<AtomicBool>,
+        wants_read: WantsReadFn,
+    },
+    Remove {
+        id: u64,
+        done: Sender<()>,
+    },
+}
+
+/// A single thread that drives the network traffic of many clients,
+/// by polling their sockets, in place of the thread per client that
+/// is otherwise started by libmosquitto.
+/// This allows an application to maintain hundreds of connections
+/// without a corresponding number of threads.
+///
+/// Clients are attached to the loop when they are created, via
+/// [Client::with_id_and_event_loop](struct.Client.html#method.with_id_and_event_loop)
+/// or
+/// [Client::with_auto_id_and_event_loop](struct.Client.html#method.with_auto_id_and_event_loop).
+/// The loop reconnects clients whose connection is lost, waiting
+/// between attempts as described by its [Backoff](struct.Backoff.html);
+/// the delays set via
+/// [Client::set_reconnect_delay](struct.Client.html#method.set_reconnect_delay)
+/// don't apply to these clients.
+///
+/// The thread exits once the `EventLoop` and all of the clients that
+/// are attached to it have been dropped.
+///
+/// ```no_run
+/// use mosquitto_rs::*;
+///
+/// let event_loop = EventLoop::new()?;
+/// let clients = (0..100)
+///     .map(|_| Client::with_auto_id_and_event_loop(&event_loop))
+///     .collect::<Result<Vec<_>, Error>>()?;
+/// # Ok::<(), Error>(())
+/// ```
+pub struct EventLoop {
+    commands: Sender<Command>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl EventLoop {
+    /// Start a loop thread that reconnects clients using the
+    /// default [Backoff](struct.Backoff.html)
+    pub fn new() -> Result<Self, Error> {
+        Self::with_backoff(Backoff::default())
+    }
+
+    /// Start a loop thread that waits according to `backoff` between
+    /// successive failed attempts to reconnect a client
+    pub fn with_backoff(backoff: Backoff) -> Result<Self, Error> {
+        let (commands, rx) = channel();
+        std::thread::Builder::new()
+            .name("mosquitto-rs event loop".to_string())
+            .spawn(move || run(rx, backoff))
+            .map_err(Error::Create)?;
+        Ok(Self {
+            commands,
+            next_id: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Start driving `mosq` from the loop thread.
+    /// The client is driven until the returned registration is dropped.
+    ///
+    /// # Safety
+    /// The loop thread must not already be running for `mosq`, and the
+    /// registration must be dropped before `mosq`.
+    pub(crate) unsafe fn register<CB: Callbacks>(&self, mosq: &Mosq<CB>) -> Registration {
+        let registration = Registration {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            commands: self.commands.clone(),
+        };
+        registration.attach(mosq);
+        registration
+    }
+}
+
+/// Detaches a client from an [EventLoop](struct.EventLoop.html) when dropped
+pub(crate) struct Registration {
+    id: u64,
+    commands: Sender<Command>,
+}
+
+impl Registration {
+    /// Start driving `mosq` from the loop thread, after it was
+    /// previously stopped by [detach](#method.detach).
+    ///
+    /// # Safety
+    /// The same requirements as for `EventLoop::register` apply.
+    pub(crate) unsafe fn attach<CB: Callbacks>(&self, mosq: &Mosq<CB>) {
+        // Otherwise libmosquitto writes QoS 0 publishes from the thread
+        // that makes them, calling back into the client on that thread
+        let _ = mosq.set_threaded(true);
+        let _ = self.commands.send(Command::Add {
+            id: self.id,
+            mosq: mosq.ptr(),
+            connection_lost: mosq.connection_lost(),
+            wants_read: mosq.wants_read_fn(),
+        });
+    }
+
+    /// Stop driving the client from the loop thread, waiting until
+    /// the loop is no longer using it
+    pub(crate) fn detach(&self) {
+        let (done, wait) = channel();
+        if self
+            .commands
+            .send(Command::Remove { id: self.id, done })
+            .is_ok()
+        {
+            let _ = wait.recv();
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        // The client is destroyed after this, so must no longer be
+        // in use by the loop
+        self.detach();
+    }
+}
+
+struct Entry {
+    id: u64,
+    mosq: MosqPtr,
+    connection_lost: Arc<AtomicBool>,
+    wants_read: WantsReadFn,
+    /// The number of consecutive failed attempts to reconnect
+    failures: u32,
+    /// When to next attempt to reconnect, if the connection was lost
+    reconnect_at: Option<Instant>,
+}
+
+impl Entry {
+    fn schedule_reconnect(&mut self, backoff: &Backoff) {
+        self.failures = self.failures.saturating_add(1);
+        self.reconnect_at
+            .replace(Instant::now() + backoff.delay(self.failures));
+    }
+}
+
+fn run(commands: Receiver<Command>, backoff: Backoff) {
+    let mut entries: Vec<Entry> = vec![];
+    let mut fds: Vec<libc::pollfd> = vec![];
+
+    loop {
+        loop {
+            match commands.try_recv() {
+                Ok(Command::Add {
+                    id,
+                    mosq,
+                    connection_lost,
+                    wants_read,
+                }) => entries.push(Entry {
+                    id,
+                    mosq,
+                    connection_lost,
+                    wants_read,
+                    failures: 0,
+                    reconnect_at: None,
+                }),
+                Ok(Command::Remove { id, done }) => {
+                    entries.retain(|entry| entry.id != id);
+                    let _ = done.send(());
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+
+        fds.clear();
+        for entry in &entries {
+            // Safety: wants_read came from the same client as mosq
+            let wants_read = unsafe { entry.mosq.wants_read(entry.wants_read) };
+            entry.mosq.with_client(|client| {
+                let fd = client.socket().unwrap_or(-1);
+                let mut events = if wants_read { libc::POLLIN } else { 0 };
+                if client.want_write() {
+                    events |= libc::POLLOUT;
+                }
+                // poll ignores entries with a negative descriptor
+                fds.push(libc::pollfd {
+                    fd,
+                    events,
+                    revents: 0,
+                });
+            });
+        }
+
+        let now = Instant::now();
+        let timeout = entries
+            .iter()
+            .filter_map(|entry| entry.reconnect_at)
+            .map(|at| at.saturating_duration_since(now))
+            .fold(POLL_INTERVAL, Duration::min);
+        unsafe {
+            libc::poll(
+                fds.as_mut_ptr(),
+                fds.len() as libc::nfds_t,
+                timeout.as_millis() as libc::c_int,
+            );
+        }
+
+        let now = Instant::now();
+        for (entry, fd) in entries.iter_mut().zip(&fds) {
+            let mut received = false;
+            entry.mosq.with_client(|client| {
+                let readable = libc::POLLIN | libc::POLLERR | libc::POLLHUP;
+                if fd.revents & readable != 0 {
+                    received = client.loop_read(1).is_ok();
+                }
+                if fd.revents & libc::POLLOUT != 0 {
+                    let _ = client.loop_write();
+                }
+                let _ = client.loop_misc();
+            });
+            if received {
+                entry.failures = 0;
+            }
+
+            if entry.connection_lost.swap(false, Ordering::SeqCst) {
+                entry.schedule_reconnect(&backoff);
+            }
+
+            match entry.reconnect_at {
+                Some(at) if at <= now => {
+                    entry.reconnect_at.take();
+                    let mut result = Ok(());
+                    entry
+                        .mosq
+                        .with_client(|client| result = client.reconnect_non_blocking());
+                    if result.is_err() {
+                        entry.schedule_reconnect(&backoff);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
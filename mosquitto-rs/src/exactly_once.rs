@@ -0,0 +1,190 @@
+use crate::{
+    DedupWindow, Error, Message, OutgoingPublish, Properties, Property, PublishMiddleware, Store,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The name of the MQTT 5 User Property that carries the id assigned
+/// by [AssignMessageIds](struct.AssignMessageIds.html)
+pub const MESSAGE_ID_PROPERTY: &str = "mosquitto-rs-message-id";
+
+/// Returns the id assigned by [AssignMessageIds](struct.AssignMessageIds.html),
+/// if any
+pub(crate) fn message_id(properties: &Properties) -> Option<&str> {
+    properties
+        .user_properties()
+        .find(|(name, _)| *name == MESSAGE_ID_PROPERTY)
+        .map(|(_, value)| value)
+}
+
+/// A [PublishMiddleware](trait.PublishMiddleware.html) that attaches
+/// a unique id to each outgoing message, as a User Property named
+/// [MESSAGE_ID_PROPERTY](constant.MESSAGE_ID_PROPERTY.html), so that
+/// receivers can recognize redeliveries using
+/// [ExactlyOnce](struct.ExactlyOnce.html).
+/// Messages that already have an id keep it.
+///
+/// The id is recorded along with the message in the offline queue,
+/// if any, so that a message that is sent again after a restart
+/// carries the same id.
+///
+/// This requires MQTT 5, as earlier versions of the protocol don't
+/// support properties.
+#[derive(Debug)]
+pub struct AssignMessageIds {
+    prefix: String,
+    counter: AtomicU64,
+}
+
+impl AssignMessageIds {
+    pub fn new() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        Self {
+            prefix: format!("{:x}-{:x}", nanos, std::process::id()),
+            counter: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Default for AssignMessageIds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PublishMiddleware for AssignMessageIds {
+    fn on_publish(&self, publish: &mut OutgoingPublish) -> Result<(), Error> {
+        if message_id(&publish.properties).is_none() {
+            let n = self.counter.fetch_add(1, Ordering::Relaxed);
+            publish.properties.push(Property::UserProperty(
+                MESSAGE_ID_PROPERTY.to_string(),
+                format!("{}-{:x}", self.prefix, n),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Remembers which received messages have been processed, so that
+/// each message is processed once, even if it is delivered again
+/// after a reconnection or a restart of the application.
+///
+/// Together with an offline queue on the publishing client, see
+/// [Client::set_offline_queue](struct.Client.html#method.set_offline_queue),
+/// this provides effectively-once processing: the publisher sends each
+/// message at least once, and the receiver skips the repeats.
+/// Messages are recognized by the id assigned by the publisher's
+/// [AssignMessageIds](struct.AssignMessageIds.html) middleware; messages
+/// without an id are always processed.
+///
+/// The ids of processed messages are kept in a
+/// [DedupWindow](struct.DedupWindow.html), which should use a durable
+/// [Store](trait.Store.html) and be large enough to cover the messages
+/// that might be repeated.
+///
+/// ```no_run
+/// use mosquitto_rs::*;
+///
+/// let mut processed = ExactlyOnce::new(FileStore::open("processed.journal")?, 10_000)?;
+/// # let message = Message::default();
+/// processed.process_exactly_once(&message, |payload| {
+///     println!("{:?}", payload);
+///     Ok::<(), Error>(())
+/// })?;
+/// # Ok::<(), Error>(())
+/// ```
+pub struct ExactlyOnce {
+    window: DedupWindow,
+}
+
+impl ExactlyOnce {
+    /// Remember the ids of up to `capacity` processed messages in `store`
+    pub fn new<S: Store + 'static>(store: S, capacity: usize) -> Result<Self, Error> {
+        Ok(Self {
+            window: DedupWindow::new(store, capacity)?,
+        })
+    }
+
+    /// Returns true if `message` has already been processed
+    pub fn is_processed(&self, message: &Message) -> bool {
+        message_id(&message.properties).is_some_and(|id| self.window.contains(id.as_bytes()))
+    }
+
+    /// Call `process` with the payload of `message`, unless it has
+    /// already been processed, in which case `Ok(None)` is returned.
+    ///
+    /// The message is recorded as processed only if `process` succeeds,
+    /// so that a failure can be retried when the message is delivered
+    /// again.
+    /// If the application stops after `process` succeeds but before the
+    /// message is recorded, the message will be processed again; an
+    /// application that cannot tolerate this should make `process`
+    /// idempotent.
+    pub fn process_exactly_once<T, E, F>(
+        &mut self,
+        message: &Message,
+        process: F,
+    ) -> Result<Option<T>, E>
+    where
+        F: FnOnce(&[u8]) -> Result<T, E>,
+        E: From<Error>,
+    {
+        if self.is_processed(message) {
+            return Ok(None);
+        }
+        let result = process(&message.payload)?;
+        if let Some(id) = message_id(&message.properties) {
+            self.window.insert(id.as_bytes())?;
+        }
+        Ok(Some(result))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{MemoryStore, QoS};
+
+    #[test]
+    fn process_exactly_once() {
+        let ids = AssignMessageIds::new();
+        let mut publish = OutgoingPublish {
+            topic: "a".to_string(),
+            payload: b"hello".to_vec(),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            properties: Properties::new(),
+        };
+        ids.on_publish(&mut publish).unwrap();
+        ids.on_publish(&mut publish).unwrap();
+        assert_eq!(publish.properties.user_properties().count(), 1);
+
+        let message = Message {
+            topic: publish.topic,
+            payload: publish.payload,
+            properties: publish.properties,
+            ..Message::default()
+        };
+        let mut processed = ExactlyOnce::new(MemoryStore::new(), 10).unwrap();
+
+        let failed: Result<Option<()>, Error> =
+            processed.process_exactly_once(&message, |_| Err(Error::Timeout));
+        assert!(failed.is_err());
+        assert!(!processed.is_processed(&message));
+
+        let result: Result<_, Error> = processed.process_exactly_once(&message, |p| Ok(p.len()));
+        assert_eq!(result.unwrap(), Some(5));
+        let result: Result<_, Error> = processed.process_exactly_once(&message, |p| Ok(p.len()));
+        assert_eq!(result.unwrap(), None);
+
+        // Messages without an id are always processed
+        let anonymous = Message::default();
+        let result: Result<_, Error> = processed.process_exactly_once(&anonymous, |_| Ok(()));
+        assert_eq!(result.unwrap(), Some(()));
+        let result: Result<_, Error> = processed.process_exactly_once(&anonymous, |_| Ok(()));
+        assert_eq!(result.unwrap(), Some(()));
+    }
+}
@@ -0,0 +1,39 @@
+use crate::Message;
+
+/// A `MessageFilter` selects received messages by their topic, payload
+/// or properties; see
+/// [Client::filtered_subscriber](struct.Client.html#method.filtered_subscriber).
+///
+/// Filters are evaluated on the thread that receives messages from the
+/// broker, before the messages are queued, so they should be quick to
+/// evaluate.
+///
+/// Any `Fn(&Message) -> bool` closure can be used as a filter, which
+/// allows for arbitrary predicates, such as regular expressions on the
+/// topic or comparisons of a field of a JSON payload:
+///
+/// ```no_run
+/// use mosquitto_rs::*;
+///
+/// let client = Client::with_auto_id()?;
+/// let alarms = client.filtered_subscriber(
+///     |message: &Message| {
+///         message.topic.ends_with("/alarm") && message.payload.starts_with(b"CRITICAL")
+///     },
+///     Some(16),
+/// );
+/// # Ok::<(), Error>(())
+/// ```
+pub trait MessageFilter: Send + Sync {
+    /// Returns true if `message` should be delivered
+    fn matches(&self, message: &Message) -> bool;
+}
+
+impl<F> MessageFilter for F
+where
+    F: Fn(&Message) -> bool + Send + Sync,
+{
+    fn matches(&self, message: &Message) -> bool {
+        (self)(message)
+    }
+}
@@ -0,0 +1,149 @@
+use crate::lowlevel::topic_matches_sub;
+use crate::{Error, Message};
+use async_channel::{bounded, Receiver, Sender};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+type Values = Arc<Mutex<HashMap<String, Message>>>;
+
+/// The receiving side of a [LatestValues](struct.LatestValues.html),
+/// which records the messages that match `pattern`
+pub(crate) struct Watch {
+    pattern: String,
+    values: Values,
+    /// Signals that a value has changed; the capacity of one coalesces
+    /// changes that arrive before the previous one was observed
+    changed: Sender<()>,
+}
+
+impl Watch {
+    pub fn new(pattern: &str) -> (Self, LatestValues) {
+        let values = Values::default();
+        let (changed, rx) = bounded(1);
+        let watch = Self {
+            pattern: pattern.to_string(),
+            values: Arc::clone(&values),
+            changed,
+        };
+        let latest = LatestValues {
+            pattern: pattern.to_string(),
+            values,
+            changed: rx,
+        };
+        (watch, latest)
+    }
+
+    /// Returns true unless the `LatestValues` has been dropped
+    pub fn is_active(&self) -> bool {
+        !self.changed.is_closed()
+    }
+
+    /// Record `message` if it matches the pattern, returning true if
+    /// it was recorded
+    pub fn dispatch(&self, message: &Message) -> bool {
+        if !topic_matches_sub(&self.pattern, &message.topic).unwrap_or(false) {
+            return false;
+        }
+        let mut values = self.values.lock().unwrap();
+        if message.payload.is_empty() {
+            // The retained value of the topic has been cleared
+            values.remove(&message.topic);
+        } else {
+            values.insert(message.topic.clone(), message.clone());
+        }
+        let _ = self.changed.try_send(());
+        true
+    }
+}
+
+/// Holds only the most recent message received on each topic that
+/// matches a pattern; see [Client::watch](struct.Client.html#method.watch).
+///
+/// This suits state topics, such as temperatures or the status of a
+/// device, where only the current value is of interest: intermediate
+/// values that are superseded before they are looked at are dropped by
+/// design, so a slow consumer never falls behind.
+/// A message with an empty payload, which clears a retained message,
+/// removes the value of its topic.
+///
+/// ```no_run
+/// # use mosquitto_rs::*;
+/// # async fn thermostat(client: &Client) -> Result<(), Error> {
+/// let temperatures = client.watch("sensors/+/temp", QoS::AtMostOnce).await?;
+/// while temperatures.changed().await.is_ok() {
+///     if let Some(message) = temperatures.latest("sensors/kitchen/temp") {
+///         println!("kitchen: {}", String::from_utf8_lossy(&message.payload));
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct LatestValues {
+    pattern: String,
+    values: Values,
+    changed: Receiver<()>,
+}
+
+impl LatestValues {
+    /// Returns the pattern that topics are matched against
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns the most recent message received on `topic`, if any
+    pub fn latest(&self, topic: &str) -> Option<Message> {
+        self.values.lock().unwrap().get(topic).cloned()
+    }
+
+    /// Returns the topics that currently have a value, in no
+    /// particular order
+    pub fn topics(&self) -> Vec<String> {
+        self.values.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Returns the most recent message of every topic
+    pub fn snapshot(&self) -> HashMap<String, Message> {
+        self.values.lock().unwrap().clone()
+    }
+
+    /// Wait until a value has changed since the previous call.
+    /// Any number of changes that occurred in the meantime are reported
+    /// only once.
+    /// Yields `Error::Closed` once the client has been dropped.
+    pub async fn changed(&self) -> Result<(), Error> {
+        self.changed.recv().await.map_err(|_| Error::Closed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(topic: &str, payload: &[u8]) -> Message {
+        Message {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            ..Message::default()
+        }
+    }
+
+    #[test]
+    fn keeps_latest() {
+        let (watch, latest) = Watch::new("temp/+");
+        assert!(watch.dispatch(&message("temp/a", b"1")));
+        assert!(watch.dispatch(&message("temp/a", b"2")));
+        assert!(watch.dispatch(&message("temp/b", b"3")));
+        assert!(!watch.dispatch(&message("humidity/a", b"4")));
+
+        assert_eq!(latest.latest("temp/a").unwrap().payload, b"2");
+        assert_eq!(latest.snapshot().len(), 2);
+        assert_eq!(latest.changed.len(), 1);
+
+        assert!(watch.dispatch(&message("temp/b", b"")));
+        assert!(latest.latest("temp/b").is_none());
+        assert_eq!(latest.topics(), vec!["temp/a".to_string()]);
+
+        drop(latest);
+        assert!(!watch.is_active());
+    }
+}
@@ -35,10 +35,234 @@
 //!
 //! *  `vendored-mosquitto` - use bundled libmosquitto 2.4 library. This is on by default.
 //! * `vendored-openssl` - build openssl from source, rather than using the system library. Recommended for macOS and Windows users to enable this.
+//! * `async` - the high level async [Client](struct.Client.html). This is on by default.
+//!   Disabling it leaves just the low level [Mosq](struct.Mosq.html) wrapper,
+//!   which is useful for resource constrained deployments.
+//! * `rumqttc` - `From`/`Into` conversions between this crate's `QoS`, `Message`
+//!   and subscription option types and their equivalents in the
+//!   [rumqttc](https://docs.rs/rumqttc) crate, to help with sharing code
+//!   or migrating between the two.
+//! * `json-schema` - validate JSON message payloads against per-topic
+//!   schemas using a [SchemaRegistry](struct.SchemaRegistry.html).
+//! * `otel` - propagate OpenTelemetry trace context through MQTT 5
+//!   user properties; see [TracePropagation](struct.TracePropagation.html).
+//!   This implies `async`.
+//! * `sqlite` - a [Store](trait.Store.html) implementation that keeps
+//!   its entries in an SQLite database, using [rusqlite](https://docs.rs/rusqlite).
+//! * `sled` - a [Store](trait.Store.html) implementation that keeps
+//!   its entries in a [sled](https://docs.rs/sled) database.
+//! * `serde` - `Serialize` and `Deserialize` implementations for
+//!   [Properties](struct.Properties.html), so that MQTT 5 properties can
+//!   be stored in configuration files or captured and replayed.
+//! * `derive` - `#[derive(MqttMessage)]`, which maps a struct to a topic
+//!   and a payload; see [MqttMessage](trait.MqttMessage.html).
+//!   This implies `async` and `serde`.
+//! * `encryption` - [AesGcmCipher](struct.AesGcmCipher.html), which encrypts
+//!   message payloads end-to-end with AES-256-GCM and per-topic keys, using
+//!   [aes-gcm](https://docs.rs/aes-gcm); see [Encryption](struct.Encryption.html).
+//!   This implies `async`.
+//! * `record` - capture the messages that a client receives and publishes
+//!   to a file with a [Recorder](struct.Recorder.html), and replay them
+//!   with their original pacing via [Recording](struct.Recording.html).
+//!   This implies `async` and `serde`.
+//! * `cli` - build the `mosquitto-rs` command line client, which has
+//!   `pub` and `sub` subcommands along the lines of `mosquitto_pub`
+//!   and `mosquitto_sub`.
+//!
+//! ## Cancellation
+//!
+//! The futures returned by [Client](struct.Client.html) may be dropped
+//! before they complete, for example when they lose a `select!` against
+//! a shutdown signal or a timeout, and the client remains usable.
+//! Dropping a future stops waiting, but doesn't undo what has already
+//! been sent to the broker:
+//!
+//! * Dropping `connect` before the broker has answered aborts the
+//!   connection attempt.
+//! * Dropping a publish, `subscribe` or `unsubscribe` that is waiting
+//!   for its acknowledgement forgets the request, and the acknowledgement
+//!   is ignored when it arrives; the broker may still act on the request.
+//! * Dropping `request` forgets the correlation data, so a late reply
+//!   is not delivered.
+//! * Dropping `snapshot_retained` removes its subscription.
+#[cfg(feature = "async")]
+mod actor;
+#[cfg(feature = "async")]
+mod alias;
+#[cfg(feature = "async")]
+mod backoff;
+#[cfg(feature = "async")]
+mod broadcast;
+mod capabilities;
+#[cfg(feature = "async")]
 mod client;
+#[cfg(feature = "async")]
+mod conformance;
+#[cfg(feature = "async")]
+mod connack;
+#[cfg(feature = "async")]
+mod credentials;
+#[cfg(feature = "async")]
+mod deadletter;
+#[cfg(feature = "async")]
+mod encryption;
 mod error;
+#[cfg(feature = "async")]
+mod event;
+#[cfg(all(unix, feature = "async"))]
+mod event_loop;
+#[cfg(feature = "async")]
+mod exactly_once;
+#[cfg(feature = "async")]
+mod filter;
+#[cfg(feature = "async")]
+mod latest;
 mod lowlevel;
+#[cfg(feature = "async")]
+mod memory;
+#[cfg(feature = "async")]
+mod middleware;
+#[cfg(feature = "async")]
+mod offline;
+#[cfg(feature = "otel")]
+mod otel;
+#[cfg(feature = "async")]
+mod overflow;
+#[cfg(feature = "async")]
+mod pool;
+#[cfg(feature = "async")]
+mod presence;
+#[cfg(feature = "async")]
+mod probe;
+mod properties;
+mod proxy;
+mod reason;
+#[cfg(feature = "record")]
+mod record;
+mod redirect;
+#[cfg(feature = "async")]
+mod refusal;
+#[cfg(feature = "async")]
+mod report;
+mod resolve;
+#[cfg(feature = "async")]
+mod response;
+#[cfg(feature = "rumqttc")]
+mod rumqttc_interop;
+#[cfg(feature = "json-schema")]
+mod schema;
+#[cfg(feature = "async")]
+mod scope;
+#[cfg(feature = "async")]
+mod selftest;
+#[cfg(feature = "async")]
+mod shutdown;
+#[cfg(feature = "async")]
+mod simulate;
+#[cfg(feature = "async")]
+mod sink;
+mod socket;
+#[cfg(feature = "async")]
+mod stats;
+mod store;
+mod subscribe;
+#[cfg(feature = "async")]
+mod subscriber;
+mod tls;
+mod topic_filter;
+mod topic_tree;
+#[cfg(feature = "async")]
+mod tuning;
+#[cfg(feature = "async")]
+mod typed;
+#[cfg(feature = "async")]
+mod watchdog;
+mod will;
 
+#[cfg(feature = "async")]
+pub use actor::*;
+#[cfg(feature = "async")]
+pub use backoff::*;
+#[cfg(feature = "async")]
+pub use broadcast::BroadcastReceiver;
+pub use capabilities::*;
+#[cfg(feature = "async")]
 pub use client::*;
+#[cfg(feature = "async")]
+pub use conformance::*;
+#[cfg(feature = "async")]
+pub use connack::*;
+#[cfg(feature = "async")]
+pub use credentials::*;
+#[cfg(feature = "async")]
+pub use deadletter::*;
+#[cfg(feature = "async")]
+pub use encryption::*;
 pub use error::*;
+#[cfg(feature = "async")]
+pub use event::*;
+#[cfg(all(unix, feature = "async"))]
+pub use event_loop::*;
+#[cfg(feature = "async")]
+pub use exactly_once::*;
+#[cfg(feature = "async")]
+pub use filter::*;
+#[cfg(feature = "async")]
+pub use latest::LatestValues;
 pub use lowlevel::*;
+#[cfg(feature = "async")]
+pub use memory::MemoryUsage;
+#[cfg(feature = "async")]
+pub use middleware::*;
+#[cfg(feature = "derive")]
+pub use mosquitto_rs_derive::MqttMessage;
+#[cfg(feature = "otel")]
+pub use otel::*;
+#[cfg(feature = "async")]
+pub use overflow::*;
+#[cfg(feature = "async")]
+pub use pool::*;
+#[cfg(feature = "async")]
+pub use presence::*;
+pub use properties::*;
+pub use proxy::*;
+pub use reason::*;
+#[cfg(feature = "record")]
+pub use record::*;
+pub use redirect::*;
+#[cfg(feature = "async")]
+pub use refusal::*;
+#[cfg(feature = "async")]
+pub use report::*;
+pub use resolve::*;
+#[cfg(feature = "async")]
+pub use response::*;
+#[cfg(feature = "json-schema")]
+pub use schema::*;
+#[cfg(feature = "async")]
+pub use scope::ScopedClient;
+#[cfg(feature = "async")]
+pub use selftest::*;
+#[cfg(feature = "async")]
+pub use shutdown::*;
+#[cfg(feature = "async")]
+pub use simulate::*;
+#[cfg(feature = "async")]
+pub use sink::*;
+pub use socket::*;
+#[cfg(feature = "async")]
+pub use stats::*;
+pub use store::*;
+pub use subscribe::*;
+#[cfg(feature = "async")]
+pub use subscriber::*;
+pub use tls::*;
+pub use topic_filter::*;
+pub use topic_tree::*;
+#[cfg(feature = "async")]
+pub use tuning::*;
+#[cfg(feature = "async")]
+pub use typed::*;
+#[cfg(feature = "async")]
+pub use watchdog::*;
+pub use will::*;
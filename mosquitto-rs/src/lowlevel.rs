@@ -1,22 +1,68 @@
-use crate::Error;
+use crate::tls::{copy_password, KeyPassword, KeyPasswordFn};
+use crate::{
+    Error, LastWill, Properties, ReasonCode, SocketOptions, SubscribeOptions, TlsConfig, TlsVersion,
+};
 pub(crate) use libmosquitto_sys as sys;
+use std::borrow::Cow;
 use std::cell::{Ref, RefCell};
 use std::convert::TryInto;
 use std::ffi::{CStr, CString};
 use std::os::raw::{c_char, c_int, c_void};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::sync::Once;
+use std::sync::Mutex;
 use std::time::Duration;
 
-static INIT: Once = Once::new();
+/// Whether mosquitto_lib_init has been called since the last
+/// call to mosquitto_lib_cleanup
+static INITIALIZED: Mutex<bool> = Mutex::new(false);
+
+/// The result of probing the library, which can't change while the
+/// process is running
+static FEATURES: Mutex<Option<LibraryFeatures>> = Mutex::new(None);
 
 fn init_library() {
-    // Note: we never call mosquitto_lib_cleanup as we can't ever
-    // know when it will be safe to do so.
-    INIT.call_once(|| unsafe {
-        sys::mosquitto_lib_init();
-    });
+    let mut initialized = INITIALIZED.lock().unwrap();
+    if !*initialized {
+        unsafe {
+            sys::mosquitto_lib_init();
+        }
+        *initialized = true;
+    }
+}
+
+/// Initialize the mosquitto library.
+///
+/// This happens automatically when the first client is created,
+/// so there is normally no need to call this.  It is provided for
+/// embedders that want to control when initialization happens, for
+/// example to re-initialize after calling [lib_cleanup](fn.lib_cleanup.html).
+/// Calling this when the library is already initialized has no effect.
+pub fn lib_init() {
+    init_library();
+}
+
+/// Release the resources held by the mosquitto library, such as
+/// its TLS state.
+///
+/// The library is never cleaned up automatically, because there is
+/// no point at which it is known to be safe to do so.  Embedders that
+/// need deterministic teardown, such as plugins that are about to be
+/// unloaded, can call this once they are done with the library.
+/// The library is initialized again if another client is created.
+/// Calling this when the library is not initialized has no effect.
+///
+/// # Safety
+/// All clients must have been dropped, and no other thread may be
+/// using the library, for example by creating a client, while this
+/// function runs.
+pub unsafe fn lib_cleanup() {
+    let mut initialized = INITIALIZED.lock().unwrap();
+    if *initialized {
+        sys::mosquitto_lib_cleanup();
+        *initialized = false;
+    }
 }
 
 /// Represents the version of the linked mosquitto client library
@@ -55,10 +101,156 @@ pub fn lib_version() -> LibraryVersion {
     vers
 }
 
+/// The optional features that the linked mosquitto client library was
+/// built with, as reported by [lib_features](fn.lib_features.html)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct LibraryFeatures {
+    pub version: LibraryVersion,
+    /// Connecting using TLS, as configured via
+    /// [Mosq::configure_tls](struct.Mosq.html#method.configure_tls)
+    pub tls: bool,
+    /// Connecting using TLS with a pre-shared key, as configured via
+    /// [Mosq::set_tls_psk](struct.Mosq.html#method.set_tls_psk)
+    pub tls_psk: bool,
+    /// Connecting using WebSockets.
+    /// This is inferred from the version, because libmosquitto only
+    /// gained a WebSocket client transport in 2.1, and earlier versions
+    /// have no option that reveals whether it was compiled in.
+    pub websockets: bool,
+    /// Locating the broker via DNS SRV records
+    pub srv: bool,
+    /// Running the message loop on a thread of its own, as done by
+    /// [Mosq::start_loop_thread](struct.Mosq.html#method.start_loop_thread)
+    pub threading: bool,
+    /// Connecting through a SOCKS5 proxy, as configured via
+    /// [Mosq::set_socks5_proxy](struct.Mosq.html#method.set_socks5_proxy)
+    pub socks: bool,
+}
+
+/// Returns the optional features that the linked mosquitto client
+/// library was built with, so that an application can check for them
+/// up front, rather than discovering that they are missing from the
+/// error returned when they are used.
+///
+/// The library doesn't report its build configuration, so each
+/// feature is detected by making a call that fails with
+/// `MOSQ_ERR_NOT_SUPPORTED` when it is missing, on a client instance
+/// that is created for the purpose and never connected.
+/// The result is computed once, and then reused.
+pub fn lib_features() -> LibraryFeatures {
+    let mut features = FEATURES.lock().unwrap();
+    if let Some(features) = *features {
+        return features;
+    }
+    // The probe creates a client, which requires the library to be set up
+    init_library();
+    let version = lib_version();
+    let mut probed = LibraryFeatures {
+        version,
+        tls: false,
+        tls_psk: false,
+        websockets: (version.major, version.minor) >= (2, 1),
+        srv: false,
+        threading: false,
+        socks: false,
+    };
+    unsafe {
+        let m = sys::mosquitto_new(std::ptr::null(), true, std::ptr::null_mut());
+        if m.is_null() {
+            // Out of memory; report nothing rather than guess, and
+            // try again next time
+            return probed;
+        }
+        let supported = |err: c_int| err != sys::mosq_err_t::MOSQ_ERR_NOT_SUPPORTED as c_int;
+        probed.tls = supported(sys::mosquitto_tls_insecure_set(m, false));
+        // Fails with MOSQ_ERR_INVAL for the missing key when supported
+        probed.tls_psk = supported(sys::mosquitto_tls_psk_set(
+            m,
+            std::ptr::null(),
+            std::ptr::null(),
+            std::ptr::null(),
+        ));
+        // An out of range keepalive is rejected before any lookup
+        probed.srv = supported(sys::mosquitto_connect_srv(
+            m,
+            std::ptr::null(),
+            -1,
+            std::ptr::null(),
+        ));
+        // Fails with MOSQ_ERR_INVAL when no loop thread is running
+        probed.threading = supported(sys::mosquitto_loop_stop(m, false));
+        // Fails with MOSQ_ERR_INVAL for the missing host when supported
+        probed.socks = supported(sys::mosquitto_socks5_set(
+            m,
+            std::ptr::null(),
+            0,
+            std::ptr::null(),
+            std::ptr::null(),
+        ));
+        sys::mosquitto_destroy(m);
+    }
+    features.replace(probed);
+    probed
+}
+
+/// Fails with `Error::LibraryUnsupported` if the linked library lacks
+/// the feature selected by `supported`
+fn require_feature(
+    name: &'static str,
+    supported: impl FnOnce(&LibraryFeatures) -> bool,
+) -> Result<(), Error> {
+    if supported(&lib_features()) {
+        Ok(())
+    } else {
+        Err(Error::LibraryUnsupported(name))
+    }
+}
+
 pub(crate) fn cstr(s: &str) -> Result<CString, Error> {
     Ok(CString::new(s)?)
 }
 
+/// Converts `strings` for passing to a function that takes an array of
+/// C strings, returning the strings, which must outlive the call, along
+/// with the array of pointers to them
+fn cstr_array(strings: &[&str]) -> Result<(Vec<CString>, Vec<*mut c_char>), Error> {
+    let strings = strings
+        .iter()
+        .map(|s| cstr(s))
+        .collect::<Result<Vec<_>, _>>()?;
+    let ptrs = strings.iter().map(|s| s.as_ptr() as *mut c_char).collect();
+    Ok((strings, ptrs))
+}
+
+/// Signals a loop started via
+/// [Mosq::loop_until_stopped](struct.Mosq.html#method.loop_until_stopped)
+/// to stop.
+/// Clones of a handle share the same state, so one can be kept by the
+/// thread running the loop while another is given to whatever decides
+/// when to stop it.
+#[derive(Debug, Clone, Default)]
+pub struct StopHandle {
+    stopped: Arc<AtomicBool>,
+}
+
+impl StopHandle {
+    /// Create a handle that has not yet been signalled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Signal the loop to stop.
+    /// This only sets a flag, so it is safe to call from a signal handler.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns true if `stop` has been called
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+}
+
 /// `Mosq` is the low-level mosquitto client.
 /// You probably want to look at [Client](struct.Client.html) instead.
 pub struct Mosq<CB = ()>
@@ -84,7 +276,9 @@ impl<CB: Callbacks> Mosq<CB> {
             if m.is_null() {
                 Err(Error::Create(std::io::Error::last_os_error()))
             } else {
-                Ok(Self::set_callbacks(Self { m, cb: Some(cb) }))
+                let mosq = Self { m, cb: Some(cb) };
+                mosq.set_callbacks();
+                Ok(mosq)
             }
         }
     }
@@ -104,7 +298,9 @@ impl<CB: Callbacks> Mosq<CB> {
             if m.is_null() {
                 Err(Error::Create(std::io::Error::last_os_error()))
             } else {
-                Ok(Self::set_callbacks(Self { m, cb: Some(cb) }))
+                let mosq = Self { m, cb: Some(cb) };
+                mosq.set_callbacks();
+                Ok(mosq)
             }
         }
     }
@@ -159,28 +355,73 @@ impl<CB: Callbacks> Mosq<CB> {
         keep_alive_interval: Duration,
         bind_address: Option<&str>,
     ) -> Result<(), Error> {
-        let host = cstr(host)?;
+        let context = |e: Error| e.connecting(host, port);
+        let host = cstr(host).map_err(context)?;
         let ba;
         let bind_address = match bind_address {
             Some(b) => {
-                ba = cstr(b)?;
+                ba = cstr(b).map_err(context)?;
                 ba.as_ptr()
             }
             None => std::ptr::null(),
         };
+        let keep_alive_interval = keep_alive_interval
+            .as_secs()
+            .try_into()
+            .map_err(|_| context(Error::InvalidArgument))?;
         let err = unsafe {
             sys::mosquitto_connect_bind(
                 self.m,
                 host.as_ptr(),
                 port,
-                keep_alive_interval
-                    .as_secs()
-                    .try_into()
-                    .map_err(|_| Error::Mosq(sys::mosq_err_t::MOSQ_ERR_INVAL))?,
+                keep_alive_interval,
                 bind_address,
             )
         };
-        Error::result(err, ())
+        Error::result(err, ()).map_err(context)
+    }
+
+    /// Connect to the broker on the specified host and port, sending
+    /// `properties` with the MQTT 5 CONNECT packet, such as a Session
+    /// Expiry Interval.
+    /// libmosquitto sends the same properties when it reconnects.
+    ///
+    /// The other parameters have the same meaning as for
+    /// [connect](#method.connect).
+    pub fn connect_with_properties(
+        &self,
+        host: &str,
+        port: c_int,
+        keep_alive_interval: Duration,
+        bind_address: Option<&str>,
+        properties: &Properties,
+    ) -> Result<(), Error> {
+        let context = |e: Error| e.connecting(host, port);
+        let host = cstr(host).map_err(context)?;
+        let ba;
+        let bind_address = match bind_address {
+            Some(b) => {
+                ba = cstr(b).map_err(context)?;
+                ba.as_ptr()
+            }
+            None => std::ptr::null(),
+        };
+        let keep_alive_interval = keep_alive_interval
+            .as_secs()
+            .try_into()
+            .map_err(|_| context(Error::InvalidArgument))?;
+        let props = properties.to_raw().map_err(context)?;
+        let err = unsafe {
+            sys::mosquitto_connect_bind_v5(
+                self.m,
+                host.as_ptr(),
+                port,
+                keep_alive_interval,
+                bind_address,
+                props.as_ptr(),
+            )
+        };
+        Error::result(err, ()).map_err(context)
     }
 
     /// Connect to the broker on the specified host and port,
@@ -208,28 +449,30 @@ impl<CB: Callbacks> Mosq<CB> {
         keep_alive_interval: Duration,
         bind_address: Option<&str>,
     ) -> Result<(), Error> {
-        let host = cstr(host)?;
+        let context = |e: Error| e.connecting(host, port);
+        let host = cstr(host).map_err(context)?;
         let ba;
         let bind_address = match bind_address {
             Some(b) => {
-                ba = cstr(b)?;
+                ba = cstr(b).map_err(context)?;
                 ba.as_ptr()
             }
             None => std::ptr::null(),
         };
+        let keep_alive_interval = keep_alive_interval
+            .as_secs()
+            .try_into()
+            .map_err(|_| context(Error::InvalidArgument))?;
         let err = unsafe {
             sys::mosquitto_connect_bind_async(
                 self.m,
                 host.as_ptr(),
                 port,
-                keep_alive_interval
-                    .as_secs()
-                    .try_into()
-                    .map_err(|_| Error::Mosq(sys::mosq_err_t::MOSQ_ERR_INVAL))?,
+                keep_alive_interval,
                 bind_address,
             )
         };
-        Error::result(err, ())
+        Error::result(err, ()).map_err(context)
     }
 
     /// Reconnect a disconnected client using the same parameters
@@ -238,12 +481,92 @@ impl<CB: Callbacks> Mosq<CB> {
         Error::result(unsafe { sys::mosquitto_reconnect(self.m) }, ())
     }
 
+    /// Reconnect a disconnected client using the same parameters
+    /// as were originally used to connect it, without waiting for
+    /// the connection to be established.
+    pub fn reconnect_non_blocking(&self) -> Result<(), Error> {
+        Error::result(unsafe { sys::mosquitto_reconnect_async(self.m) }, ())
+    }
+
     /// Disconnect the client.
     /// This will cause the message loop to terminate.
     pub fn disconnect(&self) -> Result<(), Error> {
         Error::result(unsafe { sys::mosquitto_disconnect(self.m) }, ())
     }
 
+    /// Configure the will message, which the broker publishes to
+    /// `topic` if the connection is lost without the client having
+    /// explicitly disconnected.
+    /// The will is sent to the broker as part of connecting, so it must
+    /// be set before calling `connect`; setting it while connected takes
+    /// effect from the next connection.
+    pub fn set_last_will(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<(), Error> {
+        let topic = cstr(topic)?;
+        let payload_len = payload
+            .len()
+            .try_into()
+            .map_err(|_| Error::PayloadTooLarge)?;
+        let err = unsafe {
+            sys::mosquitto_will_set(
+                self.m,
+                topic.as_ptr(),
+                payload_len,
+                payload.as_ptr() as *const _,
+                qos as c_int,
+                retain,
+            )
+        };
+        Error::result(err, ())
+    }
+
+    /// Configure the will message described by `will`, as for
+    /// [set_last_will](#method.set_last_will), along with its MQTT 5
+    /// properties, such as the will delay interval.
+    /// Setting a will that has properties fails unless the client has
+    /// been configured to use MQTT 5.
+    pub fn set_will(&self, will: &LastWill) -> Result<(), Error> {
+        let properties = will.all_properties();
+        if properties.is_empty() {
+            return self.set_last_will(will.topic(), will.payload(), will.qos(), will.retain());
+        }
+        let topic = cstr(will.topic())?;
+        let payload_len = will
+            .payload()
+            .len()
+            .try_into()
+            .map_err(|_| Error::PayloadTooLarge)?;
+        let props = properties.to_raw()?;
+        let err = unsafe {
+            sys::mosquitto_will_set_v5(
+                self.m,
+                topic.as_ptr(),
+                payload_len,
+                will.payload().as_ptr() as *const _,
+                will.qos() as c_int,
+                will.retain(),
+                props.as_ptr() as *mut _,
+            )
+        };
+        Error::result(err, ())?;
+        // libmosquitto owns the list once the will has been set
+        props.into_ptr();
+        Ok(())
+    }
+
+    /// Remove the will message, so that the broker publishes nothing
+    /// if the connection is lost.
+    /// As with [set_last_will](#method.set_last_will), this takes
+    /// effect from the next connection.
+    pub fn clear_last_will(&self) -> Result<(), Error> {
+        Error::result(unsafe { sys::mosquitto_will_clear(self.m) }, ())
+    }
+
     /// Publish a message to the specified topic.
     ///
     /// The payload size can be 0-283, 435 or 455 bytes; other values
@@ -263,22 +586,60 @@ impl<CB: Callbacks> Mosq<CB> {
         qos: QoS,
         retain: bool,
     ) -> Result<MessageId, Error> {
+        let context = |e: Error| e.publishing(topic, None);
         let mut mid = 0;
+        let topic_c = cstr(topic).map_err(context)?;
+        let payload_len = payload
+            .len()
+            .try_into()
+            .map_err(|_| context(Error::PayloadTooLarge))?;
         let err = unsafe {
             sys::mosquitto_publish(
                 self.m,
                 &mut mid,
-                cstr(topic)?.as_ptr(),
-                payload
-                    .len()
-                    .try_into()
-                    .map_err(|_| Error::Mosq(sys::mosq_err_t::MOSQ_ERR_PAYLOAD_SIZE))?,
+                topic_c.as_ptr(),
+                payload_len,
+                payload.as_ptr() as *const _,
+                qos as c_int,
+                retain,
+            )
+        };
+        Error::result(err, mid).map_err(context)
+    }
+
+    /// Publish a message with MQTT 5 properties attached.
+    /// The properties are ignored by brokers that use an earlier
+    /// version of the protocol.
+    /// See [publish](#method.publish) for details of the other parameters.
+    pub fn publish_with_properties(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+        properties: &Properties,
+    ) -> Result<MessageId, Error> {
+        let context = |e: Error| e.publishing(topic, None);
+        let mut mid = 0;
+        let topic_c = cstr(topic).map_err(context)?;
+        let payload_len = payload
+            .len()
+            .try_into()
+            .map_err(|_| context(Error::PayloadTooLarge))?;
+        let props = properties.to_raw().map_err(context)?;
+        let err = unsafe {
+            sys::mosquitto_publish_v5(
+                self.m,
+                &mut mid,
+                topic_c.as_ptr(),
+                payload_len,
                 payload.as_ptr() as *const _,
                 qos as c_int,
                 retain,
+                props.as_ptr(),
             )
         };
-        Error::result(err, mid)
+        Error::result(err, mid).map_err(context)
     }
 
     /// Establish a subscription for topics that match `pattern`.
@@ -290,22 +651,261 @@ impl<CB: Callbacks> Mosq<CB> {
     /// won't be active until the broker has processed the request.
     /// You can use an `on_subscribe` handler to determine when that is ready.
     pub fn subscribe(&self, pattern: &str, qos: QoS) -> Result<MessageId, Error> {
+        let context = |e: Error| e.subscribing(pattern);
+        let mut mid = 0;
+        let pattern_c = cstr(pattern).map_err(context)?;
+        let err =
+            unsafe { sys::mosquitto_subscribe(self.m, &mut mid, pattern_c.as_ptr(), qos as _) };
+        Error::result(err, mid).map_err(context)
+    }
+
+    /// Establish a subscription for topics that match `pattern`, using
+    /// MQTT 5 subscription options.
+    /// The options are ignored when connected using an earlier version
+    /// of the protocol.
+    ///
+    /// Otherwise, this behaves the same as [subscribe](#method.subscribe).
+    pub fn subscribe_with_options(
+        &self,
+        pattern: &str,
+        qos: QoS,
+        options: SubscribeOptions,
+    ) -> Result<MessageId, Error> {
+        self.subscribe_with_properties(pattern, qos, options, &Properties::new())
+    }
+
+    /// Establish a subscription for topics that match `pattern`, using
+    /// MQTT 5 subscription options and properties, such as a
+    /// Subscription Identifier or User Properties.
+    /// The options and properties are ignored when connected using an
+    /// earlier version of the protocol.
+    ///
+    /// Otherwise, this behaves the same as [subscribe](#method.subscribe).
+    pub fn subscribe_with_properties(
+        &self,
+        pattern: &str,
+        qos: QoS,
+        options: SubscribeOptions,
+        properties: &Properties,
+    ) -> Result<MessageId, Error> {
+        let context = |e: Error| e.subscribing(pattern);
+        let mut mid = 0;
+        let pattern_c = cstr(pattern).map_err(context)?;
+        let props = properties.to_raw().map_err(context)?;
+        let err = unsafe {
+            sys::mosquitto_subscribe_v5(
+                self.m,
+                &mut mid,
+                pattern_c.as_ptr(),
+                qos as _,
+                options.bits(),
+                props.as_ptr(),
+            )
+        };
+        Error::result(err, mid).map_err(context)
+    }
+
+    /// Establish subscriptions to each of `patterns`, with the same QoS
+    /// and options, in a single SUBSCRIBE packet, which is much cheaper
+    /// than subscribing to hundreds of filters one at a time.
+    ///
+    /// Returns the MessageId of the request; your
+    /// `Callbacks::on_subscribe` handler will be called with it and
+    /// the QoS granted to each pattern, in order.
+    pub fn subscribe_multiple(
+        &self,
+        patterns: &[&str],
+        qos: QoS,
+        options: SubscribeOptions,
+    ) -> Result<MessageId, Error> {
+        let context = |e: Error| e.subscribing(&patterns.join(", "));
+        let (_patterns_c, ptrs) = cstr_array(patterns).map_err(context)?;
+        let count = ptrs
+            .len()
+            .try_into()
+            .map_err(|_| context(Error::InvalidArgument))?;
         let mut mid = 0;
         let err = unsafe {
-            sys::mosquitto_subscribe(self.m, &mut mid, cstr(pattern)?.as_ptr(), qos as _)
+            sys::mosquitto_subscribe_multiple(
+                self.m,
+                &mut mid,
+                count,
+                ptrs.as_ptr(),
+                qos as _,
+                options.bits(),
+                std::ptr::null(),
+            )
+        };
+        Error::result(err, mid).map_err(context)
+    }
+
+    /// Remove the subscriptions for each of `patterns` in a single
+    /// UNSUBSCRIBE packet.
+    ///
+    /// Returns the MessageId of the request; your
+    /// `Callbacks::on_unsubscribe` handler will be called with it once
+    /// the broker has acknowledged the request.
+    pub fn unsubscribe_multiple(&self, patterns: &[&str]) -> Result<MessageId, Error> {
+        let context = |e: Error| e.subscribing(&patterns.join(", "));
+        let (_patterns_c, ptrs) = cstr_array(patterns).map_err(context)?;
+        let count = ptrs
+            .len()
+            .try_into()
+            .map_err(|_| context(Error::InvalidArgument))?;
+        let mut mid = 0;
+        let err = unsafe {
+            sys::mosquitto_unsubscribe_multiple(
+                self.m,
+                &mut mid,
+                count,
+                ptrs.as_ptr(),
+                std::ptr::null(),
+            )
         };
-        Error::result(err, mid)
+        Error::result(err, mid).map_err(context)
+    }
+
+    /// Remove the subscription for `pattern`, attaching MQTT 5
+    /// properties, such as User Properties, to the request.
+    /// The properties are ignored when connected using an earlier
+    /// version of the protocol.
+    ///
+    /// Returns the MessageId of the unsubscribe request; the
+    /// subscription remains active until the broker has processed it.
+    pub fn unsubscribe_with_properties(
+        &self,
+        pattern: &str,
+        properties: &Properties,
+    ) -> Result<MessageId, Error> {
+        let context = |e: Error| e.subscribing(pattern);
+        let mut mid = 0;
+        let pattern_c = cstr(pattern).map_err(context)?;
+        let props = properties.to_raw().map_err(context)?;
+        let err = unsafe {
+            sys::mosquitto_unsubscribe_v5(self.m, &mut mid, pattern_c.as_ptr(), props.as_ptr())
+        };
+        Error::result(err, mid).map_err(context)
+    }
+
+    /// Remove the subscription for `pattern`.
+    ///
+    /// Returns the MessageId of the unsubscribe request; your
+    /// `Callbacks::on_unsubscribe` handler will be called with it once
+    /// the broker has acknowledged the request.
+    /// The subscription remains active until then.
+    pub fn unsubscribe(&self, pattern: &str) -> Result<MessageId, Error> {
+        let context = |e: Error| e.subscribing(pattern);
+        let mut mid = 0;
+        let pattern_c = cstr(pattern).map_err(context)?;
+        let err = unsafe { sys::mosquitto_unsubscribe(self.m, &mut mid, pattern_c.as_ptr()) };
+        Error::result(err, mid).map_err(context)
     }
 
-    fn set_callbacks(self) -> Self {
+    fn set_callbacks(&self) {
         unsafe {
-            sys::mosquitto_connect_callback_set(self.m, Some(CallbackWrapper::<CB>::connect));
-            sys::mosquitto_disconnect_callback_set(self.m, Some(CallbackWrapper::<CB>::disconnect));
-            sys::mosquitto_publish_callback_set(self.m, Some(CallbackWrapper::<CB>::publish));
-            sys::mosquitto_subscribe_callback_set(self.m, Some(CallbackWrapper::<CB>::subscribe));
-            sys::mosquitto_message_callback_set(self.m, Some(CallbackWrapper::<CB>::message));
+            sys::mosquitto_connect_v5_callback_set(self.m, Some(CallbackWrapper::<CB>::connect));
+            sys::mosquitto_disconnect_v5_callback_set(
+                self.m,
+                Some(CallbackWrapper::<CB>::disconnect),
+            );
+            sys::mosquitto_publish_v5_callback_set(self.m, Some(CallbackWrapper::<CB>::publish));
+            sys::mosquitto_subscribe_v5_callback_set(
+                self.m,
+                Some(CallbackWrapper::<CB>::subscribe),
+            );
+            sys::mosquitto_unsubscribe_v5_callback_set(
+                self.m,
+                Some(CallbackWrapper::<CB>::unsubscribe),
+            );
+            sys::mosquitto_message_v5_callback_set(self.m, Some(CallbackWrapper::<CB>::message));
         }
-        self
+        let logging = self
+            .cb
+            .as_ref()
+            .is_some_and(|cb| cb.logging.load(Ordering::SeqCst));
+        self.install_log_callback(logging);
+    }
+
+    /// Choose whether `Callbacks::on_log` is called.
+    /// While it is, libmosquitto formats a log message for each packet
+    /// that it sends or receives, so it is off by default.
+    pub fn set_logging(&self, enabled: bool) {
+        self.cb
+            .as_ref()
+            .expect("set_logging not to be called on a transient Mosq")
+            .logging
+            .store(enabled, Ordering::SeqCst);
+        self.install_log_callback(enabled);
+    }
+
+    fn install_log_callback(&self, enabled: bool) {
+        unsafe {
+            if enabled {
+                sys::mosquitto_log_callback_set(self.m, Some(CallbackWrapper::<CB>::log));
+            } else {
+                sys::mosquitto_log_callback_set(self.m, None);
+            }
+        }
+    }
+
+    /// Return the client to the state that it was in when it was
+    /// created, but with the specified id and `clean_session` flag,
+    /// keeping the callbacks.
+    /// `id` may only be `None` if `clean_session` is true, in which
+    /// case a random id is used.
+    ///
+    /// Any connection is closed without sending a DISCONNECT, and all
+    /// options, including the username and password, TLS configuration
+    /// and reconnect delay, are reset to their defaults.
+    /// The loop thread must be stopped before calling this.
+    pub fn reinitialise(&mut self, id: Option<&str>, clean_session: bool) -> Result<(), Error> {
+        let cb = self
+            .cb
+            .as_ref()
+            .expect("reinitialise not to be called on a transient Mosq");
+        let id = id.map(cstr).transpose()?;
+        let err = unsafe {
+            sys::mosquitto_reinitialise(
+                self.m,
+                id.as_ref().map_or(std::ptr::null(), |id| id.as_ptr()),
+                clean_session,
+                Arc::as_ptr(cb) as *mut _,
+            )
+        };
+        Error::result(err, ())?;
+        self.set_callbacks();
+        Ok(())
+    }
+
+    /// Returns a copy of the underlying client pointer that can be
+    /// moved to another thread.
+    ///
+    /// # Safety
+    /// The caller must ensure that the returned `MosqPtr` is not used
+    /// after this `Mosq` has been dropped.
+    #[cfg(feature = "async")]
+    pub(crate) unsafe fn ptr(&self) -> MosqPtr {
+        MosqPtr(self.m)
+    }
+
+    /// Returns a flag that is set when the connection is closed other
+    /// than by an explicit disconnect, so that an external event loop
+    /// knows to reconnect.
+    #[cfg(all(unix, feature = "async"))]
+    pub(crate) fn connection_lost(&self) -> Arc<AtomicBool> {
+        self.cb
+            .as_ref()
+            .expect("connection_lost not to be called on a transient Mosq")
+            .connection_lost
+            .clone()
+    }
+
+    /// Returns a function that asks the callbacks of the client whether
+    /// it should read from the broker, for use with `MosqPtr::wants_read`,
+    /// so that an external event loop knows whether to poll for reading.
+    #[cfg(all(unix, feature = "async"))]
+    pub(crate) fn wants_read_fn(&self) -> WantsReadFn {
+        CallbackWrapper::<CB>::wants_read
     }
 
     /// Returns a reference to the callbacks previously registered
@@ -333,7 +933,7 @@ impl<CB: Callbacks> Mosq<CB> {
                     timeout
                         .as_millis()
                         .try_into()
-                        .map_err(|_| Error::Mosq(sys::mosq_err_t::MOSQ_ERR_INVAL))?,
+                        .map_err(|_| Error::InvalidArgument)?,
                     max_packets,
                 ),
                 (),
@@ -341,10 +941,107 @@ impl<CB: Callbacks> Mosq<CB> {
         }
     }
 
+    /// Runs the message loop for the client until `stop` is signalled,
+    /// or the client is explicitly disconnected via the `disconnect`
+    /// method, reconnecting if the connection is lost.
+    ///
+    /// Unlike `loop_until_explicitly_disconnected`, this allows another
+    /// thread, such as a signal handler or shutdown coordinator, to
+    /// stop the loop without disconnecting the client.
+    ///
+    /// `timeout` specifies the longest that each iteration waits for
+    /// network activity, and so bounds the time taken to notice that
+    /// `stop` has been signalled.
+    /// After the connection is lost, the loop waits one second between
+    /// attempts to reconnect.
+    pub fn loop_until_stopped(&self, timeout: Duration, stop: &StopHandle) -> Result<(), Error> {
+        const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+        let timeout_ms: c_int = timeout
+            .as_millis()
+            .try_into()
+            .map_err(|_| Error::InvalidArgument)?;
+        let connection_lost = || {
+            self.cb
+                .as_ref()
+                .map(|cb| cb.connection_lost.swap(false, Ordering::SeqCst))
+                .unwrap_or(false)
+        };
+
+        while !stop.is_stopped() {
+            let max_packets = 1;
+            let err = unsafe { sys::mosquitto_loop(self.m, timeout_ms, max_packets) };
+            match Error::result(err, ()) {
+                Ok(()) => continue,
+                Err(Error::NoConnection) if !connection_lost() => return Ok(()),
+                Err(_) => {}
+            }
+
+            // The connection was lost; keep trying to reconnect
+            // until successful or stopped
+            loop {
+                let deadline = std::time::Instant::now() + RECONNECT_DELAY;
+                while std::time::Instant::now() < deadline {
+                    if stop.is_stopped() {
+                        return Ok(());
+                    }
+                    std::thread::sleep(timeout.min(RECONNECT_DELAY));
+                }
+                if self.reconnect().is_ok() {
+                    connection_lost();
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads and processes up to `max_packets` incoming packets.
+    ///
+    /// This, along with `loop_write` and `loop_misc`, is used to drive
+    /// the client from an external event loop that polls the descriptor
+    /// returned by `socket`, in place of `start_loop_thread`.
+    /// Call this when the socket is readable.
+    pub fn loop_read(&self, max_packets: c_int) -> Result<(), Error> {
+        unsafe { Error::result(sys::mosquitto_loop_read(self.m, max_packets), ()) }
+    }
+
+    /// Writes any outgoing packets that are pending.
+    /// For use with an external event loop; call this when the socket
+    /// is writable and `want_write` returns true.
+    pub fn loop_write(&self) -> Result<(), Error> {
+        // libmosquitto ignores max_packets here
+        let max_packets = 1;
+        unsafe { Error::result(sys::mosquitto_loop_write(self.m, max_packets), ()) }
+    }
+
+    /// Performs periodic housekeeping, such as sending keepalive pings
+    /// and retrying messages.
+    /// For use with an external event loop; call this at least once
+    /// per second.
+    pub fn loop_misc(&self) -> Result<(), Error> {
+        unsafe { Error::result(sys::mosquitto_loop_misc(self.m), ()) }
+    }
+
+    /// Returns true if there are outgoing packets waiting to be
+    /// written, in which case an external event loop should poll
+    /// the socket for writability.
+    pub fn want_write(&self) -> bool {
+        unsafe { sys::mosquitto_want_write(self.m) }
+    }
+
+    /// Tell libmosquitto that the network traffic of the client is
+    /// driven by a thread that it didn't start itself, so that packets
+    /// are left for that thread to write, rather than written from
+    /// within calls such as `publish`
+    pub fn set_threaded(&self, threaded: bool) -> Result<(), Error> {
+        unsafe { Error::result(sys::mosquitto_threaded_set(self.m, threaded), ()) }
+    }
+
     /// Starts a new thread to run the message loop for the client.
     /// The thread will run until the client is disconnected,
     /// or until `stop_loop_thread` is called.
     pub fn start_loop_thread(&self) -> Result<(), Error> {
+        require_feature("threading", |f| f.threading)?;
         unsafe { Error::result(sys::mosquitto_loop_start(self.m), ()) }
     }
 
@@ -353,6 +1050,27 @@ impl<CB: Callbacks> Mosq<CB> {
         unsafe { Error::result(sys::mosquitto_loop_stop(self.m, force_cancel), ()) }
     }
 
+    /// Returns the socket descriptor used for the connection to the
+    /// broker, or `None` if the client is not connected.
+    pub fn socket(&self) -> Option<c_int> {
+        let fd = unsafe { sys::mosquitto_socket(self.m) };
+        if fd == -1 {
+            None
+        } else {
+            Some(fd)
+        }
+    }
+
+    /// Applies `options` to the socket used for the connection to the
+    /// broker.  The client must be connected for this to succeed.
+    /// Options are not preserved across reconnects; the high level
+    /// [Client](struct.Client.html) re-applies them automatically.
+    pub fn set_socket_options(&self, options: &SocketOptions) -> Result<(), Error> {
+        let fd = self.socket().ok_or(Error::NoConnection)?;
+        options.apply(fd)?;
+        Ok(())
+    }
+
     /// Sets an option with a string value
     pub fn set_string_option(&self, option: sys::mosq_opt_t, value: &str) -> Result<(), Error> {
         let err = unsafe { sys::mosquitto_string_option(self.m, option, cstr(value)?.as_ptr()) };
@@ -361,11 +1079,9 @@ impl<CB: Callbacks> Mosq<CB> {
 
     /// Sets an option with an integer value
     pub fn set_int_option(&self, option: sys::mosq_opt_t, value: c_int) -> Result<(), Error> {
-        // Ideally we'd use sys::mosquitto_int_option here, but it isn't present in 1.4
-        let mut value = value;
-        let err = unsafe {
-            sys::mosquitto_opts_set(self.m, option, &mut value as *mut c_int as *mut c_void)
-        };
+        // mosquitto_opts_set only accepts the protocol version and the
+        // SSL_CTX options, so the others must be set this way
+        let err = unsafe { sys::mosquitto_int_option(self.m, option, value) };
         Error::result(err, ())
     }
 
@@ -413,6 +1129,7 @@ impl<CB: Callbacks> Mosq<CB> {
         CERTFILE: AsRef<Path>,
         KEYFILE: AsRef<Path>,
     {
+        require_feature("TLS", |f| f.tls)?;
         let ca_file = path_to_cstring(ca_file)?;
         let ca_path = path_to_cstring(ca_path)?;
         let cert_file = path_to_cstring(cert_file)?;
@@ -432,6 +1149,188 @@ impl<CB: Callbacks> Mosq<CB> {
         Error::result(err, ())
     }
 
+    /// Configures the certificates and keys used to connect to the
+    /// broker over TLS; see [TlsConfig](struct.TlsConfig.html).
+    ///
+    /// Fails with `Error::InvalidArgument` if the config has neither a
+    /// CA file nor a CA path, or has only one of the client certificate
+    /// and key.
+    pub fn set_tls(&self, config: &TlsConfig) -> Result<(), Error> {
+        config.check()?;
+        let pw_callback = match config.key_password() {
+            None => None,
+            #[cfg(feature = "async")]
+            Some(KeyPassword::Raw(callback)) => Some(*callback),
+            Some(KeyPassword::Closure(password)) => {
+                self.cb
+                    .as_ref()
+                    .expect("set_tls not to be called on a transient Mosq")
+                    .key_password
+                    .lock()
+                    .unwrap()
+                    .replace(Arc::clone(password));
+                Some(CallbackWrapper::<CB>::key_password as PasswdCallback)
+            }
+        };
+        // libmosquitto only accepts a config without a CA file or path
+        // once it knows to use the OS certificates.
+        // These are left alone when not requested, so as not to undo
+        // them if they were set separately.
+        if config.use_os_certs() {
+            self.set_tls_use_os_certs(true)?;
+        }
+        if config.ocsp_required() {
+            self.set_tls_ocsp_required(true)?;
+        }
+        self.configure_tls(
+            config.ca_file(),
+            config.ca_path(),
+            config.cert_file(),
+            config.key_file(),
+            pw_callback,
+        )?;
+        if config.has_options() {
+            self.set_tls_options(config.verify_peer(), config.version(), config.ciphers())?;
+        }
+        self.set_tls_insecure(config.insecure())
+    }
+
+    /// Sets the TLS protocol options of the client.
+    ///
+    /// `verify_peer` chooses whether the certificate of the broker is
+    /// verified, which it should be other than when testing.
+    ///
+    /// `version` is the lowest version of the protocol that may be
+    /// used; the highest version that both ends support is still
+    /// negotiated, so `TlsVersion::V1_2` also allows TLS 1.3.
+    /// If `None`, versions older than TLS 1.2 are refused.
+    ///
+    /// `ciphers` restricts the cipher suites that may be used, in the
+    /// format of `openssl ciphers`; if `None`, the OpenSSL defaults
+    /// are used.
+    pub fn set_tls_options(
+        &self,
+        verify_peer: bool,
+        version: Option<TlsVersion>,
+        ciphers: Option<&str>,
+    ) -> Result<(), Error> {
+        require_feature("TLS", |f| f.tls)?;
+        let version = version.map(|v| cstr(v.as_str())).transpose()?;
+        let ciphers = ciphers.map(cstr).transpose()?;
+        let err = unsafe {
+            sys::mosquitto_tls_opts_set(
+                self.m,
+                // SSL_VERIFY_PEER or SSL_VERIFY_NONE
+                if verify_peer { 1 } else { 0 },
+                opt_cstring_to_ptr(&version),
+                opt_cstring_to_ptr(&ciphers),
+            )
+        };
+        Error::result(err, ())
+    }
+
+    /// Choose whether the broker is verified against the CA certificates
+    /// trusted by the operating system, in addition to any configured
+    /// via [configure_tls](#method.configure_tls).
+    /// When this is enabled, `configure_tls` may be called without a
+    /// CA file or path.
+    /// This must be set before connecting.
+    pub fn set_tls_use_os_certs(&self, use_os_certs: bool) -> Result<(), Error> {
+        self.set_int_option(
+            sys::mosq_opt_t::MOSQ_OPT_TLS_USE_OS_CERTS,
+            use_os_certs as c_int,
+        )
+    }
+
+    /// Choose whether the broker must provide a stapled OCSP response
+    /// that shows its certificate hasn't been revoked; the connection
+    /// fails if it doesn't.
+    /// This must be set before connecting.
+    pub fn set_tls_ocsp_required(&self, required: bool) -> Result<(), Error> {
+        self.set_int_option(
+            sys::mosq_opt_t::MOSQ_OPT_TLS_OCSP_REQUIRED,
+            required as c_int,
+        )
+    }
+
+    /// Choose whether to skip checking that the hostname in the
+    /// certificate of the broker matches the host that was connected
+    /// to, which allows a test broker to use a certificate that was
+    /// issued for another host.
+    /// The certificate is still verified against the CA certificates.
+    ///
+    /// This must only be used for testing, as it allows a malicious
+    /// server to impersonate the broker.
+    pub fn set_tls_insecure(&self, insecure: bool) -> Result<(), Error> {
+        require_feature("TLS", |f| f.tls)?;
+        let err = unsafe { sys::mosquitto_tls_insecure_set(self.m, insecure) };
+        Error::result(err, ())
+    }
+
+    /// Configures the client to connect to the broker through the SOCKS5
+    /// proxy listening on `host` and `port`, authenticating with the
+    /// username and password in `credentials` if they are provided.
+    /// The broker host name is passed to the proxy, so TLS verification
+    /// is still performed against it.
+    ///
+    /// libmosquitto has no way to stop using a proxy once one has been
+    /// configured; it can only be replaced by another one.
+    pub fn set_socks5_proxy(
+        &self,
+        host: &str,
+        port: u16,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<(), Error> {
+        require_feature("SOCKS5", |f| f.socks)?;
+        let host = cstr(host)?;
+        let credentials = match credentials {
+            Some((username, password)) => Some((cstr(username)?, cstr(password)?)),
+            None => None,
+        };
+        let (username, password) = match &credentials {
+            Some((username, password)) => (username.as_ptr(), password.as_ptr()),
+            None => (std::ptr::null(), std::ptr::null()),
+        };
+        let err = unsafe {
+            sys::mosquitto_socks5_set(self.m, host.as_ptr(), port.into(), username, password)
+        };
+        Error::result(err, ())
+    }
+
+    /// Configures the client to connect using TLS with a pre-shared key
+    /// rather than certificates, as is common on constrained devices.
+    ///
+    /// `identity` is the identity that the client presents to the
+    /// broker, which must match the one that the broker has configured
+    /// for the key.
+    ///
+    /// `psk_hex` is the pre-shared key, as hexadecimal digits without
+    /// a leading `0x`.
+    ///
+    /// `ciphers` optionally restricts the cipher suites that may be
+    /// used, in the format of `openssl ciphers`; if `None`, the
+    /// library default is used.
+    pub fn set_tls_psk(
+        &self,
+        identity: &str,
+        psk_hex: &str,
+        ciphers: Option<&str>,
+    ) -> Result<(), Error> {
+        require_feature("TLS-PSK", |f| f.tls_psk)?;
+        let identity = cstr(identity)?;
+        let psk = cstr(psk_hex)?;
+        let ciphers = ciphers.map(cstr).transpose()?;
+        let err = unsafe {
+            sys::mosquitto_tls_psk_set(
+                self.m,
+                psk.as_ptr(),
+                identity.as_ptr(),
+                opt_cstring_to_ptr(&ciphers),
+            )
+        };
+        Error::result(err, ())
+    }
+
     /// Controls reconnection behavior when running in the message loop.
     /// By default, if a client is unexpectedly disconnected, mosquitto will
     /// try to reconnect.  The default reconnect parameters are to retry once
@@ -460,11 +1359,11 @@ impl<CB: Callbacks> Mosq<CB> {
                 reconnect_delay
                     .as_secs()
                     .try_into()
-                    .map_err(|_| Error::Mosq(sys::mosq_err_t::MOSQ_ERR_INVAL))?,
+                    .map_err(|_| Error::InvalidArgument)?,
                 max_reconnect_delay
                     .as_secs()
                     .try_into()
-                    .map_err(|_| Error::Mosq(sys::mosq_err_t::MOSQ_ERR_INVAL))?,
+                    .map_err(|_| Error::InvalidArgument)?,
                 use_exponential_backoff,
             )
         };
@@ -494,9 +1393,7 @@ fn path_to_cstring<P: AsRef<Path>>(p: Option<P>) -> Result<Option<CString>, Erro
             #[cfg(windows)]
             {
                 // This isn't 100% correct, but it's probably good enough :-/
-                let s = p
-                    .to_str()
-                    .ok_or(Error::Mosq(sys::mosq_err_t::MOSQ_ERR_MALFORMED_UTF8))?;
+                let s = p.to_str().ok_or(Error::MalformedUtf8)?;
                 let c = cstr(s)?;
                 Ok(Some(c))
             }
@@ -536,12 +1433,71 @@ impl std::fmt::Debug for ConnectionStatus {
 impl ConnectionStatus {
     /// Returns true if the connection attempt was successful.
     pub fn is_successful(&self) -> bool {
-        self.0 == sys::mqtt311_connack_codes::CONNACK_ACCEPTED as _
+        self.0 == sys::mqtt311_connack_codes::CONNACK_ACCEPTED as c_int
+    }
+
+    /// Returns a suggested minimum delay before retrying the connection
+    /// if the broker refused it for a transient reason, such as being
+    /// overloaded, or `None` if retrying is not expected to help.
+    /// Both MQTT 3.1.1 CONNACK codes and MQTT 5 reason codes are
+    /// understood.
+    pub fn retry_after(&self) -> Option<Duration> {
+        if self.0 == sys::mqtt311_connack_codes::CONNACK_REFUSED_SERVER_UNAVAILABLE as c_int {
+            ReasonCode::SERVER_UNAVAILABLE.retry_after()
+        } else if (0x80..=0xff).contains(&self.0) {
+            ReasonCode(self.0 as u8).retry_after()
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the broker refused the connection for a reason
+    /// that retrying is not expected to fix, such as bad credentials.
+    pub fn is_permanent_refusal(&self) -> bool {
+        !self.is_successful() && self.retry_after().is_none()
     }
 }
 
 struct CallbackWrapper<T: Callbacks> {
     cb: RefCell<T>,
+    /// Set when the connection is closed other than by an
+    /// explicit disconnect
+    connection_lost: Arc<AtomicBool>,
+    /// The password set via `Mosq::set_tls`
+    key_password: Mutex<Option<Arc<KeyPasswordFn>>>,
+    /// Set via `Mosq::set_logging`, and restored by `reinitialise`
+    logging: AtomicBool,
+}
+
+/// Calls `Callbacks::wants_read` for a client, see `Mosq::wants_read_fn`
+#[cfg(all(unix, feature = "async"))]
+pub(crate) type WantsReadFn = unsafe fn(*mut sys::mosquitto) -> bool;
+
+/// A copy of the pointer to a libmosquitto client, as returned by
+/// `Mosq::ptr`, without any associated callbacks.
+#[cfg(feature = "async")]
+pub(crate) struct MosqPtr(*mut sys::mosquitto);
+
+#[cfg(feature = "async")]
+unsafe impl Send for MosqPtr {}
+
+#[cfg(feature = "async")]
+impl MosqPtr {
+    /// Call `func` with a transient `Mosq` that refers to the client
+    pub(crate) fn with_client<F: FnOnce(&mut Mosq)>(&self, func: F) {
+        with_transient_client(self.0, func)
+    }
+
+    /// Ask the callbacks of the client whether it should read from the
+    /// broker, via `func`.
+    ///
+    /// # Safety
+    /// `func` must have been returned by `Mosq::wants_read_fn` for the
+    /// client that this refers to.
+    #[cfg(unix)]
+    pub(crate) unsafe fn wants_read(&self, func: WantsReadFn) -> bool {
+        func(self.0)
+    }
 }
 
 fn with_transient_client<F: FnOnce(&mut Mosq)>(m: *mut sys::mosquitto, func: F) {
@@ -554,31 +1510,92 @@ impl<T: Callbacks> CallbackWrapper<T> {
     fn new(cb: T) -> Self {
         Self {
             cb: RefCell::new(cb),
+            connection_lost: Arc::new(AtomicBool::new(false)),
+            key_password: Mutex::new(None),
+            logging: AtomicBool::new(false),
         }
     }
 
+    /// The OpenSSL password callback used for `TlsConfig::with_key_password`.
+    /// libmosquitto passes the client as the userdata, from which the
+    /// password closure is found.
+    unsafe extern "C" fn key_password(
+        buf: *mut c_char,
+        size: c_int,
+        _rwflag: c_int,
+        m: *mut c_void,
+    ) -> c_int {
+        let cb = Self::resolve_self(sys::mosquitto_userdata(m as *mut sys::mosquitto));
+        let password = match cb.key_password.lock().unwrap().clone() {
+            Some(password) => password,
+            None => return 0,
+        };
+        let buf = std::slice::from_raw_parts_mut(buf as *mut u8, size.max(0) as usize);
+        copy_password(&password(), buf) as c_int
+    }
+
+    /// Calls `Callbacks::wants_read` for the client `m`, whose userdata
+    /// refers to this wrapper
+    #[cfg(all(unix, feature = "async"))]
+    unsafe fn wants_read(m: *mut sys::mosquitto) -> bool {
+        let cb = Self::resolve_self(sys::mosquitto_userdata(m));
+        let mut wants_read = true;
+        with_transient_client(m, |client| wants_read = cb.cb.borrow().wants_read(client));
+        wants_read
+    }
+
     unsafe fn resolve_self<'a>(cb: *mut c_void) -> &'a Self {
         &*(cb as *const Self)
     }
 
-    unsafe extern "C" fn connect(m: *mut sys::mosquitto, cb: *mut c_void, rc: c_int) {
+    unsafe extern "C" fn connect(
+        m: *mut sys::mosquitto,
+        cb: *mut c_void,
+        rc: c_int,
+        flags: c_int,
+        props: *const sys::mosquitto_property,
+    ) {
         let cb = Self::resolve_self(cb);
         with_transient_client(m, |client| {
-            cb.cb.borrow().on_connect(client, ConnectionStatus(rc));
+            let props = Properties::from_raw(props);
+            // The session present flag is the lowest bit of the
+            // CONNACK acknowledge flags
+            let session_present = flags & 1 != 0;
+            cb.cb
+                .borrow()
+                .on_connack(client, ConnectionStatus(rc), session_present, &props);
         });
     }
 
-    unsafe extern "C" fn disconnect(m: *mut sys::mosquitto, cb: *mut c_void, rc: c_int) {
+    unsafe extern "C" fn disconnect(
+        m: *mut sys::mosquitto,
+        cb: *mut c_void,
+        rc: c_int,
+        props: *const sys::mosquitto_property,
+    ) {
         let cb = Self::resolve_self(cb);
+        if rc != 0 {
+            cb.connection_lost.store(true, Ordering::SeqCst);
+        }
         with_transient_client(m, |client| {
-            cb.cb.borrow().on_disconnect(client, rc);
+            let props = Properties::from_raw(props);
+            cb.cb.borrow().on_disconnect_v5(client, rc, &props);
         });
     }
 
-    unsafe extern "C" fn publish(m: *mut sys::mosquitto, cb: *mut c_void, mid: MessageId) {
+    unsafe extern "C" fn publish(
+        m: *mut sys::mosquitto,
+        cb: *mut c_void,
+        mid: MessageId,
+        rc: c_int,
+        props: *const sys::mosquitto_property,
+    ) {
         let cb = Self::resolve_self(cb);
         with_transient_client(m, |client| {
-            cb.cb.borrow().on_publish(client, mid);
+            let props = Properties::from_raw(props);
+            cb.cb
+                .borrow()
+                .on_publish_v5(client, mid, ReasonCode(rc as u8), &props);
         });
     }
 
@@ -588,12 +1605,29 @@ impl<T: Callbacks> CallbackWrapper<T> {
         mid: MessageId,
         qos_count: c_int,
         granted_qos: *const c_int,
+        props: *const sys::mosquitto_property,
     ) {
         let cb = Self::resolve_self(cb);
         with_transient_client(m, |client| {
             let granted_qos = std::slice::from_raw_parts(granted_qos, qos_count as usize);
             let granted_qos: Vec<QoS> = granted_qos.iter().map(QoS::from_int).collect();
-            cb.cb.borrow().on_subscribe(client, mid, &granted_qos);
+            let properties = Properties::from_raw(props);
+            cb.cb
+                .borrow()
+                .on_subscribe_v5(client, mid, &granted_qos, &properties);
+        });
+    }
+
+    unsafe extern "C" fn unsubscribe(
+        m: *mut sys::mosquitto,
+        cb: *mut c_void,
+        mid: MessageId,
+        props: *const sys::mosquitto_property,
+    ) {
+        let cb = Self::resolve_self(cb);
+        with_transient_client(m, |client| {
+            let properties = Properties::from_raw(props);
+            cb.cb.borrow().on_unsubscribe_v5(client, mid, &properties);
         });
     }
 
@@ -601,22 +1635,87 @@ impl<T: Callbacks> CallbackWrapper<T> {
         m: *mut sys::mosquitto,
         cb: *mut c_void,
         msg: *const sys::mosquitto_message,
+        props: *const sys::mosquitto_property,
     ) {
         let cb = Self::resolve_self(cb);
         with_transient_client(m, |client| {
             let msg = &*msg;
-            let topic = CStr::from_ptr(msg.topic);
-            let topic = topic.to_string_lossy().to_string();
-            cb.cb.borrow().on_message(
-                client,
-                msg.mid,
-                topic,
-                std::slice::from_raw_parts(msg.payload as *const u8, msg.payloadlen as usize),
-                QoS::from_int(&msg.qos),
-                msg.retain,
-            );
+            let properties = Properties::from_raw(props);
+            let raw_topic = CStr::from_ptr(msg.topic);
+            let message = MessageRef {
+                mid: msg.mid,
+                topic: raw_topic.to_string_lossy(),
+                raw_topic,
+                payload: if msg.payloadlen > 0 {
+                    std::slice::from_raw_parts(msg.payload as *const u8, msg.payloadlen as usize)
+                } else {
+                    &[]
+                },
+                qos: QoS::from_int(&msg.qos),
+                retain: msg.retain,
+                properties: &properties,
+            };
+            cb.cb.borrow().on_message_borrowed(client, &message);
         });
     }
+
+    unsafe extern "C" fn log(
+        m: *mut sys::mosquitto,
+        cb: *mut c_void,
+        level: c_int,
+        message: *const c_char,
+    ) {
+        let cb = Self::resolve_self(cb);
+        with_transient_client(m, |client| {
+            let message = CStr::from_ptr(message).to_string_lossy();
+            cb.cb.borrow().on_log(client, level, &message);
+        });
+    }
+}
+
+/// A borrowed view of a message received from the broker.
+/// The topic and payload refer directly to the buffers owned by
+/// libmosquitto and are only valid for the duration of the
+/// `Callbacks::on_message_borrowed` call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageRef<'a> {
+    /// The message id
+    pub mid: MessageId,
+    /// The destination topic.
+    /// This is borrowed unless the topic is not valid UTF-8, in which
+    /// case the invalid sequences are replaced with U+FFFD.
+    pub topic: Cow<'a, str>,
+    /// The destination topic exactly as it was received.
+    /// Use this rather than `topic` if you need to handle topics that
+    /// are not valid UTF-8 without loss.
+    pub raw_topic: &'a CStr,
+    /// The data payload bytes
+    pub payload: &'a [u8],
+    /// The qos level at which the message was sent
+    pub qos: QoS,
+    /// Whether the message is a retained message
+    pub retain: bool,
+    /// The MQTT 5 properties of the message
+    pub properties: &'a Properties,
+}
+
+/// Returns true if `topic` matches the subscription pattern `sub`,
+/// which may contain the `+` and `#` wildcards.
+pub fn topic_matches_sub(sub: &str, topic: &str) -> Result<bool, Error> {
+    let sub = cstr(sub)?;
+    let topic = cstr(topic)?;
+    let mut result = false;
+    let err =
+        unsafe { sys::mosquitto_topic_matches_sub(sub.as_ptr(), topic.as_ptr(), &mut result) };
+    Error::result(err, result)
+}
+
+/// Checks that `sub` is a valid subscription pattern, returning
+/// `Error::InvalidArgument` if it is not.
+pub fn sub_topic_check(sub: &str) -> Result<(), Error> {
+    let sub = cstr(sub)?;
+    let err = unsafe { sys::mosquitto_sub_topic_check(sub.as_ptr()) };
+    Error::result(err, ())
 }
 
 /// Represents an individual message identifier.
@@ -668,16 +1767,127 @@ pub trait Callbacks {
     /// successful.
     fn on_connect(&self, _client: &mut Mosq, _reason: ConnectionStatus) {}
 
+    /// Called when the connection has been acknowledged by the broker,
+    /// along with the MQTT 5 properties from the CONNACK.
+    /// The default implementation calls `on_connect`.
+    fn on_connect_v5(&self, client: &mut Mosq, reason: ConnectionStatus, _props: &Properties) {
+        self.on_connect(client, reason);
+    }
+
+    /// Called when the connection has been acknowledged by the broker,
+    /// along with the session present flag and the MQTT 5 properties
+    /// from the CONNACK.
+    /// The default implementation calls `on_connect_v5`.
+    fn on_connack(
+        &self,
+        client: &mut Mosq,
+        reason: ConnectionStatus,
+        _session_present: bool,
+        props: &Properties,
+    ) {
+        self.on_connect_v5(client, reason, props);
+    }
+
     /// Called when the broker has received the DISCONNECT command
     fn on_disconnect(&self, _client: &mut Mosq, _reason: c_int) {}
 
+    /// Called when the client has disconnected from the broker,
+    /// along with the MQTT 5 properties from the DISCONNECT sent by
+    /// the broker, if any.
+    /// The default implementation calls `on_disconnect`.
+    fn on_disconnect_v5(&self, client: &mut Mosq, reason: c_int, _props: &Properties) {
+        self.on_disconnect(client, reason);
+    }
+
     /// Called when the message identifier by `mid` has been sent
     /// to the broker successfully.
     fn on_publish(&self, _client: &mut Mosq, _mid: MessageId) {}
 
+    /// Called when the broker has acknowledged the message identified
+    /// by `mid`, along with the reason code and MQTT 5 properties from
+    /// the acknowledgement.
+    /// When connected using an earlier version of the protocol, the
+    /// reason code is always `ReasonCode::SUCCESS`.
+    /// The default implementation calls `on_publish`, regardless of the
+    /// reason code.
+    fn on_publish_v5(
+        &self,
+        client: &mut Mosq,
+        mid: MessageId,
+        _reason: ReasonCode,
+        _props: &Properties,
+    ) {
+        self.on_publish(client, mid);
+    }
+
     /// Called when the broker responds to a subscription request.
     fn on_subscribe(&self, _client: &mut Mosq, _mid: MessageId, _granted_qos: &[QoS]) {}
 
+    /// Called when the broker responds to a subscription request,
+    /// along with the MQTT 5 properties from the SUBACK.
+    /// The default implementation calls `on_subscribe`.
+    fn on_subscribe_v5(
+        &self,
+        client: &mut Mosq,
+        mid: MessageId,
+        granted_qos: &[QoS],
+        _props: &Properties,
+    ) {
+        self.on_subscribe(client, mid, granted_qos);
+    }
+
+    /// Called when the broker has acknowledged the unsubscribe request
+    /// identified by `mid`.
+    fn on_unsubscribe(&self, _client: &mut Mosq, _mid: MessageId) {}
+
+    /// Called when the broker has acknowledged the unsubscribe request
+    /// identified by `mid`, along with the MQTT 5 properties from the
+    /// UNSUBACK.
+    /// The default implementation calls `on_unsubscribe`.
+    fn on_unsubscribe_v5(&self, client: &mut Mosq, mid: MessageId, _props: &Properties) {
+        self.on_unsubscribe(client, mid);
+    }
+
+    /// Called when a message matching a subscription is received
+    /// from the broker.
+    ///
+    /// This is the first point at which a message is delivered, and
+    /// it avoids copying the topic and payload.  The default
+    /// implementation makes owned copies and passes them on to
+    /// `on_message_v5`, so implement this method instead of `on_message_v5`
+    /// if you only need to inspect the message in place.
+    fn on_message_borrowed(&self, client: &mut Mosq, message: &MessageRef) {
+        self.on_message_v5(
+            client,
+            message.mid,
+            message.topic.to_string(),
+            message.payload,
+            message.qos,
+            message.retain,
+            message.properties,
+        );
+    }
+
+    /// Called when a message matching a subscription is received
+    /// from the broker, along with the MQTT 5 properties from the
+    /// PUBLISH, such as the content type or response topic.
+    /// When connected using an earlier version of the protocol, the
+    /// properties are always empty.
+    /// The default implementation calls `on_message`.
+    #[allow(clippy::too_many_arguments)]
+    fn on_message_v5(
+        &self,
+        client: &mut Mosq,
+        mid: MessageId,
+        topic: String,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+        _props: &Properties,
+    ) {
+        self.on_message(client, mid, topic, payload, qos, retain);
+    }
+
     /// Called when a message matching a subscription is received
     /// from the broker
     fn on_message(
@@ -690,6 +1900,22 @@ pub trait Callbacks {
         _retain: bool,
     ) {
     }
+
+    /// Called when libmosquitto produces a log message, once enabled
+    /// via [set_logging](struct.Mosq.html#method.set_logging).
+    /// `level` is one of the `MOSQ_LOG_XXX` values, such as
+    /// `MOSQ_LOG_DEBUG`.
+    fn on_log(&self, _client: &mut Mosq, _level: c_int, _message: &str) {}
+
+    /// Called by an [EventLoop](struct.EventLoop.html) before it polls
+    /// the socket of the client.
+    /// Returning false stops the loop from reading from the broker
+    /// until the next time that it is called, which applies
+    /// backpressure when the application can't keep up.
+    /// The default implementation always returns true.
+    fn wants_read(&self, _client: &mut Mosq) -> bool {
+        true
+    }
 }
 
 impl Callbacks for () {}
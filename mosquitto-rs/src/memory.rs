@@ -0,0 +1,160 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// The memory held by the queues of a [Client](struct.Client.html),
+/// as reported in [Stats](struct.Stats.html); see
+/// [Client::set_memory_budget](struct.Client.html#method.set_memory_budget).
+///
+/// Sizes are the bytes of topic and payload that are held, and don't
+/// include the overhead of the queues themselves.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// The bytes held by received messages that are waiting to be
+    /// yielded by the [subscriber](struct.Client.html#method.subscriber)
+    /// channel or a [filtered subscriber](struct.Client.html#method.filtered_subscriber)
+    pub subscriber_queues: usize,
+    /// The bytes held by the records in the offline queue; see
+    /// [Client::set_offline_queue](struct.Client.html#method.set_offline_queue)
+    pub offline_queue: usize,
+    /// The budget, or `None` if there is no limit
+    pub limit: Option<usize>,
+}
+
+impl MemoryUsage {
+    /// Returns the total number of bytes held
+    pub fn used(&self) -> usize {
+        self.subscriber_queues + self.offline_queue
+    }
+}
+
+/// Accounts for the bytes held by the queues of a client against a
+/// single limit, so that one busy queue can't starve the others of
+/// the memory that the application has allowed for.
+#[derive(Debug)]
+pub(crate) struct MemoryBudget {
+    /// `usize::MAX` if there is no limit
+    limit: AtomicUsize,
+    subscriber: AtomicUsize,
+    offline: AtomicUsize,
+    /// Set when the limit is reached, and cleared once usage falls
+    /// back below three quarters of it, so that a queue hovering at
+    /// the limit isn't reported for every message
+    exceeded: AtomicBool,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self {
+            limit: AtomicUsize::new(usize::MAX),
+            subscriber: AtomicUsize::new(0),
+            offline: AtomicUsize::new(0),
+            exceeded: AtomicBool::new(false),
+        }
+    }
+}
+
+/// The number of bytes that a message is accounted as holding
+pub(crate) fn message_size(topic: &str, payload: &[u8]) -> usize {
+    topic.len() + payload.len()
+}
+
+impl MemoryBudget {
+    pub fn set_limit(&self, limit: Option<usize>) {
+        self.limit
+            .store(limit.unwrap_or(usize::MAX), Ordering::Relaxed);
+        self.exceeded.store(false, Ordering::Relaxed);
+    }
+
+    fn limit(&self) -> Option<usize> {
+        Some(self.limit.load(Ordering::Relaxed)).filter(|&limit| limit != usize::MAX)
+    }
+
+    pub fn used(&self) -> usize {
+        self.subscriber.load(Ordering::Relaxed) + self.offline.load(Ordering::Relaxed)
+    }
+
+    pub fn usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            subscriber_queues: self.subscriber.load(Ordering::Relaxed),
+            offline_queue: self.offline.load(Ordering::Relaxed),
+            limit: self.limit(),
+        }
+    }
+
+    /// Returns true if holding another `size` bytes would exceed the limit
+    pub fn would_exceed(&self, size: usize) -> bool {
+        self.limit()
+            .is_some_and(|limit| self.used().saturating_add(size) > limit)
+    }
+
+    /// Note that the limit was reached, returning the limit if this is
+    /// the first time since usage last fell well below it
+    pub fn record_exceeded(&self) -> Option<usize> {
+        let limit = self.limit()?;
+        if self.exceeded.swap(true, Ordering::Relaxed) {
+            None
+        } else {
+            Some(limit)
+        }
+    }
+
+    fn release(&self, counter: &AtomicUsize, size: usize) {
+        let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |held| {
+            Some(held.saturating_sub(size))
+        });
+        if let Some(limit) = self.limit() {
+            if self.used() < limit / 4 * 3 {
+                self.exceeded.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn acquire_subscriber(&self, size: usize) {
+        self.subscriber.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub fn release_subscriber(&self, size: usize) {
+        self.release(&self.subscriber, size);
+    }
+
+    pub fn acquire_offline(&self, size: usize) {
+        self.offline.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub fn release_offline(&self, size: usize) {
+        self.release(&self.offline, size);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn budget() {
+        let budget = MemoryBudget::default();
+        budget.acquire_subscriber(600);
+        assert!(!budget.would_exceed(1 << 20));
+        assert_eq!(budget.record_exceeded(), None);
+
+        budget.set_limit(Some(1000));
+        budget.acquire_offline(300);
+        assert!(!budget.would_exceed(100));
+        assert!(budget.would_exceed(101));
+        assert_eq!(budget.record_exceeded(), Some(1000));
+        assert_eq!(budget.record_exceeded(), None);
+
+        // Still above three quarters of the limit
+        budget.release_subscriber(100);
+        assert_eq!(budget.record_exceeded(), None);
+        budget.release_subscriber(1000);
+        assert_eq!(
+            budget.usage(),
+            MemoryUsage {
+                subscriber_queues: 0,
+                offline_queue: 300,
+                limit: Some(1000),
+            }
+        );
+        assert_eq!(budget.record_exceeded(), Some(1000));
+    }
+}
@@ -0,0 +1,222 @@
+use crate::{Error, Message, Properties, Property, QoS};
+
+/// A message that is about to be published, as presented to a
+/// [PublishMiddleware](trait.PublishMiddleware.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingPublish {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: QoS,
+    pub retain: bool,
+    /// MQTT 5 properties to attach to the message.
+    /// These are ignored by brokers that use an earlier version
+    /// of the protocol.
+    pub properties: Properties,
+}
+
+/// A `PublishMiddleware` can inspect, modify or deny outgoing
+/// messages before they are handed to libmosquitto.
+///
+/// Middleware is installed via
+/// [Client::add_publish_middleware](struct.Client.html#method.add_publish_middleware)
+/// and runs in the order that it was added, with each layer seeing
+/// the changes made by the layers before it.
+/// Returning an error denies the publish; the error is returned
+/// from `publish` and the remaining layers are not run.
+///
+/// Any `Fn(&mut OutgoingPublish) -> Result<(), Error>` closure can
+/// be used as middleware:
+///
+/// ```no_run
+/// use mosquitto_rs::*;
+///
+/// let mut client = Client::with_auto_id()?;
+/// client.add_publish_middleware(|publish: &mut OutgoingPublish| {
+///     publish.payload.make_ascii_uppercase();
+///     Ok(())
+/// });
+/// # Ok::<(), Error>(())
+/// ```
+pub trait PublishMiddleware: Send + Sync {
+    /// Process `publish`, returning an error to deny it
+    fn on_publish(&self, publish: &mut OutgoingPublish) -> Result<(), Error>;
+}
+
+impl<F> PublishMiddleware for F
+where
+    F: Fn(&mut OutgoingPublish) -> Result<(), Error> + Send + Sync,
+{
+    fn on_publish(&self, publish: &mut OutgoingPublish) -> Result<(), Error> {
+        (self)(publish)
+    }
+}
+
+/// Denies publishes to topics that don't start with a given prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequireTopicPrefix(pub String);
+
+impl PublishMiddleware for RequireTopicPrefix {
+    fn on_publish(&self, publish: &mut OutgoingPublish) -> Result<(), Error> {
+        if publish.topic.starts_with(&self.0) {
+            Ok(())
+        } else {
+            Err(Error::Denied(format!(
+                "topic {} is outside of {}",
+                publish.topic, self.0
+            )))
+        }
+    }
+}
+
+/// Attaches an MQTT 5 User Property to every outgoing message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddUserProperty {
+    pub name: String,
+    pub value: String,
+}
+
+impl PublishMiddleware for AddUserProperty {
+    fn on_publish(&self, publish: &mut OutgoingPublish) -> Result<(), Error> {
+        publish.properties.push(Property::UserProperty(
+            self.name.clone(),
+            self.value.clone(),
+        ));
+        Ok(())
+    }
+}
+
+/// A `MessageMiddleware` can drop, rewrite or annotate received
+/// messages before they are delivered to the
+/// [subscriber](struct.Client.html#method.subscriber) channel.
+///
+/// Middleware is installed via
+/// [Client::add_message_middleware](struct.Client.html#method.add_message_middleware)
+/// and runs in the order that it was added.
+/// Returning `None` drops the message, and the remaining layers
+/// are not run.
+///
+/// The middleware runs on the libmosquitto loop thread, so it
+/// should avoid blocking.
+///
+/// Any `Fn(Message) -> Option<Message>` closure can be used as
+/// middleware:
+///
+/// ```no_run
+/// use mosquitto_rs::*;
+///
+/// let mut client = Client::with_auto_id()?;
+/// client.add_message_middleware(|message: Message| {
+///     if message.payload.is_empty() {
+///         None
+///     } else {
+///         Some(message)
+///     }
+/// });
+/// # Ok::<(), Error>(())
+/// ```
+pub trait MessageMiddleware: Send + Sync {
+    /// Process `message`, returning `None` to drop it
+    fn on_message(&self, message: Message) -> Option<Message>;
+}
+
+impl<F> MessageMiddleware for F
+where
+    F: Fn(Message) -> Option<Message> + Send + Sync,
+{
+    fn on_message(&self, message: Message) -> Option<Message> {
+        (self)(message)
+    }
+}
+
+/// Drops received messages whose topics don't start with a given prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllowTopicPrefix(pub String);
+
+impl MessageMiddleware for AllowTopicPrefix {
+    fn on_message(&self, message: Message) -> Option<Message> {
+        if message.topic.starts_with(&self.0) {
+            Some(message)
+        } else {
+            None
+        }
+    }
+}
+
+/// Run `message` through each layer of `middleware` in turn
+pub(crate) fn apply_message_middleware(
+    middleware: &[Box<dyn MessageMiddleware>],
+    message: Message,
+) -> Option<Message> {
+    middleware
+        .iter()
+        .try_fold(message, |message, layer| layer.on_message(message))
+}
+
+/// Run `publish` through each layer of `middleware` in turn
+pub(crate) fn apply_publish_middleware(
+    middleware: &[Box<dyn PublishMiddleware>],
+    publish: &mut OutgoingPublish,
+) -> Result<(), Error> {
+    for layer in middleware {
+        layer.on_publish(publish)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn layers() {
+        let middleware: Vec<Box<dyn PublishMiddleware>> = vec![
+            Box::new(RequireTopicPrefix("site/".to_string())),
+            Box::new(AddUserProperty {
+                name: "origin".to_string(),
+                value: "test".to_string(),
+            }),
+        ];
+        let mut publish = OutgoingPublish {
+            topic: "site/a".to_string(),
+            payload: vec![],
+            qos: QoS::AtMostOnce,
+            retain: false,
+            properties: Properties::new(),
+        };
+        apply_publish_middleware(&middleware, &mut publish).unwrap();
+        assert_eq!(
+            publish.properties.user_properties().collect::<Vec<_>>(),
+            vec![("origin", "test")]
+        );
+
+        publish.topic = "other/a".to_string();
+        assert!(matches!(
+            apply_publish_middleware(&middleware, &mut publish),
+            Err(Error::Denied(_))
+        ));
+    }
+
+    #[test]
+    fn message_layers() {
+        let middleware: Vec<Box<dyn MessageMiddleware>> = vec![
+            Box::new(AllowTopicPrefix("site/".to_string())),
+            Box::new(|mut message: Message| {
+                message.payload.reverse();
+                Some(message)
+            }),
+        ];
+        let message = Message {
+            topic: "site/a".to_string(),
+            payload: b"ab".to_vec(),
+            ..Message::default()
+        };
+        let message = apply_message_middleware(&middleware, message).unwrap();
+        assert_eq!(message.payload, b"ba");
+
+        let message = Message {
+            topic: "other/a".to_string(),
+            ..Message::default()
+        };
+        assert!(apply_message_middleware(&middleware, message).is_none());
+    }
+}
@@ -0,0 +1,417 @@
+use crate::lowlevel::{Callbacks, MessageId, Mosq, QoS};
+use crate::memory::MemoryBudget;
+use crate::stats::QueueCounters;
+use crate::{Error, Properties, Property, QueueStats, Store, StoreId, MESSAGE_ID_PROPERTY};
+use async_channel::{bounded, Receiver, Sender};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A publish that has been recorded in the offline queue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct QueuedPublish {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: QoS,
+    pub retain: bool,
+    /// The id assigned by `AssignMessageIds`, which is sent again
+    /// along with the message
+    pub message_id: Option<String>,
+    pub queued_at: SystemTime,
+}
+
+impl QueuedPublish {
+    fn encode(&self) -> Vec<u8> {
+        let message_id = self.message_id.as_deref().unwrap_or("");
+        let mut record =
+            Vec::with_capacity(18 + self.topic.len() + message_id.len() + self.payload.len());
+        record.push(self.qos as u8);
+        record.push(self.retain as u8);
+        let queued_at = self
+            .queued_at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        record.extend_from_slice(&queued_at.to_be_bytes());
+        for s in [self.topic.as_str(), message_id] {
+            record.extend_from_slice(&(s.len() as u32).to_be_bytes());
+            record.extend_from_slice(s.as_bytes());
+        }
+        record.extend_from_slice(&self.payload);
+        record
+    }
+
+    fn is_expired(&self, ttl: Option<Duration>, now: SystemTime) -> bool {
+        match (ttl, now.duration_since(self.queued_at)) {
+            (Some(ttl), Ok(age)) => age > ttl,
+            _ => false,
+        }
+    }
+
+    fn decode(record: &[u8]) -> Option<Self> {
+        let qos = match record.first()? {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            _ => return None,
+        };
+        let retain = *record.get(1)? != 0;
+        let queued_at = u64::from_be_bytes(record.get(2..10)?.try_into().ok()?);
+        let queued_at = UNIX_EPOCH + Duration::from_millis(queued_at);
+        let mut rest = record.get(10..)?;
+        let mut string = || {
+            let len = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+            let s = rest.get(4..4usize.checked_add(len)?)?;
+            let s = String::from_utf8(s.to_vec()).ok()?;
+            rest = &rest[4 + len..];
+            Some(s)
+        };
+        let topic = string()?;
+        let message_id = Some(string()?).filter(|id| !id.is_empty());
+        Some(Self {
+            topic,
+            payload: rest.to_vec(),
+            qos,
+            retain,
+            message_id,
+            queued_at,
+        })
+    }
+}
+
+/// Records outgoing publishes in a [Store](trait.Store.html) until
+/// libmosquitto reports that they have been sent, so that they
+/// survive a restart of the application.
+pub(crate) struct OfflineQueue {
+    store: Box<dyn Store>,
+    /// The number of entries in `store`
+    len: usize,
+    /// The size of each entry in `store`
+    sizes: HashMap<StoreId, usize>,
+    budget: Arc<MemoryBudget>,
+    /// The entries that libmosquitto is currently sending
+    sending: HashMap<MessageId, StoreId>,
+    /// The entries that are being handed to libmosquitto
+    claimed: HashSet<StoreId>,
+    /// The number of entries that expired before they could be sent
+    expired: u64,
+    counters: QueueCounters,
+    /// Signalled when an entry is removed, to wake a publish that is
+    /// waiting for room in the queue
+    space: (Sender<()>, Receiver<()>),
+}
+
+impl OfflineQueue {
+    pub fn new(store: Box<dyn Store>) -> Result<Self, Error> {
+        let sizes: HashMap<StoreId, usize> = store
+            .pending()?
+            .into_iter()
+            .map(|(id, record)| (id, record.len()))
+            .collect();
+        let len = sizes.len();
+        let counters = QueueCounters::default();
+        counters.record_depth(len);
+        let budget = Arc::new(MemoryBudget::default());
+        budget.acquire_offline(sizes.values().sum());
+        Ok(Self {
+            store,
+            len,
+            sizes,
+            budget,
+            sending: HashMap::new(),
+            claimed: HashSet::new(),
+            expired: 0,
+            counters,
+            space: bounded(1),
+        })
+    }
+
+    /// Account for the entries against `budget`, rather than a
+    /// budget of their own
+    pub fn with_budget(mut self, budget: Arc<MemoryBudget>) -> Self {
+        let held = self.sizes.values().sum();
+        self.budget.release_offline(held);
+        budget.acquire_offline(held);
+        self.budget = budget;
+        self
+    }
+
+    pub fn expired(&self) -> u64 {
+        self.expired
+    }
+
+    pub fn stats(&self, capacity: Option<usize>) -> QueueStats {
+        self.counters.snapshot(self.len, capacity)
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.expired = 0;
+        self.counters.reset();
+    }
+
+    /// Returns true if the queue holds at least `capacity` entries
+    pub fn is_full(&self, capacity: Option<usize>) -> bool {
+        capacity.is_some_and(|capacity| self.len >= capacity)
+    }
+
+    /// Returns true if recording another `size` bytes would exceed the
+    /// memory budget.
+    /// An empty queue is never over budget, as it has nothing that
+    /// could be dropped, or sent, to make room.
+    pub fn is_over_budget(&self, size: usize) -> bool {
+        self.len > 0 && self.budget.would_exceed(size)
+    }
+
+    /// Returns a channel that is signalled when an entry is removed
+    pub fn space(&self) -> Receiver<()> {
+        self.space.1.clone()
+    }
+
+    /// Record a publish before it is handed to libmosquitto
+    pub fn record(&mut self, publish: &QueuedPublish) -> Result<StoreId, Error> {
+        let record = publish.encode();
+        let id = self.store.append(&record)?;
+        self.len += 1;
+        self.sizes.insert(id, record.len());
+        self.budget.acquire_offline(record.len());
+        self.counters.record_depth(self.len);
+        Ok(id)
+    }
+
+    /// Count a message that was discarded rather than recorded
+    pub fn record_dropped(&self) {
+        self.counters.record_dropped();
+    }
+
+    /// Discard the oldest entry that libmosquitto isn't already sending,
+    /// returning it, or `None` if there is no such entry
+    pub fn drop_oldest(&mut self) -> Result<Option<QueuedPublish>, Error> {
+        let sending = self.busy();
+        let oldest = self
+            .store
+            .pending()?
+            .into_iter()
+            .find(|(id, _)| !sending.contains(id));
+        match oldest {
+            Some((id, record)) => {
+                self.remove(id)?;
+                self.counters.record_dropped();
+                Ok(QueuedPublish::decode(&record))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the entries that libmosquitto is sending, or is about to
+    fn busy(&self) -> HashSet<StoreId> {
+        self.sending
+            .values()
+            .chain(&self.claimed)
+            .copied()
+            .collect()
+    }
+
+    fn remove(&mut self, id: StoreId) -> Result<(), Error> {
+        self.store.ack(id)?;
+        self.claimed.remove(&id);
+        self.len = self.len.saturating_sub(1);
+        if let Some(size) = self.sizes.remove(&id) {
+            self.budget.release_offline(size);
+        }
+        let _ = self.space.0.try_send(());
+        Ok(())
+    }
+
+    /// Note that entry `id` is about to be handed to libmosquitto, so
+    /// that `resend` and `drop_oldest` leave it alone until it is passed
+    /// to `sending`, `release` or `discard`
+    pub fn claim(&mut self, id: StoreId) {
+        self.claimed.insert(id);
+    }
+
+    /// Leave entry `id`, which libmosquitto didn't accept, to be sent
+    /// by `resend`
+    pub fn release(&mut self, id: StoreId) {
+        self.claimed.remove(&id);
+    }
+
+    /// Note that libmosquitto accepted entry `id` as message `mid`
+    pub fn sending(&mut self, id: StoreId, mid: MessageId) {
+        self.claimed.remove(&id);
+        self.sending.insert(mid, id);
+    }
+
+    /// Returns true if `mid` is an entry that libmosquitto is sending
+    pub fn knows(&self, mid: MessageId) -> bool {
+        self.sending.contains_key(&mid)
+    }
+
+    /// Remove entry `id`, which libmosquitto will not send
+    pub fn discard(&mut self, id: StoreId) -> Result<(), Error> {
+        self.remove(id)
+    }
+
+    /// Remove the entry corresponding to `mid`, now that it has been
+    /// sent, or return `None` if there is no such entry
+    pub fn sent(&mut self, mid: MessageId) -> Option<Result<(), Error>> {
+        let id = self.sending.remove(&mid)?;
+        Some(self.remove(id))
+    }
+
+    /// Forget which entries libmosquitto was sending, as it has
+    /// discarded them; they will be sent again by `resend`
+    pub fn reset(&mut self) {
+        self.sending.clear();
+    }
+
+    /// Hand the entries that libmosquitto isn't already sending to
+    /// `client`, stopping at the first failure.
+    /// Entries that were queued longer than `ttl` ago are discarded
+    /// and passed to `expired`, as are entries that cannot be decoded.
+    pub fn resend<CB: Callbacks, F: FnMut(&QueuedPublish)>(
+        &mut self,
+        client: &Mosq<CB>,
+        ttl: Option<Duration>,
+        mut expired: F,
+    ) -> Result<(), Error> {
+        let sending = self.busy();
+        let now = SystemTime::now();
+        for (id, record) in self.store.pending()? {
+            if sending.contains(&id) {
+                continue;
+            }
+            match QueuedPublish::decode(&record) {
+                Some(p) if p.is_expired(ttl, now) => {
+                    self.remove(id)?;
+                    self.expired += 1;
+                    expired(&p);
+                }
+                Some(p) => {
+                    let mid = match p.message_id {
+                        Some(message_id) => {
+                            let props: Properties = std::iter::once(Property::UserProperty(
+                                MESSAGE_ID_PROPERTY.to_string(),
+                                message_id,
+                            ))
+                            .collect();
+                            client.publish_with_properties(
+                                &p.topic, &p.payload, p.qos, p.retain, &props,
+                            )?
+                        }
+                        None => client.publish(&p.topic, &p.payload, p.qos, p.retain)?,
+                    };
+                    self.sending.insert(mid, id);
+                }
+                None => self.remove(id)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for OfflineQueue {
+    fn drop(&mut self) {
+        self.budget.release_offline(self.sizes.values().sum());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        let publish = QueuedPublish {
+            topic: "a/b".to_string(),
+            payload: b"hello".to_vec(),
+            qos: QoS::ExactlyOnce,
+            retain: true,
+            message_id: None,
+            queued_at: UNIX_EPOCH + Duration::from_millis(1234),
+        };
+        assert_eq!(
+            QueuedPublish::decode(&publish.encode()),
+            Some(publish.clone())
+        );
+        let publish = QueuedPublish {
+            message_id: Some("id".to_string()),
+            ..publish
+        };
+        assert_eq!(
+            QueuedPublish::decode(&publish.encode()),
+            Some(publish.clone())
+        );
+        assert_eq!(
+            QueuedPublish::decode(&[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 9, b'a']),
+            None
+        );
+
+        let ttl = Some(Duration::from_secs(1));
+        let now = publish.queued_at;
+        assert!(!publish.is_expired(ttl, now + Duration::from_secs(1)));
+        assert!(publish.is_expired(ttl, now + Duration::from_secs(2)));
+        assert!(!publish.is_expired(None, now + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let mut queue = OfflineQueue::new(Box::new(crate::MemoryStore::new())).unwrap();
+        let space = queue.space();
+        for topic in ["a", "b", "c"] {
+            let publish = QueuedPublish {
+                topic: topic.to_string(),
+                payload: vec![],
+                qos: QoS::AtLeastOnce,
+                retain: false,
+                message_id: None,
+                queued_at: SystemTime::now(),
+            };
+            let id = queue.record(&publish).unwrap();
+            if topic == "a" {
+                queue.sending(id, 1);
+            }
+        }
+        assert!(queue.is_full(Some(3)));
+        assert!(!queue.is_full(None));
+
+        // "a" is being sent, so "b" is the oldest that can be dropped
+        let dropped = queue.drop_oldest().unwrap().unwrap();
+        assert_eq!(dropped.topic, "b");
+        assert!(!queue.is_full(Some(3)));
+        assert!(space.try_recv().is_ok());
+
+        let stats = queue.stats(Some(3));
+        assert_eq!(stats.depth, 2);
+        assert_eq!(stats.dropped, 1);
+    }
+
+    #[test]
+    fn claim() {
+        let mut queue = OfflineQueue::new(Box::new(crate::MemoryStore::new())).unwrap();
+        let mut ids = vec![];
+        for topic in ["a", "b"] {
+            let publish = QueuedPublish {
+                topic: topic.to_string(),
+                payload: vec![],
+                qos: QoS::AtLeastOnce,
+                retain: false,
+                message_id: None,
+                queued_at: SystemTime::now(),
+            };
+            ids.push(queue.record(&publish).unwrap());
+        }
+
+        // "a" is being handed to libmosquitto, so is left alone
+        queue.claim(ids[0]);
+        assert_eq!(queue.drop_oldest().unwrap().unwrap().topic, "b");
+        assert!(queue.drop_oldest().unwrap().is_none());
+
+        queue.sending(ids[0], 1);
+        assert!(queue.knows(1));
+        assert!(queue.drop_oldest().unwrap().is_none());
+        assert!(queue.sent(1).is_some());
+        assert!(!queue.knows(1));
+        assert_eq!(queue.stats(None).depth, 0);
+    }
+}
@@ -0,0 +1,124 @@
+//! OpenTelemetry context propagation, enabled by the `otel` feature.
+use crate::{Error, Message, OutgoingPublish, Properties, Property, PublishMiddleware};
+use opentelemetry::global::{self, BoxedSpan};
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::trace::{SpanKind, TraceContextExt, Tracer};
+use opentelemetry::Context;
+
+const TRACER_NAME: &str = "mosquitto-rs";
+
+/// Writes propagation fields as MQTT 5 User Properties,
+/// replacing any existing property with the same name
+struct PropertiesInjector<'a>(&'a mut Properties);
+
+impl<'a> Injector for PropertiesInjector<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        let props = std::mem::take(self.0);
+        *self.0 = props
+            .into_iter()
+            .filter(|prop| !matches!(prop, Property::UserProperty(name, _) if name == key))
+            .chain(std::iter::once(Property::UserProperty(
+                key.to_string(),
+                value,
+            )))
+            .collect();
+    }
+}
+
+/// Reads propagation fields from MQTT 5 User Properties
+struct PropertiesExtractor<'a>(&'a Properties);
+
+impl<'a> Extractor for PropertiesExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .user_properties()
+            .find_map(|(name, value)| if name == key { Some(value) } else { None })
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.user_properties().map(|(name, _)| name).collect()
+    }
+}
+
+/// A [PublishMiddleware](trait.PublishMiddleware.html) that starts a
+/// producer span for each outgoing message, as a child of the current
+/// OpenTelemetry context, and injects it into the message as User
+/// Properties using the globally configured propagator.
+/// With the W3C Trace Context propagator, that is the `traceparent`
+/// and `tracestate` properties.
+///
+/// On the receiving side, use
+/// [Message::start_consumer_span](struct.Message.html#method.start_consumer_span)
+/// to continue the trace.
+///
+/// ```no_run
+/// use mosquitto_rs::*;
+///
+/// let mut client = Client::with_auto_id()?;
+/// client.add_publish_middleware(TracePropagation);
+/// # Ok::<(), Error>(())
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TracePropagation;
+
+impl PublishMiddleware for TracePropagation {
+    fn on_publish(&self, publish: &mut OutgoingPublish) -> Result<(), Error> {
+        let tracer = global::tracer(TRACER_NAME);
+        let span = tracer
+            .span_builder(format!("{} publish", publish.topic))
+            .with_kind(SpanKind::Producer)
+            .start_with_context(&tracer, &Context::current());
+        let cx = Context::current().with_span(span);
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&cx, &mut PropertiesInjector(&mut publish.properties))
+        });
+        Ok(())
+    }
+}
+
+impl Message {
+    /// Returns the OpenTelemetry context that the publisher propagated
+    /// via the User Properties of this message, using the globally
+    /// configured propagator.
+    /// The context is empty if none was propagated.
+    pub fn trace_context(&self) -> Context {
+        global::get_text_map_propagator(|propagator| {
+            propagator.extract(&PropertiesExtractor(&self.properties))
+        })
+    }
+
+    /// Start a consumer span for processing this message, as a child of
+    /// the context returned by [trace_context](#method.trace_context),
+    /// and return a context holding that span.
+    /// The span ends when the returned context, and any that are
+    /// derived from it, are dropped.
+    pub fn start_consumer_span(&self) -> Context {
+        let parent = self.trace_context();
+        let tracer = global::tracer(TRACER_NAME);
+        let span: BoxedSpan = tracer
+            .span_builder(format!("{} receive", self.topic))
+            .with_kind(SpanKind::Consumer)
+            .start_with_context(&tracer, &parent);
+        parent.with_span(span)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn inject_replaces() {
+        let mut props = Properties::new();
+        props.push(Property::UserProperty(
+            "traceparent".to_string(),
+            "old".to_string(),
+        ));
+        props.push(Property::UserProperty("other".to_string(), "1".to_string()));
+        PropertiesInjector(&mut props).set("traceparent", "new".to_string());
+
+        let extractor = PropertiesExtractor(&props);
+        assert_eq!(extractor.get("traceparent"), Some("new"));
+        assert_eq!(extractor.keys(), vec!["other", "traceparent"]);
+    }
+}
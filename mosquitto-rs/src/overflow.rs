@@ -0,0 +1,39 @@
+/// Controls what happens when a message is added to one of the bounded
+/// internal queues of a [Client](struct.Client.html) while it is full.
+///
+/// The policy for the queue of received messages is set via
+/// [Client::set_subscriber_overflow_policy](struct.Client.html#method.set_subscriber_overflow_policy),
+/// and the policy for the offline queue of outgoing messages via
+/// [Client::set_offline_queue_capacity](struct.Client.html#method.set_offline_queue_capacity).
+/// Messages that are dropped are counted in [Stats](struct.Stats.html).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Discard the oldest message in the queue to make room for the
+    /// new one, which suits data where only the latest values matter
+    DropOldest,
+    /// Discard the new message
+    #[default]
+    DropNewest,
+    /// Wait until there is room in the queue.
+    ///
+    /// For received messages, this stops the client from reading from
+    /// the network until the subscriber catches up, which in turn
+    /// causes the broker to stop sending.
+    /// A client driven by an [EventLoop](struct.EventLoop.html) holds
+    /// on to the messages that it has already read and stops polling
+    /// its socket, without holding up the other clients of the loop;
+    /// any other client waits in its network thread.
+    /// If this lasts longer than the keepalive interval, the broker
+    /// may disconnect the client.
+    ///
+    /// For outgoing messages, `publish` waits until the queue drains.
+    Block,
+    /// Discard the new message and report the overflow.
+    ///
+    /// For received messages, `Event::SubscriberOverflow` is reported
+    /// via [Client::events](struct.Client.html#method.events).
+    ///
+    /// For outgoing messages, `publish` fails with `Error::QueueFull`,
+    /// leaving it to the caller to decide what to do with the message.
+    Error,
+}
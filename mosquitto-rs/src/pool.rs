@@ -0,0 +1,371 @@
+use crate::lowlevel::sub_topic_check;
+use crate::{Client, Error, Message, QoS, Subscriber};
+use async_channel::{bounded, Receiver, Sender};
+use futures_lite::FutureExt;
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Where the workers of a [WorkerPool](struct.WorkerPool.html) connect,
+/// and the shared subscription that they hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerPoolConfig {
+    pub host: String,
+    pub port: c_int,
+    pub keep_alive_interval: Duration,
+    /// The name of the share group, which must not contain `/`, `+`
+    /// or `#`.
+    /// Other applications that subscribe with the same group name
+    /// share the work with the pool.
+    pub group: String,
+    /// The topic filter, which may contain wildcards
+    pub filter: String,
+    pub qos: QoS,
+    /// How long a worker that is being stopped continues to handle
+    /// messages after it unsubscribes, so that messages that the broker
+    /// had already sent to it aren't lost
+    pub drain_period: Duration,
+}
+
+impl WorkerPoolConfig {
+    /// Create a config for the share group `group` of `filter`, with
+    /// QoS 1, a 60 second keepalive and a one second drain period
+    pub fn new(host: &str, port: c_int, group: &str, filter: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            keep_alive_interval: Duration::from_secs(60),
+            group: group.to_string(),
+            filter: filter.to_string(),
+            qos: QoS::AtLeastOnce,
+            drain_period: Duration::from_secs(1),
+        }
+    }
+
+    /// Returns the pattern that the workers subscribe to, which is
+    /// of the form `$share/{group}/{filter}`
+    pub fn pattern(&self) -> String {
+        format!("$share/{}/{}", self.group, self.filter)
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        if self.group.is_empty() || self.group.contains(['/', '+', '#']) {
+            return Err(Error::InvalidArgument);
+        }
+        sub_topic_check(&self.filter)
+    }
+}
+
+/// The activity of one of the workers of a
+/// [WorkerPool](struct.WorkerPool.html), as reported by
+/// [WorkerPool::stats](struct.WorkerPool.html#method.stats)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerStats {
+    /// Identifies the worker; this is the value that was passed to the
+    /// client factory and handler of the pool
+    pub id: usize,
+    /// The number of messages that the worker has handled
+    pub messages: u64,
+    /// The total time spent in the handler
+    pub busy_time: Duration,
+    /// The number of messages waiting for the worker
+    pub queued: usize,
+}
+
+#[derive(Default)]
+struct WorkerCounters {
+    messages: AtomicU64,
+    busy_nanos: AtomicU64,
+}
+
+struct Worker {
+    id: usize,
+    counters: Arc<WorkerCounters>,
+    subscriber: Subscriber,
+    /// Dropped to ask the worker to stop
+    stop: Sender<()>,
+    /// Closed once the worker has stopped
+    done: Receiver<()>,
+}
+
+type ClientFactory = dyn Fn(usize) -> Result<Client, Error> + Send + Sync;
+type MessageHandler = dyn Fn(usize, Message) + Send + Sync;
+
+/// A group of workers that share the messages published to a topic
+/// filter, which gives a ready-made work queue on top of MQTT 5 shared
+/// subscriptions.
+///
+/// Each worker runs in a thread of its own, with its own connection
+/// to the broker, and holds a `$share/{group}/{filter}` subscription;
+/// the broker delivers each message to only one of the members of
+/// the group.
+/// The number of workers can be changed while the pool is running via
+/// [scale_to](#method.scale_to); a worker that is removed unsubscribes
+/// first, and handles the messages that it already received before
+/// disconnecting.
+///
+/// ```no_run
+/// use mosquitto_rs::*;
+///
+/// # async fn jobs() -> Result<(), Error> {
+/// let config = WorkerPoolConfig::new("localhost", 1883, "resizers", "jobs/resize");
+/// let mut pool = WorkerPool::start(
+///     config,
+///     4,
+///     |id| Client::with_id(&format!("resizer-{}", id), true),
+///     |id, message| println!("worker {} got {:?}", id, message),
+/// )
+/// .await?;
+/// // Busy period
+/// pool.scale_to(8).await?;
+/// for worker in pool.stats() {
+///     println!("{:?}", worker);
+/// }
+/// pool.shutdown().await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct WorkerPool {
+    config: Arc<WorkerPoolConfig>,
+    new_client: Arc<ClientFactory>,
+    handler: Arc<MessageHandler>,
+    workers: Vec<Worker>,
+    next_id: usize,
+}
+
+impl WorkerPool {
+    /// Start a pool of `workers` workers, waiting until each has
+    /// connected and subscribed.
+    ///
+    /// `new_client` is called with the id of each worker, on the
+    /// thread of the worker, to create and configure its client, which
+    /// the pool then connects; each client must have a distinct id.
+    /// `handler` is called with the id of the worker and each message
+    /// that it receives.
+    pub async fn start<C, H>(
+        config: WorkerPoolConfig,
+        workers: usize,
+        new_client: C,
+        handler: H,
+    ) -> Result<Self, Error>
+    where
+        C: Fn(usize) -> Result<Client, Error> + Send + Sync + 'static,
+        H: Fn(usize, Message) + Send + Sync + 'static,
+    {
+        config.check()?;
+        let mut pool = Self {
+            config: Arc::new(config),
+            new_client: Arc::new(new_client),
+            handler: Arc::new(handler),
+            workers: vec![],
+            next_id: 0,
+        };
+        pool.scale_to(workers).await?;
+        Ok(pool)
+    }
+
+    /// Returns the number of workers
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Returns true if the pool has no workers
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+
+    /// Returns the activity of each worker, in the order that they
+    /// were started
+    pub fn stats(&self) -> Vec<WorkerStats> {
+        self.workers
+            .iter()
+            .map(|worker| WorkerStats {
+                id: worker.id,
+                messages: worker.counters.messages.load(Ordering::Relaxed),
+                busy_time: Duration::from_nanos(worker.counters.busy_nanos.load(Ordering::Relaxed)),
+                queued: worker.subscriber.len(),
+            })
+            .collect()
+    }
+
+    /// Start or stop workers so that there are `workers` of them.
+    ///
+    /// New workers are added once they have connected and subscribed;
+    /// if one fails to, the error is returned and the workers that
+    /// were already added are kept.
+    /// When scaling down, the most recently started workers are
+    /// stopped, and this waits until each has handled the messages
+    /// that it received and disconnected.
+    pub async fn scale_to(&mut self, workers: usize) -> Result<(), Error> {
+        while self.workers.len() < workers {
+            let worker = self.spawn_worker().await?;
+            self.workers.push(worker);
+        }
+        if self.workers.len() > workers {
+            let stopping = self.workers.split_off(workers);
+            stop_workers(stopping).await;
+        }
+        Ok(())
+    }
+
+    /// Stop all of the workers, as for [scale_to](#method.scale_to)
+    pub async fn shutdown(mut self) {
+        stop_workers(std::mem::take(&mut self.workers)).await;
+    }
+
+    async fn spawn_worker(&mut self) -> Result<Worker, Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let counters = Arc::new(WorkerCounters::default());
+        let (ready_tx, ready_rx) = bounded(1);
+        let (stop_tx, stop_rx) = bounded::<()>(1);
+        let (done_tx, done_rx) = bounded::<()>(1);
+        let config = Arc::clone(&self.config);
+        let new_client = Arc::clone(&self.new_client);
+        let handler = Arc::clone(&self.handler);
+        let worker_counters = Arc::clone(&counters);
+        std::thread::Builder::new()
+            .name(format!("mosquitto-rs-pool-{}", id))
+            .spawn(move || {
+                futures_lite::future::block_on(run_worker(
+                    id,
+                    &config,
+                    &*new_client,
+                    &*handler,
+                    &worker_counters,
+                    ready_tx,
+                    stop_rx,
+                ));
+                drop(done_tx);
+            })?;
+        // Fails if the worker panicked before it was ready
+        let subscriber = ready_rx.recv().await.map_err(|_| Error::Closed)??;
+        Ok(Worker {
+            id,
+            counters,
+            subscriber,
+            stop: stop_tx,
+            done: done_rx,
+        })
+    }
+}
+
+impl Drop for WorkerPool {
+    /// Ask the remaining workers to stop, without waiting for them
+    fn drop(&mut self) {
+        self.workers.clear();
+    }
+}
+
+async fn stop_workers(workers: Vec<Worker>) {
+    let mut done = vec![];
+    for worker in workers {
+        drop(worker.stop);
+        done.push(worker.done);
+    }
+    for done in done {
+        // Fails once the worker has stopped
+        let _ = done.recv().await;
+    }
+}
+
+async fn run_worker(
+    id: usize,
+    config: &WorkerPoolConfig,
+    new_client: &ClientFactory,
+    handler: &MessageHandler,
+    counters: &WorkerCounters,
+    ready: Sender<Result<Subscriber, Error>>,
+    stop: Receiver<()>,
+) {
+    let pattern = config.pattern();
+    let setup = async {
+        let mut client = new_client(id)?;
+        client
+            .connect(&config.host, config.port, config.keep_alive_interval, None)
+            .await?;
+        client.subscribe(&pattern, config.qos).await?;
+        let subscriber = client.subscriber().ok_or(Error::Closed)?;
+        Ok((client, subscriber))
+    };
+    let (mut client, subscriber) = match setup.await {
+        Ok(setup) => setup,
+        Err(err) => {
+            let _ = ready.send(Err(err)).await;
+            return;
+        }
+    };
+    if ready.send(Ok(subscriber.clone())).await.is_err() {
+        return;
+    }
+
+    let handle = |message: Message| {
+        let started = Instant::now();
+        handler(id, message);
+        counters.messages.fetch_add(1, Ordering::Relaxed);
+        counters
+            .busy_nanos
+            .fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    };
+
+    loop {
+        let next = async { subscriber.recv().await.ok() };
+        // Resolves once the pool asks the worker to stop
+        let stopping = async {
+            let _ = stop.recv().await;
+            None
+        };
+        match next.or(stopping).await {
+            Some(message) => handle(message),
+            None => break,
+        }
+    }
+
+    // Stop the broker sending more, and handle what it already sent,
+    // until none has arrived for the drain period
+    let unsubscribed = async { client.unsubscribe(&pattern).await.is_ok() }
+        .or(async {
+            async_io::Timer::after(config.drain_period).await;
+            false
+        })
+        .await;
+    if unsubscribed {
+        loop {
+            let next = async { subscriber.recv().await.ok() };
+            let quiet = async {
+                async_io::Timer::after(config.drain_period).await;
+                None
+            };
+            match next.or(quiet).await {
+                Some(message) => handle(message),
+                None => break,
+            }
+        }
+    }
+    let _ = client.shutdown(config.drain_period).await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn config() {
+        let config = WorkerPoolConfig::new("localhost", 1883, "workers", "jobs/+");
+        assert_eq!(config.pattern(), "$share/workers/jobs/+");
+        assert!(config.check().is_ok());
+        for group in ["", "a/b", "a+", "#"] {
+            let config = WorkerPoolConfig {
+                group: group.to_string(),
+                ..config.clone()
+            };
+            assert!(config.check().is_err(), "{}", group);
+        }
+        let config = WorkerPoolConfig {
+            filter: "jobs/#/x".to_string(),
+            ..config
+        };
+        assert!(config.check().is_err());
+    }
+}
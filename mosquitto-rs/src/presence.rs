@@ -0,0 +1,213 @@
+use crate::lowlevel::topic_matches_sub;
+use crate::{Error, Message, MessageFilter, Subscriber};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+
+/// A change in the presence of a peer; see
+/// [PresenceTracker](struct.PresenceTracker.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PresenceEvent {
+    /// The peer published `online` to its status topic
+    Joined(String),
+    /// The peer published `offline` to its status topic, typically via
+    /// its will, or its retained status was cleared
+    Left(String),
+}
+
+impl PresenceEvent {
+    /// Returns the id of the peer
+    pub fn peer(&self) -> &str {
+        match self {
+            Self::Joined(peer) | Self::Left(peer) => peer,
+        }
+    }
+}
+
+/// The set of peers that are online, shared between the tracker and
+/// the filter that updates it as status messages are received
+pub(crate) struct Presence {
+    pattern: String,
+    peers: Mutex<BTreeSet<String>>,
+}
+
+impl Presence {
+    pub fn new(pattern: &str) -> Arc<Self> {
+        Arc::new(Self {
+            pattern: pattern.to_string(),
+            peers: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    /// Returns the event that `message` represents, if it is a status
+    /// message; the peer id is made up of the topic levels that match
+    /// the wildcards in the pattern, or is the whole topic if the
+    /// pattern has no wildcards
+    fn event(&self, message: &Message) -> Option<PresenceEvent> {
+        if !topic_matches_sub(&self.pattern, &message.topic).unwrap_or(false) {
+            return None;
+        }
+        let levels: Vec<&str> = message.topic.split('/').collect();
+        let mut peer = vec![];
+        for (i, level) in self.pattern.split('/').enumerate() {
+            match level {
+                "+" => peer.push(levels[i].to_string()),
+                "#" => peer.push(levels[i.min(levels.len())..].join("/")),
+                _ => {}
+            }
+        }
+        let peer = if peer.is_empty() {
+            message.topic.clone()
+        } else {
+            peer.join("/")
+        };
+        match message.payload.as_slice() {
+            b"online" => Some(PresenceEvent::Joined(peer)),
+            b"offline" | b"" => Some(PresenceEvent::Left(peer)),
+            _ => None,
+        }
+    }
+
+    /// Apply `message` to the set of peers, returning true if it
+    /// changed the presence of a peer
+    fn update(&self, message: &Message) -> bool {
+        let mut peers = self.peers.lock().unwrap();
+        match self.event(message) {
+            Some(PresenceEvent::Joined(peer)) => peers.insert(peer),
+            Some(PresenceEvent::Left(peer)) => peers.remove(&peer),
+            None => false,
+        }
+    }
+}
+
+impl MessageFilter for Arc<Presence> {
+    fn matches(&self, message: &Message) -> bool {
+        self.update(message)
+    }
+}
+
+/// Tracks which of the other clients are online, from the retained
+/// `online` and `offline` messages that they publish to their status
+/// topics, with `offline` typically being published by their will;
+/// see [Client::track_presence](struct.Client.html#method.track_presence).
+///
+/// The set of peers is updated as the status messages are received,
+/// whether or not the join and leave events are consumed via
+/// [recv](#method.recv).
+/// Status messages that don't change the presence of a peer, such as
+/// a repeated `online`, produce no event, and payloads other than
+/// `online` and `offline` are ignored.
+///
+/// ```no_run
+/// # use mosquitto_rs::*;
+/// # async fn presence(client: &mut Client) -> Result<(), Error> {
+/// let presence = client.track_presence("+/status").await?;
+/// while let Ok(event) = presence.recv().await {
+///     println!("{:?}; online: {:?}", event, presence.peers());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct PresenceTracker {
+    presence: Arc<Presence>,
+    subscriber: Subscriber,
+}
+
+impl PresenceTracker {
+    pub(crate) fn new(presence: Arc<Presence>, subscriber: Subscriber) -> Self {
+        Self {
+            presence,
+            subscriber,
+        }
+    }
+
+    /// Returns the pattern of the status topics
+    pub fn pattern(&self) -> &str {
+        &self.presence.pattern
+    }
+
+    /// Returns the ids of the peers that are currently online, in order
+    pub fn peers(&self) -> Vec<String> {
+        self.presence
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Returns true if `peer` is currently online
+    pub fn is_online(&self, peer: &str) -> bool {
+        self.presence.peers.lock().unwrap().contains(peer)
+    }
+
+    /// Wait for the next peer to join or leave.
+    /// Yields `Error::Closed` once the client has been dropped.
+    pub async fn recv(&self) -> Result<PresenceEvent, Error> {
+        loop {
+            let message = self.subscriber.recv().await.map_err(|_| Error::Closed)?;
+            if let Some(event) = self.presence.event(&message) {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Returns the next join or leave event if one is already queued,
+    /// or `None` if there is none.
+    /// Yields `Error::Closed` once the client has been dropped.
+    pub fn try_next(&self) -> Result<Option<PresenceEvent>, Error> {
+        while let Some(message) = self.subscriber.try_next().map_err(|_| Error::Closed)? {
+            if let Some(event) = self.presence.event(&message) {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn status(topic: &str, payload: &[u8]) -> Message {
+        Message {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            retain: true,
+            ..Message::default()
+        }
+    }
+
+    #[test]
+    fn join_and_leave() {
+        let presence = Presence::new("devices/+/status");
+        let (tx, rx) = async_channel::unbounded();
+        let tracker = PresenceTracker::new(Arc::clone(&presence), Subscriber::new(rx));
+
+        for message in [
+            status("devices/b/status", b"online"),
+            status("devices/a/status", b"online"),
+            status("devices/a/status", b"online"),
+            status("devices/a/status", b"busy"),
+            status("devices/b/status", b"offline"),
+            status("other/a/status", b"online"),
+        ] {
+            if presence.matches(&message) {
+                tx.try_send(message).unwrap();
+            }
+        }
+
+        assert_eq!(tracker.peers(), vec!["a".to_string()]);
+        assert!(tracker.is_online("a"));
+        assert!(!tracker.is_online("b"));
+        let events: Vec<_> = std::iter::from_fn(|| tracker.try_next().unwrap()).collect();
+        assert_eq!(
+            events,
+            vec![
+                PresenceEvent::Joined("b".to_string()),
+                PresenceEvent::Joined("a".to_string()),
+                PresenceEvent::Left("b".to_string()),
+            ]
+        );
+    }
+}
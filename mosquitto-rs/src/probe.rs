@@ -0,0 +1,85 @@
+use std::convert::TryInto;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct ProbeState {
+    next_sequence: u64,
+    /// The sequence number of the probe in flight, and when it was sent
+    pending: Option<(u64, Instant)>,
+    latest: Option<Duration>,
+}
+
+/// Measures the time taken for a message to travel through the broker
+/// and back, by publishing probes to a topic that the client is
+/// subscribed to; see
+/// [Client::set_latency_probe](struct.Client.html#method.set_latency_probe).
+///
+/// Only one probe is in flight at a time; sending another abandons
+/// the previous one, so that a probe that is lost, or that returns
+/// after the next was sent, isn't measured.
+pub(crate) struct LatencyProbe {
+    topic: String,
+    state: Mutex<ProbeState>,
+}
+
+impl LatencyProbe {
+    pub fn new(topic: String) -> Self {
+        Self {
+            topic,
+            state: Mutex::new(ProbeState::default()),
+        }
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Start a new probe, returning the payload to publish
+    pub fn send(&self) -> Vec<u8> {
+        let mut state = self.state.lock().unwrap();
+        let sequence = state.next_sequence;
+        state.next_sequence += 1;
+        state.pending = Some((sequence, Instant::now()));
+        sequence.to_be_bytes().to_vec()
+    }
+
+    /// Record the return of a probe with `payload`, returning the
+    /// round trip time if it is the probe in flight
+    pub fn receive(&self, payload: &[u8]) -> Option<Duration> {
+        let sequence = u64::from_be_bytes(payload.try_into().ok()?);
+        let mut state = self.state.lock().unwrap();
+        match state.pending {
+            Some((pending, sent)) if pending == sequence => {
+                let rtt = sent.elapsed();
+                state.pending = None;
+                state.latest = Some(rtt);
+                Some(rtt)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the most recently measured round trip time
+    pub fn latest(&self) -> Option<Duration> {
+        self.state.lock().unwrap().latest
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn measures_pending_probe_only() {
+        let probe = LatencyProbe::new("probe".to_string());
+        let first = probe.send();
+        let second = probe.send();
+        assert_eq!(probe.receive(&first), None);
+        assert_eq!(probe.receive(b"junk"), None);
+        let rtt = probe.receive(&second).unwrap();
+        assert_eq!(probe.latest(), Some(rtt));
+        // A duplicate isn't measured twice
+        assert_eq!(probe.receive(&second), None);
+    }
+}
@@ -0,0 +1,794 @@
+use crate::lowlevel::{cstr, sys};
+use crate::Error;
+use std::convert::TryInto;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_void};
+use std::time::Duration;
+
+/// The most properties that are copied from a single property list
+/// received from the broker
+pub const MAX_RECEIVED_PROPERTIES: usize = 1024;
+
+/// The most bytes of string and binary data that are copied from a
+/// single property list received from the broker
+pub const MAX_RECEIVED_PROPERTY_BYTES: usize = 1024 * 1024;
+
+/// An individual MQTT 5 property.
+///
+/// With the `serde` feature, a property is represented as a map with
+/// a single entry, whose key is the kebab-case name of the property,
+/// such as `{"content-type": "text/plain"}`; a user property has a
+/// pair of strings as its value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum Property {
+    PayloadFormatIndicator(u8),
+    MessageExpiryInterval(u32),
+    ContentType(String),
+    ResponseTopic(String),
+    CorrelationData(Vec<u8>),
+    SubscriptionIdentifier(u32),
+    SessionExpiryInterval(u32),
+    AssignedClientIdentifier(String),
+    ServerKeepAlive(u16),
+    AuthenticationMethod(String),
+    AuthenticationData(Vec<u8>),
+    RequestProblemInformation(u8),
+    WillDelayInterval(u32),
+    RequestResponseInformation(u8),
+    ResponseInformation(String),
+    ServerReference(String),
+    ReasonString(String),
+    ReceiveMaximum(u16),
+    TopicAliasMaximum(u16),
+    TopicAlias(u16),
+    MaximumQos(u8),
+    RetainAvailable(u8),
+    /// A user property name and value
+    UserProperty(String, String),
+    MaximumPacketSize(u32),
+    WildcardSubAvailable(u8),
+    SubscriptionIdAvailable(u8),
+    SharedSubAvailable(u8),
+}
+
+/// A list of MQTT 5 properties, as attached to CONNACK, DISCONNECT,
+/// PUBLISH and other packets.
+/// When connected using an earlier version of the protocol,
+/// property lists are always empty.
+///
+/// With the `serde` feature, a property list is represented as a
+/// sequence of [Property](enum.Property.html) values, preserving their
+/// order and any repeated user properties.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct Properties {
+    props: Vec<Property>,
+}
+
+impl Properties {
+    /// Create an empty property list
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a property to the list
+    pub fn push(&mut self, prop: Property) {
+        self.props.push(prop);
+    }
+
+    /// Append a User Property, an application defined name and value.
+    /// A name may be added more than once.
+    ///
+    /// The `add_` methods return the list, so that calls can be chained:
+    ///
+    /// ```
+    /// use mosquitto_rs::Properties;
+    /// use std::time::Duration;
+    ///
+    /// let mut props = Properties::new();
+    /// props
+    ///     .add_content_type("application/json")
+    ///     .add_message_expiry_interval(Duration::from_secs(300))
+    ///     .add_user_property("source", "sensor-7");
+    /// assert_eq!(props.content_type(), Some("application/json"));
+    /// ```
+    pub fn add_user_property(&mut self, name: &str, value: &str) -> &mut Self {
+        self.push(Property::UserProperty(name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Append a Content Type property, which describes the payload of
+    /// a message, typically as a MIME type
+    pub fn add_content_type(&mut self, content_type: &str) -> &mut Self {
+        self.push(Property::ContentType(content_type.to_string()));
+        self
+    }
+
+    /// Append a Response Topic property, which names the topic to which
+    /// a response to a request message should be published
+    pub fn add_response_topic(&mut self, topic: &str) -> &mut Self {
+        self.push(Property::ResponseTopic(topic.to_string()));
+        self
+    }
+
+    /// Append a Correlation Data property, which is used to match a
+    /// response message with its request
+    pub fn add_correlation_data(&mut self, data: &[u8]) -> &mut Self {
+        self.push(Property::CorrelationData(data.to_vec()));
+        self
+    }
+
+    /// Append a Payload Format Indicator property, which states
+    /// whether the payload is UTF-8 encoded text
+    pub fn add_payload_is_utf8(&mut self, utf8: bool) -> &mut Self {
+        self.push(Property::PayloadFormatIndicator(utf8.into()));
+        self
+    }
+
+    /// Append a Message Expiry Interval property, after which the
+    /// broker discards a message that it hasn't yet delivered.
+    /// The interval is sent in whole seconds, up to `u32::MAX`.
+    pub fn add_message_expiry_interval(&mut self, interval: Duration) -> &mut Self {
+        self.push(Property::MessageExpiryInterval(saturating_secs(interval)));
+        self
+    }
+
+    /// Append a Session Expiry Interval property, for which the broker
+    /// keeps the session after the connection is closed.
+    /// The interval is sent in whole seconds, up to `u32::MAX`, which
+    /// means that the session never expires.
+    pub fn add_session_expiry_interval(&mut self, interval: Duration) -> &mut Self {
+        self.push(Property::SessionExpiryInterval(saturating_secs(interval)));
+        self
+    }
+
+    /// Append a Subscription Identifier property, which the broker
+    /// attaches to the messages that match the subscription.
+    /// Identifiers range from 1 to 268,435,455; libmosquitto rejects
+    /// others when the list is used.
+    pub fn add_subscription_identifier(&mut self, id: u32) -> &mut Self {
+        self.push(Property::SubscriptionIdentifier(id));
+        self
+    }
+
+    /// Returns an iterator over the properties in the list
+    pub fn iter(&self) -> std::slice::Iter<'_, Property> {
+        self.props.iter()
+    }
+
+    /// Returns true if the list has no properties
+    pub fn is_empty(&self) -> bool {
+        self.props.is_empty()
+    }
+
+    /// Returns the number of properties in the list
+    pub fn len(&self) -> usize {
+        self.props.len()
+    }
+
+    /// Returns the Server Reference property, which a broker uses
+    /// to tell the client about another server that it should use
+    pub fn server_reference(&self) -> Option<&str> {
+        self.iter().find_map(|prop| match prop {
+            Property::ServerReference(s) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns the Reason String property, which is a human readable
+    /// diagnostic explaining a reason code
+    pub fn reason_string(&self) -> Option<&str> {
+        self.iter().find_map(|prop| match prop {
+            Property::ReasonString(s) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns the Content Type property, which describes the payload
+    /// of a message, typically as a MIME type
+    pub fn content_type(&self) -> Option<&str> {
+        self.iter().find_map(|prop| match prop {
+            Property::ContentType(s) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns the Response Topic property, which names the topic to
+    /// which a response to a request message should be published
+    pub fn response_topic(&self) -> Option<&str> {
+        self.iter().find_map(|prop| match prop {
+            Property::ResponseTopic(s) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns the Correlation Data property, which is used to match
+    /// a response message with its request
+    pub fn correlation_data(&self) -> Option<&[u8]> {
+        self.iter().find_map(|prop| match prop {
+            Property::CorrelationData(d) => Some(d.as_slice()),
+            _ => None,
+        })
+    }
+
+    /// Returns the Payload Format Indicator property.
+    /// `Some(true)` indicates that the payload is UTF-8 encoded text.
+    pub fn payload_is_utf8(&self) -> Option<bool> {
+        self.iter().find_map(|prop| match prop {
+            Property::PayloadFormatIndicator(v) => Some(*v == 1),
+            _ => None,
+        })
+    }
+
+    /// Returns the Message Expiry Interval property
+    pub fn message_expiry_interval(&self) -> Option<Duration> {
+        self.iter().find_map(|prop| match prop {
+            Property::MessageExpiryInterval(secs) => Some(Duration::from_secs((*secs).into())),
+            _ => None,
+        })
+    }
+
+    /// Returns the Session Expiry Interval property, which a broker
+    /// may use to override the interval requested by the client
+    pub fn session_expiry_interval(&self) -> Option<Duration> {
+        self.iter().find_map(|prop| match prop {
+            Property::SessionExpiryInterval(secs) => Some(Duration::from_secs((*secs).into())),
+            _ => None,
+        })
+    }
+
+    /// Returns the Assigned Client Identifier property, which holds
+    /// the id that the broker chose for a client that connected
+    /// without one
+    pub fn assigned_client_identifier(&self) -> Option<&str> {
+        self.iter().find_map(|prop| match prop {
+            Property::AssignedClientIdentifier(s) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns an iterator over the names and values of the User
+    /// Properties, in the order that they appear in the list.
+    /// A name may appear more than once.
+    pub fn user_properties(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.iter().filter_map(|prop| match prop {
+            Property::UserProperty(name, value) => Some((name.as_str(), value.as_str())),
+            _ => None,
+        })
+    }
+
+    /// Returns the Subscription Identifiers of the subscriptions that
+    /// a received message matched
+    pub fn subscription_identifiers(&self) -> impl Iterator<Item = u32> + '_ {
+        self.iter().filter_map(|prop| match prop {
+            Property::SubscriptionIdentifier(id) => Some(*id),
+            _ => None,
+        })
+    }
+
+    /// Make an owned copy of a property list produced by libmosquitto.
+    /// Properties with identifiers that are not recognized, or that
+    /// cannot be read, are skipped.
+    /// The list originates from the broker, so the copy is limited to
+    /// [MAX_RECEIVED_PROPERTIES](constant.MAX_RECEIVED_PROPERTIES.html)
+    /// properties and
+    /// [MAX_RECEIVED_PROPERTY_BYTES](constant.MAX_RECEIVED_PROPERTY_BYTES.html)
+    /// bytes of data; properties beyond those limits are discarded.
+    ///
+    /// # Safety
+    /// `list` must be null, or point to a valid property list.
+    pub(crate) unsafe fn from_raw(list: *const sys::mosquitto_property) -> Self {
+        Self::from_raw_with_budget(list, Budget::default())
+    }
+
+    unsafe fn from_raw_with_budget(
+        mut list: *const sys::mosquitto_property,
+        mut budget: Budget,
+    ) -> Self {
+        let mut props = Self::new();
+        while !list.is_null() && budget.properties > 0 {
+            budget.properties -= 1;
+            if let Some(prop) = read_property(list, &mut budget) {
+                props.push(prop);
+            }
+            list = sys::mosquitto_property_next(list);
+        }
+        props
+    }
+
+    /// Build a libmosquitto property list holding these properties,
+    /// suitable for passing to the `_v5` family of functions.
+    pub(crate) fn to_raw(&self) -> Result<RawProperties, Error> {
+        let mut raw = RawProperties(std::ptr::null_mut());
+        for prop in self {
+            let err = unsafe { add_property(&mut raw.0, prop)? };
+            Error::result(err, ())?;
+        }
+        Ok(raw)
+    }
+}
+
+/// An owned libmosquitto property list, which is freed on drop
+pub(crate) struct RawProperties(*mut sys::mosquitto_property);
+
+impl RawProperties {
+    /// Returns the list, which is null if there are no properties
+    pub(crate) fn as_ptr(&self) -> *const sys::mosquitto_property {
+        self.0
+    }
+
+    /// Give up ownership of the list, for functions that take it
+    pub(crate) fn into_ptr(self) -> *mut sys::mosquitto_property {
+        let list = self.0;
+        std::mem::forget(self);
+        list
+    }
+}
+
+impl Drop for RawProperties {
+    fn drop(&mut self) {
+        unsafe {
+            sys::mosquitto_property_free_all(&mut self.0);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Properties {
+    type Item = &'a Property;
+    type IntoIter = std::slice::Iter<'a, Property>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.props.iter()
+    }
+}
+
+impl IntoIterator for Properties {
+    type Item = Property;
+    type IntoIter = std::vec::IntoIter<Property>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.props.into_iter()
+    }
+}
+
+impl std::iter::FromIterator<Property> for Properties {
+    fn from_iter<I: IntoIterator<Item = Property>>(iter: I) -> Self {
+        Self {
+            props: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Returns the whole seconds of `interval`, limited to `u32::MAX`
+pub(crate) fn saturating_secs(interval: Duration) -> u32 {
+    interval.as_secs().try_into().unwrap_or(u32::MAX)
+}
+
+/// Bounds the memory used to copy a property list received from the
+/// broker, which is untrusted input
+struct Budget {
+    /// The number of properties that may still be copied
+    properties: usize,
+    /// The number of bytes of string and binary data that may still
+    /// be copied
+    bytes: usize,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            properties: MAX_RECEIVED_PROPERTIES,
+            bytes: MAX_RECEIVED_PROPERTY_BYTES,
+        }
+    }
+}
+
+impl Budget {
+    /// Reserve `len` bytes, returning false if that would exceed the budget
+    fn take(&mut self, len: usize) -> bool {
+        match self.bytes.checked_sub(len) {
+            Some(remaining) => {
+                self.bytes = remaining;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+unsafe fn read_byte(prop: *const sys::mosquitto_property, id: c_int) -> Option<u8> {
+    let mut value = 0;
+    if sys::mosquitto_property_read_byte(prop, id, &mut value, false).is_null() {
+        return None;
+    }
+    Some(value)
+}
+
+unsafe fn read_int16(prop: *const sys::mosquitto_property, id: c_int) -> Option<u16> {
+    let mut value = 0;
+    if sys::mosquitto_property_read_int16(prop, id, &mut value, false).is_null() {
+        return None;
+    }
+    Some(value)
+}
+
+unsafe fn read_int32(prop: *const sys::mosquitto_property, id: c_int) -> Option<u32> {
+    let mut value = 0;
+    if sys::mosquitto_property_read_int32(prop, id, &mut value, false).is_null() {
+        return None;
+    }
+    Some(value)
+}
+
+unsafe fn read_varint(prop: *const sys::mosquitto_property, id: c_int) -> Option<u32> {
+    let mut value = 0;
+    if sys::mosquitto_property_read_varint(prop, id, &mut value, false).is_null() {
+        return None;
+    }
+    Some(value)
+}
+
+/// Take ownership of a string allocated by libmosquitto.
+/// The string is freed even if it exceeds the budget.
+unsafe fn take_string(s: *mut c_char, budget: &mut Budget) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+    let bytes = CStr::from_ptr(s).to_bytes();
+    let result = if budget.take(bytes.len()) {
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    } else {
+        None
+    };
+    libc::free(s as *mut c_void);
+    result
+}
+
+unsafe fn read_binary(
+    prop: *const sys::mosquitto_property,
+    id: c_int,
+    budget: &mut Budget,
+) -> Option<Vec<u8>> {
+    let mut value = std::ptr::null_mut();
+    let mut len = 0;
+    if sys::mosquitto_property_read_binary(prop, id, &mut value, &mut len, false).is_null() {
+        return None;
+    }
+    if value.is_null() {
+        // libmosquitto doesn't allocate for empty values
+        return if len == 0 { Some(vec![]) } else { None };
+    }
+    let len = len as usize;
+    let result = if budget.take(len) {
+        Some(std::slice::from_raw_parts(value as *const u8, len).to_vec())
+    } else {
+        None
+    };
+    libc::free(value);
+    result
+}
+
+unsafe fn read_string(
+    prop: *const sys::mosquitto_property,
+    id: c_int,
+    budget: &mut Budget,
+) -> Option<String> {
+    let mut value = std::ptr::null_mut();
+    if sys::mosquitto_property_read_string(prop, id, &mut value, false).is_null() {
+        return None;
+    }
+    take_string(value, budget)
+}
+
+unsafe fn read_string_pair(
+    prop: *const sys::mosquitto_property,
+    id: c_int,
+    budget: &mut Budget,
+) -> Option<(String, String)> {
+    let mut name = std::ptr::null_mut();
+    let mut value = std::ptr::null_mut();
+    if sys::mosquitto_property_read_string_pair(prop, id, &mut name, &mut value, false).is_null() {
+        return None;
+    }
+    // Both strings must be taken, so that both are freed
+    let name = take_string(name, budget);
+    let value = take_string(value, budget);
+    Some((name?, value?))
+}
+
+/// Append `prop` to `list`, returning the libmosquitto error code
+unsafe fn add_property(
+    list: &mut *mut sys::mosquitto_property,
+    prop: &Property,
+) -> Result<c_int, Error> {
+    use sys::mqtt5_property::*;
+
+    let byte =
+        |list, id: sys::mqtt5_property, v| sys::mosquitto_property_add_byte(list, id as c_int, v);
+    let int16 =
+        |list, id: sys::mqtt5_property, v| sys::mosquitto_property_add_int16(list, id as c_int, v);
+    let int32 =
+        |list, id: sys::mqtt5_property, v| sys::mosquitto_property_add_int32(list, id as c_int, v);
+    let string = |list, id: sys::mqtt5_property, v: &str| -> Result<c_int, Error> {
+        let v = cstr(v)?;
+        Ok(sys::mosquitto_property_add_string(
+            list,
+            id as c_int,
+            v.as_ptr(),
+        ))
+    };
+    let binary = |list, id: sys::mqtt5_property, v: &[u8]| -> Result<c_int, Error> {
+        let len = v.len().try_into().map_err(|_| Error::PayloadTooLarge)?;
+        Ok(sys::mosquitto_property_add_binary(
+            list,
+            id as c_int,
+            v.as_ptr() as *const c_void,
+            len,
+        ))
+    };
+
+    Ok(match prop {
+        Property::PayloadFormatIndicator(v) => byte(list, MQTT_PROP_PAYLOAD_FORMAT_INDICATOR, *v),
+        Property::MessageExpiryInterval(v) => int32(list, MQTT_PROP_MESSAGE_EXPIRY_INTERVAL, *v),
+        Property::ContentType(v) => string(list, MQTT_PROP_CONTENT_TYPE, v)?,
+        Property::ResponseTopic(v) => string(list, MQTT_PROP_RESPONSE_TOPIC, v)?,
+        Property::CorrelationData(v) => binary(list, MQTT_PROP_CORRELATION_DATA, v)?,
+        Property::SubscriptionIdentifier(v) => {
+            sys::mosquitto_property_add_varint(list, MQTT_PROP_SUBSCRIPTION_IDENTIFIER as c_int, *v)
+        }
+        Property::SessionExpiryInterval(v) => int32(list, MQTT_PROP_SESSION_EXPIRY_INTERVAL, *v),
+        Property::AssignedClientIdentifier(v) => {
+            string(list, MQTT_PROP_ASSIGNED_CLIENT_IDENTIFIER, v)?
+        }
+        Property::ServerKeepAlive(v) => int16(list, MQTT_PROP_SERVER_KEEP_ALIVE, *v),
+        Property::AuthenticationMethod(v) => string(list, MQTT_PROP_AUTHENTICATION_METHOD, v)?,
+        Property::AuthenticationData(v) => binary(list, MQTT_PROP_AUTHENTICATION_DATA, v)?,
+        Property::RequestProblemInformation(v) => {
+            byte(list, MQTT_PROP_REQUEST_PROBLEM_INFORMATION, *v)
+        }
+        Property::WillDelayInterval(v) => int32(list, MQTT_PROP_WILL_DELAY_INTERVAL, *v),
+        Property::RequestResponseInformation(v) => {
+            byte(list, MQTT_PROP_REQUEST_RESPONSE_INFORMATION, *v)
+        }
+        Property::ResponseInformation(v) => string(list, MQTT_PROP_RESPONSE_INFORMATION, v)?,
+        Property::ServerReference(v) => string(list, MQTT_PROP_SERVER_REFERENCE, v)?,
+        Property::ReasonString(v) => string(list, MQTT_PROP_REASON_STRING, v)?,
+        Property::ReceiveMaximum(v) => int16(list, MQTT_PROP_RECEIVE_MAXIMUM, *v),
+        Property::TopicAliasMaximum(v) => int16(list, MQTT_PROP_TOPIC_ALIAS_MAXIMUM, *v),
+        Property::TopicAlias(v) => int16(list, MQTT_PROP_TOPIC_ALIAS, *v),
+        Property::MaximumQos(v) => byte(list, MQTT_PROP_MAXIMUM_QOS, *v),
+        Property::RetainAvailable(v) => byte(list, MQTT_PROP_RETAIN_AVAILABLE, *v),
+        Property::UserProperty(name, value) => {
+            let name = cstr(name)?;
+            let value = cstr(value)?;
+            sys::mosquitto_property_add_string_pair(
+                list,
+                MQTT_PROP_USER_PROPERTY as c_int,
+                name.as_ptr(),
+                value.as_ptr(),
+            )
+        }
+        Property::MaximumPacketSize(v) => int32(list, MQTT_PROP_MAXIMUM_PACKET_SIZE, *v),
+        Property::WildcardSubAvailable(v) => byte(list, MQTT_PROP_WILDCARD_SUB_AVAILABLE, *v),
+        Property::SubscriptionIdAvailable(v) => byte(list, MQTT_PROP_SUBSCRIPTION_ID_AVAILABLE, *v),
+        Property::SharedSubAvailable(v) => byte(list, MQTT_PROP_SHARED_SUB_AVAILABLE, *v),
+    })
+}
+
+/// Decode the property at the head of `prop`, returning `None` if it
+/// is not recognized, cannot be read, or exceeds the budget
+unsafe fn read_property(
+    prop: *const sys::mosquitto_property,
+    budget: &mut Budget,
+) -> Option<Property> {
+    use sys::mqtt5_property::*;
+
+    let id = sys::mosquitto_property_identifier(prop);
+    let is = |p: sys::mqtt5_property| id == p as c_int;
+
+    let prop = if is(MQTT_PROP_PAYLOAD_FORMAT_INDICATOR) {
+        Property::PayloadFormatIndicator(read_byte(prop, id)?)
+    } else if is(MQTT_PROP_MESSAGE_EXPIRY_INTERVAL) {
+        Property::MessageExpiryInterval(read_int32(prop, id)?)
+    } else if is(MQTT_PROP_CONTENT_TYPE) {
+        Property::ContentType(read_string(prop, id, budget)?)
+    } else if is(MQTT_PROP_RESPONSE_TOPIC) {
+        Property::ResponseTopic(read_string(prop, id, budget)?)
+    } else if is(MQTT_PROP_CORRELATION_DATA) {
+        Property::CorrelationData(read_binary(prop, id, budget)?)
+    } else if is(MQTT_PROP_SUBSCRIPTION_IDENTIFIER) {
+        Property::SubscriptionIdentifier(read_varint(prop, id)?)
+    } else if is(MQTT_PROP_SESSION_EXPIRY_INTERVAL) {
+        Property::SessionExpiryInterval(read_int32(prop, id)?)
+    } else if is(MQTT_PROP_ASSIGNED_CLIENT_IDENTIFIER) {
+        Property::AssignedClientIdentifier(read_string(prop, id, budget)?)
+    } else if is(MQTT_PROP_SERVER_KEEP_ALIVE) {
+        Property::ServerKeepAlive(read_int16(prop, id)?)
+    } else if is(MQTT_PROP_AUTHENTICATION_METHOD) {
+        Property::AuthenticationMethod(read_string(prop, id, budget)?)
+    } else if is(MQTT_PROP_AUTHENTICATION_DATA) {
+        Property::AuthenticationData(read_binary(prop, id, budget)?)
+    } else if is(MQTT_PROP_REQUEST_PROBLEM_INFORMATION) {
+        Property::RequestProblemInformation(read_byte(prop, id)?)
+    } else if is(MQTT_PROP_WILL_DELAY_INTERVAL) {
+        Property::WillDelayInterval(read_int32(prop, id)?)
+    } else if is(MQTT_PROP_REQUEST_RESPONSE_INFORMATION) {
+        Property::RequestResponseInformation(read_byte(prop, id)?)
+    } else if is(MQTT_PROP_RESPONSE_INFORMATION) {
+        Property::ResponseInformation(read_string(prop, id, budget)?)
+    } else if is(MQTT_PROP_SERVER_REFERENCE) {
+        Property::ServerReference(read_string(prop, id, budget)?)
+    } else if is(MQTT_PROP_REASON_STRING) {
+        Property::ReasonString(read_string(prop, id, budget)?)
+    } else if is(MQTT_PROP_RECEIVE_MAXIMUM) {
+        Property::ReceiveMaximum(read_int16(prop, id)?)
+    } else if is(MQTT_PROP_TOPIC_ALIAS_MAXIMUM) {
+        Property::TopicAliasMaximum(read_int16(prop, id)?)
+    } else if is(MQTT_PROP_TOPIC_ALIAS) {
+        Property::TopicAlias(read_int16(prop, id)?)
+    } else if is(MQTT_PROP_MAXIMUM_QOS) {
+        Property::MaximumQos(read_byte(prop, id)?)
+    } else if is(MQTT_PROP_RETAIN_AVAILABLE) {
+        Property::RetainAvailable(read_byte(prop, id)?)
+    } else if is(MQTT_PROP_USER_PROPERTY) {
+        let (name, value) = read_string_pair(prop, id, budget)?;
+        Property::UserProperty(name, value)
+    } else if is(MQTT_PROP_MAXIMUM_PACKET_SIZE) {
+        Property::MaximumPacketSize(read_int32(prop, id)?)
+    } else if is(MQTT_PROP_WILDCARD_SUB_AVAILABLE) {
+        Property::WildcardSubAvailable(read_byte(prop, id)?)
+    } else if is(MQTT_PROP_SUBSCRIPTION_ID_AVAILABLE) {
+        Property::SubscriptionIdAvailable(read_byte(prop, id)?)
+    } else if is(MQTT_PROP_SHARED_SUB_AVAILABLE) {
+        Property::SharedSubAvailable(read_byte(prop, id)?)
+    } else {
+        return None;
+    };
+    Some(prop)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn getters() {
+        let props: Properties = vec![
+            Property::ContentType("application/json".to_string()),
+            Property::UserProperty("a".to_string(), "1".to_string()),
+            Property::SubscriptionIdentifier(3),
+            Property::UserProperty("a".to_string(), "2".to_string()),
+            Property::SubscriptionIdentifier(7),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(props.content_type(), Some("application/json"));
+        assert_eq!(props.response_topic(), None);
+        assert_eq!(
+            props.user_properties().collect::<Vec<_>>(),
+            vec![("a", "1"), ("a", "2")]
+        );
+        assert_eq!(
+            props.subscription_identifiers().collect::<Vec<_>>(),
+            vec![3, 7]
+        );
+    }
+
+    #[test]
+    fn builders() {
+        let mut props = Properties::new();
+        props
+            .add_user_property("a", "1")
+            .add_payload_is_utf8(true)
+            .add_message_expiry_interval(Duration::from_secs(u64::MAX))
+            .add_correlation_data(b"id");
+        assert_eq!(
+            props.user_properties().collect::<Vec<_>>(),
+            vec![("a", "1")]
+        );
+        assert_eq!(props.payload_is_utf8(), Some(true));
+        assert_eq!(
+            props.message_expiry_interval(),
+            Some(Duration::from_secs(u32::MAX.into()))
+        );
+        assert_eq!(props.correlation_data(), Some(&b"id"[..]));
+        assert!(props.to_raw().is_ok());
+    }
+
+    /// A small deterministic generator, so that failures are reproducible
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+
+        fn string(&mut self) -> String {
+            const CHARS: &[char] = &['a', 'Z', '0', '/', ' ', '+', 'é', 'ß', '日', '🦀'];
+            let len = self.next(300);
+            (0..len).map(|_| CHARS[self.next(CHARS.len())]).collect()
+        }
+
+        fn property(&mut self) -> Property {
+            match self.next(8) {
+                0 => Property::PayloadFormatIndicator(self.next(2) as u8),
+                1 => Property::MessageExpiryInterval(self.next(1 << 31) as u32),
+                2 => Property::ContentType(self.string()),
+                3 => Property::CorrelationData((0..self.next(300)).map(|i| i as u8).collect()),
+                4 => Property::SubscriptionIdentifier(1 + self.next(268_435_454) as u32),
+                5 => Property::TopicAlias(1 + self.next(u16::MAX as usize - 1) as u16),
+                6 => Property::ReasonString(self.string()),
+                _ => Property::UserProperty(self.string(), self.string()),
+            }
+        }
+    }
+
+    #[test]
+    fn fuzz_round_trip() {
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+        for _ in 0..500 {
+            let props: Properties = (0..rng.next(20)).map(|_| rng.property()).collect();
+            let raw = props.to_raw().unwrap();
+            assert_eq!(unsafe { Properties::from_raw(raw.as_ptr()) }, props);
+        }
+    }
+
+    #[test]
+    fn budget() {
+        let props: Properties = vec![
+            Property::ContentType("0123456789".to_string()),
+            Property::ReasonString("0123456789".to_string()),
+            Property::TopicAlias(3),
+        ]
+        .into_iter()
+        .collect();
+        let raw = props.to_raw().unwrap();
+
+        let budget = Budget {
+            properties: 2,
+            bytes: 1024,
+        };
+        let copy = unsafe { Properties::from_raw_with_budget(raw.as_ptr(), budget) };
+        assert_eq!(copy.len(), 2);
+
+        let budget = Budget {
+            properties: 16,
+            bytes: 15,
+        };
+        let copy = unsafe { Properties::from_raw_with_budget(raw.as_ptr(), budget) };
+        assert_eq!(copy.content_type(), Some("0123456789"));
+        assert_eq!(copy.reason_string(), None);
+        assert_eq!(copy.len(), 2);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let props: Properties = vec![
+            Property::ContentType("text/plain".to_string()),
+            Property::UserProperty("a".to_string(), "1".to_string()),
+            Property::MessageExpiryInterval(60),
+        ]
+        .into_iter()
+        .collect();
+        let json = serde_json::to_string(&props).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"content-type":"text/plain"},{"user-property":["a","1"]},{"message-expiry-interval":60}]"#
+        );
+        assert_eq!(serde_json::from_str::<Properties>(&json).unwrap(), props);
+    }
+}
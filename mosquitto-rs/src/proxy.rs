@@ -0,0 +1,423 @@
+use crate::Error;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The largest CONNECT response header block that we're prepared to read
+/// from the proxy before giving up on it.
+const MAX_RESPONSE_HEADER: usize = 8192;
+
+/// How long a local connection may take over its SOCKS5 handshake
+/// before the tunnel gives up on it
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Describes an HTTP proxy that supports the `CONNECT` method.
+///
+/// libmosquitto can only talk SOCKS5 natively, so HTTP proxies are
+/// supported by running a small local SOCKS5 server: libmosquitto
+/// connects to it on the loopback interface, names the broker that
+/// it wants to reach, and the connection is forwarded through the
+/// HTTP proxy to that broker.
+/// Because libmosquitto still connects to the broker by name, TLS
+/// server name indication and certificate verification are performed
+/// against the broker's host name rather than the loopback address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpProxy {
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+}
+
+impl HttpProxy {
+    /// Create a description of the proxy listening on `host` and `port`
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            credentials: None,
+        }
+    }
+
+    /// Use HTTP Basic authentication with the specified credentials
+    /// when issuing the `CONNECT` request.
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    /// Establish a tunnel through the proxy to `host` and `port`.
+    ///
+    /// The first connection through the proxy is made immediately so
+    /// that configuration errors are reported here, rather than
+    /// surfacing later as an unexplained connection loss.
+    ///
+    /// The returned tunnel is a SOCKS5 server on a loopback address,
+    /// which only serves clients that authenticate with its
+    /// [credentials](#method.credentials), so that other local
+    /// processes cannot use it to reach the proxy.
+    /// Point libmosquitto at it with
+    /// [Mosq::set_socks5_proxy](struct.Mosq.html#method.set_socks5_proxy).
+    pub fn open_tunnel(&self, host: &str, port: u16) -> Result<HttpTunnel, Error> {
+        let target = format_authority(host, port);
+        let first = self.connect_to(&target)?;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let local_addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let username = random_token();
+        let password = random_token();
+
+        let proxy = self.clone();
+        let mut pending = Some(first);
+        let stop = Arc::clone(&shutdown);
+        let (user, pass) = (username.clone(), password.clone());
+        std::thread::Builder::new()
+            .name("mosquitto-http-proxy".to_string())
+            .spawn(move || {
+                for local in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let mut local = match local {
+                        Ok(local) => local,
+                        Err(_) => continue,
+                    };
+                    // Dropping the local end causes libmosquitto to
+                    // see the connection fail and to schedule its
+                    // own reconnect.
+                    let requested = match socks5_accept(&mut local, &user, &pass) {
+                        Ok(requested) => requested,
+                        Err(_) => continue,
+                    };
+                    let remote = match pending.take() {
+                        Some(remote) if requested == target => Ok(remote),
+                        _ => proxy.connect_to(&requested),
+                    };
+                    match remote {
+                        Ok(remote) => {
+                            if socks5_reply(&mut local, SOCKS5_SUCCEEDED).is_ok() {
+                                splice(local, remote);
+                            }
+                        }
+                        Err(_) => {
+                            let _ = socks5_reply(&mut local, SOCKS5_GENERAL_FAILURE);
+                        }
+                    }
+                }
+            })?;
+
+        Ok(HttpTunnel {
+            local_addr,
+            username,
+            password,
+            shutdown,
+        })
+    }
+
+    /// Connect to the proxy and ask it to connect us onwards to `target`
+    fn connect_to(&self, target: &str) -> Result<TcpStream, Error> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))?;
+        stream.set_nodelay(true)?;
+
+        let mut request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", target, target);
+        if let Some((user, pass)) = &self.credentials {
+            request.push_str(&format!(
+                "Proxy-Authorization: Basic {}\r\n",
+                base64(format!("{}:{}", user, pass).as_bytes())
+            ));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+
+        // Read the response a byte at a time so that we don't consume
+        // any of the tunnelled data that may follow the headers
+        let mut response = Vec::new();
+        let mut byte = [0u8];
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() >= MAX_RESPONSE_HEADER {
+                return Err(Error::HttpProxy(
+                    "response headers are too large".to_string(),
+                ));
+            }
+            if stream.read(&mut byte)? == 0 {
+                return Err(Error::HttpProxy(
+                    "connection closed during CONNECT".to_string(),
+                ));
+            }
+            response.push(byte[0]);
+        }
+
+        let response = String::from_utf8_lossy(&response);
+        let status_line = response.lines().next().unwrap_or("");
+        let mut fields = status_line.split_whitespace();
+        let version = fields.next().unwrap_or("");
+        let status = fields.next().unwrap_or("");
+        if !version.starts_with("HTTP/1.") || !status.starts_with('2') || status.len() != 3 {
+            return Err(Error::HttpProxy(format!(
+                "CONNECT {} failed: {}",
+                target, status_line
+            )));
+        }
+
+        Ok(stream)
+    }
+}
+
+/// A running HTTP CONNECT tunnel.
+/// The tunnel stops accepting new connections when it is dropped.
+#[derive(Debug)]
+pub struct HttpTunnel {
+    local_addr: SocketAddr,
+    username: String,
+    password: String,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl HttpTunnel {
+    /// Returns the loopback address on which the tunnel accepts
+    /// connections
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns the SOCKS5 username and password that a client must
+    /// present in order to use the tunnel.
+    /// They are generated randomly when the tunnel is opened.
+    pub fn credentials(&self) -> (&str, &str) {
+        (&self.username, &self.password)
+    }
+}
+
+impl Drop for HttpTunnel {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake up the accept loop so that it can observe the shutdown flag
+        let _ = TcpStream::connect(self.local_addr);
+    }
+}
+
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_AUTH_USERPASS: u8 = 2;
+const SOCKS5_AUTH_UNACCEPTABLE: u8 = 0xff;
+const SOCKS5_CMD_CONNECT: u8 = 1;
+const SOCKS5_ATYP_IPV4: u8 = 1;
+const SOCKS5_ATYP_DOMAIN: u8 = 3;
+const SOCKS5_ATYP_IPV6: u8 = 4;
+const SOCKS5_SUCCEEDED: u8 = 0;
+const SOCKS5_GENERAL_FAILURE: u8 = 1;
+const SOCKS5_CMD_UNSUPPORTED: u8 = 7;
+const SOCKS5_ATYP_UNSUPPORTED: u8 = 8;
+
+/// Performs the server side of a SOCKS5 handshake (RFC 1928) on
+/// `stream`, requiring username/password authentication (RFC 1929)
+/// with `username` and `password`.
+/// Returns the authority of the destination that the client asked
+/// to be connected to; the caller must then send the reply.
+fn socks5_accept(
+    stream: &mut TcpStream,
+    username: &str,
+    password: &str,
+) -> std::io::Result<String> {
+    fn invalid(what: &str) -> std::io::Error {
+        std::io::Error::new(ErrorKind::InvalidData, what.to_string())
+    }
+    fn read_vec(stream: &mut TcpStream, len: usize) -> std::io::Result<Vec<u8>> {
+        let mut buf = vec![0u8; len];
+        stream.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header)?;
+    if header[0] != SOCKS5_VERSION {
+        return Err(invalid("not a SOCKS5 client"));
+    }
+    let methods = read_vec(stream, header[1].into())?;
+    if !methods.contains(&SOCKS5_AUTH_USERPASS) {
+        stream.write_all(&[SOCKS5_VERSION, SOCKS5_AUTH_UNACCEPTABLE])?;
+        return Err(invalid("client did not offer to authenticate"));
+    }
+    stream.write_all(&[SOCKS5_VERSION, SOCKS5_AUTH_USERPASS])?;
+
+    stream.read_exact(&mut header)?;
+    if header[0] != 1 {
+        return Err(invalid("unexpected authentication version"));
+    }
+    let user = read_vec(stream, header[1].into())?;
+    let mut len = [0u8];
+    stream.read_exact(&mut len)?;
+    let pass = read_vec(stream, len[0].into())?;
+    if user != username.as_bytes() || pass != password.as_bytes() {
+        stream.write_all(&[1, 1])?;
+        return Err(invalid("incorrect credentials"));
+    }
+    stream.write_all(&[1, 0])?;
+
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request)?;
+    if request[1] != SOCKS5_CMD_CONNECT {
+        socks5_reply(stream, SOCKS5_CMD_UNSUPPORTED)?;
+        return Err(invalid("unsupported command"));
+    }
+    let host = match request[3] {
+        SOCKS5_ATYP_IPV4 => {
+            let mut octets = [0u8; 4];
+            stream.read_exact(&mut octets)?;
+            Ipv4Addr::from(octets).to_string()
+        }
+        SOCKS5_ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            stream.read_exact(&mut octets)?;
+            Ipv6Addr::from(octets).to_string()
+        }
+        SOCKS5_ATYP_DOMAIN => {
+            stream.read_exact(&mut len)?;
+            let name = read_vec(stream, len[0].into())?;
+            String::from_utf8(name).map_err(|_| invalid("invalid host name"))?
+        }
+        _ => {
+            socks5_reply(stream, SOCKS5_ATYP_UNSUPPORTED)?;
+            return Err(invalid("unsupported address type"));
+        }
+    };
+    let mut port = [0u8; 2];
+    stream.read_exact(&mut port)?;
+
+    stream.set_read_timeout(None)?;
+    Ok(format_authority(&host, u16::from_be_bytes(port)))
+}
+
+/// Sends a SOCKS5 reply with status `reply` to a CONNECT request.
+/// The bound address is reported as unspecified, which clients
+/// are expected to ignore.
+fn socks5_reply(stream: &mut TcpStream, reply: u8) -> std::io::Result<()> {
+    stream.write_all(&[SOCKS5_VERSION, reply, 0, SOCKS5_ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+}
+
+/// Returns a hard to guess token, used as the credentials that a
+/// client must present to the tunnel
+fn random_token() -> String {
+    let state = RandomState::new();
+    format!(
+        "{:016x}{:016x}",
+        state.hash_one(Instant::now()),
+        state.hash_one(std::process::id())
+    )
+}
+
+/// Copy data in both directions between `a` and `b` until either
+/// side closes its connection.
+fn splice(a: TcpStream, b: TcpStream) {
+    fn pump(mut from: TcpStream, mut to: TcpStream) {
+        let _ = std::io::copy(&mut from, &mut to);
+        let _ = to.shutdown(Shutdown::Write);
+        let _ = from.shutdown(Shutdown::Read);
+    }
+
+    let (a2, b2) = match (a.try_clone(), b.try_clone()) {
+        (Ok(a2), Ok(b2)) => (a2, b2),
+        _ => return,
+    };
+    let _ = std::thread::Builder::new()
+        .name("mosquitto-http-proxy-pump".to_string())
+        .spawn(move || pump(a2, b2));
+    let _ = std::thread::Builder::new()
+        .name("mosquitto-http-proxy-pump".to_string())
+        .spawn(move || pump(b, a));
+}
+
+/// Formats `host` and `port` as an HTTP authority, taking care to
+/// bracket IPv6 literals
+fn format_authority(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        out.push(ALPHABET[(n >> 18) as usize & 63] as char);
+        out.push(ALPHABET[(n >> 12) as usize & 63] as char);
+        if chunk.len() > 1 {
+            out.push(ALPHABET[(n >> 6) as usize & 63] as char);
+        } else {
+            out.push('=');
+        }
+        if chunk.len() > 2 {
+            out.push(ALPHABET[n as usize & 63] as char);
+        } else {
+            out.push('=');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn base64_encoding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"user:pass"), "dXNlcjpwYXNz");
+    }
+
+    #[test]
+    fn authority() {
+        assert_eq!(format_authority("broker", 1883), "broker:1883");
+        assert_eq!(format_authority("::1", 8883), "[::1]:8883");
+        assert_eq!(format_authority("[::1]", 8883), "[::1]:8883");
+    }
+
+    #[test]
+    fn socks5_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = |password: &[u8]| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let mut request = vec![5, 2, 0, 2, 1, 4];
+            request.extend_from_slice(b"user");
+            request.push(password.len() as u8);
+            request.extend_from_slice(password);
+            request.extend_from_slice(&[5, 1, 0, 3, 6]);
+            request.extend_from_slice(b"broker");
+            request.extend_from_slice(&8883u16.to_be_bytes());
+            stream.write_all(&request).unwrap();
+            stream
+        };
+
+        let _stream = client(b"pass");
+        let (mut local, _) = listener.accept().unwrap();
+        assert_eq!(
+            socks5_accept(&mut local, "user", "pass").unwrap(),
+            "broker:8883"
+        );
+
+        let mut stream = client(b"wrong");
+        let (mut local, _) = listener.accept().unwrap();
+        assert!(socks5_accept(&mut local, "user", "pass").is_err());
+        let mut response = [0u8; 4];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!(response, [5, 2, 1, 1]);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::lowlevel::sys;
+use std::ffi::CStr;
+use std::os::raw::c_int;
+use std::time::Duration;
+
+/// An MQTT 5 reason code, as carried by CONNACK, PUBACK, SUBACK,
+/// DISCONNECT and other acknowledgement packets.
+/// Codes below `0x80` indicate success; the others indicate failure.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ReasonCode(pub u8);
+
+macro_rules! reason_codes {
+    ($($name:ident = $code:ident),* $(,)?) => {
+        impl ReasonCode {
+            $(
+                #[doc = concat!("`", stringify!($code), "`")]
+                pub const $name: Self = Self(sys::mqtt5_return_codes::$code as u8);
+            )*
+        }
+    };
+}
+
+reason_codes!(
+    SUCCESS = MQTT_RC_SUCCESS,
+    GRANTED_QOS1 = MQTT_RC_GRANTED_QOS1,
+    GRANTED_QOS2 = MQTT_RC_GRANTED_QOS2,
+    DISCONNECT_WITH_WILL_MSG = MQTT_RC_DISCONNECT_WITH_WILL_MSG,
+    NO_MATCHING_SUBSCRIBERS = MQTT_RC_NO_MATCHING_SUBSCRIBERS,
+    NO_SUBSCRIPTION_EXISTED = MQTT_RC_NO_SUBSCRIPTION_EXISTED,
+    CONTINUE_AUTHENTICATION = MQTT_RC_CONTINUE_AUTHENTICATION,
+    REAUTHENTICATE = MQTT_RC_REAUTHENTICATE,
+    UNSPECIFIED = MQTT_RC_UNSPECIFIED,
+    MALFORMED_PACKET = MQTT_RC_MALFORMED_PACKET,
+    PROTOCOL_ERROR = MQTT_RC_PROTOCOL_ERROR,
+    IMPLEMENTATION_SPECIFIC = MQTT_RC_IMPLEMENTATION_SPECIFIC,
+    UNSUPPORTED_PROTOCOL_VERSION = MQTT_RC_UNSUPPORTED_PROTOCOL_VERSION,
+    CLIENTID_NOT_VALID = MQTT_RC_CLIENTID_NOT_VALID,
+    BAD_USERNAME_OR_PASSWORD = MQTT_RC_BAD_USERNAME_OR_PASSWORD,
+    NOT_AUTHORIZED = MQTT_RC_NOT_AUTHORIZED,
+    SERVER_UNAVAILABLE = MQTT_RC_SERVER_UNAVAILABLE,
+    SERVER_BUSY = MQTT_RC_SERVER_BUSY,
+    BANNED = MQTT_RC_BANNED,
+    SERVER_SHUTTING_DOWN = MQTT_RC_SERVER_SHUTTING_DOWN,
+    BAD_AUTHENTICATION_METHOD = MQTT_RC_BAD_AUTHENTICATION_METHOD,
+    KEEP_ALIVE_TIMEOUT = MQTT_RC_KEEP_ALIVE_TIMEOUT,
+    SESSION_TAKEN_OVER = MQTT_RC_SESSION_TAKEN_OVER,
+    TOPIC_FILTER_INVALID = MQTT_RC_TOPIC_FILTER_INVALID,
+    TOPIC_NAME_INVALID = MQTT_RC_TOPIC_NAME_INVALID,
+    PACKET_ID_IN_USE = MQTT_RC_PACKET_ID_IN_USE,
+    PACKET_ID_NOT_FOUND = MQTT_RC_PACKET_ID_NOT_FOUND,
+    RECEIVE_MAXIMUM_EXCEEDED = MQTT_RC_RECEIVE_MAXIMUM_EXCEEDED,
+    TOPIC_ALIAS_INVALID = MQTT_RC_TOPIC_ALIAS_INVALID,
+    PACKET_TOO_LARGE = MQTT_RC_PACKET_TOO_LARGE,
+    MESSAGE_RATE_TOO_HIGH = MQTT_RC_MESSAGE_RATE_TOO_HIGH,
+    QUOTA_EXCEEDED = MQTT_RC_QUOTA_EXCEEDED,
+    ADMINISTRATIVE_ACTION = MQTT_RC_ADMINISTRATIVE_ACTION,
+    PAYLOAD_FORMAT_INVALID = MQTT_RC_PAYLOAD_FORMAT_INVALID,
+    RETAIN_NOT_SUPPORTED = MQTT_RC_RETAIN_NOT_SUPPORTED,
+    QOS_NOT_SUPPORTED = MQTT_RC_QOS_NOT_SUPPORTED,
+    USE_ANOTHER_SERVER = MQTT_RC_USE_ANOTHER_SERVER,
+    SERVER_MOVED = MQTT_RC_SERVER_MOVED,
+    SHARED_SUBS_NOT_SUPPORTED = MQTT_RC_SHARED_SUBS_NOT_SUPPORTED,
+    CONNECTION_RATE_EXCEEDED = MQTT_RC_CONNECTION_RATE_EXCEEDED,
+    MAXIMUM_CONNECT_TIME = MQTT_RC_MAXIMUM_CONNECT_TIME,
+    SUBSCRIPTION_IDS_NOT_SUPPORTED = MQTT_RC_SUBSCRIPTION_IDS_NOT_SUPPORTED,
+    WILDCARD_SUBS_NOT_SUPPORTED = MQTT_RC_WILDCARD_SUBS_NOT_SUPPORTED,
+);
+
+impl ReasonCode {
+    /// Returns true if the code indicates failure
+    pub fn is_error(&self) -> bool {
+        self.0 >= 0x80
+    }
+
+    /// Returns true if the condition indicated by this code is
+    /// transient, so that repeating the same operation later
+    /// may succeed.
+    pub fn is_retryable(&self) -> bool {
+        self.retry_after().is_some()
+    }
+
+    /// Returns a suggested minimum delay before repeating the operation
+    /// if the condition is transient, or `None` if the operation should
+    /// not be repeated without changing it first.
+    /// Conditions that indicate that the broker is overloaded or that
+    /// a rate limit was hit suggest a longer delay.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match *self {
+            Self::SERVER_UNAVAILABLE
+            | Self::SERVER_BUSY
+            | Self::SERVER_SHUTTING_DOWN
+            | Self::KEEP_ALIVE_TIMEOUT
+            | Self::MAXIMUM_CONNECT_TIME
+            | Self::RECEIVE_MAXIMUM_EXCEEDED => Some(Duration::from_secs(5)),
+            Self::QUOTA_EXCEEDED | Self::MESSAGE_RATE_TOO_HIGH | Self::CONNECTION_RATE_EXCEEDED => {
+                Some(Duration::from_secs(30))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ReasonCode {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let desc = unsafe { sys::mosquitto_reason_string(self.0 as c_int) };
+        if desc.is_null() {
+            write!(fmt, "reason code {}", self.0)
+        } else {
+            let desc = unsafe { CStr::from_ptr(desc) };
+            write!(fmt, "reason code {}: {}", self.0, desc.to_string_lossy())
+        }
+    }
+}
+
+impl std::fmt::Debug for ReasonCode {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, fmt)
+    }
+}
@@ -0,0 +1,298 @@
+use crate::{
+    Client, Error, Message, MessageMiddleware, OutgoingPublish, Properties, PublishMiddleware, QoS,
+};
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Whether a recorded message was received or published by the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// A message captured by a [Recorder](struct.Recorder.html)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedMessage {
+    /// The time since the recording started
+    pub at: Duration,
+    pub direction: Direction,
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: QoS,
+    pub retain: bool,
+    pub properties: Properties,
+}
+
+impl RecordedMessage {
+    /// Returns the message as it would be received, such as to feed it
+    /// to a [SimulatedSource](struct.SimulatedSource.html)
+    pub fn to_message(&self) -> Message {
+        Message {
+            topic: self.topic.clone(),
+            payload: self.payload.clone(),
+            qos: self.qos,
+            retain: self.retain,
+            properties: self.properties.clone(),
+            ..Message::default()
+        }
+    }
+}
+
+/// The representation of a `RecordedMessage` in a recording, which
+/// holds one of these, as JSON, per line
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Entry {
+    at_ms: u64,
+    direction: Direction,
+    topic: String,
+    payload_hex: String,
+    qos: u8,
+    retain: bool,
+    properties: Properties,
+}
+
+impl From<&RecordedMessage> for Entry {
+    fn from(message: &RecordedMessage) -> Self {
+        Self {
+            at_ms: message.at.as_millis() as u64,
+            direction: message.direction,
+            topic: message.topic.clone(),
+            payload_hex: message
+                .payload
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+            qos: message.qos as u8,
+            retain: message.retain,
+            properties: message.properties.clone(),
+        }
+    }
+}
+
+impl Entry {
+    fn into_message(self) -> Option<RecordedMessage> {
+        let hex = &self.payload_hex;
+        let payload = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        let qos = match self.qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            _ => return None,
+        };
+        Some(RecordedMessage {
+            at: Duration::from_millis(self.at_ms),
+            direction: self.direction,
+            topic: self.topic,
+            payload,
+            qos,
+            retain: self.retain,
+            properties: self.properties,
+        })
+    }
+}
+
+struct RecorderState {
+    start: Instant,
+    writer: Box<dyn Write + Send>,
+}
+
+/// Captures the messages that a client receives and publishes, with
+/// their timing and properties, so that a session can be reproduced
+/// later via [Recording](struct.Recording.html); see
+/// [Client::add_recorder](struct.Client.html#method.add_recorder).
+///
+/// Each message is written as a line of JSON as soon as it is seen.
+/// A failure to write is not reported to the client, and doesn't
+/// prevent the message from being delivered or published.
+#[derive(Clone)]
+pub struct Recorder {
+    state: Arc<Mutex<RecorderState>>,
+}
+
+impl Recorder {
+    /// Record to `writer`, starting the clock now
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(RecorderState {
+                start: Instant::now(),
+                writer: Box::new(writer),
+            })),
+        }
+    }
+
+    /// Record to the file at `path`, replacing it if it exists
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(Self::new(LineWriter::new(File::create(path)?)))
+    }
+
+    fn record(
+        &self,
+        direction: Direction,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+        properties: &Properties,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let message = RecordedMessage {
+            at: state.start.elapsed(),
+            direction,
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            qos,
+            retain,
+            properties: properties.clone(),
+        };
+        if let Ok(mut line) = serde_json::to_vec(&Entry::from(&message)) {
+            line.push(b'\n');
+            let _ = state.writer.write_all(&line);
+        }
+    }
+}
+
+impl PublishMiddleware for Recorder {
+    fn on_publish(&self, publish: &mut OutgoingPublish) -> Result<(), Error> {
+        self.record(
+            Direction::Outbound,
+            &publish.topic,
+            &publish.payload,
+            publish.qos,
+            publish.retain,
+            &publish.properties,
+        );
+        Ok(())
+    }
+}
+
+impl MessageMiddleware for Recorder {
+    fn on_message(&self, message: Message) -> Option<Message> {
+        self.record(
+            Direction::Inbound,
+            &message.topic,
+            &message.payload,
+            message.qos,
+            message.retain,
+            &message.properties,
+        );
+        Some(message)
+    }
+}
+
+/// A session captured by a [Recorder](struct.Recorder.html).
+///
+/// ```no_run
+/// # use mosquitto_rs::*;
+/// # async fn reproduce(staging: &Client) -> Result<(), Error> {
+/// let recording = Recording::load("field-issue.jsonl")?;
+/// // Publish what the device published, at the same pace
+/// recording.replay(staging, Direction::Outbound, 1.0).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    messages: Vec<RecordedMessage>,
+}
+
+impl Recording {
+    /// Read a recording from the file at `path`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Read a recording from `reader`.
+    /// Fails with `Error::Codec` if a line isn't a recorded message.
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, Error> {
+        let mut messages = vec![];
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let message = serde_json::from_str::<Entry>(&line)
+                .ok()
+                .and_then(Entry::into_message)
+                .ok_or_else(|| {
+                    Error::Codec(format!("line {} is not a recorded message", number + 1))
+                })?;
+            messages.push(message);
+        }
+        Ok(Self { messages })
+    }
+
+    /// Returns the recorded messages, in the order that they were seen
+    pub fn messages(&self) -> &[RecordedMessage] {
+        &self.messages
+    }
+
+    /// Publish the messages that were recorded travelling in
+    /// `direction` via `client`, pacing them as they were originally
+    /// seen, sped up by a factor of `speed`.
+    /// Each publish is awaited before the next is due; a publish that
+    /// takes longer delays the ones that follow it.
+    ///
+    /// Returns the number of messages that were published, or the
+    /// first error.
+    /// Fails with `Error::InvalidArgument` if `speed` isn't positive.
+    pub async fn replay(
+        &self,
+        client: &Client,
+        direction: Direction,
+        speed: f64,
+    ) -> Result<usize, Error> {
+        if speed.is_nan() || speed <= 0.0 {
+            return Err(Error::InvalidArgument);
+        }
+        let start = Instant::now();
+        let mut published = 0;
+        for message in self.messages.iter().filter(|m| m.direction == direction) {
+            async_io::Timer::at(start + message.at.div_f64(speed)).await;
+            client
+                .publish_before(
+                    &message.topic,
+                    &message.payload,
+                    message.qos,
+                    message.retain,
+                    None,
+                    message.properties.clone(),
+                )
+                .await?;
+            published += 1;
+        }
+        Ok(published)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn entry_round_trip() {
+        let message = RecordedMessage {
+            at: Duration::from_millis(1500),
+            direction: Direction::Inbound,
+            topic: "a/b".to_string(),
+            payload: vec![0, 0x7f, 0xff],
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            properties: Properties::new(),
+        };
+        let entry = Entry::from(&message);
+        assert_eq!(entry.payload_hex, "007fff");
+        assert_eq!(entry.into_message(), Some(message.clone()));
+
+        let mut odd = Entry::from(&message);
+        odd.payload_hex.push('f');
+        assert_eq!(odd.into_message(), None);
+    }
+}
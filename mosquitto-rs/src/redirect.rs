@@ -0,0 +1,144 @@
+use crate::ReasonCode;
+
+/// A server that an MQTT 5 broker has directed the client to use
+/// instead of itself, parsed from a Server Reference property.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ServerReference {
+    /// The host name or IP address of the server
+    pub host: String,
+    /// The port of the server, if specified.
+    /// When not specified, the port of the current server should be used.
+    pub port: Option<u16>,
+}
+
+impl ServerReference {
+    /// Parse a Server Reference property value.
+    /// The MQTT 5 specification doesn't define the format of the value,
+    /// but by convention it holds a space separated list of `host` or
+    /// `host:port` entries, with IPv6 addresses enclosed in brackets.
+    /// Entries that cannot be parsed are skipped.
+    pub fn parse_list(value: &str) -> Vec<Self> {
+        value.split_whitespace().filter_map(Self::parse).collect()
+    }
+
+    fn parse(entry: &str) -> Option<Self> {
+        let (host, port) = if let Some(rest) = entry.strip_prefix('[') {
+            let end = rest.find(']')?;
+            let port = match &rest[end + 1..] {
+                "" => None,
+                port => Some(port.strip_prefix(':')?),
+            };
+            (&rest[..end], port)
+        } else {
+            match entry.split_once(':') {
+                Some((host, port)) if !port.contains(':') => (host, Some(port)),
+                // An unbracketed IPv6 address
+                Some(_) => (entry, None),
+                None => (entry, None),
+            }
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let port = match port {
+            Some(port) => Some(port.parse().ok()?),
+            None => None,
+        };
+        Some(Self {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl std::fmt::Display for ServerReference {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.host.contains(':') {
+            write!(fmt, "[{}]", self.host)?;
+        } else {
+            write!(fmt, "{}", self.host)?;
+        }
+        if let Some(port) = self.port {
+            write!(fmt, ":{}", port)?;
+        }
+        Ok(())
+    }
+}
+
+/// Describes a request from an MQTT 5 broker for the client to use
+/// another server, sent either in response to the CONNECT or via
+/// a DISCONNECT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    /// Either `ReasonCode::USE_ANOTHER_SERVER` or
+    /// `ReasonCode::SERVER_MOVED`
+    pub reason: ReasonCode,
+    /// The servers that the broker suggested, in order of preference
+    pub servers: Vec<ServerReference>,
+    /// Whether the client is automatically reconnecting to the first
+    /// of the suggested servers, according to its `RedirectPolicy`
+    pub followed: bool,
+}
+
+impl Redirect {
+    /// Returns true if the broker indicated that the move is
+    /// permanent, and that the client should use the new server
+    /// for all future connections.
+    pub fn is_permanent(&self) -> bool {
+        self.reason == ReasonCode::SERVER_MOVED
+    }
+}
+
+/// Controls how the high-level client reacts when an MQTT 5 broker
+/// directs it to use another server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RedirectPolicy {
+    /// Don't reconnect; the redirect is reported as an event only.
+    #[default]
+    Ignore,
+    /// Automatically connect to the first referenced server, following
+    /// at most `max_redirects` consecutive redirects before a
+    /// connection is successfully established.
+    Follow {
+        /// The maximum number of consecutive redirects to follow
+        max_redirects: u32,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            ServerReference::parse_list("broker2.example.com:8883 10.0.0.1 [fd00::1]:1884 ::1"),
+            vec![
+                ServerReference {
+                    host: "broker2.example.com".to_string(),
+                    port: Some(8883)
+                },
+                ServerReference {
+                    host: "10.0.0.1".to_string(),
+                    port: None
+                },
+                ServerReference {
+                    host: "fd00::1".to_string(),
+                    port: Some(1884)
+                },
+                ServerReference {
+                    host: "::1".to_string(),
+                    port: None
+                },
+            ]
+        );
+        assert_eq!(
+            ServerReference::parse_list("host:bogus [fd00::1 :1883"),
+            vec![]
+        );
+        assert_eq!(
+            ServerReference::parse_list("[fd00::1]:1884")[0].to_string(),
+            "[fd00::1]:1884"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{Backoff, ConnectionStatus};
+use std::time::Duration;
+
+/// Controls how the high-level client reacts when the broker accepts
+/// the network connection but refuses the CONNECT, for example with
+/// `ServerUnavailable`.
+///
+/// Refusals are classified using
+/// [ConnectionStatus::retry_after](struct.ConnectionStatus.html#method.retry_after):
+/// those for which it suggests a delay, such as the broker being busy
+/// or a connection rate limit being hit, are transient, while the
+/// remainder, such as bad credentials or an unsupported protocol
+/// version, are permanent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RefusalPolicy {
+    /// [connect](struct.Client.html#method.connect) fails with
+    /// `Error::RejectedConnection` as soon as the broker refuses the
+    /// connection.
+    #[default]
+    Report,
+    /// [connect](struct.Client.html#method.connect) repeats the
+    /// attempt after a transient refusal, until the broker accepts the
+    /// connection, refuses it permanently, or it has been refused
+    /// `max_retries` times in succession.
+    /// Each retry waits for the longer of the delay suggested by the
+    /// refusal and the delay computed by `backoff`.
+    ///
+    /// In addition, when a permanent refusal occurs while the client
+    /// is reconnecting automatically, the client stops reconnecting,
+    /// rather than repeating an attempt that cannot succeed.
+    Retry { backoff: Backoff, max_retries: u32 },
+}
+
+impl RefusalPolicy {
+    /// Returns how long to wait before retrying after `status`, which
+    /// is refusal number `refusals` in succession, or `None` if the
+    /// connection should not be retried
+    pub(crate) fn retry_delay(&self, status: ConnectionStatus, refusals: u32) -> Option<Duration> {
+        match self {
+            Self::Report => None,
+            Self::Retry {
+                backoff,
+                max_retries,
+            } => {
+                let suggested = status.retry_after()?;
+                if refusals > *max_retries {
+                    return None;
+                }
+                Some(suggested.max(backoff.delay(refusals)))
+            }
+        }
+    }
+
+    /// Returns true if automatic reconnection should stop after `status`
+    pub(crate) fn stops_reconnecting(&self, status: ConnectionStatus) -> bool {
+        matches!(self, Self::Retry { .. }) && status.is_permanent_refusal()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn retry_delay() {
+        // MQTT 3.1.1 server unavailable, and MQTT 5 not authorized
+        let transient = ConnectionStatus(3);
+        let permanent = ConnectionStatus(0x87);
+
+        assert_eq!(RefusalPolicy::Report.retry_delay(transient, 1), None);
+
+        let policy = RefusalPolicy::Retry {
+            backoff: Backoff::default(),
+            max_retries: 3,
+        };
+        assert_eq!(
+            policy.retry_delay(transient, 1),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            policy.retry_delay(transient, 3),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(policy.retry_delay(transient, 4), None);
+        assert_eq!(policy.retry_delay(permanent, 1), None);
+        assert!(policy.stops_reconnecting(permanent));
+        assert!(!policy.stops_reconnecting(transient));
+        assert!(!RefusalPolicy::Report.stops_reconnecting(permanent));
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{Error, MessageId};
+
+/// The outcome of each publish in a group that was sent via
+/// [Client::publish_all_or_report](struct.Client.html#method.publish_all_or_report),
+/// in the order that they were given.
+///
+/// MQTT has no transactions, so when state is spread across several
+/// topics, some of the publishes may succeed while others fail; this
+/// shows which topics were updated, so that the application can retry
+/// or roll back the rest.
+#[derive(Debug)]
+pub struct PublishReport {
+    outcomes: Vec<(String, Result<MessageId, Error>)>,
+}
+
+impl PublishReport {
+    pub(crate) fn new(outcomes: Vec<(String, Result<MessageId, Error>)>) -> Self {
+        Self { outcomes }
+    }
+
+    /// Returns true if every publish was acknowledged
+    pub fn is_ok(&self) -> bool {
+        self.outcomes.iter().all(|(_, result)| result.is_ok())
+    }
+
+    /// Returns the topic and outcome of each publish
+    pub fn outcomes(&self) -> &[(String, Result<MessageId, Error>)] {
+        &self.outcomes
+    }
+
+    /// Returns the topics of the publishes that were acknowledged
+    pub fn succeeded(&self) -> impl Iterator<Item = &str> {
+        self.outcomes
+            .iter()
+            .filter(|(_, result)| result.is_ok())
+            .map(|(topic, _)| topic.as_str())
+    }
+
+    /// Returns the topics of the publishes that failed, or that weren't
+    /// acknowledged in time, with their errors
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &Error)> {
+        self.outcomes
+            .iter()
+            .filter_map(|(topic, result)| result.as_ref().err().map(|err| (topic.as_str(), err)))
+    }
+
+    /// Returns the outcomes, consuming the report
+    pub fn into_outcomes(self) -> Vec<(String, Result<MessageId, Error>)> {
+        self.outcomes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn partial_failure() {
+        let report = PublishReport::new(vec![
+            ("a".to_string(), Ok(1)),
+            ("b".to_string(), Err(Error::Timeout)),
+        ]);
+        assert!(!report.is_ok());
+        assert_eq!(report.succeeded().collect::<Vec<_>>(), vec!["a"]);
+        let failures: Vec<_> = report.failures().map(|(topic, _)| topic).collect();
+        assert_eq!(failures, vec!["b"]);
+    }
+}
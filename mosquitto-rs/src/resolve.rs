@@ -0,0 +1,469 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+/// A `Resolver` maps a broker host name and port to the list of
+/// socket addresses that should be tried, in order, when connecting.
+///
+/// By default libmosquitto resolves host names itself using
+/// `getaddrinfo`.  Installing a resolver via
+/// [Client::set_resolver](struct.Client.html#method.set_resolver)
+/// allows deployments that use service discovery to control which
+/// endpoints are used instead.
+///
+/// Any `Fn(&str, u16) -> std::io::Result<Vec<SocketAddr>>` closure
+/// can be used as a resolver:
+///
+/// ```no_run
+/// use mosquitto_rs::*;
+///
+/// let mut client = Client::with_auto_id()?;
+/// client.set_resolver(|host: &str, port: u16| {
+///     println!("resolving {}", host);
+///     Ok(vec![([10, 0, 0, 5], port).into()])
+/// });
+/// # Ok::<(), Error>(())
+/// ```
+pub trait Resolver: Send + Sync {
+    /// Returns the candidate addresses for `host` and `port`.
+    /// The addresses are tried in the order that they are returned.
+    fn resolve(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>>;
+}
+
+impl<F> Resolver for F
+where
+    F: Fn(&str, u16) -> std::io::Result<Vec<SocketAddr>> + Send + Sync,
+{
+    fn resolve(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        (self)(host, port)
+    }
+}
+
+/// Resolves host names using the system resolver via the standard
+/// library.  This produces the same results that libmosquitto would,
+/// but allows the addresses to be post-processed by a connect strategy.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        Ok((host, port).to_socket_addrs()?.collect())
+    }
+}
+
+/// A resolver that ignores the requested host name and always yields
+/// a fixed list of pre-resolved addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticResolver {
+    addrs: Vec<SocketAddr>,
+}
+
+impl StaticResolver {
+    /// Create a resolver that always yields `addrs`
+    pub fn new(addrs: Vec<SocketAddr>) -> Self {
+        Self { addrs }
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn resolve(&self, _host: &str, _port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        Ok(self.addrs.clone())
+    }
+}
+
+/// Combines a discovery mechanism, such as a resolver that looks up
+/// DNS SRV records, with a static list of fallback addresses.
+///
+/// The discovered addresses are tried first, followed by those fallback
+/// addresses that weren't also discovered; if discovery fails, only the
+/// fallback addresses are used, so that the client can still connect
+/// while the discovery service is unavailable.
+///
+/// Combined with
+/// [Client::set_endpoint_refresh](struct.Client.html#method.set_endpoint_refresh),
+/// this allows a long-running client to follow a broker that is
+/// migrated to new addresses behind DNS.
+pub struct HybridResolver<D> {
+    discovery: D,
+    fallback: Vec<SocketAddr>,
+}
+
+impl<D: Resolver> HybridResolver<D> {
+    /// Create a resolver that tries the addresses yielded by `discovery`
+    /// before `fallback`
+    pub fn new(discovery: D, fallback: Vec<SocketAddr>) -> Self {
+        Self {
+            discovery,
+            fallback,
+        }
+    }
+}
+
+impl<D: Resolver> Resolver for HybridResolver<D> {
+    fn resolve(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        match self.discovery.resolve(host, port) {
+            Ok(mut addrs) => {
+                for addr in &self.fallback {
+                    if !addrs.contains(addr) {
+                        addrs.push(*addr);
+                    }
+                }
+                Ok(addrs)
+            }
+            Err(err) if self.fallback.is_empty() => Err(err),
+            Err(_) => Ok(self.fallback.clone()),
+        }
+    }
+}
+
+/// Controls how the high-level client chooses between multiple
+/// candidate addresses for the broker.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConnectStrategy {
+    /// Try each address in turn, in the order produced by the resolver.
+    #[default]
+    Sequential,
+    /// Interleave IPv6 and IPv4 addresses and race TCP connection
+    /// attempts against them, starting each attempt `stagger` after
+    /// the previous one (in the style of RFC 8305 "Happy Eyeballs").
+    /// The first address to accept a TCP connection is then used for
+    /// the MQTT connection.
+    /// This is useful for brokers with unreliable IPv6 connectivity.
+    HappyEyeballs {
+        /// The delay between starting successive attempts
+        stagger: Duration,
+        /// How long to wait for an individual attempt to succeed
+        timeout: Duration,
+    },
+}
+
+impl ConnectStrategy {
+    /// Returns a `HappyEyeballs` strategy with the delay of 250ms
+    /// recommended by RFC 8305, and a 10 second per-attempt timeout.
+    pub fn happy_eyeballs() -> Self {
+        Self::HappyEyeballs {
+            stagger: Duration::from_millis(250),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Controls which of the candidate addresses for the broker the
+/// high-level client uses, both when it connects and when it reconnects
+/// after losing the connection; see
+/// [Client::set_endpoint_selection](struct.Client.html#method.set_endpoint_selection).
+///
+/// This applies when the broker host name is resolved by this crate,
+/// or when connecting via
+/// [Client::connect_to_addrs](struct.Client.html#method.connect_to_addrs).
+/// The first address produced by the resolver is the primary.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum EndpointSelection {
+    /// Try the addresses in the order produced by the resolver, and
+    /// reconnect to the same address after losing the connection
+    #[default]
+    InOrder,
+    /// Use the primary address for as long as it works.
+    /// When the connection is lost, fail over to the next address; once
+    /// the client has been using other addresses for `failback`, it
+    /// returns to the primary, closing a working connection to do so.
+    StickyPrimary { failback: Duration },
+    /// Choose an address at random, in proportion to its weight, for
+    /// each connection and reconnection, so that clients spread their
+    /// load across a cluster as it is provisioned.
+    /// Addresses that aren't listed have a weight of 1; those with a
+    /// weight of 0 are only used when no others remain.
+    WeightedRandom(Vec<(SocketAddr, u32)>),
+    /// Start each connection at the address after the one used by the
+    /// previous connection, and move on to the next address after
+    /// losing the connection
+    RoundRobin,
+}
+
+#[cfg(feature = "async")]
+impl EndpointSelection {
+    /// Returns `addrs` in the order that they should be tried for the
+    /// connection numbered `attempt`, using `random` as a source of
+    /// random numbers
+    pub(crate) fn order(
+        &self,
+        addrs: &[SocketAddr],
+        attempt: usize,
+        random: &mut dyn FnMut() -> u64,
+    ) -> Vec<SocketAddr> {
+        match self {
+            Self::InOrder | Self::StickyPrimary { .. } => addrs.to_vec(),
+            Self::RoundRobin => {
+                let mut addrs = addrs.to_vec();
+                if !addrs.is_empty() {
+                    let len = addrs.len();
+                    addrs.rotate_left(attempt % len);
+                }
+                addrs
+            }
+            Self::WeightedRandom(weights) => {
+                let mut remaining = addrs.to_vec();
+                let mut ordered = Vec::with_capacity(addrs.len());
+                while let Some(index) = self.pick(weights, &remaining, random) {
+                    ordered.push(remaining.remove(index));
+                }
+                ordered.extend(remaining);
+                ordered
+            }
+        }
+    }
+
+    /// Returns the address to reconnect to after losing the connection
+    /// to `current`, or `None` to reconnect to the same address.
+    /// `failed_over_for` is how long the client has been using
+    /// addresses other than the primary.
+    pub(crate) fn after_loss(
+        &self,
+        addrs: &[SocketAddr],
+        current: SocketAddr,
+        failed_over_for: Option<Duration>,
+        random: &mut dyn FnMut() -> u64,
+    ) -> Option<SocketAddr> {
+        let next = || {
+            let index = addrs.iter().position(|addr| *addr == current)?;
+            addrs.get((index + 1) % addrs.len()).copied()
+        };
+        let chosen = match self {
+            Self::InOrder => None,
+            Self::StickyPrimary { failback } => match failed_over_for {
+                Some(elapsed) if elapsed >= *failback => addrs.first().copied(),
+                _ => next(),
+            },
+            Self::RoundRobin => next(),
+            Self::WeightedRandom(weights) => {
+                let others: Vec<SocketAddr> = addrs
+                    .iter()
+                    .copied()
+                    .filter(|addr| *addr != current)
+                    .collect();
+                self.pick(weights, &others, random)
+                    .map(|index| others[index])
+            }
+        };
+        chosen.filter(|addr| *addr != current)
+    }
+
+    /// Returns the index of an address from `addrs` chosen at random in
+    /// proportion to `weights`, or `None` if they all have a weight of 0
+    fn pick(
+        &self,
+        weights: &[(SocketAddr, u32)],
+        addrs: &[SocketAddr],
+        random: &mut dyn FnMut() -> u64,
+    ) -> Option<usize> {
+        let weight_of = |addr: &SocketAddr| {
+            weights
+                .iter()
+                .find(|(a, _)| a == addr)
+                .map(|(_, weight)| u64::from(*weight))
+                .unwrap_or(1)
+        };
+        let total: u64 = addrs.iter().map(weight_of).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut target = random() % total;
+        for (index, addr) in addrs.iter().enumerate() {
+            let weight = weight_of(addr);
+            if target < weight {
+                return Some(index);
+            }
+            target -= weight;
+        }
+        None
+    }
+}
+
+/// Reorders `addrs` so that the address families alternate, starting
+/// with the family of the first address, while preserving the
+/// relative order of addresses within each family.
+#[cfg(feature = "async")]
+pub(crate) fn interleave_families(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let first_is_v6 = addrs.first().map(SocketAddr::is_ipv6).unwrap_or(true);
+    let (mut preferred, mut other): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        addrs.iter().partition(|addr| addr.is_ipv6() == first_is_v6);
+    let mut result = Vec::with_capacity(addrs.len());
+    preferred.reverse();
+    other.reverse();
+    loop {
+        match (preferred.pop(), other.pop()) {
+            (None, None) => break,
+            (a, b) => {
+                result.extend(a);
+                result.extend(b);
+            }
+        }
+    }
+    result
+}
+
+/// Races TCP connection attempts against `addrs`, starting each one
+/// `stagger` after the previous, and returns the first address that
+/// accepted a connection.
+/// The attempts are driven by the async runtime, so waiting for them
+/// doesn't block the executor.
+/// libmosquitto has no way to adopt an established socket, so the
+/// winning connection is closed again along with the others before
+/// returning, and the caller connects to the address that it yields.
+#[cfg(feature = "async")]
+pub(crate) async fn race_connect(
+    addrs: &[SocketAddr],
+    stagger: Duration,
+    timeout: Duration,
+) -> std::io::Result<SocketAddr> {
+    use async_io::{Async, Timer};
+    use std::future::Future;
+    use std::io::{Error, ErrorKind};
+    use std::net::TcpStream;
+    use std::pin::Pin;
+    use std::task::Poll;
+    use std::time::Instant;
+
+    type Attempt =
+        Pin<Box<dyn Future<Output = (SocketAddr, std::io::Result<Async<TcpStream>>)> + Send>>;
+
+    let start = Instant::now();
+    let mut attempts: Vec<Attempt> = addrs
+        .iter()
+        .enumerate()
+        .map(|(index, addr)| {
+            let addr = *addr;
+            let begin = start + stagger * index as u32;
+            Box::pin(async move {
+                Timer::at(begin).await;
+                let expired = async {
+                    Timer::after(timeout).await;
+                    Err(Error::new(
+                        ErrorKind::TimedOut,
+                        "timed out connecting to the broker",
+                    ))
+                };
+                (
+                    addr,
+                    futures_lite::future::or(Async::<TcpStream>::connect(addr), expired).await,
+                )
+            }) as Attempt
+        })
+        .collect();
+
+    let mut last_error = None;
+    futures_lite::future::poll_fn(|cx| {
+        let mut index = 0;
+        while index < attempts.len() {
+            match attempts[index].as_mut().poll(cx) {
+                Poll::Ready((addr, Ok(_stream))) => return Poll::Ready(Ok(addr)),
+                Poll::Ready((_, Err(err))) => {
+                    drop(attempts.swap_remove(index));
+                    last_error = Some(err);
+                }
+                Poll::Pending => index += 1,
+            }
+        }
+        if attempts.is_empty() {
+            Poll::Ready(Err(last_error.take().unwrap_or_else(|| {
+                Error::new(ErrorKind::TimedOut, "timed out connecting to the broker")
+            })))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+#[cfg(all(test, feature = "async"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interleave() {
+        let a4: SocketAddr = "10.0.0.1:1883".parse().unwrap();
+        let b4: SocketAddr = "10.0.0.2:1883".parse().unwrap();
+        let c4: SocketAddr = "10.0.0.3:1883".parse().unwrap();
+        let a6: SocketAddr = "[fd00::1]:1883".parse().unwrap();
+        let b6: SocketAddr = "[fd00::2]:1883".parse().unwrap();
+
+        assert_eq!(
+            interleave_families(&[a6, b6, a4, b4, c4]),
+            vec![a6, a4, b6, b4, c4]
+        );
+        assert_eq!(interleave_families(&[a4, b4, a6, c4]), vec![a4, a6, b4, c4]);
+        assert_eq!(interleave_families(&[]), vec![]);
+    }
+
+    #[test]
+    fn race() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let listening = listener.local_addr().unwrap();
+        let closed = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let stagger = Duration::from_millis(50);
+        let timeout = Duration::from_secs(5);
+
+        let winner = smol::block_on(race_connect(&[closed, listening], stagger, timeout));
+        assert_eq!(winner.unwrap(), listening);
+        assert!(smol::block_on(race_connect(&[closed], stagger, timeout)).is_err());
+    }
+
+    #[test]
+    fn endpoint_selection() {
+        let a: SocketAddr = "10.0.0.1:1883".parse().unwrap();
+        let b: SocketAddr = "10.0.0.2:1883".parse().unwrap();
+        let c: SocketAddr = "10.0.0.3:1883".parse().unwrap();
+        let addrs = [a, b, c];
+        let mut zero = || 0;
+
+        let round_robin = EndpointSelection::RoundRobin;
+        assert_eq!(round_robin.order(&addrs, 4, &mut zero), vec![b, c, a]);
+        assert_eq!(round_robin.after_loss(&addrs, c, None, &mut zero), Some(a));
+
+        let sticky = EndpointSelection::StickyPrimary {
+            failback: Duration::from_secs(60),
+        };
+        assert_eq!(sticky.after_loss(&addrs, a, None, &mut zero), Some(b));
+        let recently = Some(Duration::from_secs(1));
+        assert_eq!(sticky.after_loss(&addrs, b, recently, &mut zero), Some(c));
+        let long_ago = Some(Duration::from_secs(61));
+        assert_eq!(sticky.after_loss(&addrs, b, long_ago, &mut zero), Some(a));
+
+        // `a` is never chosen while others remain
+        let weighted = EndpointSelection::WeightedRandom(vec![(a, 0), (c, 3)]);
+        let mut sequence = [3, 0].iter().copied().cycle();
+        let mut random = || sequence.next().unwrap();
+        assert_eq!(weighted.order(&addrs, 0, &mut random), vec![c, b, a]);
+        assert_eq!(weighted.after_loss(&addrs, c, None, &mut random), Some(b));
+        assert_eq!(weighted.after_loss(&[a, b], b, None, &mut random), None);
+
+        assert_eq!(
+            EndpointSelection::InOrder.after_loss(&addrs, a, None, &mut zero),
+            None
+        );
+    }
+
+    #[test]
+    fn hybrid() {
+        let a: SocketAddr = "10.0.0.1:1883".parse().unwrap();
+        let b: SocketAddr = "10.0.0.2:1883".parse().unwrap();
+        let c: SocketAddr = "10.0.0.3:1883".parse().unwrap();
+
+        let resolver = HybridResolver::new(StaticResolver::new(vec![c, a]), vec![a, b]);
+        assert_eq!(resolver.resolve("broker", 1883).unwrap(), vec![c, a, b]);
+
+        let failing = |_: &str, _: u16| -> std::io::Result<Vec<SocketAddr>> {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "no SRV records",
+            ))
+        };
+        let resolver = HybridResolver::new(failing, vec![b]);
+        assert_eq!(resolver.resolve("broker", 1883).unwrap(), vec![b]);
+        assert!(HybridResolver::new(failing, vec![])
+            .resolve("broker", 1883)
+            .is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use crate::{Error, Message};
+use async_channel::{bounded, Receiver, Sender};
+use futures_lite::FutureExt;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct Pending {
+    registration: u64,
+    deadline: Instant,
+    tx: Sender<Message>,
+}
+
+#[derive(Default)]
+struct Inner {
+    pending: Mutex<HashMap<Vec<u8>, Pending>>,
+    next_id: AtomicU64,
+    next_registration: AtomicU64,
+}
+
+/// Matches MQTT 5 response messages with the requests that produced
+/// them, using the Correlation Data property.
+///
+/// The requester registers the correlation data that it attached to
+/// a request, and feeds received messages to
+/// [dispatch](#method.dispatch); the message carrying the same
+/// correlation data completes the corresponding
+/// [PendingResponse](struct.PendingResponse.html).
+///
+/// The matcher is independent of any particular client, so it can be
+/// used with custom request/response topologies, for example when
+/// responses arrive on a shared topic and need to be routed to one
+/// of several concurrent requests.
+///
+/// ```no_run
+/// # use mosquitto_rs::*;
+/// # async fn request(client: &mut Client) -> Result<(), Error> {
+/// let matcher = ResponseMatcher::new();
+/// let responses = client.subscriber().unwrap();
+/// client.subscribe("responses/me", QoS::AtLeastOnce).await?;
+///
+/// let correlation = matcher.next_correlation_data();
+/// let pending = matcher.register(correlation, std::time::Duration::from_secs(5));
+/// // ... publish a request carrying the correlation data ...
+///
+/// while let Ok(message) = responses.recv().await {
+///     if let Some(unmatched) = matcher.dispatch(message) {
+///         println!("not a response: {:?}", unmatched);
+///     }
+///     if pending.is_complete() {
+///         break;
+///     }
+/// }
+/// let response = pending.wait().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct ResponseMatcher {
+    inner: Arc<Inner>,
+}
+
+impl ResponseMatcher {
+    /// Create a new, empty, matcher
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns correlation data that is unique within this matcher,
+    /// suitable for attaching to a request
+    pub fn next_correlation_data(&self) -> Vec<u8> {
+        self.inner
+            .next_id
+            .fetch_add(1, Ordering::Relaxed)
+            .to_be_bytes()
+            .to_vec()
+    }
+
+    /// Register interest in the response carrying `correlation_data`.
+    /// The response must arrive within `timeout`; otherwise waiting
+    /// on the returned `PendingResponse` yields `Error::Timeout`.
+    ///
+    /// Registering the same correlation data again replaces the
+    /// earlier registration.
+    pub fn register(&self, correlation_data: Vec<u8>, timeout: Duration) -> PendingResponse {
+        let (tx, rx) = bounded(1);
+        let deadline = Instant::now() + timeout;
+        let registration = self.inner.next_registration.fetch_add(1, Ordering::Relaxed);
+        self.inner.pending.lock().unwrap().insert(
+            correlation_data.clone(),
+            Pending {
+                registration,
+                deadline,
+                tx,
+            },
+        );
+        PendingResponse {
+            matcher: self.clone(),
+            registration,
+            correlation_data,
+            deadline,
+            rx,
+        }
+    }
+
+    /// Deliver `message` to the pending response with matching
+    /// correlation data.
+    /// Returns the message if it has no correlation data, or
+    /// if no response with that correlation data is pending.
+    pub fn dispatch(&self, message: Message) -> Option<Message> {
+        let pending = match message.properties.correlation_data() {
+            Some(data) => self.inner.pending.lock().unwrap().remove(data),
+            None => None,
+        };
+        match pending {
+            Some(pending) => match pending.tx.try_send(message) {
+                Ok(()) => None,
+                Err(err) => Some(err.into_inner()),
+            },
+            None => Some(message),
+        }
+    }
+
+    /// Remove any registrations whose timeout has passed, returning
+    /// the number that were removed.
+    /// Registrations are also removed when their `PendingResponse`
+    /// is dropped, so calling this is only necessary if pending
+    /// responses are kept around without being awaited.
+    pub fn expire(&self) -> usize {
+        let now = Instant::now();
+        let mut pending = self.inner.pending.lock().unwrap();
+        let before = pending.len();
+        pending.retain(|_, p| p.deadline > now);
+        before - pending.len()
+    }
+
+    /// Returns the number of responses that are pending
+    pub fn len(&self) -> usize {
+        self.inner.pending.lock().unwrap().len()
+    }
+
+    /// Returns true if no responses are pending
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A response that has been registered with a
+/// [ResponseMatcher](struct.ResponseMatcher.html).
+/// Dropping it cancels the registration.
+pub struct PendingResponse {
+    matcher: ResponseMatcher,
+    registration: u64,
+    correlation_data: Vec<u8>,
+    deadline: Instant,
+    rx: Receiver<Message>,
+}
+
+impl PendingResponse {
+    /// Returns the correlation data that was registered
+    pub fn correlation_data(&self) -> &[u8] {
+        &self.correlation_data
+    }
+
+    /// Returns true if the response has been received
+    pub fn is_complete(&self) -> bool {
+        !self.rx.is_empty()
+    }
+
+    /// Wait for the response to arrive.
+    /// Yields `Error::Timeout` if it doesn't arrive before the timeout
+    /// that was specified when registering, or if the registration
+    /// was replaced or expired.
+    pub async fn wait(self) -> Result<Message, Error> {
+        let response = async { self.rx.recv().await.map_err(|_| Error::Timeout) };
+        let timeout = async {
+            async_io::Timer::at(self.deadline).await;
+            Err(Error::Timeout)
+        };
+        response.or(timeout).await
+    }
+}
+
+impl Drop for PendingResponse {
+    fn drop(&mut self) {
+        let mut pending = self.matcher.inner.pending.lock().unwrap();
+        // Only remove the registration if it is ours, and hasn't
+        // been replaced by a subsequent registration
+        if let Some(p) = pending.get(&self.correlation_data) {
+            if p.registration == self.registration {
+                pending.remove(&self.correlation_data);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Properties, Property};
+
+    fn response(correlation_data: &[u8]) -> Message {
+        let mut properties = Properties::new();
+        properties.push(Property::CorrelationData(correlation_data.to_vec()));
+        Message {
+            topic: "responses".to_string(),
+            properties,
+            ..Message::default()
+        }
+    }
+
+    #[test]
+    fn dispatch() {
+        let matcher = ResponseMatcher::new();
+        let a = matcher.register(matcher.next_correlation_data(), Duration::from_secs(5));
+        let b = matcher.register(matcher.next_correlation_data(), Duration::from_secs(5));
+        assert_ne!(a.correlation_data(), b.correlation_data());
+        assert_eq!(matcher.len(), 2);
+
+        assert!(matcher.dispatch(response(b.correlation_data())).is_none());
+        assert!(b.is_complete());
+        assert!(!a.is_complete());
+        assert!(matcher.dispatch(response(b"unknown")).is_some());
+        assert!(matcher.dispatch(Message::default()).is_some());
+
+        drop(a);
+        assert!(matcher.is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+//! Conversions between the types in this crate and the equivalent
+//! types in the `rumqttc` crate, enabled by the `rumqttc` feature.
+use crate::{QoS, RetainHandling, SubscribeOptions};
+use rumqttc::v5::mqttbytes::v5::{Filter, RetainForwardRule};
+
+impl From<QoS> for rumqttc::QoS {
+    fn from(qos: QoS) -> Self {
+        match qos {
+            QoS::AtMostOnce => Self::AtMostOnce,
+            QoS::AtLeastOnce => Self::AtLeastOnce,
+            QoS::ExactlyOnce => Self::ExactlyOnce,
+        }
+    }
+}
+
+impl From<rumqttc::QoS> for QoS {
+    fn from(qos: rumqttc::QoS) -> Self {
+        match qos {
+            rumqttc::QoS::AtMostOnce => Self::AtMostOnce,
+            rumqttc::QoS::AtLeastOnce => Self::AtLeastOnce,
+            rumqttc::QoS::ExactlyOnce => Self::ExactlyOnce,
+        }
+    }
+}
+
+impl From<QoS> for rumqttc::v5::mqttbytes::QoS {
+    fn from(qos: QoS) -> Self {
+        match qos {
+            QoS::AtMostOnce => Self::AtMostOnce,
+            QoS::AtLeastOnce => Self::AtLeastOnce,
+            QoS::ExactlyOnce => Self::ExactlyOnce,
+        }
+    }
+}
+
+impl From<rumqttc::v5::mqttbytes::QoS> for QoS {
+    fn from(qos: rumqttc::v5::mqttbytes::QoS) -> Self {
+        use rumqttc::v5::mqttbytes::QoS as V5QoS;
+        match qos {
+            V5QoS::AtMostOnce => Self::AtMostOnce,
+            V5QoS::AtLeastOnce => Self::AtLeastOnce,
+            V5QoS::ExactlyOnce => Self::ExactlyOnce,
+        }
+    }
+}
+
+impl From<RetainHandling> for RetainForwardRule {
+    fn from(handling: RetainHandling) -> Self {
+        match handling {
+            RetainHandling::SendAlways => Self::OnEverySubscribe,
+            RetainHandling::SendNew => Self::OnNewSubscribe,
+            RetainHandling::SendNever => Self::Never,
+        }
+    }
+}
+
+impl From<RetainForwardRule> for RetainHandling {
+    fn from(rule: RetainForwardRule) -> Self {
+        match rule {
+            RetainForwardRule::OnEverySubscribe => Self::SendAlways,
+            RetainForwardRule::OnNewSubscribe => Self::SendNew,
+            RetainForwardRule::Never => Self::SendNever,
+        }
+    }
+}
+
+impl From<&Filter> for SubscribeOptions {
+    fn from(filter: &Filter) -> Self {
+        Self {
+            no_local: filter.nolocal,
+            retain_as_published: filter.preserve_retain,
+            retain_handling: filter.retain_forward_rule.clone().into(),
+        }
+    }
+}
+
+impl SubscribeOptions {
+    /// Returns a `rumqttc` subscription filter for `pattern` at `qos`
+    /// that uses these options
+    pub fn to_rumqttc_filter(&self, pattern: &str, qos: QoS) -> Filter {
+        Filter {
+            path: pattern.to_string(),
+            qos: qos.into(),
+            nolocal: self.no_local,
+            preserve_retain: self.retain_as_published,
+            retain_forward_rule: self.retain_handling.into(),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+mod message {
+    use crate::{Message, RetainKind};
+
+    impl From<rumqttc::Publish> for Message {
+        fn from(publish: rumqttc::Publish) -> Self {
+            Self {
+                topic: publish.topic.clone(),
+                payload: publish.payload.to_vec(),
+                qos: publish.qos.into(),
+                retain: publish.retain,
+                retain_kind: RetainKind::classify(publish.retain, false),
+                mid: publish.pkid.into(),
+                possibly_duplicate: publish.dup,
+                ..Self::default()
+            }
+        }
+    }
+
+    impl From<&Message> for rumqttc::Publish {
+        fn from(message: &Message) -> Self {
+            let mut publish =
+                rumqttc::Publish::new(&message.topic, message.qos.into(), message.payload.clone());
+            publish.retain = message.retain;
+            publish.dup = message.possibly_duplicate;
+            publish
+        }
+    }
+
+    impl From<Message> for rumqttc::Publish {
+        fn from(message: Message) -> Self {
+            (&message).into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn filter_round_trip() {
+        let options = SubscribeOptions {
+            no_local: true,
+            retain_as_published: false,
+            retain_handling: RetainHandling::SendNew,
+        };
+        let filter = options.to_rumqttc_filter("a/#", QoS::AtLeastOnce);
+        assert_eq!(filter.path, "a/#");
+        assert_eq!(QoS::from(filter.qos), QoS::AtLeastOnce);
+        assert_eq!(SubscribeOptions::from(&filter), options);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::lowlevel::topic_matches_sub;
+use crate::Error;
+use jsonschema::Validator;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// A registry of JSON Schemas that describe the payloads that are
+/// expected on particular topics, enabled by the `json-schema` feature.
+///
+/// Installing a registry via
+/// [Client::set_schema_registry](struct.Client.html#method.set_schema_registry)
+/// causes both outgoing and received messages to be validated.
+/// Publishing a message that doesn't match the schema for its topic
+/// fails with `Error::SchemaViolation`, while received messages that
+/// don't match are diverted to the
+/// [dead letter](struct.Client.html#method.dead_letters) channel.
+///
+/// ```no_run
+/// use mosquitto_rs::*;
+///
+/// let mut registry = SchemaRegistry::new();
+/// registry.add(
+///     "sensors/+/temperature",
+///     &serde_json::json!({
+///         "type": "object",
+///         "required": ["celsius"],
+///     }),
+/// )?;
+/// # Ok::<(), Error>(())
+/// ```
+#[derive(Clone, Default)]
+pub struct SchemaRegistry {
+    schemas: Vec<(String, Arc<Validator>)>,
+}
+
+impl SchemaRegistry {
+    /// Create a new, empty, registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require that payloads on topics matching `pattern` are JSON
+    /// documents that conform to `schema`.
+    /// When a topic matches more than one pattern, the payload must
+    /// conform to all of the corresponding schemas.
+    pub fn add(&mut self, pattern: &str, schema: &Value) -> Result<(), Error> {
+        let validator =
+            jsonschema::validator_for(schema).map_err(|e| Error::InvalidSchema(e.to_string()))?;
+        self.schemas
+            .push((pattern.to_string(), Arc::new(validator)));
+        Ok(())
+    }
+
+    /// Returns true if no schemas have been added
+    pub fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+
+    /// Check `payload` against the schemas that apply to `topic`.
+    /// Topics that don't match any pattern are not validated.
+    pub fn validate(&self, topic: &str, payload: &[u8]) -> Result<(), Error> {
+        let violation = |message: String| Error::SchemaViolation {
+            topic: topic.to_string(),
+            message,
+        };
+
+        let mut document = None;
+        for (pattern, validator) in &self.schemas {
+            if !topic_matches_sub(pattern, topic).unwrap_or(false) {
+                continue;
+            }
+            if document.is_none() {
+                let value: Value =
+                    serde_json::from_slice(payload).map_err(|e| violation(e.to_string()))?;
+                document.replace(value);
+            }
+            if let Some(document) = &document {
+                validator
+                    .validate(document)
+                    .map_err(|e| violation(e.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validate() {
+        let mut registry = SchemaRegistry::new();
+        registry
+            .add(
+                "sensors/+",
+                &serde_json::json!({
+                    "type": "object",
+                    "required": ["celsius"],
+                }),
+            )
+            .unwrap();
+
+        assert!(registry
+            .validate("sensors/a", br#"{"celsius": 20}"#)
+            .is_ok());
+        assert!(registry.validate("other", b"not json").is_ok());
+        assert!(matches!(
+            registry.validate("sensors/a", br#"{"fahrenheit": 68}"#),
+            Err(Error::SchemaViolation { .. })
+        ));
+        assert!(matches!(
+            registry.validate("sensors/a", b"not json"),
+            Err(Error::SchemaViolation { .. })
+        ));
+    }
+}
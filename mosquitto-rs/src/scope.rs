@@ -0,0 +1,161 @@
+use crate::lowlevel::{sub_topic_check, topic_matches_sub};
+use crate::{Client, Error, Message, MessageFilter, MessageId, Properties, QoS, Subscriber};
+use std::sync::{Arc, Mutex};
+
+/// The subscription patterns of a [ScopedClient](struct.ScopedClient.html),
+/// including its prefix, which select the messages delivered to it
+#[derive(Default)]
+pub(crate) struct ScopedPatterns(Mutex<Vec<String>>);
+
+impl MessageFilter for Arc<ScopedPatterns> {
+    fn matches(&self, message: &Message) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|pattern| topic_matches_sub(pattern, &message.topic).unwrap_or(false))
+    }
+}
+
+/// Returns `prefix` as a complete topic level, so that the namespace
+/// `tenant/a` cannot reach the topics of `tenant/abc`
+pub(crate) fn normalize_prefix(prefix: &str) -> Result<String, Error> {
+    if prefix.is_empty() || prefix.contains(['+', '#']) {
+        return Err(Error::InvalidArgument);
+    }
+    if prefix.ends_with('/') {
+        Ok(prefix.to_string())
+    } else {
+        Ok(format!("{}/", prefix))
+    }
+}
+
+/// A view of a [Client](struct.Client.html) that is confined to the
+/// topics beneath a prefix; see
+/// [Client::scoped](struct.Client.html#method.scoped).
+///
+/// Topics and patterns passed to it are relative to the prefix, and
+/// the topics of the messages that it yields have the prefix removed,
+/// so code that is given a scoped client cannot publish or subscribe
+/// outside of its namespace.
+/// Wildcards are confined too, since they can only appear after the
+/// prefix: `#` subscribes to everything in the namespace.
+///
+/// The messages of its subscriptions are delivered via its own queue,
+/// as for [Client::filtered_subscriber](struct.Client.html#method.filtered_subscriber).
+///
+/// ```no_run
+/// # use mosquitto_rs::*;
+/// # async fn device(client: &Client) -> Result<(), Error> {
+/// let device = client.scoped("tenant/device123")?;
+/// device.subscribe("commands/#", QoS::AtLeastOnce).await?;
+/// while let Ok(command) = device.recv().await {
+///     // Received on tenant/device123/commands/..., reported as commands/...
+///     println!("{}", command.topic);
+///     device.publish("status", b"busy", QoS::AtLeastOnce, true).await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct ScopedClient<'a> {
+    client: &'a Client,
+    prefix: String,
+    patterns: Arc<ScopedPatterns>,
+    subscriber: Subscriber,
+}
+
+impl<'a> ScopedClient<'a> {
+    pub(crate) fn new(
+        client: &'a Client,
+        prefix: String,
+        patterns: Arc<ScopedPatterns>,
+        subscriber: Subscriber,
+    ) -> Self {
+        Self {
+            client,
+            prefix,
+            patterns,
+            subscriber,
+        }
+    }
+
+    /// Returns the prefix of the topics, which ends with `/`
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Returns the full topic of `topic`, which is relative to the prefix
+    pub fn topic(&self, topic: &str) -> String {
+        format!("{}{}", self.prefix, topic)
+    }
+
+    /// Publish a message to `topic`, beneath the prefix; see
+    /// [Client::publish](struct.Client.html#method.publish).
+    pub async fn publish(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<MessageId, Error> {
+        self.client
+            .publish_before(
+                &self.topic(topic),
+                payload,
+                qos,
+                retain,
+                None,
+                Properties::new(),
+            )
+            .await
+    }
+
+    /// Subscribe to the topics beneath the prefix that match `pattern`;
+    /// see [Client::subscribe](struct.Client.html#method.subscribe).
+    /// The messages are delivered via [recv](#method.recv).
+    pub async fn subscribe(&self, pattern: &str, qos: QoS) -> Result<(), Error> {
+        let pattern = self.topic(pattern);
+        sub_topic_check(&pattern)?;
+        self.patterns.0.lock().unwrap().push(pattern.clone());
+        self.client.subscribe(&pattern, qos).await
+    }
+
+    /// Remove the prefix from the topic of `message`
+    fn unscope(&self, mut message: Message) -> Message {
+        if let Some(topic) = message.topic.strip_prefix(&self.prefix) {
+            message.topic = topic.to_string();
+        }
+        message
+    }
+
+    /// Wait for the next message of the subscriptions made via
+    /// [subscribe](#method.subscribe), whose topic is relative to
+    /// the prefix.
+    /// Yields `Error::Closed` once the client has been dropped.
+    pub async fn recv(&self) -> Result<Message, Error> {
+        let message = self.subscriber.recv().await.map_err(|_| Error::Closed)?;
+        Ok(self.unscope(message))
+    }
+
+    /// Returns the next message if one is already queued, or `None` if
+    /// there is none.
+    /// Yields `Error::Closed` once the client has been dropped.
+    pub fn try_next(&self) -> Result<Option<Message>, Error> {
+        let message = self.subscriber.try_next().map_err(|_| Error::Closed)?;
+        Ok(message.map(|message| self.unscope(message)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prefix() {
+        assert_eq!(normalize_prefix("tenant/a").unwrap(), "tenant/a/");
+        assert_eq!(normalize_prefix("tenant/a/").unwrap(), "tenant/a/");
+        assert!(normalize_prefix("").is_err());
+        assert!(normalize_prefix("tenant/+").is_err());
+        assert!(normalize_prefix("tenant/#").is_err());
+    }
+}
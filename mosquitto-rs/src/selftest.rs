@@ -0,0 +1,35 @@
+use crate::{Error, QoS};
+use std::time::Duration;
+
+/// The outcome of [Client::self_test](struct.Client.html#method.self_test),
+/// which checks that the client can subscribe to a topic, publish to it,
+/// and receive its own message back.
+///
+/// A failure to subscribe or publish usually indicates that the
+/// credentials of the client don't grant access to the test topic,
+/// while a message that doesn't arrive points to a broker that doesn't
+/// deliver messages back to their publisher, or to a connection that is
+/// too slow for the timeout.
+#[derive(Debug)]
+pub struct SelfTestReport {
+    /// The topic that was used for the test
+    pub topic: String,
+    /// The outcome of subscribing to the test topic
+    pub subscribe: Result<(), Error>,
+    /// The QoS that the broker granted to the subscription
+    pub granted_qos: Option<QoS>,
+    /// The outcome of publishing the test message
+    pub publish: Result<(), Error>,
+    /// The time between starting the publish and its acknowledgement
+    pub publish_latency: Option<Duration>,
+    /// The time between starting the publish and receiving the message
+    /// back via the subscription, or `None` if it didn't arrive in time
+    pub round_trip: Option<Duration>,
+}
+
+impl SelfTestReport {
+    /// Returns true if every step of the test succeeded
+    pub fn is_ok(&self) -> bool {
+        self.subscribe.is_ok() && self.publish.is_ok() && self.round_trip.is_some()
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{Client, Error, Properties, QoS};
+use std::future::Future;
+use std::pin::Pin;
+
+/// The future returned by [ShutdownHook::run](trait.ShutdownHook.html#tymethod.run)
+pub type ShutdownFuture<'a> = Pin<Box<dyn Future<Output = Result<(), Error>> + 'a>>;
+
+/// Teardown work that runs when the client is shut down via
+/// [Client::shutdown](struct.Client.html#method.shutdown), such as
+/// publishing final state or an offline status, while the connection
+/// is still available.
+///
+/// Hooks are registered via
+/// [Client::add_shutdown_hook](struct.Client.html#method.add_shutdown_hook)
+/// and run in the order that they were added.
+/// [PublishOnShutdown](struct.PublishOnShutdown.html) covers the
+/// common case of publishing a message.
+pub trait ShutdownHook: Send + Sync {
+    /// Perform the teardown work using `client`
+    fn run<'a>(&'a self, client: &'a Client) -> ShutdownFuture<'a>;
+}
+
+/// A [ShutdownHook](trait.ShutdownHook.html) that publishes a message,
+/// and waits for it to be acknowledged.
+///
+/// ```no_run
+/// # use mosquitto_rs::*;
+/// # use std::time::Duration;
+/// # async fn run(mut client: Client) -> Result<(), Error> {
+/// client.add_shutdown_hook(PublishOnShutdown::new(
+///     "devices/pump/status",
+///     b"offline",
+///     QoS::AtLeastOnce,
+///     true,
+/// ));
+/// client.add_shutdown_hook(PublishOnShutdown::clear_retained("devices/pump/alarm"));
+/// // ... run the application ...
+/// client.shutdown(Duration::from_secs(5)).await
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishOnShutdown {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: QoS,
+    pub retain: bool,
+}
+
+impl PublishOnShutdown {
+    pub fn new(topic: &str, payload: &[u8], qos: QoS, retain: bool) -> Self {
+        Self {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            qos,
+            retain,
+        }
+    }
+
+    /// Remove the retained message of `topic` from the broker, by
+    /// publishing an empty retained message to it
+    pub fn clear_retained(topic: &str) -> Self {
+        Self::new(topic, b"", QoS::AtLeastOnce, true)
+    }
+}
+
+impl ShutdownHook for PublishOnShutdown {
+    fn run<'a>(&'a self, client: &'a Client) -> ShutdownFuture<'a> {
+        Box::pin(async move {
+            client
+                .publish_before(
+                    &self.topic,
+                    &self.payload,
+                    self.qos,
+                    self.retain,
+                    None,
+                    Properties::new(),
+                )
+                .await
+                .map(|_| ())
+        })
+    }
+}
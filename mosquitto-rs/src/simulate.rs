@@ -0,0 +1,125 @@
+use crate::lowlevel::{sub_topic_check, topic_matches_sub};
+use crate::{Error, Message, MqttMessage, Subscriber, TypedSubscriber};
+use async_channel::{unbounded, Sender};
+
+/// Feeds recorded or synthetic messages to the same
+/// [Subscriber](struct.Subscriber.html) and
+/// [TypedSubscriber](struct.TypedSubscriber.html) types that a
+/// [Client](struct.Client.html) yields, without a broker, so that the
+/// code that handles messages can be tested in-process and
+/// deterministically.
+///
+/// Each subscriber receives the messages that match its pattern, in the
+/// order that they were sent; a message is queued immediately, so it can
+/// be received as soon as `send` returns.
+/// Dropping the source closes its subscribers, once they have received
+/// the messages that were already queued, as happens when a client is
+/// dropped.
+///
+/// ```
+/// use mosquitto_rs::*;
+///
+/// let mut source = SimulatedSource::new();
+/// let temperatures = source.subscriber("sensors/+/temp")?;
+/// source.publish("sensors/kitchen/temp", b"21.5")?;
+/// source.publish("sensors/kitchen/humidity", b"40")?;
+///
+/// let message = temperatures.try_next()?.unwrap();
+/// assert_eq!(message.payload, b"21.5");
+/// assert!(temperatures.try_next()?.is_none());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct SimulatedSource {
+    queues: Vec<(String, Sender<Message>)>,
+}
+
+impl SimulatedSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a subscriber that receives the messages whose topic
+    /// matches `pattern`, which may contain the `+` and `#` wildcards.
+    pub fn subscriber(&mut self, pattern: &str) -> Result<Subscriber, Error> {
+        sub_topic_check(pattern)?;
+        let (tx, rx) = unbounded();
+        self.queues.push((pattern.to_string(), tx));
+        Ok(Subscriber::new(rx))
+    }
+
+    /// Returns a subscriber that receives the messages of type `M`,
+    /// as for [Client::subscribe_messages](struct.Client.html#method.subscribe_messages).
+    pub fn typed_subscriber<M: MqttMessage>(&mut self) -> Result<TypedSubscriber<M>, Error> {
+        let subscriber = self.subscriber(&M::subscription_pattern())?;
+        Ok(TypedSubscriber::new(subscriber))
+    }
+
+    /// Deliver `message` to each subscriber whose pattern matches its
+    /// topic, returning the number of subscribers that it was queued for.
+    /// Subscribers that have been dropped are forgotten.
+    pub fn send(&mut self, message: Message) -> usize {
+        self.queues.retain(|(_, tx)| !tx.is_closed());
+        let mut delivered = 0;
+        for (pattern, tx) in &self.queues {
+            if topic_matches_sub(pattern, &message.topic).unwrap_or(false)
+                && tx.try_send(message.clone()).is_ok()
+            {
+                delivered += 1;
+            }
+        }
+        delivered
+    }
+
+    /// Deliver each of `messages` in turn, as for [send](#method.send),
+    /// such as to replay messages that were previously captured
+    pub fn send_all<I: IntoIterator<Item = Message>>(&mut self, messages: I) {
+        for message in messages {
+            self.send(message);
+        }
+    }
+
+    /// Deliver a synthetic message with `payload` to `topic`, with the
+    /// other fields of the message set to their defaults.
+    /// Fails with `Error::InvalidArgument` if `topic` contains a wildcard.
+    pub fn publish(&mut self, topic: &str, payload: &[u8]) -> Result<usize, Error> {
+        if topic.contains(['+', '#']) {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(self.send(Message {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            ..Message::default()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn routes_by_pattern() {
+        let mut source = SimulatedSource::new();
+        let all = source.subscriber("#").unwrap();
+        let kitchen = source.subscriber("kitchen/+").unwrap();
+        assert_eq!(source.publish("kitchen/temp", b"1").unwrap(), 2);
+        assert_eq!(source.publish("garage/temp", b"2").unwrap(), 1);
+        assert!(source.publish("kitchen/#", b"3").is_err());
+
+        assert_eq!(all.len(), 2);
+        assert_eq!(kitchen.try_next().unwrap().unwrap().topic, "kitchen/temp");
+        assert!(kitchen.try_next().unwrap().is_none());
+
+        drop(kitchen);
+        assert_eq!(source.publish("kitchen/temp", b"4").unwrap(), 1);
+
+        // Queued messages outlive the source
+        drop(source);
+        assert_eq!(all.len(), 3);
+        for _ in 0..3 {
+            assert!(all.try_next().unwrap().is_some());
+        }
+        assert!(all.try_next().is_err());
+    }
+}
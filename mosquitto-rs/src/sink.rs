@@ -0,0 +1,126 @@
+use crate::{Client, Error, Message, MessageId, Subscriber};
+use futures_sink::Sink;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+type PendingPublish<'a> = Pin<Box<dyn Future<Output = Result<MessageId, Error>> + 'a>>;
+
+/// The publishing half of a client that has been split via
+/// [Client::split](struct.Client.html#method.split)
+pub type MessageSink<'a> = Publisher<'a>;
+
+/// The receiving half of a client that has been split via
+/// [Client::split](struct.Client.html#method.split), which is a
+/// `Stream` of the messages of its subscriptions
+pub type MessageStream = Subscriber;
+
+/// Publishes the messages that are sent to it, as a `futures::Sink`;
+/// see [Client::publisher](struct.Client.html#method.publisher).
+///
+/// Each message is published to its topic with its QoS, retain flag
+/// and properties, as for [Client::publish](struct.Client.html#method.publish).
+/// Up to the number of publishes permitted by
+/// [Client::set_inflight_limit](struct.Client.html#method.set_inflight_limit),
+/// or 20 if no limit is set, may be awaiting acknowledgement at once;
+/// beyond that the sink is not ready to accept more messages until an
+/// earlier one is acknowledged, which applies backpressure to whatever
+/// is feeding it.
+///
+/// The first publish that fails is reported as the error of the sink.
+///
+/// ```ignore
+/// # use mosquitto_rs::*;
+/// # async fn forward(client: &Client, messages: Subscriber) -> Result<(), Error> {
+/// use futures::StreamExt;
+///
+/// // Republish everything that arrives on one broker to another
+/// messages.map(Ok).forward(client.publisher()).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Publisher<'a> {
+    client: &'a Client,
+    pending: Vec<PendingPublish<'a>>,
+    capacity: usize,
+}
+
+impl<'a> Publisher<'a> {
+    /// The number of publishes that may await acknowledgement when no
+    /// inflight limit has been set, which matches the default maximum
+    /// number of inflight messages of libmosquitto
+    const DEFAULT_CAPACITY: usize = 20;
+
+    pub(crate) fn new(client: &'a Client, inflight_limit: Option<usize>) -> Self {
+        Self {
+            client,
+            pending: vec![],
+            capacity: inflight_limit.unwrap_or(Self::DEFAULT_CAPACITY),
+        }
+    }
+
+    /// Returns the number of publishes that are awaiting acknowledgement
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Drive the pending publishes, removing those that have completed
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Result<(), Error> {
+        let mut result = Ok(());
+        self.pending
+            .retain_mut(|publish| match publish.as_mut().poll(cx) {
+                Poll::Ready(Ok(_)) => false,
+                Poll::Ready(Err(err)) => {
+                    if result.is_ok() {
+                        result = Err(err);
+                    }
+                    false
+                }
+                Poll::Pending => true,
+            });
+        result
+    }
+}
+
+impl<'a> Sink<Message> for Publisher<'a> {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.poll_pending(cx)?;
+        if self.pending.len() < self.capacity {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: Message) -> Result<(), Error> {
+        let client = self.client;
+        self.pending.push(Box::pin(async move {
+            client
+                .publish_before(
+                    &message.topic,
+                    &message.payload,
+                    message.qos,
+                    message.retain,
+                    None,
+                    message.properties,
+                )
+                .await
+        }));
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.poll_pending(cx)?;
+        if self.pending.is_empty() {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.poll_flush(cx)
+    }
+}
@@ -0,0 +1,100 @@
+use std::os::raw::c_int;
+use std::time::Duration;
+
+/// Options that are applied to the TCP socket used for the connection
+/// to the broker.
+///
+/// The operating system defaults are frequently a poor fit for
+/// high-latency links such as cellular connections, where it can be
+/// desirable to detect a dead peer sooner, or to size the buffers to
+/// match the bandwidth-delay product of the link.
+///
+/// Options that are `None` are left at their current values.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// Enable or disable `SO_KEEPALIVE`
+    pub keepalive: Option<bool>,
+    /// The size of the kernel send buffer, `SO_SNDBUF`
+    pub send_buffer_size: Option<usize>,
+    /// The size of the kernel receive buffer, `SO_RCVBUF`
+    pub recv_buffer_size: Option<usize>,
+    /// The maximum time that transmitted data may remain
+    /// unacknowledged before the connection is forcibly closed,
+    /// `TCP_USER_TIMEOUT`.  This is only supported on Linux.
+    pub user_timeout: Option<Duration>,
+}
+
+impl SocketOptions {
+    /// Apply the options to the socket `fd`
+    #[cfg(unix)]
+    pub(crate) fn apply(&self, fd: c_int) -> std::io::Result<()> {
+        if let Some(keepalive) = self.keepalive {
+            setsockopt(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, keepalive as c_int)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            setsockopt(fd, libc::SOL_SOCKET, libc::SO_SNDBUF, int_value(size)?)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            setsockopt(fd, libc::SOL_SOCKET, libc::SO_RCVBUF, int_value(size)?)?;
+        }
+        if let Some(timeout) = self.user_timeout {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            setsockopt(
+                fd,
+                libc::IPPROTO_TCP,
+                libc::TCP_USER_TIMEOUT,
+                int_value(timeout.as_millis())?,
+            )?;
+
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            {
+                let _ = timeout;
+                return Err(unsupported("TCP_USER_TIMEOUT"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the options to the socket `fd`
+    #[cfg(windows)]
+    pub(crate) fn apply(&self, _fd: c_int) -> std::io::Result<()> {
+        if *self == Self::default() {
+            Ok(())
+        } else {
+            Err(unsupported("setting socket options"))
+        }
+    }
+}
+
+#[cfg(unix)]
+fn setsockopt(fd: c_int, level: c_int, name: c_int, value: c_int) -> std::io::Result<()> {
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const c_int as *const libc::c_void,
+            std::mem::size_of::<c_int>() as libc::socklen_t,
+        )
+    };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(unix)]
+fn int_value<T: std::convert::TryInto<c_int>>(value: T) -> std::io::Result<c_int> {
+    value.try_into().map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "socket option value is out of range",
+        )
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn unsupported(what: &str) -> std::io::Error {
+    std::io::Error::other(format!("{} is not supported on this platform", what))
+}
@@ -0,0 +1,402 @@
+use crate::{MemoryUsage, QoS};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A snapshot of the traffic counters of a [Client](struct.Client.html),
+/// obtained via [Client::stats](struct.Client.html#method.stats).
+///
+/// Byte counts are estimates of the size of the MQTT packets on the
+/// wire, including the acknowledgements that are exchanged for QoS 1
+/// and QoS 2 messages, and the keepalive pings that are exchanged
+/// while connected.
+/// They don't include the CONNECT and SUBSCRIBE exchanges,
+/// MQTT 5 properties, or TCP, IP and TLS overheads, so the amount
+/// of data billed on a metered link will be somewhat higher.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of messages published by the client
+    pub messages_sent: u64,
+    /// The number of messages received by the client
+    pub messages_received: u64,
+    /// The number of received messages that were discarded because
+    /// their payload exceeded the limit set via
+    /// [Client::set_max_payload_size](struct.Client.html#method.set_max_payload_size).
+    /// These are included in `messages_received`.
+    pub messages_oversized: u64,
+    /// The estimated number of bytes sent to the broker
+    pub bytes_sent: u64,
+    /// The estimated number of bytes received from the broker
+    pub bytes_received: u64,
+    /// The state of the queue that feeds the
+    /// [subscriber](struct.Client.html#method.subscriber) channel
+    pub subscriber_queue: QueueStats,
+    /// The number of messages that were dropped from the offline queue
+    /// because they expired before they could be sent; see
+    /// [Client::set_offline_queue_ttl](struct.Client.html#method.set_offline_queue_ttl)
+    pub messages_expired: u64,
+    /// The state of the offline queue; see
+    /// [Client::set_offline_queue](struct.Client.html#method.set_offline_queue).
+    /// This is all zeros if there is no offline queue.
+    pub offline_queue: QueueStats,
+    /// The most recent round trip time measured by the latency probe,
+    /// or `None` if it isn't enabled or no probe has returned yet; see
+    /// [Client::set_latency_probe](struct.Client.html#method.set_latency_probe)
+    pub broker_latency: Option<Duration>,
+    /// The memory held by the queues of the client, and the budget
+    /// set via
+    /// [Client::set_memory_budget](struct.Client.html#method.set_memory_budget)
+    pub memory: MemoryUsage,
+}
+
+/// A snapshot of the state of one of the internal message queues of
+/// a [Client](struct.Client.html).
+/// Watching `depth` approach `capacity` reveals a slow consumer before
+/// messages start being dropped.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    /// The number of messages waiting to be received
+    pub depth: usize,
+    /// The maximum number of messages that the queue can hold,
+    /// or `None` if it is unbounded
+    pub capacity: Option<usize>,
+    /// The greatest depth that the queue has reached
+    pub high_water_mark: usize,
+    /// The number of messages that were dropped because the
+    /// queue was full or closed
+    pub dropped: u64,
+}
+
+/// The messages received on one topic, as reported by
+/// [Client::top_topics](struct.Client.html#method.top_topics)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicStats {
+    pub topic: String,
+    /// The number of messages received on the topic
+    pub messages: u64,
+    /// The estimated number of bytes received on the topic,
+    /// as for `Stats::bytes_received`
+    pub bytes: u64,
+}
+
+/// The size of a PINGREQ or PINGRESP packet
+const PING_SIZE: u64 = 2;
+/// The size of a PUBACK, PUBREC, PUBREL or PUBCOMP packet
+const ACK_SIZE: u64 = 4;
+
+#[derive(Default)]
+pub(crate) struct TrafficCounters {
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    messages_oversized: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    /// Pings exchanged on connections that have since ended
+    pings: AtomicU64,
+    /// The start time and keepalive interval of the current connection
+    connection: Mutex<Option<(Instant, Duration)>>,
+    /// The keepalive interval of the pending connection attempt
+    keep_alive: Mutex<Option<Duration>>,
+}
+
+impl TrafficCounters {
+    /// Record a message published by the client
+    pub fn record_sent(&self, topic: &str, payload_len: usize, qos: QoS) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        let (outgoing, incoming) = publish_sizes(topic, payload_len, qos);
+        self.bytes_sent.fetch_add(outgoing, Ordering::Relaxed);
+        self.bytes_received.fetch_add(incoming, Ordering::Relaxed);
+    }
+
+    /// Record a message received by the client
+    pub fn record_received(&self, topic: &str, payload_len: usize, qos: QoS) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        let (incoming, outgoing) = publish_sizes(topic, payload_len, qos);
+        self.bytes_sent.fetch_add(outgoing, Ordering::Relaxed);
+        self.bytes_received.fetch_add(incoming, Ordering::Relaxed);
+    }
+
+    /// Record a received message that was discarded because its
+    /// payload was too large
+    pub fn record_oversized(&self) {
+        self.messages_oversized.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the keepalive interval that is about to be used
+    /// to connect to the broker
+    pub fn connecting(&self, keep_alive: Duration) {
+        self.keep_alive.lock().unwrap().replace(keep_alive);
+    }
+
+    pub fn connected(&self) {
+        if let Some(keep_alive) = *self.keep_alive.lock().unwrap() {
+            self.disconnected();
+            self.connection
+                .lock()
+                .unwrap()
+                .replace((Instant::now(), keep_alive));
+        }
+    }
+
+    pub fn disconnected(&self) {
+        if let Some(connection) = self.connection.lock().unwrap().take() {
+            self.pings
+                .fetch_add(pings_during(connection), Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> Stats {
+        let pings = self.pings.load(Ordering::Relaxed)
+            + self
+                .connection
+                .lock()
+                .unwrap()
+                .map(pings_during)
+                .unwrap_or(0);
+        Stats {
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            messages_oversized: self.messages_oversized.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed) + pings * PING_SIZE,
+            bytes_received: self.bytes_received.load(Ordering::Relaxed) + pings * PING_SIZE,
+            subscriber_queue: QueueStats::default(),
+            messages_expired: 0,
+            offline_queue: QueueStats::default(),
+            broker_latency: None,
+            memory: MemoryUsage::default(),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.messages_sent.store(0, Ordering::Relaxed);
+        self.messages_received.store(0, Ordering::Relaxed);
+        self.messages_oversized.store(0, Ordering::Relaxed);
+        self.bytes_sent.store(0, Ordering::Relaxed);
+        self.bytes_received.store(0, Ordering::Relaxed);
+        self.pings.store(0, Ordering::Relaxed);
+        if let Some((start, _)) = self.connection.lock().unwrap().as_mut() {
+            *start = Instant::now();
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct QueueCounters {
+    high_water_mark: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+impl QueueCounters {
+    /// Record the depth of the queue after a message was added to it
+    pub fn record_depth(&self, depth: usize) {
+        self.high_water_mark.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    /// Record a message that couldn't be added to the queue
+    pub fn record_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self, depth: usize, capacity: Option<usize>) -> QueueStats {
+        QueueStats {
+            depth,
+            capacity,
+            high_water_mark: self.high_water_mark.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.high_water_mark.store(0, Ordering::Relaxed);
+        self.dropped.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Counts the messages received on each topic, for up to `capacity`
+/// topics.
+/// When a message arrives on a new topic and the map is full, the
+/// topic that has gone the longest without a message is forgotten.
+pub(crate) struct TopicCounters {
+    capacity: usize,
+    /// Incremented for each message, to order the topics by how
+    /// recently they were seen
+    clock: u64,
+    /// The counts, along with the clock value of the latest message
+    topics: HashMap<String, (TopicStats, u64)>,
+}
+
+impl TopicCounters {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            clock: 0,
+            topics: HashMap::new(),
+        }
+    }
+
+    pub fn record_received(&mut self, topic: &str, payload_len: usize, qos: QoS) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.clock += 1;
+        if !self.topics.contains_key(topic) && self.topics.len() >= self.capacity {
+            let stalest = self
+                .topics
+                .iter()
+                .min_by_key(|(_, (_, seen))| *seen)
+                .map(|(topic, _)| topic.clone());
+            if let Some(stalest) = stalest {
+                self.topics.remove(&stalest);
+            }
+        }
+        if !self.topics.contains_key(topic) {
+            let stats = TopicStats {
+                topic: topic.to_string(),
+                messages: 0,
+                bytes: 0,
+            };
+            self.topics.insert(topic.to_string(), (stats, 0));
+        }
+        if let Some((stats, seen)) = self.topics.get_mut(topic) {
+            let (incoming, _) = publish_sizes(topic, payload_len, qos);
+            stats.messages += 1;
+            stats.bytes += incoming;
+            *seen = self.clock;
+        }
+    }
+
+    /// Returns the `n` topics with the most messages, busiest first
+    pub fn top(&self, n: usize) -> Vec<TopicStats> {
+        let mut topics: Vec<TopicStats> = self
+            .topics
+            .values()
+            .map(|(stats, _)| stats.clone())
+            .collect();
+        topics.sort_by(|a, b| {
+            b.messages
+                .cmp(&a.messages)
+                .then(b.bytes.cmp(&a.bytes))
+                .then(a.topic.cmp(&b.topic))
+        });
+        topics.truncate(n);
+        topics
+    }
+
+    pub fn reset(&mut self) {
+        self.topics.clear();
+    }
+}
+
+/// Estimates the number of ping round trips during a connection.
+/// The client pings the broker when it has sent nothing for a keepalive
+/// interval, so this is an upper bound for a connection that is busy.
+fn pings_during((start, keep_alive): (Instant, Duration)) -> u64 {
+    if keep_alive.is_zero() {
+        return 0;
+    }
+    (start.elapsed().as_secs_f64() / keep_alive.as_secs_f64()) as u64
+}
+
+/// Returns the number of bytes sent by the publisher and by the receiver
+/// of a PUBLISH, including the acknowledgements for the QoS level.
+fn publish_sizes(topic: &str, payload_len: usize, qos: QoS) -> (u64, u64) {
+    let packet_id = if qos == QoS::AtMostOnce { 0 } else { 2 };
+    let remaining = (2 + topic.len() + packet_id + payload_len) as u64;
+    let publish = 1 + varint_len(remaining) + remaining;
+    match qos {
+        QoS::AtMostOnce => (publish, 0),
+        // PUBACK
+        QoS::AtLeastOnce => (publish, ACK_SIZE),
+        // PUBREC, PUBREL, PUBCOMP
+        QoS::ExactlyOnce => (publish + ACK_SIZE, 2 * ACK_SIZE),
+    }
+}
+
+/// The number of bytes used by the MQTT variable length encoding of `n`
+fn varint_len(n: u64) -> u64 {
+    match n {
+        0..=127 => 1,
+        128..=16_383 => 2,
+        16_384..=2_097_151 => 3,
+        _ => 4,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn publish_packet_sizes() {
+        assert_eq!(publish_sizes("a/b", 4, QoS::AtMostOnce), (11, 0));
+        assert_eq!(publish_sizes("a/b", 4, QoS::AtLeastOnce), (13, 4));
+        assert_eq!(publish_sizes("a/b", 4, QoS::ExactlyOnce), (17, 8));
+        assert_eq!(publish_sizes("a/b", 200, QoS::AtMostOnce), (208, 0));
+    }
+
+    #[test]
+    fn counters() {
+        let counters = TrafficCounters::default();
+        counters.record_sent("a/b", 4, QoS::AtLeastOnce);
+        counters.record_received("a/b", 4, QoS::AtMostOnce);
+        assert_eq!(
+            counters.snapshot(),
+            Stats {
+                messages_sent: 1,
+                messages_received: 1,
+                bytes_sent: 13,
+                bytes_received: 15,
+                ..Stats::default()
+            }
+        );
+        counters.reset();
+        assert_eq!(counters.snapshot(), Stats::default());
+    }
+
+    #[test]
+    fn topic_counters() {
+        let mut counters = TopicCounters::new(2);
+        counters.record_received("a", 4, QoS::AtMostOnce);
+        counters.record_received("a", 4, QoS::AtMostOnce);
+        counters.record_received("b", 4, QoS::AtMostOnce);
+        counters.record_received("a", 4, QoS::AtMostOnce);
+        // "b" is the stalest, so makes way for "c"
+        counters.record_received("c", 100, QoS::AtMostOnce);
+        assert_eq!(
+            counters.top(5),
+            vec![
+                TopicStats {
+                    topic: "a".to_string(),
+                    messages: 3,
+                    bytes: 27,
+                },
+                TopicStats {
+                    topic: "c".to_string(),
+                    messages: 1,
+                    bytes: 105,
+                },
+            ]
+        );
+        assert_eq!(counters.top(1).len(), 1);
+    }
+
+    #[test]
+    fn queue_counters() {
+        let counters = QueueCounters::default();
+        counters.record_depth(3);
+        counters.record_depth(1);
+        counters.record_dropped();
+        assert_eq!(
+            counters.snapshot(1, Some(4)),
+            QueueStats {
+                depth: 1,
+                capacity: Some(4),
+                high_water_mark: 3,
+                dropped: 1,
+            }
+        );
+        counters.reset();
+        assert_eq!(counters.snapshot(0, None), QueueStats::default());
+    }
+}
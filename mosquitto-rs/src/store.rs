@@ -0,0 +1,505 @@
+use crate::Error;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Identifies an entry in a [Store](trait.Store.html).
+/// Ids increase in the order that entries are appended.
+pub type StoreId = u64;
+
+/// Durable storage for a queue of records that are pending until they
+/// are acknowledged.
+/// This is used by the offline queue installed via
+/// [Client::set_offline_queue](struct.Client.html#method.set_offline_queue)
+/// and by [DedupWindow](struct.DedupWindow.html), so that their contents
+/// can be persisted in a way that suits the deployment environment.
+///
+/// The following implementations are provided:
+///
+/// * [MemoryStore](struct.MemoryStore.html), which doesn't persist anything
+/// * [FileStore](struct.FileStore.html), an append-only journal file
+/// * `SqliteStore`, a table in an SQLite database, with the `sqlite` feature
+/// * `SledStore`, a tree in a [sled](https://docs.rs/sled) database,
+///   with the `sled` feature
+pub trait Store: Send {
+    /// Add `record` to the end of the queue, returning its id
+    fn append(&mut self, record: &[u8]) -> Result<StoreId, Error>;
+
+    /// Remove the entry with the specified id from the queue.
+    /// Acknowledging an entry that isn't pending has no effect.
+    fn ack(&mut self, id: StoreId) -> Result<(), Error>;
+
+    /// Returns the entries that have not been acknowledged, in the
+    /// order that they were appended
+    fn pending(&self) -> Result<Vec<(StoreId, Vec<u8>)>, Error>;
+}
+
+impl<S: Store + ?Sized> Store for Box<S> {
+    fn append(&mut self, record: &[u8]) -> Result<StoreId, Error> {
+        (**self).append(record)
+    }
+
+    fn ack(&mut self, id: StoreId) -> Result<(), Error> {
+        (**self).ack(id)
+    }
+
+    fn pending(&self) -> Result<Vec<(StoreId, Vec<u8>)>, Error> {
+        (**self).pending()
+    }
+}
+
+/// A [Store](trait.Store.html) that holds its entries in memory,
+/// so they are lost when the process exits
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStore {
+    entries: BTreeMap<StoreId, Vec<u8>>,
+    next_id: StoreId,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Store for MemoryStore {
+    fn append(&mut self, record: &[u8]) -> Result<StoreId, Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.insert(id, record.to_vec());
+        Ok(id)
+    }
+
+    fn ack(&mut self, id: StoreId) -> Result<(), Error> {
+        self.entries.remove(&id);
+        Ok(())
+    }
+
+    fn pending(&self) -> Result<Vec<(StoreId, Vec<u8>)>, Error> {
+        Ok(self
+            .entries
+            .iter()
+            .map(|(id, record)| (*id, record.clone()))
+            .collect())
+    }
+}
+
+const APPEND: u8 = b'A';
+const ACK: u8 = b'K';
+
+/// A [Store](trait.Store.html) that records each operation in an
+/// append-only journal file, which is synced to disk before the
+/// operation returns.
+///
+/// The pending entries are also held in memory.
+/// The journal is rewritten to contain just the pending entries once
+/// the acknowledged entries outnumber them.
+/// A partially written operation at the end of the journal, as can be
+/// left behind by a crash, is discarded when the journal is opened.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    file: File,
+    entries: BTreeMap<StoreId, Vec<u8>>,
+    next_id: StoreId,
+    /// The number of entries in the journal that have been acknowledged
+    acked: usize,
+}
+
+impl FileStore {
+    /// Open the journal at `path`, creating it if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let mut entries = BTreeMap::new();
+        let mut next_id = 0;
+        let mut acked = 0;
+        let mut valid_len = 0;
+        let mut reader = BufReader::new(&mut file);
+        loop {
+            let mut op = [0u8; 9];
+            if reader.read_exact(&mut op).is_err() {
+                break;
+            }
+            let id = u64::from_be_bytes(op[1..].try_into().unwrap());
+            match op[0] {
+                APPEND => {
+                    let mut len = [0u8; 4];
+                    if reader.read_exact(&mut len).is_err() {
+                        break;
+                    }
+                    // Read incrementally, rather than trusting the length
+                    // of a record that may have been partially written
+                    let len = u32::from_be_bytes(len) as u64;
+                    let mut record = vec![];
+                    match reader.by_ref().take(len).read_to_end(&mut record) {
+                        Ok(n) if n as u64 == len => {}
+                        _ => break,
+                    }
+                    valid_len += op.len() + 4 + record.len();
+                    entries.insert(id, record);
+                    next_id = next_id.max(id + 1);
+                }
+                ACK => {
+                    if entries.remove(&id).is_some() {
+                        acked += 1;
+                    }
+                    valid_len += op.len();
+                }
+                _ => break,
+            }
+        }
+        file.set_len(valid_len as u64)?;
+        file.seek(SeekFrom::End(0))?;
+
+        Ok(Self {
+            path,
+            file,
+            entries,
+            next_id,
+            acked,
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.file.write_all(data)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Replace the journal with one that contains only the pending entries
+    fn compact(&mut self) -> Result<(), Error> {
+        let mut tmp_name = self.path.clone().into_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
+
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        for (id, record) in &self.entries {
+            writer.write_all(&encode_append(*id, record))?;
+        }
+        let tmp = writer.into_inner().map_err(|e| e.into_error())?;
+        tmp.sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.acked = 0;
+        Ok(())
+    }
+}
+
+fn encode_append(id: StoreId, record: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(13 + record.len());
+    data.push(APPEND);
+    data.extend_from_slice(&id.to_be_bytes());
+    data.extend_from_slice(&(record.len() as u32).to_be_bytes());
+    data.extend_from_slice(record);
+    data
+}
+
+impl Store for FileStore {
+    fn append(&mut self, record: &[u8]) -> Result<StoreId, Error> {
+        if record.len() > u32::MAX as usize {
+            return Err(Error::PayloadTooLarge);
+        }
+        let id = self.next_id;
+        self.write(&encode_append(id, record))?;
+        self.next_id += 1;
+        self.entries.insert(id, record.to_vec());
+        Ok(id)
+    }
+
+    fn ack(&mut self, id: StoreId) -> Result<(), Error> {
+        if !self.entries.contains_key(&id) {
+            return Ok(());
+        }
+        let mut data = vec![ACK];
+        data.extend_from_slice(&id.to_be_bytes());
+        self.write(&data)?;
+        self.entries.remove(&id);
+        self.acked += 1;
+        if self.acked > self.entries.len() {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    fn pending(&self) -> Result<Vec<(StoreId, Vec<u8>)>, Error> {
+        Ok(self
+            .entries
+            .iter()
+            .map(|(id, record)| (*id, record.clone()))
+            .collect())
+    }
+}
+
+/// A [Store](trait.Store.html) that keeps its entries in a table of
+/// an SQLite database
+#[cfg(feature = "sqlite")]
+pub struct SqliteStore {
+    conn: rusqlite::Connection,
+    table: String,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    /// Open the database at `path`, creating it if it doesn't exist,
+    /// and keep the entries in `table`, which is also created if
+    /// required.
+    /// `table` may only contain ASCII letters, digits and underscores.
+    pub fn open<P: AsRef<Path>>(path: P, table: &str) -> Result<Self, Error> {
+        let conn = rusqlite::Connection::open(path).map_err(sqlite_error)?;
+        Self::new(conn, table)
+    }
+
+    /// Keep the entries in `table` of the database that `conn` is
+    /// connected to, creating the table if required.
+    /// `table` may only contain ASCII letters, digits and underscores.
+    pub fn new(conn: rusqlite::Connection, table: &str) -> Result<Self, Error> {
+        if table.is_empty() || !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(Error::InvalidArgument);
+        }
+        conn.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} \
+                 (id INTEGER PRIMARY KEY AUTOINCREMENT, record BLOB NOT NULL)",
+                table
+            ),
+            rusqlite::params![],
+        )
+        .map_err(sqlite_error)?;
+        Ok(Self {
+            conn,
+            table: table.to_string(),
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_error(err: rusqlite::Error) -> Error {
+    Error::Store(err.to_string())
+}
+
+#[cfg(feature = "sqlite")]
+impl Store for SqliteStore {
+    fn append(&mut self, record: &[u8]) -> Result<StoreId, Error> {
+        self.conn
+            .execute(
+                &format!("INSERT INTO {} (record) VALUES (?1)", self.table),
+                rusqlite::params![record],
+            )
+            .map_err(sqlite_error)?;
+        Ok(self.conn.last_insert_rowid() as StoreId)
+    }
+
+    fn ack(&mut self, id: StoreId) -> Result<(), Error> {
+        self.conn
+            .execute(
+                &format!("DELETE FROM {} WHERE id = ?1", self.table),
+                rusqlite::params![id as i64],
+            )
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+
+    fn pending(&self) -> Result<Vec<(StoreId, Vec<u8>)>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!(
+                "SELECT id, record FROM {} ORDER BY id",
+                self.table
+            ))
+            .map_err(sqlite_error)?;
+        let rows = stmt
+            .query_map(rusqlite::params![], |row| {
+                Ok((row.get::<_, i64>(0)? as StoreId, row.get::<_, Vec<u8>>(1)?))
+            })
+            .map_err(sqlite_error)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(sqlite_error)
+    }
+}
+
+/// A [Store](trait.Store.html) that keeps its entries in a tree of
+/// a [sled](https://docs.rs/sled) database
+#[cfg(feature = "sled")]
+pub struct SledStore {
+    tree: sled::Tree,
+    next_id: StoreId,
+}
+
+#[cfg(feature = "sled")]
+impl SledStore {
+    /// Open the database at `path`, creating it if it doesn't exist,
+    /// and keep the entries in the tree named `tree`
+    pub fn open<P: AsRef<Path>>(path: P, tree: &str) -> Result<Self, Error> {
+        let db = sled::open(path).map_err(sled_error)?;
+        Self::new(db.open_tree(tree).map_err(sled_error)?)
+    }
+
+    /// Keep the entries in `tree`
+    pub fn new(tree: sled::Tree) -> Result<Self, Error> {
+        let next_id = match tree.last().map_err(sled_error)? {
+            Some((key, _)) => sled_id(&key)? + 1,
+            None => 0,
+        };
+        Ok(Self { tree, next_id })
+    }
+}
+
+#[cfg(feature = "sled")]
+fn sled_error(err: sled::Error) -> Error {
+    Error::Store(err.to_string())
+}
+
+#[cfg(feature = "sled")]
+fn sled_id(key: &[u8]) -> Result<StoreId, Error> {
+    key.try_into()
+        .map(StoreId::from_be_bytes)
+        .map_err(|_| Error::Store("unexpected key in sled tree".to_string()))
+}
+
+#[cfg(feature = "sled")]
+impl Store for SledStore {
+    fn append(&mut self, record: &[u8]) -> Result<StoreId, Error> {
+        let id = self.next_id;
+        self.tree
+            .insert(id.to_be_bytes(), record)
+            .map_err(sled_error)?;
+        self.tree.flush().map_err(sled_error)?;
+        self.next_id += 1;
+        Ok(id)
+    }
+
+    fn ack(&mut self, id: StoreId) -> Result<(), Error> {
+        self.tree.remove(id.to_be_bytes()).map_err(sled_error)?;
+        self.tree.flush().map_err(sled_error)?;
+        Ok(())
+    }
+
+    fn pending(&self) -> Result<Vec<(StoreId, Vec<u8>)>, Error> {
+        self.tree
+            .iter()
+            .map(|entry| {
+                let (key, record) = entry.map_err(sled_error)?;
+                Ok((sled_id(&key)?, record.to_vec()))
+            })
+            .collect()
+    }
+}
+
+/// Remembers the keys of the most recently processed items, so that
+/// an item that is delivered again, for example after a reconnection
+/// or a restart, can be recognized and skipped.
+///
+/// The keys are persisted in a [Store](trait.Store.html); the oldest
+/// key is forgotten once `capacity` keys are held.
+pub struct DedupWindow {
+    store: Box<dyn Store>,
+    capacity: usize,
+    keys: HashSet<Vec<u8>>,
+    order: VecDeque<(StoreId, Vec<u8>)>,
+}
+
+impl DedupWindow {
+    /// Create a window that holds up to `capacity` keys in `store`,
+    /// starting with the keys that are already pending in `store`
+    pub fn new<S: Store + 'static>(store: S, capacity: usize) -> Result<Self, Error> {
+        let mut window = Self {
+            store: Box::new(store),
+            capacity: capacity.max(1),
+            keys: HashSet::new(),
+            order: VecDeque::new(),
+        };
+        for (id, key) in window.store.pending()? {
+            window.keys.insert(key.clone());
+            window.order.push_back((id, key));
+        }
+        window.evict()?;
+        Ok(window)
+    }
+
+    /// Returns true if `key` is in the window
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.keys.contains(key)
+    }
+
+    /// Add `key` to the window.
+    /// Returns false if it was already present.
+    pub fn insert(&mut self, key: &[u8]) -> Result<bool, Error> {
+        if self.keys.contains(key) {
+            return Ok(false);
+        }
+        let id = self.store.append(key)?;
+        self.keys.insert(key.to_vec());
+        self.order.push_back((id, key.to_vec()));
+        self.evict()?;
+        Ok(true)
+    }
+
+    fn evict(&mut self) -> Result<(), Error> {
+        while self.order.len() > self.capacity {
+            if let Some((id, key)) = self.order.front() {
+                self.store.ack(*id)?;
+                self.keys.remove(key);
+            }
+            self.order.pop_front();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_store() {
+        let path = std::env::temp_dir().join(format!("mosquitto-rs-store-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut store = FileStore::open(&path).unwrap();
+        let a = store.append(b"a").unwrap();
+        let b = store.append(b"b").unwrap();
+        let c = store.append(b"c").unwrap();
+        store.ack(b).unwrap();
+        drop(store);
+
+        // Simulate a crash part way through an append
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&encode_append(99, b"torn")[..8]).unwrap();
+        drop(file);
+
+        let mut store = FileStore::open(&path).unwrap();
+        assert_eq!(
+            store.pending().unwrap(),
+            vec![(a, b"a".to_vec()), (c, b"c".to_vec())]
+        );
+        let d = store.append(b"d").unwrap();
+        assert!(d > c);
+        store.ack(a).unwrap();
+        store.ack(c).unwrap();
+        drop(store);
+
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.pending().unwrap(), vec![(d, b"d".to_vec())]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn dedup_window() {
+        let mut window = DedupWindow::new(MemoryStore::new(), 2).unwrap();
+        assert!(window.insert(b"a").unwrap());
+        assert!(!window.insert(b"a").unwrap());
+        assert!(window.insert(b"b").unwrap());
+        assert!(window.insert(b"c").unwrap());
+        assert!(!window.contains(b"a"));
+        assert!(window.contains(b"b"));
+        assert_eq!(window.store.pending().unwrap().len(), 2);
+    }
+}
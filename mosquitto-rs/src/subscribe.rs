@@ -0,0 +1,101 @@
+use crate::lowlevel::sys::mqtt5_sub_options;
+use std::os::raw::c_int;
+
+/// Controls whether the broker sends retained messages when a
+/// subscription is established.  This is an MQTT 5 feature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RetainHandling {
+    /// Send retained messages every time the subscription is made.
+    /// This is the default, and matches the behavior of earlier
+    /// versions of the protocol.
+    #[default]
+    SendAlways,
+    /// Send retained messages only if the subscription didn't
+    /// already exist
+    SendNew,
+    /// Never send retained messages when subscribing
+    SendNever,
+}
+
+/// MQTT 5 subscription options.
+/// The default options match the behavior of earlier versions of
+/// the protocol.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SubscribeOptions {
+    /// Don't deliver messages that were published by this client
+    pub no_local: bool,
+    /// Deliver messages with the retain flag as set by the publisher,
+    /// rather than only setting it on retained messages that are sent
+    /// when the subscription is made
+    pub retain_as_published: bool,
+    /// Whether to send retained messages when subscribing
+    pub retain_handling: RetainHandling,
+}
+
+impl SubscribeOptions {
+    /// Returns the options encoded as the libmosquitto `options` bitmask
+    pub(crate) fn bits(&self) -> c_int {
+        let mut bits = match self.retain_handling {
+            RetainHandling::SendAlways => mqtt5_sub_options::MQTT_SUB_OPT_SEND_RETAIN_ALWAYS,
+            RetainHandling::SendNew => mqtt5_sub_options::MQTT_SUB_OPT_SEND_RETAIN_NEW,
+            RetainHandling::SendNever => mqtt5_sub_options::MQTT_SUB_OPT_SEND_RETAIN_NEVER,
+        } as c_int;
+        if self.no_local {
+            bits |= mqtt5_sub_options::MQTT_SUB_OPT_NO_LOCAL as c_int;
+        }
+        if self.retain_as_published {
+            bits |= mqtt5_sub_options::MQTT_SUB_OPT_RETAIN_AS_PUBLISHED as c_int;
+        }
+        bits
+    }
+}
+
+/// Describes what the retain flag of a received message means,
+/// which depends on the options of the subscription that it matched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RetainKind {
+    /// The retain flag is not set
+    #[default]
+    NotRetained,
+    /// The message is a retained message that the broker sent
+    /// because a matching subscription was made
+    Replay,
+    /// The publisher set the retain flag.  This is reported for
+    /// messages that matched a subscription made with
+    /// `retain_as_published`, where the flag is passed through
+    /// unchanged, so the message may either be a replay or a
+    /// message that was published while subscribed.
+    AsPublished,
+}
+
+impl RetainKind {
+    /// Classify a message with the specified raw `retain` flag.
+    /// `retain_as_published` is true if any of the subscriptions
+    /// that the message matched used the `retain_as_published` option.
+    pub fn classify(retain: bool, retain_as_published: bool) -> Self {
+        match (retain, retain_as_published) {
+            (false, _) => Self::NotRetained,
+            (true, false) => Self::Replay,
+            (true, true) => Self::AsPublished,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn option_bits() {
+        assert_eq!(SubscribeOptions::default().bits(), 0);
+        assert_eq!(
+            SubscribeOptions {
+                no_local: true,
+                retain_as_published: true,
+                retain_handling: RetainHandling::SendNever,
+            }
+            .bits(),
+            4 | 8 | 32
+        );
+    }
+}
@@ -0,0 +1,487 @@
+use crate::lowlevel::{sub_topic_check, topic_matches_sub};
+use crate::memory::{message_size, MemoryBudget};
+use crate::{Error, Message};
+use async_channel::{Receiver, RecvError, Sender, TryRecvError};
+use futures_lite::{FutureExt, Stream};
+use std::pin::Pin;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Yields the messages received from topics that the client has
+/// subscribed to; see [Client::subscriber](struct.Client.html#method.subscriber).
+///
+/// Messages can be received asynchronously, via [recv](#method.recv) or
+/// as a `Stream`, or from code that doesn't run in an async runtime,
+/// via [recv_blocking](#method.recv_blocking),
+/// [recv_timeout](#method.recv_timeout) and [try_next](#method.try_next).
+///
+/// Cloning a `Subscriber` doesn't duplicate the messages; each message
+/// is yielded by only one of the clones.
+///
+/// ```no_run
+/// # use mosquitto_rs::*;
+/// # fn poll(subscriber: &Subscriber) -> Result<(), Error> {
+/// // Handle whatever has arrived, without waiting
+/// while let Ok(Some(message)) = subscriber.try_next() {
+///     println!("{:?}", message);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Subscriber {
+    rx: Receiver<Message>,
+    pause: Arc<Pause>,
+    /// The pause that `poll_next` is waiting on
+    gate: Option<Receiver<()>>,
+    /// Released as messages are taken from the queue
+    budget: Option<Arc<MemoryBudget>>,
+}
+
+/// Whether [Client::pause_delivery](struct.Client.html#method.pause_delivery)
+/// also stops the broker sending messages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PausePolicy {
+    /// Keep the subscriptions, so that messages continue to be received
+    /// and queued while the subscriber is paused
+    #[default]
+    KeepSubscriptions,
+    /// Unsubscribe from each of the subscriptions of the client, and
+    /// subscribe again, with the same options, on resumption.
+    /// Messages published in the meantime are missed, other than
+    /// retained messages, which the broker sends again.
+    Unsubscribe,
+}
+
+/// Whether a subscriber and its clones are paused
+#[derive(Debug, Default)]
+struct Pause {
+    /// Dropped on resumption, which wakes anything waiting on the
+    /// receiver
+    paused: Mutex<Option<(Sender<()>, Receiver<()>)>>,
+}
+
+impl Pause {
+    /// Returns a receiver that is closed on resumption, if paused
+    fn gate(&self) -> Option<Receiver<()>> {
+        let paused = self.paused.lock().unwrap();
+        paused.as_ref().map(|(_, rx)| rx.clone())
+    }
+}
+
+impl Subscriber {
+    pub(crate) fn new(rx: Receiver<Message>) -> Self {
+        Self {
+            rx,
+            pause: Arc::new(Pause::default()),
+            gate: None,
+            budget: None,
+        }
+    }
+
+    /// Account for the messages in the queue against `budget`
+    pub(crate) fn with_budget(mut self, budget: Arc<MemoryBudget>) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    fn taken(&self, message: &Message) {
+        if let Some(budget) = &self.budget {
+            budget.release_subscriber(message_size(&message.topic, &message.payload));
+        }
+    }
+
+    /// Stop yielding messages until [resume](#method.resume) is called,
+    /// such as while the consumer performs maintenance.
+    /// This applies to the clones of the subscriber too.
+    ///
+    /// Messages continue to be queued while the subscriber is paused,
+    /// subject to the capacity and overflow policy of the queue, and
+    /// are yielded once it resumes.
+    /// To also stop the broker sending messages, use
+    /// [Client::pause_delivery](struct.Client.html#method.pause_delivery).
+    pub fn pause(&self) {
+        let mut paused = self.pause.paused.lock().unwrap();
+        if paused.is_none() {
+            paused.replace(async_channel::bounded(1));
+        }
+    }
+
+    /// Yield messages again, after a call to [pause](#method.pause)
+    pub fn resume(&self) {
+        self.pause.paused.lock().unwrap().take();
+    }
+
+    /// Returns true if the subscriber is paused
+    pub fn is_paused(&self) -> bool {
+        self.pause.paused.lock().unwrap().is_some()
+    }
+
+    /// Wait for the next message.
+    /// Yields an error once the client has been dropped and all of
+    /// the queued messages have been received.
+    pub async fn recv(&self) -> Result<Message, RecvError> {
+        while let Some(gate) = self.pause.gate() {
+            // Fails once the subscriber is resumed
+            let _ = gate.recv().await;
+        }
+        let message = self.rx.recv().await?;
+        self.taken(&message);
+        Ok(message)
+    }
+
+    /// Block the current thread until the next message arrives.
+    /// This must not be called from async code, as it would prevent
+    /// other tasks from running on the same thread.
+    pub fn recv_blocking(&self) -> Result<Message, RecvError> {
+        futures_lite::future::block_on(self.recv())
+    }
+
+    /// Block the current thread until the next message arrives, or
+    /// until `timeout` has elapsed.
+    /// As with [recv_blocking](#method.recv_blocking), this must not be
+    /// called from async code.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Message, RecvTimeoutError> {
+        let recv = async {
+            self.recv()
+                .await
+                .map_err(|_| RecvTimeoutError::Disconnected)
+        };
+        let timeout = async {
+            async_io::Timer::after(timeout).await;
+            Err(RecvTimeoutError::Timeout)
+        };
+        futures_lite::future::block_on(recv.or(timeout))
+    }
+
+    /// Returns the next message if one is already queued, or `None`
+    /// if there is none, or if the subscriber is paused.
+    /// Yields an error once the client has been dropped and all of
+    /// the queued messages have been received.
+    pub fn try_next(&self) -> Result<Option<Message>, RecvError> {
+        if self.is_paused() {
+            return Ok(None);
+        }
+        match self.rx.try_recv() {
+            Ok(message) => {
+                self.taken(&message);
+                Ok(Some(message))
+            }
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Closed) => Err(RecvError),
+        }
+    }
+
+    /// Returns the number of messages that are waiting to be received.
+    ///
+    /// A consumer that falls behind can use this to shed load, for
+    /// example by skipping work for all but the latest messages:
+    ///
+    /// ```no_run
+    /// # use mosquitto_rs::*;
+    /// # async fn render(subscriber: &Subscriber) -> Result<(), Error> {
+    /// while let Ok(message) = subscriber.recv().await {
+    ///     if subscriber.len() > 10 {
+    ///         // A newer frame is already waiting
+    ///         continue;
+    ///     }
+    ///     println!("{:?}", message);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn len(&self) -> usize {
+        self.rx.len()
+    }
+
+    /// Returns true if no messages are waiting to be received
+    pub fn is_empty(&self) -> bool {
+        self.rx.is_empty()
+    }
+
+    /// Returns the maximum number of messages that can wait to be
+    /// received, or `None` if the queue is unbounded.
+    /// The capacity is set via
+    /// [Client::apply_tuning](struct.Client.html#method.apply_tuning);
+    /// what happens when it is reached is set via
+    /// [Client::set_subscriber_overflow_policy](struct.Client.html#method.set_subscriber_overflow_policy).
+    pub fn capacity(&self) -> Option<usize> {
+        self.rx.capacity()
+    }
+
+    /// Returns true if the client has been dropped, so that no more
+    /// messages will arrive.
+    /// Messages that were already queued can still be received.
+    pub fn is_closed(&self) -> bool {
+        self.rx.is_closed()
+    }
+
+    /// Returns a view of this subscriber that yields only the messages
+    /// whose topic matches `pattern`, which may contain the `+` and `#`
+    /// wildcards.
+    /// This is useful when a broad wildcard is subscribed to at the
+    /// broker, to reduce the number of subscriptions, and the messages
+    /// are then divided up by the application.
+    ///
+    /// The view takes messages from the same queue as this subscriber,
+    /// and discards those that don't match, so a message that is
+    /// received via a view isn't received again via the subscriber
+    /// or its other views.
+    ///
+    /// ```no_run
+    /// # use mosquitto_rs::*;
+    /// # async fn temperatures(client: &mut Client) -> Result<(), Error> {
+    /// client.subscribe("sensors/#", QoS::AtMostOnce).await?;
+    /// let temperatures = client.subscriber().unwrap().filter_topic("sensors/+/temp")?;
+    /// while let Ok(message) = temperatures.recv().await {
+    ///     println!("{:?}", message);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn filter_topic(&self, pattern: &str) -> Result<FilteredSubscriber, Error> {
+        sub_topic_check(pattern)?;
+        Ok(FilteredSubscriber {
+            subscriber: self.clone(),
+            pattern: pattern.to_string(),
+        })
+    }
+
+    /// Returns a view of this subscriber that yields messages in
+    /// batches of up to `max` messages.
+    /// A batch is yielded once it is full, or once `window` has elapsed
+    /// since its first message arrived, whichever happens first, so
+    /// the latency added to each message is bounded by `window`.
+    ///
+    /// This reduces the per-message overhead for consumers that write
+    /// to a database or make other requests that are much cheaper in
+    /// bulk.
+    ///
+    /// ```no_run
+    /// # use mosquitto_rs::*;
+    /// # use std::time::Duration;
+    /// # async fn store(subscriber: &Subscriber) -> Result<(), Error> {
+    /// let batches = subscriber.batched(100, Duration::from_millis(250));
+    /// while let Ok(batch) = batches.recv().await {
+    ///     println!("inserting {} rows", batch.len());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn batched(&self, max: usize, window: Duration) -> Batched {
+        Batched {
+            subscriber: self.clone(),
+            max: max.max(1),
+            window,
+        }
+    }
+}
+
+/// A view of a [Subscriber](struct.Subscriber.html) that yields
+/// messages in batches; see
+/// [Subscriber::batched](struct.Subscriber.html#method.batched).
+#[derive(Debug, Clone)]
+pub struct Batched {
+    subscriber: Subscriber,
+    max: usize,
+    window: Duration,
+}
+
+impl Batched {
+    /// Wait for the next batch, which holds at least one message.
+    /// Yields an error once the client has been dropped and all of
+    /// the queued messages have been received; a batch that was
+    /// partially filled when the client was dropped is yielded first.
+    pub async fn recv(&self) -> Result<Vec<Message>, RecvError> {
+        let mut batch = vec![self.subscriber.recv().await?];
+        let deadline = Instant::now() + self.window;
+        while batch.len() < self.max {
+            if let Ok(Some(message)) = self.subscriber.try_next() {
+                batch.push(message);
+                continue;
+            }
+            let next = async { self.subscriber.recv().await.ok() };
+            let timeout = async {
+                async_io::Timer::at(deadline).await;
+                None
+            };
+            match next.or(timeout).await {
+                Some(message) => batch.push(message),
+                None => break,
+            }
+        }
+        Ok(batch)
+    }
+}
+
+/// A view of a [Subscriber](struct.Subscriber.html) that yields only
+/// the messages whose topic matches a pattern; see
+/// [Subscriber::filter_topic](struct.Subscriber.html#method.filter_topic).
+#[derive(Debug, Clone)]
+pub struct FilteredSubscriber {
+    subscriber: Subscriber,
+    pattern: String,
+}
+
+impl FilteredSubscriber {
+    /// Returns the pattern that topics are matched against
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    fn matches(&self, message: &Message) -> bool {
+        topic_matches_sub(&self.pattern, &message.topic).unwrap_or(false)
+    }
+
+    /// Wait for the next matching message.
+    /// Yields an error once the client has been dropped and all of
+    /// the queued messages have been received.
+    pub async fn recv(&self) -> Result<Message, RecvError> {
+        loop {
+            let message = self.subscriber.recv().await?;
+            if self.matches(&message) {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Returns the next matching message if one is already queued, or
+    /// `None` if there is none; messages that don't match are discarded.
+    /// Yields an error once the client has been dropped and all of
+    /// the queued messages have been received.
+    pub fn try_next(&self) -> Result<Option<Message>, RecvError> {
+        while let Some(message) = self.subscriber.try_next()? {
+            if self.matches(&message) {
+                return Ok(Some(message));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Stream for FilteredSubscriber {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        loop {
+            match Pin::new(&mut self.subscriber).poll_next(cx) {
+                Poll::Ready(Some(message)) if !self.matches(&message) => {}
+                poll => return poll,
+            }
+        }
+    }
+}
+
+impl Stream for Subscriber {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        loop {
+            if self.gate.is_none() {
+                self.gate = self.pause.gate();
+            }
+            match self.gate.as_mut() {
+                Some(gate) => match Pin::new(gate).poll_next(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    // The subscriber was resumed
+                    Poll::Ready(_) => self.gate = None,
+                },
+                None => {
+                    let poll = Pin::new(&mut self.rx).poll_next(cx);
+                    if let Poll::Ready(Some(message)) = &poll {
+                        self.taken(message);
+                    }
+                    return poll;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn receive_without_runtime() {
+        let (tx, rx) = async_channel::unbounded();
+        let subscriber = Subscriber::new(rx);
+        assert!(subscriber.is_empty());
+        assert!(matches!(subscriber.try_next(), Ok(None)));
+        assert!(matches!(
+            subscriber.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        ));
+
+        tx.try_send(Message::default()).unwrap();
+        tx.try_send(Message::default()).unwrap();
+        assert_eq!(subscriber.len(), 2);
+        assert!(matches!(subscriber.try_next(), Ok(Some(_))));
+        assert!(subscriber.recv_timeout(Duration::from_millis(10)).is_ok());
+
+        drop(tx);
+        assert!(subscriber.is_closed());
+        assert!(subscriber.try_next().is_err());
+        assert!(matches!(
+            subscriber.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn pause() {
+        let (tx, rx) = async_channel::unbounded();
+        let subscriber = Subscriber::new(rx);
+        let clone = subscriber.clone();
+        tx.try_send(Message::default()).unwrap();
+
+        subscriber.pause();
+        assert!(clone.is_paused());
+        assert!(matches!(clone.try_next(), Ok(None)));
+        assert!(matches!(
+            subscriber.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        ));
+        assert_eq!(subscriber.len(), 1);
+
+        subscriber.resume();
+        assert!(matches!(clone.try_next(), Ok(Some(_))));
+    }
+
+    #[test]
+    fn filter_topic() {
+        let (tx, rx) = async_channel::unbounded();
+        let subscriber = Subscriber::new(rx);
+        assert!(subscriber.filter_topic("a/#/b").is_err());
+        let temperatures = subscriber.filter_topic("sensors/+/temp").unwrap();
+
+        for topic in ["sensors/1/humidity", "sensors/1/temp", "sensors/2/humidity"] {
+            tx.try_send(Message {
+                topic: topic.to_string(),
+                ..Message::default()
+            })
+            .unwrap();
+        }
+        let message = temperatures.try_next().unwrap().unwrap();
+        assert_eq!(message.topic, "sensors/1/temp");
+        assert!(matches!(temperatures.try_next(), Ok(None)));
+        assert!(subscriber.is_empty());
+    }
+
+    #[test]
+    fn batched() {
+        let (tx, rx) = async_channel::unbounded();
+        let batches = Subscriber::new(rx).batched(2, Duration::from_millis(10));
+        for _ in 0..3 {
+            tx.try_send(Message::default()).unwrap();
+        }
+        let recv = || futures_lite::future::block_on(batches.recv());
+        // The first batch is limited by count, and the second by time
+        assert_eq!(recv().unwrap().len(), 2);
+        assert_eq!(recv().unwrap().len(), 1);
+        drop(tx);
+        assert!(recv().is_err());
+    }
+}
@@ -0,0 +1,357 @@
+use crate::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Supplies the password that decrypts the private key of a
+/// [TlsConfig](struct.TlsConfig.html)
+pub(crate) type KeyPasswordFn = dyn Fn() -> String + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) enum KeyPassword {
+    /// A callback passed to `Client::configure_tls`, handed to
+    /// libmosquitto as-is
+    #[cfg(feature = "async")]
+    Raw(crate::PasswdCallback),
+    Closure(Arc<KeyPasswordFn>),
+}
+
+/// A version of the TLS protocol, for use with
+/// [TlsConfig::with_version](struct.TlsConfig.html#method.with_version)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TlsVersion {
+    V1_1,
+    V1_2,
+    V1_3,
+}
+
+impl TlsVersion {
+    /// Returns the name that libmosquitto uses for the version
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::V1_1 => "tlsv1.1",
+            Self::V1_2 => "tlsv1.2",
+            Self::V1_3 => "tlsv1.3",
+        }
+    }
+}
+
+/// The certificates and keys used to connect to a broker over TLS;
+/// see [Mosq::set_tls](struct.Mosq.html#method.set_tls) and
+/// [Client::set_tls](struct.Client.html#method.set_tls).
+///
+/// The broker is verified against the CA certificates in `ca_file` or
+/// `ca_path`, or in the certificate store of the operating system, one
+/// of which must be set.
+/// A client certificate and its private key are presented to brokers
+/// that require them:
+///
+/// ```
+/// use mosquitto_rs::*;
+///
+/// let tls = TlsConfig::new()
+///     .with_ca_file("/etc/mqtt/ca.pem")
+///     .with_client_certificate("/etc/mqtt/client.pem", "/etc/mqtt/client.key")
+///     .with_key_password(|| std::env::var("MQTT_KEY_PASSWORD").unwrap_or_default());
+/// assert!(tls.has_key_password());
+/// ```
+///
+/// During development, a test broker with a self-signed certificate
+/// can be used by requiring a recent protocol version and skipping the
+/// check of its hostname:
+///
+/// ```
+/// use mosquitto_rs::*;
+///
+/// let tls = TlsConfig::new()
+///     .with_ca_file("test-ca.pem")
+///     .with_version(TlsVersion::V1_3)
+///     .with_insecure(true);
+/// assert_eq!(tls.version(), Some(TlsVersion::V1_3));
+/// ```
+#[derive(Clone)]
+pub struct TlsConfig {
+    ca_file: Option<PathBuf>,
+    ca_path: Option<PathBuf>,
+    cert_file: Option<PathBuf>,
+    key_file: Option<PathBuf>,
+    key_password: Option<KeyPassword>,
+    version: Option<TlsVersion>,
+    ciphers: Option<String>,
+    verify_peer: bool,
+    insecure: bool,
+    use_os_certs: bool,
+    ocsp_required: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            ca_file: None,
+            ca_path: None,
+            cert_file: None,
+            key_file: None,
+            key_password: None,
+            version: None,
+            ciphers: None,
+            verify_peer: true,
+            insecure: false,
+            use_os_certs: false,
+            ocsp_required: false,
+        }
+    }
+}
+
+impl TlsConfig {
+    /// Create an empty config; a CA file or path must be added
+    /// before it can be used
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use the PEM encoded CA certificates in the file `ca_file` to
+    /// verify the broker
+    pub fn with_ca_file<P: AsRef<Path>>(mut self, ca_file: P) -> Self {
+        self.ca_file = Some(ca_file.as_ref().to_path_buf());
+        self
+    }
+
+    /// Use the PEM encoded CA certificates in the directory `ca_path`
+    /// to verify the broker.
+    /// The directory must have been prepared with `openssl rehash`.
+    pub fn with_ca_path<P: AsRef<Path>>(mut self, ca_path: P) -> Self {
+        self.ca_path = Some(ca_path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Present the PEM encoded certificate in `cert_file` to the
+    /// broker, using the PEM encoded private key in `key_file`
+    pub fn with_client_certificate<C: AsRef<Path>, K: AsRef<Path>>(
+        mut self,
+        cert_file: C,
+        key_file: K,
+    ) -> Self {
+        self.cert_file = Some(cert_file.as_ref().to_path_buf());
+        self.key_file = Some(key_file.as_ref().to_path_buf());
+        self
+    }
+
+    /// Decrypt the private key with the password returned by `password`,
+    /// which is called each time the key is loaded, on the thread that
+    /// is connecting.
+    /// A password that doesn't fit in the buffer that OpenSSL provides,
+    /// typically 1KiB, is treated as a failure to obtain one.
+    pub fn with_key_password<F>(mut self, password: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.key_password = Some(KeyPassword::Closure(Arc::new(password)));
+        self
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn with_raw_password_callback(
+        mut self,
+        callback: Option<crate::PasswdCallback>,
+    ) -> Self {
+        self.key_password = callback.map(KeyPassword::Raw);
+        self
+    }
+
+    /// Refuse versions of the protocol older than `version`.
+    /// The highest version that both ends support is still used, so
+    /// `TlsVersion::V1_2` also allows TLS 1.3.
+    /// By default, versions older than TLS 1.2 are refused.
+    pub fn with_version(mut self, version: TlsVersion) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Restrict the cipher suites that may be used to `ciphers`, in
+    /// the format of `openssl ciphers`.
+    /// By default, the OpenSSL defaults are used.
+    pub fn with_ciphers(mut self, ciphers: &str) -> Self {
+        self.ciphers = Some(ciphers.to_string());
+        self
+    }
+
+    /// Choose whether the certificate of the broker is verified
+    /// against the CA certificates, which is the default.
+    /// Not verifying it leaves the connection open to
+    /// man-in-the-middle attacks.
+    pub fn with_verify_peer(mut self, verify_peer: bool) -> Self {
+        self.verify_peer = verify_peer;
+        self
+    }
+
+    /// Choose whether to skip checking that the hostname in the
+    /// certificate of the broker matches the host that was connected
+    /// to; see [Mosq::set_tls_insecure](struct.Mosq.html#method.set_tls_insecure).
+    /// This is intended for testing only.
+    pub fn with_insecure(mut self, insecure: bool) -> Self {
+        self.insecure = insecure;
+        self
+    }
+
+    /// Choose whether the broker is also verified against the CA
+    /// certificates trusted by the operating system, so that a broker
+    /// with a publicly issued certificate can be used without shipping
+    /// a CA file; see
+    /// [Mosq::set_tls_use_os_certs](struct.Mosq.html#method.set_tls_use_os_certs).
+    pub fn with_os_certs(mut self, use_os_certs: bool) -> Self {
+        self.use_os_certs = use_os_certs;
+        self
+    }
+
+    /// Choose whether the broker must staple an OCSP response showing
+    /// that its certificate hasn't been revoked; see
+    /// [Mosq::set_tls_ocsp_required](struct.Mosq.html#method.set_tls_ocsp_required).
+    pub fn with_ocsp_required(mut self, ocsp_required: bool) -> Self {
+        self.ocsp_required = ocsp_required;
+        self
+    }
+
+    pub fn ca_file(&self) -> Option<&Path> {
+        self.ca_file.as_deref()
+    }
+
+    pub fn ca_path(&self) -> Option<&Path> {
+        self.ca_path.as_deref()
+    }
+
+    pub fn cert_file(&self) -> Option<&Path> {
+        self.cert_file.as_deref()
+    }
+
+    pub fn key_file(&self) -> Option<&Path> {
+        self.key_file.as_deref()
+    }
+
+    pub fn version(&self) -> Option<TlsVersion> {
+        self.version
+    }
+
+    pub fn ciphers(&self) -> Option<&str> {
+        self.ciphers.as_deref()
+    }
+
+    pub fn verify_peer(&self) -> bool {
+        self.verify_peer
+    }
+
+    pub fn insecure(&self) -> bool {
+        self.insecure
+    }
+
+    pub fn use_os_certs(&self) -> bool {
+        self.use_os_certs
+    }
+
+    pub fn ocsp_required(&self) -> bool {
+        self.ocsp_required
+    }
+
+    /// Returns true if the protocol options differ from the defaults
+    pub(crate) fn has_options(&self) -> bool {
+        self.version.is_some() || self.ciphers.is_some() || !self.verify_peer
+    }
+
+    /// Returns true if a password is supplied for the private key
+    pub fn has_key_password(&self) -> bool {
+        self.key_password.is_some()
+    }
+
+    pub(crate) fn key_password(&self) -> Option<&KeyPassword> {
+        self.key_password.as_ref()
+    }
+
+    /// Fails with `Error::InvalidArgument` if there is no source of CA
+    /// certificates, or if only one of the certificate and key is set
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        if self.ca_file.is_none() && self.ca_path.is_none() && !self.use_os_certs {
+            return Err(Error::InvalidArgument);
+        }
+        if self.cert_file.is_some() != self.key_file.is_some() {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for TlsConfig {
+    /// The password is not shown
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("ca_file", &self.ca_file)
+            .field("ca_path", &self.ca_path)
+            .field("cert_file", &self.cert_file)
+            .field("key_file", &self.key_file)
+            .field("key_password", &self.has_key_password())
+            .field("version", &self.version)
+            .field("ciphers", &self.ciphers)
+            .field("verify_peer", &self.verify_peer)
+            .field("insecure", &self.insecure)
+            .field("use_os_certs", &self.use_os_certs)
+            .field("ocsp_required", &self.ocsp_required)
+            .finish()
+    }
+}
+
+/// Copy `password` into the NUL terminated buffer `buf`, returning
+/// its length, or 0 if it doesn't fit
+pub(crate) fn copy_password(password: &str, buf: &mut [u8]) -> usize {
+    let password = password.as_bytes();
+    if password.len() >= buf.len() {
+        return 0;
+    }
+    buf[..password.len()].copy_from_slice(password);
+    buf[password.len()] = 0;
+    password.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check() {
+        assert!(TlsConfig::new().check().is_err());
+        assert!(TlsConfig::new().with_os_certs(true).check().is_ok());
+        assert!(TlsConfig::new()
+            .with_ca_path("/etc/ssl/certs")
+            .check()
+            .is_ok());
+        let config = TlsConfig::new().with_ca_file("ca.pem");
+        assert!(config.check().is_ok());
+        assert!(config
+            .clone()
+            .with_client_certificate("client.pem", "client.key")
+            .check()
+            .is_ok());
+        let config = TlsConfig {
+            key_file: None,
+            ..config.with_client_certificate("client.pem", "client.key")
+        };
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn options() {
+        let config = TlsConfig::new().with_ca_file("ca.pem");
+        assert!(config.verify_peer());
+        assert!(!config.has_options());
+        assert!(config.clone().with_insecure(true).check().is_ok());
+        assert!(!config.clone().with_insecure(true).has_options());
+        assert!(config.clone().with_verify_peer(false).has_options());
+        assert!(config.with_version(TlsVersion::V1_2).has_options());
+        assert_eq!(TlsVersion::V1_3.as_str(), "tlsv1.3");
+    }
+
+    #[test]
+    fn password() {
+        let mut buf = [0xffu8; 8];
+        assert_eq!(copy_password("secret", &mut buf), 6);
+        assert_eq!(&buf[..7], b"secret\0");
+        assert_eq!(copy_password("12345678", &mut buf), 0);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::Error;
+
+/// Checks the syntax of a subscription pattern, returning a description
+/// of the first problem; this is `const` so that [topic!](macro.topic.html)
+/// can run it at compile time
+const fn check(filter: &str) -> Result<(), &'static str> {
+    let bytes = filter.as_bytes();
+    if bytes.is_empty() {
+        return Err("a topic filter must not be empty");
+    }
+    if bytes.len() > 65535 {
+        return Err("a topic filter must not be longer than 65535 bytes");
+    }
+    let mut i = 0;
+    while i < bytes.len() {
+        let starts_level = i == 0 || bytes[i - 1] == b'/';
+        let ends_level = i + 1 == bytes.len() || bytes[i + 1] == b'/';
+        match bytes[i] {
+            b'+' if !(starts_level && ends_level) => {
+                return Err("`+` must occupy a whole level of a topic filter");
+            }
+            b'#' if !(starts_level && i + 1 == bytes.len()) => {
+                return Err("`#` must be the whole of the last level of a topic filter");
+            }
+            0 => return Err("a topic filter must not contain a NUL character"),
+            _ => {}
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+/// A subscription pattern, which may contain the `+` and `#` wildcards,
+/// whose syntax is known to be valid.
+///
+/// The [topic!](macro.topic.html) macro checks the pattern at compile
+/// time, so that a typo such as `device/#/state` is caught before the
+/// program runs.
+/// A `TopicFilter` dereferences to `str`, so it can be passed to
+/// [Client::subscribe](struct.Client.html#method.subscribe) and the other
+/// methods that accept a pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TopicFilter(&'static str);
+
+impl TopicFilter {
+    /// Check the syntax of `filter` at runtime, returning
+    /// `Error::InvalidArgument` if it isn't a valid pattern
+    pub fn new(filter: &'static str) -> Result<Self, Error> {
+        match check(filter) {
+            Ok(()) => Ok(Self(filter)),
+            Err(_) => Err(Error::InvalidArgument),
+        }
+    }
+
+    /// Used by [topic!](macro.topic.html); panics, which is a compile
+    /// error in a constant, if `filter` isn't a valid pattern
+    #[doc(hidden)]
+    pub const fn from_static(filter: &'static str) -> Self {
+        match check(filter) {
+            Ok(()) => Self(filter),
+            Err(problem) => panic!("{}", problem),
+        }
+    }
+
+    /// Returns the pattern
+    pub const fn as_str(&self) -> &'static str {
+        self.0
+    }
+
+    /// Returns true if `topic` matches the pattern
+    pub fn matches(&self, topic: &str) -> bool {
+        crate::lowlevel::topic_matches_sub(self.0, topic).unwrap_or(false)
+    }
+}
+
+impl std::ops::Deref for TopicFilter {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.0
+    }
+}
+
+impl AsRef<str> for TopicFilter {
+    fn as_ref(&self) -> &str {
+        self.0
+    }
+}
+
+impl std::fmt::Display for TopicFilter {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.write_str(self.0)
+    }
+}
+
+/// Produces a [TopicFilter](struct.TopicFilter.html) constant from a
+/// string literal, checking its syntax at compile time.
+///
+/// ```
+/// use mosquitto_rs::{topic, TopicFilter};
+///
+/// const DEVICE_STATE: TopicFilter = topic!("devices/+/state");
+/// assert_eq!(DEVICE_STATE.as_str(), "devices/+/state");
+/// ```
+///
+/// A misplaced wildcard fails to compile:
+///
+/// ```compile_fail
+/// let filter = mosquitto_rs::topic!("device/#/state");
+/// ```
+#[macro_export]
+macro_rules! topic {
+    ($filter:expr) => {{
+        const FILTER: $crate::TopicFilter = $crate::TopicFilter::from_static($filter);
+        FILTER
+    }};
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn syntax() {
+        for valid in &[
+            "a", "a/b", "+", "#", "a/+/b", "a/#", "+/+", "/", "a//b", "$SYS/#",
+        ] {
+            assert_eq!(check(valid), Ok(()), "{}", valid);
+        }
+        for invalid in &["", "a/#/b", "a#", "a/b#", "a+", "+a/b", "a/+b", "a\0b"] {
+            assert!(check(invalid).is_err(), "{}", invalid);
+        }
+        const FILTER: TopicFilter = topic!("devices/+/state");
+        assert_eq!(&*FILTER, "devices/+/state");
+    }
+}
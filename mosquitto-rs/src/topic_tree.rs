@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+
+#[derive(Debug, Clone)]
+struct Node<T> {
+    value: Option<T>,
+    children: BTreeMap<String, Node<T>>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Self {
+            value: None,
+            children: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> Node<T> {
+    fn is_empty(&self) -> bool {
+        self.value.is_none() && self.children.is_empty()
+    }
+
+    /// Remove the value at `levels` below this node, pruning nodes
+    /// that are left empty
+    fn remove(&mut self, levels: &[&str]) -> Option<T> {
+        match levels.split_first() {
+            None => self.value.take(),
+            Some((level, rest)) => {
+                let child = self.children.get_mut(*level)?;
+                let value = child.remove(rest);
+                if child.is_empty() {
+                    self.children.remove(*level);
+                }
+                value
+            }
+        }
+    }
+
+    /// Collect the values below this node, at `path`, whose topic
+    /// matches the remaining `levels` of a filter
+    fn matches<'a>(&'a self, path: Option<&str>, levels: &[&str], out: &mut Vec<(String, &'a T)>) {
+        let (level, rest) = match (levels.split_first(), path) {
+            (Some(split), _) => split,
+            (None, Some(path)) => {
+                if let Some(value) = &self.value {
+                    out.push((path.to_string(), value));
+                }
+                return;
+            }
+            (None, None) => return,
+        };
+        match *level {
+            "#" => {
+                // `#` also matches the parent level
+                if let (Some(value), Some(path)) = (&self.value, path) {
+                    out.push((path.to_string(), value));
+                }
+                for (topic, value) in TopicTreeIter::below(path, self) {
+                    // Topics beginning with `$` aren't matched by a
+                    // wildcard in the first level
+                    if path.is_none() && topic.starts_with('$') {
+                        continue;
+                    }
+                    out.push((topic, value));
+                }
+            }
+            "+" => {
+                for (name, child) in &self.children {
+                    if path.is_none() && name.starts_with('$') {
+                        continue;
+                    }
+                    child.matches(Some(&join(path, name)), rest, out);
+                }
+            }
+            name => {
+                if let Some(child) = self.children.get(name) {
+                    child.matches(Some(&join(path, name)), rest, out);
+                }
+            }
+        }
+    }
+}
+
+/// Extend the topic `path` by one level; the root has no path, which
+/// is distinct from a topic that begins with an empty level
+fn join(path: Option<&str>, level: &str) -> String {
+    match path {
+        Some(path) => format!("{}/{}", path, level),
+        None => level.to_string(),
+    }
+}
+
+/// Associates values with topics, organised by topic level, so that
+/// the values beneath a topic, or those whose topic matches a filter
+/// with wildcards, can be found without examining every topic.
+///
+/// Iteration yields the topics in order, level by level.
+///
+/// ```
+/// use mosquitto_rs::TopicTree;
+///
+/// let mut tree = TopicTree::new();
+/// tree.insert("sensors/kitchen/temp", 21.5);
+/// tree.insert("sensors/kitchen/humidity", 40.0);
+/// tree.insert("sensors/garage/temp", 12.0);
+///
+/// assert_eq!(tree.get("sensors/garage/temp"), Some(&12.0));
+/// assert_eq!(tree.subtree("sensors/kitchen").count(), 2);
+/// let temperatures: Vec<_> = tree.matches("sensors/+/temp").collect();
+/// assert_eq!(
+///     temperatures,
+///     vec![
+///         ("sensors/garage/temp".to_string(), &12.0),
+///         ("sensors/kitchen/temp".to_string(), &21.5),
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct TopicTree<T> {
+    root: Node<T>,
+    len: usize,
+}
+
+impl<T> Default for TopicTree<T> {
+    fn default() -> Self {
+        Self {
+            root: Node::default(),
+            len: 0,
+        }
+    }
+}
+
+impl<T> TopicTree<T> {
+    /// Create an empty tree
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of topics that have a value
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if no topic has a value
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Set the value of `topic`, returning its previous value, if any
+    pub fn insert(&mut self, topic: &str, value: T) -> Option<T> {
+        let node = topic.split('/').fold(&mut self.root, |node, level| {
+            node.children.entry(level.to_string()).or_default()
+        });
+        let previous = node.value.replace(value);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous
+    }
+
+    fn node(&self, topic: &str) -> Option<&Node<T>> {
+        topic
+            .split('/')
+            .try_fold(&self.root, |node, level| node.children.get(level))
+    }
+
+    /// Returns the value of `topic`, if it has one
+    pub fn get(&self, topic: &str) -> Option<&T> {
+        self.node(topic)?.value.as_ref()
+    }
+
+    /// Returns a mutable reference to the value of `topic`, if it has one
+    pub fn get_mut(&mut self, topic: &str) -> Option<&mut T> {
+        topic
+            .split('/')
+            .try_fold(&mut self.root, |node, level| node.children.get_mut(level))?
+            .value
+            .as_mut()
+    }
+
+    /// Remove the value of `topic`, returning it if there was one
+    pub fn remove(&mut self, topic: &str) -> Option<T> {
+        let levels: Vec<&str> = topic.split('/').collect();
+        let value = self.root.remove(&levels);
+        if value.is_some() {
+            self.len -= 1;
+        }
+        value
+    }
+
+    /// Returns an iterator over the topics and their values
+    pub fn iter(&self) -> TopicTreeIter<'_, T> {
+        TopicTreeIter::below(None, &self.root)
+    }
+
+    /// Returns an iterator over `topic` and the topics beneath it,
+    /// along with their values
+    pub fn subtree(&self, topic: &str) -> TopicTreeIter<'_, T> {
+        match self.node(topic) {
+            Some(node) => TopicTreeIter {
+                stack: vec![(topic.to_string(), node)],
+            },
+            None => TopicTreeIter { stack: vec![] },
+        }
+    }
+
+    /// Returns an iterator over the topics that match `filter`, which
+    /// may contain the `+` and `#` wildcards, along with their values
+    pub fn matches(&self, filter: &str) -> std::vec::IntoIter<(String, &T)> {
+        let levels: Vec<&str> = filter.split('/').collect();
+        let mut out = vec![];
+        self.root.matches(None, &levels, &mut out);
+        out.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a TopicTree<T> {
+    type Item = (String, &'a T);
+    type IntoIter = TopicTreeIter<'a, T>;
+
+    fn into_iter(self) -> TopicTreeIter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<S: AsRef<str>, T> FromIterator<(S, T)> for TopicTree<T> {
+    fn from_iter<I: IntoIterator<Item = (S, T)>>(iter: I) -> Self {
+        let mut tree = Self::new();
+        for (topic, value) in iter {
+            tree.insert(topic.as_ref(), value);
+        }
+        tree
+    }
+}
+
+/// Walks a [TopicTree](struct.TopicTree.html), yielding each topic
+/// that has a value along with that value.
+pub struct TopicTreeIter<'a, T> {
+    /// The nodes that remain to be visited, and their topics, with
+    /// the next node at the end
+    stack: Vec<(String, &'a Node<T>)>,
+}
+
+impl<'a, T> TopicTreeIter<'a, T> {
+    /// Walk the nodes beneath `node`, but not `node` itself
+    fn below(path: Option<&str>, node: &'a Node<T>) -> Self {
+        let stack = node
+            .children
+            .iter()
+            .rev()
+            .map(|(level, child)| (join(path, level), child))
+            .collect();
+        Self { stack }
+    }
+}
+
+impl<'a, T> Iterator for TopicTreeIter<'a, T> {
+    type Item = (String, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((path, node)) = self.stack.pop() {
+            for (level, child) in node.children.iter().rev() {
+                self.stack.push((join(Some(&path), level), child));
+            }
+            if let Some(value) = &node.value {
+                return Some((path, value));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_and_remove() {
+        let mut tree: TopicTree<u32> = vec![("a/b", 1), ("a", 2), ("a/b/c", 3), ("/x", 4)]
+            .into_iter()
+            .collect();
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.insert("a/b", 5), Some(1));
+        *tree.get_mut("a").unwrap() += 10;
+
+        let all: Vec<_> = tree.iter().map(|(topic, value)| (topic, *value)).collect();
+        assert_eq!(
+            all,
+            vec![
+                ("/x".to_string(), 4),
+                ("a".to_string(), 12),
+                ("a/b".to_string(), 5),
+                ("a/b/c".to_string(), 3),
+            ]
+        );
+
+        assert_eq!(tree.remove("a/b/c"), Some(3));
+        assert_eq!(tree.remove("a/b/c"), None);
+        assert_eq!(tree.subtree("a").count(), 2);
+        assert_eq!(tree.subtree("a/b/c").count(), 0);
+        assert_eq!(tree.len(), 3);
+    }
+
+    #[test]
+    fn matches() {
+        let tree: TopicTree<()> = ["a", "a/b", "a/c/d", "$SYS/x", "b/b"]
+            .iter()
+            .map(|topic| (topic, ()))
+            .collect();
+        let matches =
+            |filter| -> Vec<String> { tree.matches(filter).map(|(topic, _)| topic).collect() };
+        assert_eq!(matches("a/#"), vec!["a", "a/b", "a/c/d"]);
+        assert_eq!(matches("+/b"), vec!["a/b", "b/b"]);
+        assert_eq!(matches("+/+/d"), vec!["a/c/d"]);
+        assert_eq!(matches("$SYS/#"), vec!["$SYS/x"]);
+        assert_eq!(matches("#").len(), 4);
+        assert!(matches("a/b/c").is_empty());
+    }
+}
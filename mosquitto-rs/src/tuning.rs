@@ -0,0 +1,66 @@
+/// A coherent set of performance related settings for a
+/// [Client](struct.Client.html), applied via
+/// [Client::apply_tuning](struct.Client.html#method.apply_tuning).
+///
+/// Most applications can use one of the presets; the fields can be
+/// adjusted individually after selecting a preset if required.
+///
+/// libmosquitto's loop functions accept a `max_packets` parameter, but it
+/// is ignored by all current versions of the library, so it isn't
+/// represented here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tuning {
+    /// Disable Nagle's algorithm on the connection to the broker,
+    /// so that small packets are sent immediately rather than
+    /// being coalesced.
+    pub tcp_nodelay: bool,
+    /// The maximum number of outgoing QoS 1 and QoS 2 messages that
+    /// may be in flight at once.
+    pub max_inflight: u16,
+    /// The capacity of the queue of received messages that feeds
+    /// the [subscriber](struct.Client.html#method.subscriber) channel.
+    /// `None` means that the queue is unbounded.
+    /// What happens when a bounded queue is full is controlled by
+    /// [Client::set_subscriber_overflow_policy](struct.Client.html#method.set_subscriber_overflow_policy);
+    /// by default, newly received messages are discarded until the
+    /// subscriber catches up.
+    pub subscriber_queue_capacity: Option<usize>,
+}
+
+impl Default for Tuning {
+    /// Returns the settings that are used if no tuning is applied
+    fn default() -> Self {
+        Self {
+            tcp_nodelay: false,
+            max_inflight: 20,
+            subscriber_queue_capacity: None,
+        }
+    }
+}
+
+impl Tuning {
+    /// Settings that minimize the delay between a publish or receipt and
+    /// its delivery, at the cost of throughput.
+    /// Packets are sent immediately, and only a short queue of received
+    /// messages is kept so that consumers see fresh data rather than
+    /// working through a backlog.
+    pub fn low_latency() -> Self {
+        Self {
+            tcp_nodelay: true,
+            max_inflight: 20,
+            subscriber_queue_capacity: Some(64),
+        }
+    }
+
+    /// Settings that maximize the rate at which messages can be sent and
+    /// received, at the cost of latency and memory.
+    /// Packets may be coalesced, many messages may be in flight at once,
+    /// and a deep queue absorbs bursts of received messages.
+    pub fn high_throughput() -> Self {
+        Self {
+            tcp_nodelay: false,
+            max_inflight: 1024,
+            subscriber_queue_capacity: Some(65536),
+        }
+    }
+}
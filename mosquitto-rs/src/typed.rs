@@ -0,0 +1,136 @@
+use crate::{Error, QoS, Subscriber};
+use std::marker::PhantomData;
+
+/// A type that is published to, and received from, a topic that is
+/// derived from its fields.
+///
+/// This is usually implemented via `#[derive(MqttMessage)]`, which is
+/// available with the `derive` feature, and accepts these attributes:
+///
+/// * `topic = "..."` - the topic template, which is required.
+///   A level of the form `{field}` is replaced by the value of that
+///   field, which must implement `Display`, when publishing, and by
+///   the `+` wildcard when subscribing.
+/// * `qos = 0`, `1` or `2` - the QoS level; the default is 0.
+/// * `retain` - publish the messages as retained messages.
+/// * `codec = "path::to::Codec"` - the [Codec](trait.Codec.html) that
+///   converts the struct to and from the payload; the default is
+///   [Json](struct.Json.html), which requires the struct to implement
+///   `serde::Serialize` and `serde::Deserialize`.
+///
+/// The derive also adds `publish_to(&self, &mut Client)` and
+/// `subscribe(&mut Client)` methods to the struct, which call
+/// [Client::publish_message](struct.Client.html#method.publish_message)
+/// and [Client::subscribe_messages](struct.Client.html#method.subscribe_messages).
+///
+/// ```ignore
+/// use mosquitto_rs::*;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(MqttMessage, Serialize, Deserialize)]
+/// #[mqtt(topic = "sensors/{device}/temp", qos = 1, retain)]
+/// struct Temperature {
+///     device: String,
+///     celsius: f32,
+/// }
+///
+/// # async fn example(client: &mut Client) -> Result<(), Error> {
+/// let temperatures = Temperature::subscribe(client).await?;
+/// Temperature { device: "kitchen".to_string(), celsius: 21.5 }
+///     .publish_to(client)
+///     .await?;
+/// let temperature = temperatures.recv().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait MqttMessage: Sized {
+    /// The topic template
+    const TOPIC: &'static str;
+    const QOS: QoS;
+    const RETAIN: bool;
+
+    /// Returns the topic that this message is published to
+    fn topic(&self) -> String;
+
+    /// Produce the payload of this message
+    fn encode(&self) -> Result<Vec<u8>, Error>;
+
+    /// Reconstruct a message from its payload
+    fn decode(payload: &[u8]) -> Result<Self, Error>;
+
+    /// Returns the pattern that matches the topics of all messages of
+    /// this type
+    fn subscription_pattern() -> String {
+        Self::TOPIC
+            .split('/')
+            .map(|level| {
+                if level.starts_with('{') && level.ends_with('}') {
+                    "+"
+                } else {
+                    level
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+/// Converts values of type `T` to and from message payloads, for use
+/// by [MqttMessage](trait.MqttMessage.html).
+/// Failures should be reported as `Error::Codec`.
+pub trait Codec<T> {
+    fn encode(value: &T) -> Result<Vec<u8>, Error>;
+    fn decode(payload: &[u8]) -> Result<T, Error>;
+}
+
+/// A [Codec](trait.Codec.html) that represents values as JSON, using
+/// [serde_json](https://docs.rs/serde_json)
+#[cfg(feature = "derive")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+#[cfg(feature = "derive")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for Json {
+    fn encode(value: &T) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(value).map_err(|e| Error::Codec(e.to_string()))
+    }
+
+    fn decode(payload: &[u8]) -> Result<T, Error> {
+        serde_json::from_slice(payload).map_err(|e| Error::Codec(e.to_string()))
+    }
+}
+
+/// Yields the received messages of type `M`; see
+/// [Client::subscribe_messages](struct.Client.html#method.subscribe_messages).
+pub struct TypedSubscriber<M> {
+    subscriber: Subscriber,
+    _message: PhantomData<fn() -> M>,
+}
+
+impl<M: MqttMessage> TypedSubscriber<M> {
+    pub(crate) fn new(subscriber: Subscriber) -> Self {
+        Self {
+            subscriber,
+            _message: PhantomData,
+        }
+    }
+
+    /// Wait for the next message.
+    /// Yields `Error::Codec` for a message that couldn't be decoded,
+    /// after which further messages can still be received, and
+    /// `Error::Closed` once the client has been dropped.
+    pub async fn recv(&self) -> Result<M, Error> {
+        let message = self.subscriber.recv().await.map_err(|_| Error::Closed)?;
+        M::decode(&message.payload)
+    }
+
+    /// Returns the next message if one is already queued, or `None`
+    /// if there is none.
+    /// Errors are reported as for [recv](#method.recv).
+    pub fn try_next(&self) -> Result<Option<M>, Error> {
+        match self.subscriber.try_next().map_err(|_| Error::Closed)? {
+            Some(message) => M::decode(&message.payload).map(Some),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::lowlevel::{Callbacks, Mosq, MosqPtr};
+use crate::Event;
+use async_channel::Sender;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// What a [Watchdog](struct.Watchdog.html) does when the broker
+/// has been silent for too long
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SilenceAction {
+    /// Report `Event::BrokerSilent` via
+    /// [Client::events](struct.Client.html#method.events)
+    #[default]
+    Notify,
+    /// Report `Event::BrokerSilent` and close the connection, so that
+    /// the client reconnects using the delays configured via
+    /// [set_reconnect_delay](struct.Client.html#method.set_reconnect_delay).
+    /// Closing the connection is only supported on unix systems;
+    /// elsewhere this behaves like `Notify`.
+    Reconnect,
+}
+
+/// Monitors the time since the client last received a packet from
+/// the broker, including the responses to keepalive pings.
+///
+/// libmosquitto only gives up on a connection after one and a half
+/// keepalive intervals without a response, and the TCP connection
+/// may appear to be healthy for much longer than that when a NAT
+/// gateway has silently discarded its state, as is common on mobile
+/// networks.  A watchdog with a `timeout` a little longer than the
+/// keepalive interval detects this sooner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchdog {
+    /// The period of silence after which the broker is considered
+    /// to be unresponsive
+    pub timeout: Duration,
+    /// What to do when that happens
+    pub action: SilenceAction,
+}
+
+/// Tracks the time of the last packet received from the broker
+#[derive(Default)]
+pub(crate) struct Activity {
+    /// `None` while disconnected
+    last_inbound: Mutex<Option<Instant>>,
+}
+
+impl Activity {
+    pub fn connected(&self) {
+        self.last_inbound.lock().unwrap().replace(Instant::now());
+    }
+
+    pub fn disconnected(&self) {
+        self.last_inbound.lock().unwrap().take();
+    }
+
+    /// Record a packet received from the broker
+    pub fn touch(&self) {
+        if let Some(last) = self.last_inbound.lock().unwrap().as_mut() {
+            *last = Instant::now();
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.last_inbound.lock().unwrap().is_some()
+    }
+
+    /// Returns how long the broker has been silent, or `None`
+    /// if the client is not connected
+    fn silent_for(&self) -> Option<Duration> {
+        self.last_inbound.lock().unwrap().map(|last| last.elapsed())
+    }
+}
+
+/// A thread that repeatedly calls a function, sleeping for the
+/// duration that it returns between calls.
+/// The thread is stopped when this is dropped.
+pub(crate) struct TimerThread {
+    stop: Option<std::sync::mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TimerThread {
+    pub fn spawn<F: FnMut() -> Duration + Send + 'static>(mut tick: F) -> Self {
+        let (stop, stopped) = channel();
+        let thread = std::thread::spawn(move || {
+            // Stop when the sender is dropped
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(tick()) {}
+        });
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Start a thread that monitors `activity`.
+    /// The owner of `mosq` must drop the returned thread before
+    /// dropping the client.
+    pub fn watchdog(
+        watchdog: Watchdog,
+        activity: Arc<Activity>,
+        events: Arc<Mutex<Option<Sender<Event>>>>,
+        mosq: MosqPtr,
+    ) -> Self {
+        Self::spawn(move || match activity.silent_for() {
+            Some(silent_for) if silent_for >= watchdog.timeout => {
+                if let Some(tx) = events.lock().unwrap().as_ref() {
+                    let _ = tx.try_send(Event::BrokerSilent { silent_for });
+                }
+                if watchdog.action == SilenceAction::Reconnect {
+                    mosq.with_client(|client| close_socket(client));
+                }
+                // Don't report the same silence again until
+                // another timeout has passed
+                activity.touch();
+                watchdog.timeout
+            }
+            Some(silent_for) => watchdog.timeout - silent_for,
+            None => watchdog.timeout,
+        })
+    }
+}
+
+impl Drop for TimerThread {
+    fn drop(&mut self) {
+        // Dropping the sender wakes up the thread
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Shut down the socket of the client, so that the loop thread sees
+/// the connection fail and reconnects
+pub(crate) fn close_socket<CB: Callbacks>(client: &Mosq<CB>) {
+    #[cfg(unix)]
+    if let Some(fd) = client.socket() {
+        unsafe {
+            libc::shutdown(fd, libc::SHUT_RDWR);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = client;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn activity() {
+        let activity = Activity::default();
+        activity.touch();
+        assert_eq!(activity.silent_for(), None);
+        activity.connected();
+        assert!(activity.silent_for().unwrap() < Duration::from_secs(5));
+        activity.disconnected();
+        assert_eq!(activity.silent_for(), None);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::properties::saturating_secs;
+use crate::{Properties, Property, QoS};
+use std::time::Duration;
+
+/// The will message, which the broker publishes on behalf of a client
+/// if its connection is lost without it having explicitly disconnected;
+/// see [Mosq::set_will](struct.Mosq.html#method.set_will) and
+/// [Client::set_last_will](struct.Client.html#method.set_last_will).
+///
+/// This is typically used to announce that a device has gone offline:
+///
+/// ```
+/// use mosquitto_rs::*;
+/// use std::time::Duration;
+///
+/// let will = LastWill::new("devices/kitchen/status", b"offline")
+///     .with_qos(QoS::AtLeastOnce)
+///     .with_retain(true)
+///     .with_delay(Duration::from_secs(30));
+/// assert_eq!(will.delay(), Some(Duration::from_secs(30)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastWill {
+    topic: String,
+    payload: Vec<u8>,
+    qos: QoS,
+    retain: bool,
+    delay: Option<Duration>,
+    properties: Properties,
+}
+
+impl LastWill {
+    /// Create a will that publishes `payload` to `topic` at QoS 0,
+    /// without the retain flag
+    pub fn new(topic: &str, payload: &[u8]) -> Self {
+        Self {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            qos: QoS::AtMostOnce,
+            retain: false,
+            delay: None,
+            properties: Properties::new(),
+        }
+    }
+
+    pub fn with_qos(mut self, qos: QoS) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    pub fn with_retain(mut self, retain: bool) -> Self {
+        self.retain = retain;
+        self
+    }
+
+    /// Ask an MQTT 5 broker to wait for `delay` before publishing the
+    /// will, so that it isn't published if the client reconnects and
+    /// resumes its session in the meantime.
+    /// Setting the delay requires MQTT 5; with an earlier version of
+    /// the protocol, setting the will fails.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Add an MQTT 5 property, such as a content type or user
+    /// property, to be published with the will
+    pub fn with_property(mut self, property: Property) -> Self {
+        self.properties.push(property);
+        self
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    pub fn qos(&self) -> QoS {
+        self.qos
+    }
+
+    pub fn retain(&self) -> bool {
+        self.retain
+    }
+
+    /// Returns the delay set via [with_delay](#method.with_delay)
+    pub fn delay(&self) -> Option<Duration> {
+        self.delay
+    }
+
+    /// Returns the properties added via [with_property](#method.with_property)
+    pub fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    /// Returns the properties to send, including the delay
+    pub(crate) fn all_properties(&self) -> Properties {
+        let mut properties = self.properties.clone();
+        if let Some(delay) = self.delay {
+            properties.push(Property::WillDelayInterval(saturating_secs(delay)));
+        }
+        properties
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn delay() {
+        let will = LastWill::new("status", b"offline");
+        assert_eq!(will.delay(), None);
+        let will = will
+            .with_delay(Duration::from_secs(10))
+            .with_delay(Duration::from_secs(5));
+        assert_eq!(will.delay(), Some(Duration::from_secs(5)));
+        assert!(will.properties().is_empty());
+        assert_eq!(
+            will.all_properties().iter().collect::<Vec<_>>(),
+            vec![&Property::WillDelayInterval(5)]
+        );
+    }
+}